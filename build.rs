@@ -1,14 +1,36 @@
 use std::path::Path;
 use std::process::Command;
 
+// cargo features that select FFmpeg components, see deps/ffmpeg.sh
+const FFMPEG_FEATURES: [&str; 4] = ["vaapi", "nvenc", "amf", "mjpeg"];
+
+fn feature_enabled(feature: &str) -> bool {
+    std::env::var_os(format!("CARGO_FEATURE_{}", feature.to_uppercase())).is_some()
+}
+
 fn build_ffmpeg() {
+    let features = FFMPEG_FEATURES
+        .iter()
+        .copied()
+        .filter(|feature| feature_enabled(feature))
+        .collect::<Vec<_>>()
+        .join(",");
+    // the features ffmpeg has been built with are stored next to it, if they changed it is built
+    // again, a deps/dist without this file has been provided by the user and is used as it is
+    let built_features = Path::new("deps/dist/features");
     if Path::new("deps/dist").exists() {
-        return;
+        match std::fs::read_to_string(built_features) {
+            Ok(built) if built != features => {
+                std::fs::remove_dir_all("deps/dist").expect("Failed to remove deps/dist!")
+            }
+            _ => return,
+        }
     }
 
     if !Command::new("bash")
         .arg(Path::new("build.sh"))
         .current_dir("deps")
+        .env("FFMPEG_FEATURES", &features)
         .status()
         .expect("Failed to run bash!")
         .success()
@@ -16,6 +38,7 @@ fn build_ffmpeg() {
         println!("cargo:warning=Failed to build ffmpeg!");
         std::process::exit(1);
     }
+    std::fs::write(built_features, &features).expect("Failed to write deps/dist/features!");
 }
 
 fn main() {
@@ -52,17 +75,15 @@ fn main() {
     cc::Build::new().file("lib/error.c").compile("error");
 
     println!("cargo:rerun-if-changed=lib/encode_video.c");
+    println!("cargo:rerun-if-changed=lib/mux_video.c");
     cc::Build::new()
         .file("lib/encode_video.c")
+        .file("lib/mux_video.c")
         .include("deps/dist/include")
         .compile("video");
     println!("cargo:rustc-link-lib=static=avcodec");
-    println!("cargo:rustc-link-lib=static=avdevice");
-    println!("cargo:rustc-link-lib=static=avfilter");
     println!("cargo:rustc-link-lib=static=avformat");
     println!("cargo:rustc-link-lib=static=avutil");
-    println!("cargo:rustc-link-lib=static=postproc");
-    println!("cargo:rustc-link-lib=static=swresample");
     println!("cargo:rustc-link-lib=static=swscale");
     println!("cargo:rustc-link-lib=static=x264");
     println!("cargo:rustc-link-search=deps/dist/lib");
@@ -73,19 +94,33 @@ fn main() {
 
 #[cfg(target_os = "linux")]
 fn linux() {
+    println!("cargo:rerun-if-changed=lib/linux/drmcapture.c");
     println!("cargo:rerun-if-changed=lib/linux/uniput.c");
+    println!("cargo:rerun-if-changed=lib/linux/v4l2loopback.c");
     println!("cargo:rerun-if-changed=lib/linux/xcapture.c");
     println!("cargo:rerun-if-changed=lib/linux/xhelper.c");
     println!("cargo:rerun-if-changed=lib/linux/xhelper.h");
-    cc::Build::new()
+    let mut build = cc::Build::new();
+    build
+        .file("lib/linux/drmcapture.c")
         .file("lib/linux/uinput.c")
+        .file("lib/linux/v4l2loopback.c")
         .file("lib/linux/xcapture.c")
-        .file("lib/linux/xhelper.c")
-        .compile("linux");
+        .file("lib/linux/xhelper.c");
+    if feature_enabled("xcomposite") {
+        build.define("HAS_XCOMPOSITE", None);
+        println!("cargo:rustc-link-lib=Xcomposite");
+    }
+    build.compile("linux");
     println!("cargo:rustc-link-lib=X11");
     println!("cargo:rustc-link-lib=Xext");
     println!("cargo:rustc-link-lib=Xrandr");
     println!("cargo:rustc-link-lib=Xfixes");
-    println!("cargo:rustc-link-lib=Xcomposite");
     println!("cargo:rustc-link-lib=Xi");
+    if feature_enabled("vaapi") {
+        println!("cargo:rustc-link-lib=va");
+        println!("cargo:rustc-link-lib=va-drm");
+    }
+    // the Nvidia driver is loaded at runtime
+    println!("cargo:rustc-link-lib=dl");
 }
@@ -15,6 +15,15 @@ else
 fi
 
 ./x264.sh
+if [ "$(uname)" == "Linux" ] && [[ "${FFMPEG_FEATURES-nvenc}" == *nvenc* ]]; then
+    # headers required to load the Nvidia driver at runtime, there is nothing to compile
+    make -C nv-codec-headers install PREFIX="$(pwd)/dist"
+fi
+if [ "$RUNNER_OS" == "Windows" ] && [[ "${FFMPEG_FEATURES-amf}" == *amf* ]]; then
+    # headers required to load the AMD driver at runtime
+    mkdir -p dist/include/AMF
+    cp -r AMF/amf/public/include/* dist/include/AMF/
+fi
 ./ffmpeg.sh
 
 if [ "$RUNNER_OS" == "Windows" ]; then
@@ -2,9 +2,13 @@
 
 set -ex
 
-rm -rf x264 ffmpeg
+rm -rf x264 ffmpeg nv-codec-headers AMF
 git clone -b stable https://code.videolan.org/videolan/x264.git x264
+git clone -b n9.1.23.1 https://git.videolan.org/git/ffmpeg/nv-codec-headers.git nv-codec-headers
 git clone -b n4.3 https://git.ffmpeg.org/ffmpeg.git ffmpeg
+if [ "$RUNNER_OS" == "Windows" ]; then
+	git clone --depth 1 -b v1.4.18 https://github.com/GPUOpen-LibrariesAndSDKs/AMF.git AMF
+fi
 cd ffmpeg
 
 if [ "$RUNNER_OS" == "Windows" ]; then
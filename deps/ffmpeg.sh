@@ -2,6 +2,35 @@
 
 set -ex
 
+# optional encoders to build, set by build.rs from the cargo features, all of them if this is run
+# by hand
+FFMPEG_FEATURES="${FFMPEG_FEATURES-vaapi,nvenc,amf,mjpeg}"
+
+has_feature() {
+	[[ ",$FFMPEG_FEATURES," == *",$1,"* ]]
+}
+
+# only the encoders and muxers Weylus uses are built, everything else would just bloat the binary
+ENCODERS="libx264"
+# the drivers of the hardware encoders are loaded at runtime, only their headers are required
+if [ "$(uname)" == "Linux" ] && has_feature vaapi; then
+	HW_ENCODERS="$HW_ENCODERS --enable-vaapi"
+	ENCODERS="$ENCODERS,h264_vaapi"
+fi
+if [ "$(uname)" == "Linux" ] && has_feature nvenc; then
+	HW_ENCODERS="$HW_ENCODERS --enable-ffnvcodec --enable-nvenc"
+	ENCODERS="$ENCODERS,h264_nvenc"
+fi
+if [ "$RUNNER_OS" == "Windows" ] && has_feature amf; then
+	HW_ENCODERS="$HW_ENCODERS --enable-amf"
+	ENCODERS="$ENCODERS,h264_amf"
+fi
+if has_feature mjpeg; then
+	ENCODERS="$ENCODERS,mjpeg"
+fi
+
+export PKG_CONFIG_PATH="$(pwd)/dist/lib/pkgconfig:$PKG_CONFIG_PATH"
+
 cd ffmpeg
 ./configure \
 	--prefix=../dist \
@@ -12,7 +41,17 @@ cd ffmpeg
 	--enable-stripping \
 	--disable-programs \
 	--enable-gpl \
+	--disable-everything \
+	--disable-avdevice \
+	--disable-avfilter \
+	--disable-postproc \
+	--disable-swresample \
+	--disable-network \
 	--enable-libx264 \
+	$HW_ENCODERS \
+	--enable-encoder="$ENCODERS" \
+	--enable-muxer=mp4,mpegts \
+	--enable-bsf=h264_mp4toannexb \
 	--disable-autodetect \
 	--extra-cflags="$FFMPEG_CFLAGS" \
 	--extra-ldflags="$FFMPEG_LIBRARY_PATH" \
@@ -0,0 +1,21 @@
+//! Parse arbitrary data as message sent by the client. Weylus is not a library, the protocol is
+//! included directly from the sources instead.
+#![no_main]
+
+#[macro_use]
+extern crate bitflags;
+
+use libfuzzer_sys::fuzz_target;
+
+#[path = "../../src/protocol.rs"]
+#[allow(dead_code)]
+mod protocol;
+
+use protocol::NetMessage;
+
+fuzz_target!(|data: &[u8]| {
+    // websocket text messages are always valid UTF-8
+    if let Ok(s) = std::str::from_utf8(data) {
+        let _: Result<NetMessage, _> = serde_json::from_str(s);
+    }
+});
@@ -0,0 +1,73 @@
+//! Feed arbitrary messages through the processing PointerStreamHandler applies to pointer events
+//! before they reach the input device: smoothing, filtering of touch noise and detection of long
+//! presses. Every line of the input is a separate message, state is kept between them like for a
+//! single connection.
+#![no_main]
+
+#[macro_use]
+extern crate bitflags;
+
+use libfuzzer_sys::fuzz_target;
+
+#[path = "../../src/protocol.rs"]
+#[allow(dead_code)]
+mod protocol;
+
+#[path = "../../src/input/long_press.rs"]
+#[allow(dead_code)]
+mod long_press;
+#[path = "../../src/input/smoothing.rs"]
+#[allow(dead_code)]
+mod smoothing;
+#[path = "../../src/input/touch_filter.rs"]
+#[allow(dead_code)]
+mod touch_filter;
+
+use long_press::{LongPressDetector, LongPressOptions};
+use smoothing::{PointerSmoother, Smoothing};
+use touch_filter::{TouchFilter, TouchFilterOptions};
+use protocol::{NetMessage, PointerEvent};
+
+struct Pipeline {
+    smoother: PointerSmoother,
+    touch_filter: TouchFilter,
+    long_press: LongPressDetector,
+}
+
+impl Pipeline {
+    fn process(&mut self, mut events: Vec<PointerEvent>) {
+        for event in events.iter_mut() {
+            self.smoother.smooth(event);
+        }
+        let mut filtered = Vec::new();
+        for event in &events {
+            self.touch_filter.process(event, &mut filtered);
+        }
+        let mut forwarded = Vec::new();
+        for event in &filtered {
+            self.long_press.process(event, &mut forwarded);
+        }
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    let s = match std::str::from_utf8(data) {
+        Ok(s) => s,
+        Err(_) => return,
+    };
+    let mut pipeline = Pipeline {
+        smoother: PointerSmoother::new(Smoothing::High),
+        touch_filter: TouchFilter::new(TouchFilterOptions {
+            dead_zone: 0.01,
+            debounce: 50,
+        }),
+        long_press: LongPressDetector::new(LongPressOptions::default()),
+    };
+    for line in s.lines() {
+        match serde_json::from_str(line) {
+            Ok(NetMessage::PointerEvent(event)) => pipeline.process(vec![event]),
+            Ok(NetMessage::PointerEvents(events)) => pipeline.process(events),
+            _ => (),
+        }
+    }
+});
@@ -1,8 +1,7 @@
 #include <libavcodec/avcodec.h>
-#include <libavformat/avformat.h>
-#include <libavformat/avio.h>
 #include <libavutil/dict.h>
 #include <libavutil/frame.h>
+#include <libavutil/hwcontext.h>
 #include <libavutil/mem.h>
 #include <libavutil/pixfmt.h>
 
@@ -11,27 +10,178 @@
 
 #include <libswscale/swscale.h>
 #include <stdint.h>
+#include <string.h>
 
 #include "error.h"
 
+// mirrors Codec in video.rs
+enum Codec
+{
+	CODEC_X264 = 0,
+	CODEC_VAAPI = 1,
+	CODEC_NVENC = 2,
+	CODEC_MJPEG = 3,
+	CODEC_AMF = 4,
+};
+
+// mirrors enum PixelFormat in video.rs
+enum PixelFormat
+{
+	PIXEL_FORMAT_BGRA = 0,
+	PIXEL_FORMAT_RGBX = 1,
+	PIXEL_FORMAT_NV12 = 2,
+	PIXEL_FORMAT_I420 = 3,
+};
+
+typedef struct VideoEncoderOptions
+{
+	int scaling_filter;
+	int colorspace;
+	int full_range;
+	int pointer_roi;
+	int codec;
+	int threads;
+	// set if the muxer needs the parameter sets out of band, e.g. for fragmented mp4
+	int global_header;
+	// 0 if there is no budget
+	int latency_budget_ms;
+	int intra_refresh;
+} VideoEncoderOptions;
+
 typedef struct VideoContext
 {
-	AVFormatContext* oc;
 	AVCodecContext* c;
 	AVFrame* frame;
 	AVPacket* pkt;
-	AVStream* st;
+	// dimensions of the encoded video, these are even as required by yuv420p
 	int width;
 	int height;
-	size_t buf_size;
-	void* buf;
+	// dimensions of the captured image, if they are odd the video is padded by a row or column
+	int content_width;
+	int content_height;
 	void* rust_ctx;
 	int pts;
 	struct SwsContext* sws;
+	// pixel format and source dimensions sws has been created for, the source dimensions differ
+	// from the dimensions of the encoder if the captured image is resized during a stream
+	int sws_pixel_format;
+	int sws_width;
+	int sws_height;
+	VideoEncoderOptions options;
+	int roi_enabled;
+	int roi_left;
+	int roi_top;
+	int roi_right;
+	int roi_bottom;
+	// the next frame is encoded as keyframe, see request_keyframe
+	int force_keyframe;
+	// frames are encoded with lower quality while set, see set_draft_quality
+	int draft;
+	// whether the encoder has been reconfigured for draft quality
+	int draft_applied;
+	// only used by VAAPI: the frame filled by Rust is converted to NV12 and uploaded to the GPU
+	AVBufferRef* hw_device_ctx;
+	AVFrame* nv12_frame;
+	AVFrame* hw_frame;
+	struct SwsContext* sws_nv12;
 	int initialized;
 } VideoContext;
 
-int write_video_packet(void* rust_ctx, uint8_t* buf, int buf_size);
+// packets are passed to the muxer chosen in Rust, timestamps are in milliseconds
+int write_video_packet(
+	void* rust_ctx, uint8_t* buf, int buf_size, int64_t pts, int64_t dts, int keyframe);
+
+// rate factors of x264, higher values mean lower quality
+#define DEFAULT_CRF "23"
+#define DRAFT_CRF "32"
+// the quantizer of MJPEG is multiplied by this while drafting
+#define DRAFT_QSCALE_FACTOR 2
+// bitrate the VBV buffer of a latency budget is sized for in bits per pixel and frame at 60 fps,
+// this is plenty for screen content
+#define LATENCY_BUDGET_BITS_PER_PIXEL 0.1
+
+const char* encoder_name(int codec)
+{
+	switch (codec)
+	{
+	case CODEC_VAAPI:
+		return "h264_vaapi";
+	case CODEC_NVENC:
+		return "h264_nvenc";
+	case CODEC_AMF:
+		return "h264_amf";
+	case CODEC_MJPEG:
+		return "mjpeg";
+	default:
+		return "libx264";
+	}
+}
+
+void open_vaapi(VideoContext* ctx, Error* err)
+{
+	int ret = av_hwdevice_ctx_create(&ctx->hw_device_ctx, AV_HWDEVICE_TYPE_VAAPI, NULL, NULL, 0);
+	if (ret < 0)
+		ERROR(err, 1, "Failed to open VAAPI device: %s", av_err2str(ret));
+
+	AVBufferRef* hw_frames_ref = av_hwframe_ctx_alloc(ctx->hw_device_ctx);
+	if (!hw_frames_ref)
+		ERROR(err, 1, "Failed to allocate VAAPI frame context");
+	AVHWFramesContext* frames_ctx = (AVHWFramesContext*)hw_frames_ref->data;
+	frames_ctx->format = AV_PIX_FMT_VAAPI;
+	frames_ctx->sw_format = AV_PIX_FMT_NV12;
+	frames_ctx->width = ctx->width;
+	frames_ctx->height = ctx->height;
+	frames_ctx->initial_pool_size = 4;
+	ret = av_hwframe_ctx_init(hw_frames_ref);
+	if (ret < 0)
+	{
+		av_buffer_unref(&hw_frames_ref);
+		ERROR(err, 1, "Failed to initialize VAAPI frame context: %s", av_err2str(ret));
+	}
+	ctx->c->hw_frames_ctx = av_buffer_ref(hw_frames_ref);
+	av_buffer_unref(&hw_frames_ref);
+	if (!ctx->c->hw_frames_ctx)
+		ERROR(err, 1, "Failed to reference VAAPI frame context");
+
+	ctx->nv12_frame = av_frame_alloc();
+	ctx->hw_frame = av_frame_alloc();
+	if (!ctx->nv12_frame || !ctx->hw_frame)
+		ERROR(err, 1, "Could not allocate VAAPI frames");
+	ctx->nv12_frame->format = AV_PIX_FMT_NV12;
+	ctx->nv12_frame->width = ctx->width;
+	ctx->nv12_frame->height = ctx->height;
+	ret = av_frame_get_buffer(ctx->nv12_frame, 32);
+	if (ret < 0)
+		ERROR(err, 1, "Could not allocate NV12 frame data");
+
+	// only the layout of the chroma planes changes, no scaling is required
+	ctx->sws_nv12 = sws_getContext(
+		ctx->width,
+		ctx->height,
+		AV_PIX_FMT_YUV420P,
+		ctx->width,
+		ctx->height,
+		AV_PIX_FMT_NV12,
+		SWS_POINT,
+		NULL,
+		NULL,
+		NULL);
+	if (!ctx->sws_nv12)
+		ERROR(err, 1, "Could not create conversion to NV12");
+}
+
+// keep the time to encode and send a frame within the latency budget: x264 looks ahead at no
+// frames, splits frames into slices that are encoded in parallel and the VBV buffer holds no more
+// than what is sent within the budget, so large frames are compressed more instead of taking
+// longer to transmit
+void set_x264_latency_budget(VideoContext* ctx)
+{
+	int budget = ctx->options.latency_budget_ms;
+	ctx->c->rc_max_rate = (int64_t)(ctx->width * ctx->height * 60 * LATENCY_BUDGET_BITS_PER_PIXEL);
+	ctx->c->rc_buffer_size = (int)(ctx->c->rc_max_rate * budget / 1000);
+	av_opt_set(ctx->c->priv_data, "rc-lookahead", "0", 0);
+	av_opt_set(ctx->c->priv_data, "x264-params", "sliced-threads=1:sync-lookahead=0", 0);
+}
 
 void open_video(VideoContext* ctx, Error* err)
 {
@@ -40,17 +190,12 @@ void open_video(VideoContext* ctx, Error* err)
 
 	const AVCodec* codec;
 	int ret;
+	int codec_id = ctx->options.codec;
 
-	avformat_alloc_output_context2(&ctx->oc, NULL, "mp4", NULL);
-	if (!ctx->oc)
-	{
-		ERROR(err, 1, "Could not find output format mp4.");
-	}
-
-	codec = avcodec_find_encoder_by_name("libx264");
+	codec = avcodec_find_encoder_by_name(encoder_name(codec_id));
 	if (!codec)
 	{
-		ERROR(err, 1, "Codec 'libx264' not found");
+		ERROR(err, 1, "Codec '%s' not found", encoder_name(codec_id));
 	}
 
 	ctx->c = avcodec_alloc_context3(codec);
@@ -69,14 +214,74 @@ void open_video(VideoContext* ctx, Error* err)
 	// no B-frames to reduce latency
 	ctx->c->max_b_frames = 0;
 	ctx->c->pix_fmt = AV_PIX_FMT_YUV420P;
-	if (ctx->oc->oformat->flags & AVFMT_GLOBALHEADER)
+	if (codec_id == CODEC_VAAPI)
+		ctx->c->pix_fmt = AV_PIX_FMT_VAAPI;
+	else if (codec_id == CODEC_MJPEG)
+		ctx->c->pix_fmt = AV_PIX_FMT_YUVJ420P;
+
+	// signal the colorspace used for conversion, otherwise clients have to guess and may end up
+	// with washed out or crushed colors
+	ctx->c->color_range = ctx->options.full_range ? AVCOL_RANGE_JPEG : AVCOL_RANGE_MPEG;
+	if (ctx->options.colorspace == 1)
+	{
+		ctx->c->colorspace = AVCOL_SPC_BT709;
+		ctx->c->color_primaries = AVCOL_PRI_BT709;
+		ctx->c->color_trc = AVCOL_TRC_BT709;
+	}
+	else
+	{
+		ctx->c->colorspace = AVCOL_SPC_SMPTE170M;
+		ctx->c->color_primaries = AVCOL_PRI_SMPTE170M;
+		ctx->c->color_trc = AVCOL_TRC_SMPTE170M;
+	}
+	if (ctx->options.global_header)
 		ctx->c->flags |= AV_CODEC_FLAG_GLOBAL_HEADER;
 
-	av_opt_set(ctx->c->priv_data, "preset", "ultrafast", 0);
-	av_opt_set(ctx->c->priv_data, "tune", "zerolatency", 0);
-	av_opt_set(ctx->c->priv_data, "crf", "23", 0);
+	// libavcodec defaults to a single thread, slice threads split each frame instead of encoding
+	// several frames in parallel, which would add a frame of latency per thread
+	ctx->c->thread_count = ctx->options.threads;
+	ctx->c->thread_type = FF_THREAD_SLICE;
 
-	ctx->st = avformat_new_stream(ctx->oc, NULL);
+	switch (codec_id)
+	{
+	case CODEC_X264:
+		av_opt_set(ctx->c->priv_data, "preset", "ultrafast", 0);
+		av_opt_set(ctx->c->priv_data, "tune", "zerolatency", 0);
+		av_opt_set(ctx->c->priv_data, "crf", DEFAULT_CRF, 0);
+		// frames forced to be keyframes become IDR frames, decoders can start over from these
+		av_opt_set(ctx->c->priv_data, "forced-idr", "1", 0);
+		// x264 ignores regions of interest if adaptive quantization is disabled, which is the
+		// case for the ultrafast preset
+		if (ctx->options.pointer_roi)
+			av_opt_set(ctx->c->priv_data, "aq-mode", "variance", 0);
+		if (ctx->options.latency_budget_ms > 0)
+			set_x264_latency_budget(ctx);
+		// instead of a keyframe every gop_size frames a column of intra coded blocks moves across
+		// the picture within that many frames, keyframes are only sent if forced
+		if (ctx->options.intra_refresh)
+			av_opt_set(ctx->c->priv_data, "intra-refresh", "1", 0);
+		break;
+	case CODEC_VAAPI:
+		open_vaapi(ctx, err);
+		OK_OR_ABORT(err);
+		break;
+	case CODEC_NVENC:
+		av_opt_set(ctx->c->priv_data, "preset", "llhp", 0);
+		av_opt_set(ctx->c->priv_data, "zerolatency", "1", 0);
+		av_opt_set(ctx->c->priv_data, "delay", "0", 0);
+		av_opt_set(ctx->c->priv_data, "forced-idr", "1", 0);
+		break;
+	case CODEC_AMF:
+		av_opt_set(ctx->c->priv_data, "usage", "ultralowlatency", 0);
+		av_opt_set(ctx->c->priv_data, "quality", "speed", 0);
+		av_opt_set(ctx->c->priv_data, "forced_idr", "1", 0);
+		break;
+	case CODEC_MJPEG:
+		// fixed quality, lower values mean higher quality
+		ctx->c->flags |= AV_CODEC_FLAG_QSCALE;
+		ctx->c->global_quality = FF_QP2LAMBDA * 5;
+		break;
+	}
 
 	/* open it */
 	ret = avcodec_open2(ctx->c, codec, NULL);
@@ -85,16 +290,19 @@ void open_video(VideoContext* ctx, Error* err)
 		ERROR(err, 1, "Could not open codec: %s", av_err2str(ret));
 	}
 
-	avcodec_parameters_from_context(ctx->st->codecpar, ctx->c);
-
 	ctx->frame = av_frame_alloc();
 	if (!ctx->frame)
 	{
 		ERROR(err, 1, "Could not allocate video frame");
 	}
-	ctx->frame->format = ctx->c->pix_fmt;
+	// hardware frames are filled from this frame, see encode_video_frame
+	ctx->frame->format = codec_id == CODEC_VAAPI ? AV_PIX_FMT_YUV420P : ctx->c->pix_fmt;
 	ctx->frame->width = ctx->c->width;
 	ctx->frame->height = ctx->c->height;
+	ctx->frame->color_range = ctx->c->color_range;
+	ctx->frame->colorspace = ctx->c->colorspace;
+	ctx->frame->color_primaries = ctx->c->color_primaries;
+	ctx->frame->color_trc = ctx->c->color_trc;
 
 	ret = av_frame_get_buffer(ctx->frame, 32);
 	if (ret < 0)
@@ -106,45 +314,116 @@ void open_video(VideoContext* ctx, Error* err)
 	if (!ctx->pkt)
 		ERROR(err, 1, "Failed to allocate packet");
 
-	ctx->buf_size = 1024 * 1024;
-	ctx->buf = av_malloc(ctx->buf_size);
-	ctx->oc->pb = avio_alloc_context(
-		ctx->buf, ctx->buf_size, AVIO_FLAG_WRITE, ctx->rust_ctx, NULL, write_video_packet, NULL);
-	if (!ctx->oc->pb)
-		ERROR(err, 1, "Failed to allocate avio context");
-
-	av_dump_format(ctx->oc, 0, NULL, 1);
-	AVDictionary* opt = NULL;
-	// enable writing fragmented mp4
-	av_dict_set(&opt, "movflags", "frag_custom+empty_moov+default_base_moof", 0);
-	ret = avformat_write_header(ctx->oc, &opt);
-	av_dict_free(&opt);
 	ctx->initialized = 1;
 }
 
+AVCodecContext* video_codec_context(VideoContext* ctx)
+{
+	return ctx->c;
+}
+
+int video_encoder_available(int codec)
+{
+	return avcodec_find_encoder_by_name(encoder_name(codec)) != NULL;
+}
+
 void destroy_video_encoder(VideoContext* ctx)
 {
 	if (ctx->initialized)
 	{
-		av_write_trailer(ctx->oc);
-		avio_context_free(&ctx->oc->pb);
-		avformat_free_context(ctx->oc);
 		avcodec_free_context(&ctx->c);
 		av_frame_free(&ctx->frame);
 		av_packet_free(&ctx->pkt);
-		av_free(ctx->buf);
 		sws_freeContext(ctx->sws);
+		av_frame_free(&ctx->nv12_frame);
+		av_frame_free(&ctx->hw_frame);
+		sws_freeContext(ctx->sws_nv12);
+		av_buffer_unref(&ctx->hw_device_ctx);
 	}
 	free(ctx);
 }
 
+// upload the frame filled by Rust to the GPU, the result is stored in hw_frame
+void upload_frame(VideoContext* ctx, Error* err)
+{
+	sws_scale(
+		ctx->sws_nv12,
+		(const uint8_t* const*)ctx->frame->data,
+		ctx->frame->linesize,
+		0,
+		ctx->height,
+		ctx->nv12_frame->data,
+		ctx->nv12_frame->linesize);
+	av_frame_unref(ctx->hw_frame);
+	int ret = av_hwframe_get_buffer(ctx->c->hw_frames_ctx, ctx->hw_frame, 0);
+	if (ret < 0)
+	{
+		ERROR(err, 1, "Failed to allocate VAAPI frame: %s", av_err2str(ret));
+	}
+	ret = av_hwframe_transfer_data(ctx->hw_frame, ctx->nv12_frame, 0);
+	if (ret < 0)
+	{
+		ERROR(err, 1, "Failed to upload frame to VAAPI: %s", av_err2str(ret));
+	}
+	av_frame_copy_props(ctx->hw_frame, ctx->frame);
+}
+
 void encode_video_frame(VideoContext* ctx, int micros, Error* err)
 {
 	int ret;
 
 	ctx->frame->pts = micros;
 
-	ret = avcodec_send_frame(ctx->c, ctx->frame);
+	av_frame_remove_side_data(ctx->frame, AV_FRAME_DATA_REGIONS_OF_INTEREST);
+	if (ctx->options.pointer_roi && ctx->roi_enabled)
+	{
+		AVFrameSideData* sd = av_frame_new_side_data(
+			ctx->frame, AV_FRAME_DATA_REGIONS_OF_INTEREST, sizeof(AVRegionOfInterest));
+		if (sd)
+		{
+			AVRegionOfInterest* roi = (AVRegionOfInterest*)sd->data;
+			roi->self_size = sizeof(AVRegionOfInterest);
+			roi->left = ctx->roi_left;
+			roi->top = ctx->roi_top;
+			roi->right = ctx->roi_right;
+			roi->bottom = ctx->roi_bottom;
+			// negative offsets lower the quantizer, that is increase quality
+			roi->qoffset = (AVRational){-1, 3};
+		}
+	}
+
+	AVFrame* frame = ctx->frame;
+	if (ctx->options.codec == CODEC_VAAPI)
+	{
+		upload_frame(ctx, err);
+		OK_OR_ABORT(err);
+		frame = ctx->hw_frame;
+	}
+	else if (ctx->options.codec == CODEC_MJPEG)
+		frame->quality = ctx->c->global_quality * (ctx->draft ? DRAFT_QSCALE_FACTOR : 1);
+
+	if (ctx->draft != ctx->draft_applied)
+	{
+		// libx264 picks up the changed rate factor with the next frame, other encoders keep their
+		// quality
+		if (ctx->options.codec == CODEC_X264)
+			av_opt_set(ctx->c->priv_data, "crf", ctx->draft ? DRAFT_CRF : DEFAULT_CRF, 0);
+		ctx->draft_applied = ctx->draft;
+	}
+
+	if (ctx->force_keyframe)
+	{
+		frame->pict_type = AV_PICTURE_TYPE_I;
+		frame->key_frame = 1;
+		ctx->force_keyframe = 0;
+	}
+	else
+	{
+		frame->pict_type = AV_PICTURE_TYPE_NONE;
+		frame->key_frame = 0;
+	}
+
+	ret = avcodec_send_frame(ctx->c, frame);
 	if (ret < 0)
 		ERROR(err, 1, "Error sending a frame for encoding");
 
@@ -158,37 +437,82 @@ void encode_video_frame(VideoContext* ctx, int micros, Error* err)
 			ERROR(err, 1, "Error during encoding");
 		}
 
-		av_packet_rescale_ts(ctx->pkt, ctx->c->time_base, ctx->st->time_base);
-		av_write_frame(ctx->oc, ctx->pkt);
+		write_video_packet(
+			ctx->rust_ctx,
+			ctx->pkt->data,
+			ctx->pkt->size,
+			ctx->pkt->pts,
+			ctx->pkt->dts,
+			(ctx->pkt->flags & AV_PKT_FLAG_KEY) != 0);
 		av_packet_unref(ctx->pkt);
+	}
+}
 
-		// new fragment on every frame for lowest latency
-		av_write_frame(ctx->oc, NULL);
+int get_sws_flags(int scaling_filter)
+{
+	switch (scaling_filter)
+	{
+	case 1:
+		return SWS_BICUBIC;
+	case 2:
+		return SWS_LANCZOS;
+	default:
+		return SWS_FAST_BILINEAR;
 	}
 }
 
-VideoContext* init_video_encoder(void* rust_ctx, int width, int height)
+VideoContext* init_video_encoder(
+	void* rust_ctx, int width, int height, const VideoEncoderOptions* options)
 {
-	VideoContext* ctx = malloc(sizeof(VideoContext));
+	// zeroed so fields only set on request, like the region of interest or a forced keyframe,
+	// start out unset
+	VideoContext* ctx = calloc(1, sizeof(VideoContext));
 	ctx->rust_ctx = rust_ctx;
-	ctx->width = width - width%2;
-	ctx->height = height - height%2;
+	ctx->content_width = width;
+	ctx->content_height = height;
+	ctx->width = width + width % 2;
+	ctx->height = height + height % 2;
 	ctx->pts = 0;
 	ctx->initialized = 0;
-	ctx->sws = sws_getContext(
-		width,
-		height,
-		AV_PIX_FMT_BGRA,
-		ctx->width, // note that this is != width, this is in purpose as this allows proper
-		ctx->height, // rescaling if dimensions of provided image data are not even
-		AV_PIX_FMT_YUV420P,
-		SWS_FAST_BILINEAR,
-		NULL,
-		NULL,
-		NULL);
+	ctx->options = *options;
+	ctx->roi_enabled = 0;
+	ctx->draft = 0;
+	ctx->draft_applied = 0;
+	ctx->hw_device_ctx = NULL;
+	ctx->nv12_frame = NULL;
+	ctx->hw_frame = NULL;
+	ctx->sws_nv12 = NULL;
+	// created on first use as the pixel format is only known once something has been captured
+	ctx->sws = NULL;
+	ctx->sws_pixel_format = -1;
+	ctx->sws_width = 0;
+	ctx->sws_height = 0;
 	return ctx;
 }
 
+void set_region_of_interest(
+	VideoContext* ctx, int enabled, int left, int top, int right, int bottom)
+{
+	ctx->roi_enabled = enabled;
+	ctx->roi_left = left;
+	ctx->roi_top = top;
+	ctx->roi_right = right;
+	ctx->roi_bottom = bottom;
+}
+
+void request_keyframe(VideoContext* ctx)
+{
+	ctx->force_keyframe = 1;
+}
+
+void set_draft_quality(VideoContext* ctx, int draft)
+{
+	// refresh the whole picture in full quality once drafting ends
+	if (ctx->draft && !draft)
+		ctx->force_keyframe = 1;
+	ctx->draft = draft;
+}
+
 uint8_t** get_video_frame_data(VideoContext* ctx, int** linesizes)
 {
 	// make sure the frame data is writable
@@ -198,15 +522,93 @@ uint8_t** get_video_frame_data(VideoContext* ctx, int** linesizes)
 	return ctx->frame->data;
 }
 
-void convert_bgra2yuv420p(
+// replicate the last column and row of the captured image into the padding, the chroma planes
+// already cover the padding as their size is rounded up
+void pad_frame(VideoContext* ctx, uint8_t* const* dst, const int* dst_stride)
+{
+	uint8_t* y = dst[0];
+	if (ctx->content_width < ctx->width)
+		for (int row = 0; row < ctx->content_height; ++row)
+			y[row * dst_stride[0] + ctx->content_width] =
+				y[row * dst_stride[0] + ctx->content_width - 1];
+	if (ctx->content_height < ctx->height)
+		memcpy(
+			y + ctx->content_height * dst_stride[0],
+			y + (ctx->content_height - 1) * dst_stride[0],
+			ctx->width);
+}
+
+enum AVPixelFormat av_pixel_format(int pixel_format)
+{
+	switch (pixel_format)
+	{
+	case PIXEL_FORMAT_RGBX:
+		return AV_PIX_FMT_RGB0;
+	case PIXEL_FORMAT_NV12:
+		return AV_PIX_FMT_NV12;
+	case PIXEL_FORMAT_I420:
+		return AV_PIX_FMT_YUV420P;
+	default:
+		return AV_PIX_FMT_BGRA;
+	}
+}
+
+struct SwsContext* create_sws_context(VideoContext* ctx, int pixel_format, int width, int height)
+{
+	struct SwsContext* sws = sws_getContext(
+		width,
+		height,
+		av_pixel_format(pixel_format),
+		ctx->content_width, // this differs from width if frames are scaled to the size of a
+		ctx->content_height, // running stream
+		AV_PIX_FMT_YUV420P,
+		get_sws_flags(ctx->options.scaling_filter),
+		NULL,
+		NULL,
+		NULL);
+	if (sws)
+	{
+		const int* coefficients =
+			sws_getCoefficients(ctx->options.colorspace == 1 ? SWS_CS_ITU709 : SWS_CS_ITU601);
+		// captured RGB data is always full range, captured YUV data is expected to already be in
+		// the range of the output
+		int src_range = pixel_format == PIXEL_FORMAT_NV12 || pixel_format == PIXEL_FORMAT_I420
+							? ctx->options.full_range
+							: 1;
+		sws_setColorspaceDetails(
+			sws,
+			coefficients,
+			src_range,
+			coefficients,
+			ctx->options.full_range,
+			0,
+			1 << 16,
+			1 << 16);
+	}
+	return sws;
+}
+
+void convert_to_yuv420p(
 	VideoContext* ctx,
-	const void* data,
+	int pixel_format,
+	const uint8_t* const* src,
+	const int* src_stride,
 	int width,
 	int height,
 	uint8_t* const* dst,
 	const int* dst_stride)
 {
-	const uint8_t* const* src = (const uint8_t* const*)&data;
-	const int src_stride[] = {width * 4, 0, 0, 0};
+	if (ctx->sws_pixel_format != pixel_format || ctx->sws_width != width ||
+		ctx->sws_height != height)
+	{
+		sws_freeContext(ctx->sws);
+		ctx->sws = create_sws_context(ctx, pixel_format, width, height);
+		ctx->sws_pixel_format = pixel_format;
+		ctx->sws_width = width;
+		ctx->sws_height = height;
+	}
+	if (!ctx->sws)
+		return;
 	sws_scale(ctx->sws, src, src_stride, 0, height, dst, dst_stride);
+	pad_frame(ctx, dst, dst_stride);
 }
@@ -0,0 +1,204 @@
+#include <errno.h>
+#include <fcntl.h>
+#include <stdint.h>
+#include <stdlib.h>
+#include <string.h>
+#include <sys/ioctl.h>
+#include <sys/mman.h>
+#include <unistd.h>
+
+#include <drm/drm.h>
+#include <drm/drm_mode.h>
+
+#include "../error.h"
+
+// Captures the framebuffer a CRTC scans out via DRM/KMS, this works without any display server.
+// Getting a handle to the framebuffer of another process requires CAP_SYS_ADMIN and only linear
+// framebuffers with 32 bits per pixel (XRGB8888) can be read, this is what the Linux console and
+// most kiosk setups use.
+
+struct DrmCaptureContext
+{
+	int fd;
+	uint32_t crtc_id;
+	// the mapped framebuffer, this changes whenever the client flips pages
+	uint32_t fb_id;
+	uint32_t handle;
+	void* map;
+	size_t map_size;
+	uint32_t width;
+	uint32_t height;
+	uint32_t pitch;
+	char* data;
+};
+
+typedef struct DrmCaptureContext DrmCaptureContext;
+
+struct Image
+{
+	char* data;
+	unsigned int width;
+	unsigned int height;
+};
+
+static void unmap_framebuffer(DrmCaptureContext* ctx)
+{
+	if (ctx->map)
+	{
+		munmap(ctx->map, ctx->map_size);
+		ctx->map = NULL;
+	}
+	if (ctx->handle)
+	{
+		struct drm_gem_close gem_close;
+		memset(&gem_close, 0, sizeof(gem_close));
+		gem_close.handle = ctx->handle;
+		ioctl(ctx->fd, DRM_IOCTL_GEM_CLOSE, &gem_close);
+		ctx->handle = 0;
+	}
+	ctx->fb_id = 0;
+}
+
+static void map_framebuffer(DrmCaptureContext* ctx, uint32_t fb_id, Error* err)
+{
+	unmap_framebuffer(ctx);
+
+	struct drm_mode_fb_cmd fb;
+	memset(&fb, 0, sizeof(fb));
+	fb.fb_id = fb_id;
+	if (ioctl(ctx->fd, DRM_IOCTL_MODE_GETFB, &fb) < 0)
+		ERROR(err, 1, "DRM_IOCTL_MODE_GETFB: %s", strerror(errno));
+	if (!fb.handle)
+		ERROR(err, 1, "Got no handle to the framebuffer, capturing via DRM requires CAP_SYS_ADMIN.");
+	ctx->handle = fb.handle;
+	if (fb.bpp != 32)
+		ERROR(err, 1, "Unsupported framebuffer with %u bits per pixel.", fb.bpp);
+
+	struct drm_mode_map_dumb map;
+	memset(&map, 0, sizeof(map));
+	map.handle = fb.handle;
+	if (ioctl(ctx->fd, DRM_IOCTL_MODE_MAP_DUMB, &map) < 0)
+		ERROR(
+			err,
+			1,
+			"DRM_IOCTL_MODE_MAP_DUMB: %s, only linear framebuffers can be captured.",
+			strerror(errno));
+	ctx->map_size = (size_t)fb.pitch * fb.height;
+	void* m = mmap(NULL, ctx->map_size, PROT_READ, MAP_SHARED, ctx->fd, map.offset);
+	if (m == MAP_FAILED)
+		ERROR(err, 1, "Failed to map framebuffer: %s", strerror(errno));
+	ctx->map = m;
+
+	char* data = realloc(ctx->data, (size_t)fb.width * fb.height * 4);
+	if (!data)
+		ERROR(err, 1, "Failed to allocate memory for a %ux%u image.", fb.width, fb.height);
+	ctx->data = data;
+	ctx->width = fb.width;
+	ctx->height = fb.height;
+	ctx->pitch = fb.pitch;
+	ctx->fb_id = fb_id;
+}
+
+// the first CRTC that is switched on
+static void find_active_crtc(DrmCaptureContext* ctx, Error* err)
+{
+	struct drm_mode_card_res res;
+	memset(&res, 0, sizeof(res));
+	if (ioctl(ctx->fd, DRM_IOCTL_MODE_GETRESOURCES, &res) < 0)
+		ERROR(err, 1, "DRM_IOCTL_MODE_GETRESOURCES: %s, is this a KMS device?", strerror(errno));
+	uint32_t count = res.count_crtcs;
+	if (count == 0)
+		ERROR(err, 1, "The device has no CRTCs.");
+
+	uint32_t* crtcs = calloc(count, sizeof(uint32_t));
+	if (!crtcs)
+		ERROR(err, 1, "Failed to allocate memory for CRTCs.");
+	// only the CRTCs are queried, everything else is left empty
+	memset(&res, 0, sizeof(res));
+	res.count_crtcs = count;
+	res.crtc_id_ptr = (uint64_t)(uintptr_t)crtcs;
+	if (ioctl(ctx->fd, DRM_IOCTL_MODE_GETRESOURCES, &res) < 0)
+	{
+		free(crtcs);
+		ERROR(err, 1, "DRM_IOCTL_MODE_GETRESOURCES: %s", strerror(errno));
+	}
+
+	for (uint32_t i = 0; i < count && i < res.count_crtcs; ++i)
+	{
+		struct drm_mode_crtc crtc;
+		memset(&crtc, 0, sizeof(crtc));
+		crtc.crtc_id = crtcs[i];
+		if (ioctl(ctx->fd, DRM_IOCTL_MODE_GETCRTC, &crtc) < 0)
+			continue;
+		if (crtc.mode_valid && crtc.fb_id)
+		{
+			ctx->crtc_id = crtc.crtc_id;
+			free(crtcs);
+			return;
+		}
+	}
+	free(crtcs);
+	ERROR(err, 1, "No active CRTC found, is a display connected?");
+}
+
+void* start_drm_capture(const char* path, Error* err)
+{
+	DrmCaptureContext* ctx = calloc(1, sizeof(DrmCaptureContext));
+	if (!ctx)
+	{
+		fill_error(err, 1, "Failed to allocate memory for the capture context.");
+		return NULL;
+	}
+	ctx->fd = open(path, O_RDWR | O_CLOEXEC);
+	if (ctx->fd < 0)
+	{
+		fill_error(err, 1, "Failed to open %s: %s", path, strerror(errno));
+		free(ctx);
+		return NULL;
+	}
+	find_active_crtc(ctx, err);
+	if (err->code)
+	{
+		close(ctx->fd);
+		free(ctx);
+		return NULL;
+	}
+	return ctx;
+}
+
+void capture_drm(DrmCaptureContext* ctx, struct Image* img, Error* err)
+{
+	struct drm_mode_crtc crtc;
+	memset(&crtc, 0, sizeof(crtc));
+	crtc.crtc_id = ctx->crtc_id;
+	if (ioctl(ctx->fd, DRM_IOCTL_MODE_GETCRTC, &crtc) < 0)
+		ERROR(err, 1, "DRM_IOCTL_MODE_GETCRTC: %s", strerror(errno));
+	// the display is switched off, keep the last image
+	if (!crtc.mode_valid || !crtc.fb_id)
+		ERROR(err, 2, "CRTC %u is not active.", ctx->crtc_id);
+
+	if (crtc.fb_id != ctx->fb_id)
+	{
+		map_framebuffer(ctx, crtc.fb_id, err);
+		if (err->code)
+		{
+			unmap_framebuffer(ctx);
+			return;
+		}
+	}
+
+	size_t row_size = (size_t)ctx->width * 4;
+	for (uint32_t y = 0; y < ctx->height; ++y)
+		memcpy(ctx->data + y * row_size, (char*)ctx->map + (size_t)y * ctx->pitch, row_size);
+	img->data = ctx->data;
+	img->width = ctx->width;
+	img->height = ctx->height;
+}
+
+void stop_drm_capture(DrmCaptureContext* ctx)
+{
+	unmap_framebuffer(ctx);
+	close(ctx->fd);
+	free(ctx->data);
+	free(ctx);
+}
@@ -9,6 +9,7 @@
 #include <stdio.h>
 #include <stdlib.h>
 #include <string.h>
+#include <sys/socket.h>
 #include <unistd.h>
 
 #include "../error.h"
@@ -63,6 +64,18 @@ void init_mouse(int fd, const char* name, Error* err)
 		ERROR(err, 1, "error: ioctl UI_SET_EVBIT EV_KEY");
 	if (ioctl(fd, UI_SET_KEYBIT, BTN_LEFT) < 0)
 		ERROR(err, 1, "error: ioctl UI_SET_KEYBIT BTN_STYLUS");
+	if (ioctl(fd, UI_SET_KEYBIT, BTN_RIGHT) < 0)
+		ERROR(err, 1, "error: ioctl UI_SET_KEYBIT BTN_RIGHT");
+	if (ioctl(fd, UI_SET_KEYBIT, BTN_MIDDLE) < 0)
+		ERROR(err, 1, "error: ioctl UI_SET_KEYBIT BTN_MIDDLE");
+
+	// enable mouse wheel
+	if (ioctl(fd, UI_SET_EVBIT, EV_REL) < 0)
+		ERROR(err, 1, "error: ioctl UI_SET_EVBIT EV_REL");
+	if (ioctl(fd, UI_SET_RELBIT, REL_WHEEL) < 0)
+		ERROR(err, 1, "error: ioctl UI_SET_RELBIT REL_WHEEL");
+	if (ioctl(fd, UI_SET_RELBIT, REL_HWHEEL) < 0)
+		ERROR(err, 1, "error: ioctl UI_SET_RELBIT REL_HWHEEL");
 
 	// setup sending timestamps
 	if (ioctl(fd, UI_SET_EVBIT, EV_MSC) < 0)
@@ -99,6 +112,12 @@ void init_stylus(int fd, const char* name, Error* err)
 		ERROR(err, 1, "error: ioctl UI_SET_EVBIT EV_KEY");
 	if (ioctl(fd, UI_SET_KEYBIT, BTN_TOOL_PEN) < 0)
 		ERROR(err, 1, "error: ioctl UI_SET_KEYBIT BTN_TOOL_PEN");
+	if (ioctl(fd, UI_SET_KEYBIT, BTN_TOOL_RUBBER) < 0)
+		ERROR(err, 1, "error: ioctl UI_SET_KEYBIT BTN_TOOL_RUBBER");
+	if (ioctl(fd, UI_SET_KEYBIT, BTN_TOOL_BRUSH) < 0)
+		ERROR(err, 1, "error: ioctl UI_SET_KEYBIT BTN_TOOL_BRUSH");
+	if (ioctl(fd, UI_SET_KEYBIT, BTN_TOUCH) < 0)
+		ERROR(err, 1, "error: ioctl UI_SET_KEYBIT BTN_TOUCH");
 
 	// setup sending timestamps
 	if (ioctl(fd, UI_SET_EVBIT, EV_MSC) < 0)
@@ -165,10 +184,11 @@ void init_touch(int fd, const char* name, Error* err)
 	setup_abs(fd, ABS_Y, 0, ABS_MAXVAL, 200, err);
 	OK_OR_ABORT(err);
 
-	// 5 fingers 5 multitouch slots.
-	setup_abs(fd, ABS_MT_SLOT, 0, 4, 0, err);
+	// 10 multitouch slots, every contact gets a new tracking id, these are reused after 65535
+	// contacts, keep in sync with uinput_device.rs
+	setup_abs(fd, ABS_MT_SLOT, 0, 9, 0, err);
 	OK_OR_ABORT(err);
-	setup_abs(fd, ABS_MT_TRACKING_ID, 0, 4, 0, err);
+	setup_abs(fd, ABS_MT_TRACKING_ID, 0, 65535, 0, err);
 	OK_OR_ABORT(err);
 	setup_abs(fd, ABS_MT_POSITION_X, 0, ABS_MAXVAL, 200, err);
 	OK_OR_ABORT(err);
@@ -243,5 +263,132 @@ void send_uinput_event(int device, int type, int code, int value, Error* err)
 	ev.code = code;
 	ev.value = value;
 	if (write(device, &ev, sizeof(ev)) < 0)
+	{
+		// the device is gone, for example because the uinput module has been reloaded
+		if (errno == ENODEV || errno == EBADF)
+			ERROR(err, 2, "device vanished, filedescriptor: %d", device);
 		ERROR(err, 1, "error writing to device, filedescriptor: %d)", device);
+	}
+}
+
+// release everything that might still be pressed, events a device does not support are dropped by
+// the kernel, so this works for all devices
+void release_uinput_device(int fd)
+{
+	static const int keys[] = {
+		BTN_LEFT,
+		BTN_RIGHT,
+		BTN_MIDDLE,
+		BTN_TOUCH,
+		BTN_TOOL_PEN,
+		BTN_TOOL_RUBBER,
+		BTN_TOOL_BRUSH,
+		BTN_TOOL_FINGER,
+		BTN_TOOL_DOUBLETAP,
+		BTN_TOOL_TRIPLETAP,
+		BTN_TOOL_QUADTAP,
+		BTN_TOOL_QUINTTAP,
+	};
+	Error err;
+	memset(&err, 0, sizeof(err));
+	// 10 multitouch slots, see init_touch
+	for (int slot = 0; slot < 10; ++slot)
+	{
+		send_uinput_event(fd, EV_ABS, ABS_MT_SLOT, slot, &err);
+		send_uinput_event(fd, EV_ABS, ABS_MT_TRACKING_ID, -1, &err);
+	}
+	for (size_t i = 0; i < sizeof(keys) / sizeof(keys[0]); ++i)
+		send_uinput_event(fd, EV_KEY, keys[i], 0, &err);
+	send_uinput_event(fd, EV_SYN, SYN_REPORT, 0, &err);
+}
+
+// Filedescriptors of uinput devices are passed between Weylus and its input helper process via
+// SCM_RIGHTS, see uinput_helper.rs.
+
+// send buf and, if fd is not -1, a duplicate of fd, returns -1 on errors
+int send_with_fd(int sock, const void* buf, size_t len, int fd)
+{
+	struct msghdr msg;
+	struct iovec iov;
+	char control[CMSG_SPACE(sizeof(int))];
+	memset(&msg, 0, sizeof(msg));
+	memset(control, 0, sizeof(control));
+	iov.iov_base = (void*)buf;
+	iov.iov_len = len;
+	msg.msg_iov = &iov;
+	msg.msg_iovlen = 1;
+	if (fd >= 0)
+	{
+		msg.msg_control = control;
+		msg.msg_controllen = sizeof(control);
+		struct cmsghdr* cmsg = CMSG_FIRSTHDR(&msg);
+		cmsg->cmsg_level = SOL_SOCKET;
+		cmsg->cmsg_type = SCM_RIGHTS;
+		cmsg->cmsg_len = CMSG_LEN(sizeof(int));
+		memcpy(CMSG_DATA(cmsg), &fd, sizeof(int));
+	}
+	size_t sent = 0;
+	while (sent < len)
+	{
+		ssize_t res = sendmsg(sock, &msg, MSG_NOSIGNAL);
+		if (res < 0)
+		{
+			if (errno == EINTR)
+				continue;
+			return -1;
+		}
+		sent += res;
+		// the filedescriptor is attached to the first part only
+		msg.msg_control = NULL;
+		msg.msg_controllen = 0;
+		iov.iov_base = (char*)buf + sent;
+		iov.iov_len = len - sent;
+	}
+	return 0;
+}
+
+// receive exactly len bytes into buf, fd is set to a received filedescriptor or -1, returns 1 on
+// success, 0 if the socket has been closed and -1 on errors
+int recv_with_fd(int sock, void* buf, size_t len, int* fd)
+{
+	struct msghdr msg;
+	struct iovec iov;
+	char control[CMSG_SPACE(sizeof(int))];
+	*fd = -1;
+	size_t received = 0;
+	while (received < len)
+	{
+		memset(&msg, 0, sizeof(msg));
+		iov.iov_base = (char*)buf + received;
+		iov.iov_len = len - received;
+		msg.msg_iov = &iov;
+		msg.msg_iovlen = 1;
+		msg.msg_control = control;
+		msg.msg_controllen = sizeof(control);
+		ssize_t res = recvmsg(sock, &msg, MSG_CMSG_CLOEXEC);
+		if (res < 0)
+		{
+			if (errno == EINTR)
+				continue;
+			if (errno == ECONNRESET && received == 0)
+				return 0;
+			return -1;
+		}
+		if (res == 0)
+			return received == 0 ? 0 : -1;
+		struct cmsghdr* cmsg = CMSG_FIRSTHDR(&msg);
+		if (cmsg && cmsg->cmsg_level == SOL_SOCKET && cmsg->cmsg_type == SCM_RIGHTS)
+			memcpy(fd, CMSG_DATA(cmsg), sizeof(int));
+		received += res;
+	}
+	return 1;
+}
+
+// the guard must outlive Weylus, Ctrl+C in a terminal is sent to all processes of the foreground
+// process group though
+void uinput_guard_ignore_signals()
+{
+	signal(SIGINT, SIG_IGN);
+	signal(SIGHUP, SIG_IGN);
+	signal(SIGTERM, SIG_IGN);
 }
@@ -0,0 +1,27 @@
+#include <errno.h>
+#include <linux/videodev2.h>
+#include <string.h>
+#include <sys/ioctl.h>
+
+#include "../error.h"
+
+// configure an opened v4l2loopback device to accept yuv420p frames of the given size via write
+void set_v4l2loopback_format(int fd, int width, int height, Error* err)
+{
+	struct v4l2_format fmt;
+	memset(&fmt, 0, sizeof(fmt));
+	fmt.type = V4L2_BUF_TYPE_VIDEO_OUTPUT;
+	if (ioctl(fd, VIDIOC_G_FMT, &fmt) < 0)
+		ERROR(err, 1, "error: VIDIOC_G_FMT: %s, is this a v4l2loopback device?", strerror(errno));
+
+	fmt.fmt.pix.width = width;
+	fmt.fmt.pix.height = height;
+	fmt.fmt.pix.pixelformat = V4L2_PIX_FMT_YUV420;
+	fmt.fmt.pix.field = V4L2_FIELD_NONE;
+	fmt.fmt.pix.bytesperline = width;
+	fmt.fmt.pix.sizeimage = width * height * 3 / 2;
+	fmt.fmt.pix.colorspace = V4L2_COLORSPACE_SMPTE170M;
+	fmt.fmt.pix.quantization = V4L2_QUANTIZATION_LIM_RANGE;
+	if (ioctl(fd, VIDIOC_S_FMT, &fmt) < 0)
+		ERROR(err, 1, "error: VIDIOC_S_FMT %dx%d: %s", width, height, strerror(errno));
+}
@@ -3,12 +3,20 @@
 #include <X11/Xutil.h>
 
 #include <X11/extensions/XShm.h>
+#ifdef HAS_XCOMPOSITE
 #include <X11/extensions/Xcomposite.h>
+#else
+// built without XComposite, has_offscreen is never set then so none of these are called
+#define XCompositeRedirectWindow(disp, win, update)
+#define XCompositeUnredirectWindow(disp, win, update)
+#define XCompositeNameWindowPixmap(disp, win) None
+#endif
 #include <X11/extensions/Xfixes.h>
 #include <stdlib.h>
 #include <string.h>
 #include <sys/ipc.h>
 #include <sys/shm.h>
+#include <unistd.h>
 
 #include <stdint.h>
 
@@ -55,6 +63,8 @@ void* start_capture(Capturable* cap, CaptureContext* ctx, Error* err)
 	{
 		ctx = malloc(sizeof(CaptureContext));
 
+		ctx->has_offscreen = 0;
+#ifdef HAS_XCOMPOSITE
 		int major, minor;
 		Bool pixmaps = False;
 		XShmQueryVersion(cap->disp, &major, &minor, &pixmaps);
@@ -67,6 +77,7 @@ void* start_capture(Capturable* cap, CaptureContext* ctx, Error* err)
 			if (ctx->has_offscreen)
 				XCompositeRedirectWindow(cap->disp, cap->c.winfo.win, False);
 		}
+#endif
 	}
 	ctx->cap = *cap;
 	ctx->last_img_return = True;
@@ -122,7 +133,162 @@ void stop_capture(CaptureContext* ctx, Error* err)
 	free(ctx);
 }
 
-void capture_sceen(CaptureContext* ctx, struct Image* img, int capture_cursor, Error* err)
+// find the child of the root window containing win, usually the frame of the window manager
+Window get_toplevel(Display* disp, Window win)
+{
+	Window root, parent, *children;
+	unsigned int num_children;
+	while (XQueryTree(disp, win, &root, &parent, &children, &num_children))
+	{
+		if (children)
+			XFree(children);
+		if (parent == root || parent == None)
+			break;
+		win = parent;
+	}
+	return win;
+}
+
+// whether win or a window within its frame is a dialog of the captured window
+int is_transient_for(Display* disp, Window win, Window captured, int depth)
+{
+	Window transient_for;
+	if (XGetTransientForHint(disp, win, &transient_for) && transient_for == captured)
+		return 1;
+	if (depth == 0)
+		return 0;
+	Window root, parent, *children;
+	unsigned int num_children;
+	if (!XQueryTree(disp, win, &root, &parent, &children, &num_children))
+		return 0;
+	int ret = 0;
+	for (unsigned int i = 0; i < num_children && !ret; ++i)
+		ret = is_transient_for(disp, children[i], captured, depth - 1);
+	if (children)
+		XFree(children);
+	return ret;
+}
+
+// Menus, tooltips and dialogs are separate toplevel windows and not part of the captured window
+// itself, copy those stacked above it from the screen into the captured image.
+void composite_popups(CaptureContext* ctx, int x, int y, unsigned int width, unsigned int height)
+{
+	Display* disp = ctx->cap.disp;
+	Window root = DefaultRootWindow(disp);
+	Window toplevel = get_toplevel(disp, ctx->cap.c.winfo.win);
+	Window root_ret, parent_ret, *children;
+	unsigned int num_children;
+	if (!XQueryTree(disp, root, &root_ret, &parent_ret, &children, &num_children))
+		return;
+	// children are sorted from bottom to top of the stack, only windows above the captured one
+	// can be popups
+	int above = 0;
+	for (unsigned int i = 0; i < num_children; ++i)
+	{
+		if (children[i] == toplevel)
+		{
+			above = 1;
+			continue;
+		}
+		XWindowAttributes attr;
+		if (!above || !XGetWindowAttributes(disp, children[i], &attr) ||
+			attr.map_state != IsViewable)
+			continue;
+		// part of the popup inside the captured area
+		int x0 = clamp(attr.x, x, x + (int)width);
+		int y0 = clamp(attr.y, y, y + (int)height);
+		int x1 = clamp(attr.x + attr.width + 2 * attr.border_width, x, x + (int)width);
+		int y1 = clamp(attr.y + attr.height + 2 * attr.border_width, y, y + (int)height);
+		// only what is on screen can be copied from the root window
+		x0 = clamp(x0, 0, ctx->cap.screen->width);
+		y0 = clamp(y0, 0, ctx->cap.screen->height);
+		x1 = clamp(x1, 0, ctx->cap.screen->width);
+		y1 = clamp(y1, 0, ctx->cap.screen->height);
+		if (x0 >= x1 || y0 >= y1)
+			continue;
+		// override redirect windows are menus and tooltips, they can not be linked to their
+		// application reliably so any overlapping the captured window are included
+		if (!attr.override_redirect &&
+			!is_transient_for(disp, children[i], ctx->cap.c.winfo.win, 2))
+			continue;
+		XImage* popup = XGetImage(disp, root, x0, y0, x1 - x0, y1 - y0, AllPlanes, ZPixmap);
+		if (!popup)
+			continue;
+		for (int row = 0; row < y1 - y0; ++row)
+			memcpy(
+				ctx->ximg->data + (row + y0 - y) * ctx->ximg->bytes_per_line + (x0 - x) * 4,
+				popup->data + row * popup->bytes_per_line,
+				(x1 - x0) * 4);
+		XDestroyImage(popup);
+	}
+	if (children)
+		XFree(children);
+}
+
+// whether win or a window within its frame has been created by this process
+int is_own_window(Display* disp, Window win, int depth)
+{
+	unsigned long* pid =
+		(unsigned long*)get_property(disp, win, XA_CARDINAL, "_NET_WM_PID", NULL, NULL);
+	if (pid)
+	{
+		int ret = *pid == (unsigned long)getpid();
+		free(pid);
+		if (ret)
+			return 1;
+	}
+	if (depth == 0)
+		return 0;
+	Window root, parent, *children;
+	unsigned int num_children;
+	if (!XQueryTree(disp, win, &root, &parent, &children, &num_children))
+		return 0;
+	int ret = 0;
+	for (unsigned int i = 0; i < num_children && !ret; ++i)
+		ret = is_own_window(disp, children[i], depth - 1);
+	if (children)
+		XFree(children);
+	return ret;
+}
+
+// black out the windows of Weylus itself so the GUI and the URL or QR code it shows do not end up
+// on the tablet
+void blank_own_windows(CaptureContext* ctx, int x, int y, unsigned int width, unsigned int height)
+{
+	Display* disp = ctx->cap.disp;
+	Window root_ret, parent_ret, *children;
+	unsigned int num_children;
+	if (!XQueryTree(
+			disp, DefaultRootWindow(disp), &root_ret, &parent_ret, &children, &num_children))
+		return;
+	for (unsigned int i = 0; i < num_children; ++i)
+	{
+		XWindowAttributes attr;
+		if (!XGetWindowAttributes(disp, children[i], &attr) || attr.map_state != IsViewable)
+			continue;
+		int x0 = clamp(attr.x, x, x + (int)width);
+		int y0 = clamp(attr.y, y, y + (int)height);
+		int x1 = clamp(attr.x + attr.width + 2 * attr.border_width, x, x + (int)width);
+		int y1 = clamp(attr.y + attr.height + 2 * attr.border_width, y, y + (int)height);
+		if (x0 >= x1 || y0 >= y1 || !is_own_window(disp, children[i], 2))
+			continue;
+		for (int row = y0; row < y1; ++row)
+			memset(
+				ctx->ximg->data + (row - y) * ctx->ximg->bytes_per_line + (x0 - x) * 4,
+				0,
+				(x1 - x0) * 4);
+	}
+	if (children)
+		XFree(children);
+}
+
+void capture_sceen(
+	CaptureContext* ctx,
+	struct Image* img,
+	int capture_cursor,
+	int xcomposite,
+	int hide_own_windows,
+	Error* err)
 {
 	Window root = DefaultRootWindow(ctx->cap.disp);
 	int x, y;
@@ -143,6 +309,8 @@ void capture_sceen(CaptureContext* ctx, struct Image* img, int capture_cursor, E
 	}
 
 	Bool get_img_ret = False;
+	// set if only the window itself has been captured, without anything stacked above it
+	int window_only = 0;
 
 	switch (ctx->cap.type)
 	{
@@ -155,6 +323,17 @@ void capture_sceen(CaptureContext* ctx, struct Image* img, int capture_cursor, E
 						   (x < 0 || y < 0 || x + (int)width > ctx->cap.screen->width ||
 							y + (int)height > ctx->cap.screen->height);
 
+		// windows redirected via XComposite keep their content in a pixmap even if they are
+		// covered by other windows or off screen
+		if (xcomposite && ctx->has_offscreen)
+		{
+			Pixmap pm = XCompositeNameWindowPixmap(ctx->cap.disp, ctx->cap.c.winfo.win);
+			get_img_ret = XShmGetImage(ctx->cap.disp, pm, ctx->ximg, 0, 0, 0x00ffffff);
+			XFreePixmap(ctx->cap.disp, pm);
+			window_only = 1;
+			break;
+		}
+
 		active_window =
 			(Window*)get_property(ctx->cap.disp, root, XA_WINDOW, "_NET_ACTIVE_WINDOW", &size, err);
 		if (*active_window == ctx->cap.c.winfo.win && !is_offscreen)
@@ -188,6 +367,7 @@ void capture_sceen(CaptureContext* ctx, struct Image* img, int capture_cursor, E
 				get_img_ret =
 					XShmGetImage(ctx->cap.disp, ctx->cap.c.winfo.win, ctx->ximg, 0, 0, 0x00ffffff);
 		}
+		window_only = *active_window != ctx->cap.c.winfo.win || is_offscreen;
 		free(active_window);
 		break;
 	}
@@ -213,6 +393,11 @@ void capture_sceen(CaptureContext* ctx, struct Image* img, int capture_cursor, E
 	}
 
 
+	if (get_img_ret == True && window_only)
+		composite_popups(ctx, x, y, width, height);
+	if (get_img_ret == True && hide_own_windows && ctx->cap.type == RECT)
+		blank_own_windows(ctx, x, y, width, height);
+
 	// capture cursor if requested and if XFixes is available
 	if (capture_cursor && ctx->has_xfixes)
 	{
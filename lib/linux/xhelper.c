@@ -159,30 +159,103 @@ Window* get_client_list(Display* disp, unsigned long* size, Error* err)
 	return client_list;
 }
 
-int create_capturables(Display* disp, Capturable** capturables, int size, Error* err)
+// a monitor as reported by RandR or the area shown by a CRTC
+typedef struct MonitorRect
 {
-	if (size <= 0)
+	char name[64];
+	int x;
+	int y;
+	unsigned int width;
+	unsigned int height;
+} MonitorRect;
+
+// Geometry of the monitors, preferably the monitors of RandR 1.5 as these include monitors
+// configured via xrandr --setmonitor. Older X servers and drivers that report no monitors fall back
+// to the CRTCs that are in use, CRTCs mirroring the same area are listed once.
+static int get_monitors(Display* disp, Window root, MonitorRect** monitors, Error* err)
+{
+	*monitors = NULL;
+	int event_base, error_base, major, minor;
+	if (!XRRQueryExtension(disp, &event_base, &error_base) || !XRRQueryVersion(disp, &major, &minor))
+	{
+		fill_error(err, 2, "Xrandr is unsupported on this X server.");
 		return 0;
+	}
 
-	int screen = DefaultScreen(disp);
-	Window root = RootWindow(disp, screen);
-
-	int event_base, error_base, major, minor;
 	int num_monitors = 0;
-	XRRMonitorInfo* monitors = NULL;
-	if (XRRQueryExtension(disp, &event_base, &error_base) && XRRQueryVersion(disp, &major, &minor))
+	if (major > 1 || (major == 1 && minor >= 5))
 	{
-		monitors = XRRGetMonitors(disp, root, True, &num_monitors);
-		if (num_monitors < 0)
+		XRRMonitorInfo* info = XRRGetMonitors(disp, root, True, &num_monitors);
+		if (num_monitors > 0)
 		{
-			num_monitors = 0;
-			fill_error(err, 2, "Failed to query monitor info via xrandr.");
+			*monitors = malloc(num_monitors * sizeof(MonitorRect));
+			for (int i = 0; i < num_monitors; ++i)
+			{
+				MonitorRect* m = &(*monitors)[i];
+				char* name = XGetAtomName(disp, info[i].name);
+				snprintf(m->name, sizeof(m->name), "%s", name ? name : "unknown");
+				XFree(name);
+				m->x = info[i].x;
+				m->y = info[i].y;
+				m->width = info[i].width;
+				m->height = info[i].height;
+			}
+			XRRFreeMonitors(info);
+			return num_monitors;
 		}
+		if (info)
+			XRRFreeMonitors(info);
+		num_monitors = 0;
 	}
-	else
+
+	XRRScreenResources* resources = XRRGetScreenResourcesCurrent(disp, root);
+	if (resources == NULL)
 	{
-		fill_error(err, 2, "Xrandr is unsupported on this X server.");
+		fill_error(err, 2, "Failed to query monitor info via xrandr.");
+		return 0;
+	}
+	*monitors = malloc((resources->ncrtc + 1) * sizeof(MonitorRect));
+	for (int i = 0; i < resources->ncrtc; ++i)
+	{
+		XRRCrtcInfo* crtc = XRRGetCrtcInfo(disp, resources, resources->crtcs[i]);
+		if (crtc == NULL)
+			continue;
+		int in_use = crtc->mode != None && crtc->noutput > 0;
+		for (int j = 0; in_use && j < num_monitors; ++j)
+		{
+			MonitorRect* m = &(*monitors)[j];
+			if (m->x == crtc->x && m->y == crtc->y && m->width == crtc->width &&
+				m->height == crtc->height)
+				in_use = 0;
+		}
+		if (in_use)
+		{
+			MonitorRect* m = &(*monitors)[num_monitors++];
+			XRROutputInfo* output = XRRGetOutputInfo(disp, resources, crtc->outputs[0]);
+			snprintf(m->name, sizeof(m->name), "%s", output ? output->name : "unknown");
+			if (output)
+				XRRFreeOutputInfo(output);
+			m->x = crtc->x;
+			m->y = crtc->y;
+			m->width = crtc->width;
+			m->height = crtc->height;
+		}
+		XRRFreeCrtcInfo(crtc);
 	}
+	XRRFreeScreenResources(resources);
+	return num_monitors;
+}
+
+int create_capturables(Display* disp, Capturable** capturables, int size, Error* err)
+{
+	if (size <= 0)
+		return 0;
+
+	int screen = DefaultScreen(disp);
+	Window root = RootWindow(disp, screen);
+
+	MonitorRect* monitors;
+	int num_monitors = get_monitors(disp, root, &monitors, err);
 
 	Window* client_list;
 	unsigned long client_list_size;
@@ -197,6 +270,8 @@ int create_capturables(Display* disp, Capturable** capturables, int size, Error*
 	c->disp = disp;
 	c->screen = ScreenOfDisplay(disp, screen);
 	strncpy(c->name, "Desktop", sizeof(c->name) - 1);
+	c->class_name[0] = '\0';
+	c->pid = 0;
 	c->type = WINDOW;
 	c->c.winfo.win = root;
 	c->c.winfo.is_regular_window = 0;
@@ -206,12 +281,12 @@ int create_capturables(Display* disp, Capturable** capturables, int size, Error*
 	{
 		Capturable* c = malloc(sizeof(Capturable));
 		capturables[i] = c;
-		XRRMonitorInfo* m = &monitors[i - 1];
+		MonitorRect* m = &monitors[i - 1];
 		c->disp = disp;
 		c->screen = ScreenOfDisplay(disp, screen);
-		char* name = XGetAtomName(disp, m->name);
-		snprintf(c->name, sizeof(c->name) - 1, "Monitor: %s", name);
-		XFree(name);
+		snprintf(c->name, sizeof(c->name) - 1, "Monitor: %s", m->name);
+		c->class_name[0] = '\0';
+		c->pid = 0;
 		c->type = RECT;
 		c->c.rinfo.x = m->x;
 		c->c.rinfo.y = m->y;
@@ -244,13 +319,29 @@ int create_capturables(Display* disp, Capturable** capturables, int size, Error*
 		c->screen = ScreenOfDisplay(disp, screen);
 		c->type = WINDOW;
 		strncpy(c->name, title_utf8, sizeof(c->name) - 1);
+		c->class_name[0] = '\0';
+		XClassHint class_hint;
+		if (XGetClassHint(disp, client_list[j], &class_hint))
+		{
+			snprintf(c->class_name, sizeof(c->class_name), "%s", class_hint.res_class);
+			XFree(class_hint.res_name);
+			XFree(class_hint.res_class);
+		}
+		c->pid = 0;
+		unsigned long* pid = (unsigned long*)get_property(
+			disp, client_list[j], XA_CARDINAL, "_NET_WM_PID", NULL, NULL);
+		if (pid)
+		{
+			c->pid = *pid;
+			free(pid);
+		}
 		c->c.winfo.win = client_list[j];
 		c->c.winfo.is_regular_window = 1;
 		free(title_utf8);
 		free(desktop);
 	}
 	free(client_list);
-	XRRFreeMonitors(monitors);
+	free(monitors);
 	return i;
 }
 
@@ -313,6 +404,16 @@ void get_geometry_relative(
 	*height = height_tmp / (float)cap->screen->height;
 }
 
+int capturable_is_visible_window(Capturable* cap)
+{
+	if (cap->type != WINDOW || !cap->c.winfo.is_regular_window)
+		return 0;
+	XWindowAttributes attr;
+	if (!XGetWindowAttributes(cap->disp, cap->c.winfo.win, &attr))
+		return 0;
+	return attr.map_state == IsViewable;
+}
+
 void client_msg(
 	Display* disp,
 	Window win,
@@ -397,6 +498,10 @@ void capturable_before_input(Capturable* cap, Error* err)
 
 const char* get_capturable_name(Capturable* c) { return c->name; }
 
+const char* get_capturable_class(Capturable* c) { return c->class_name; }
+
+unsigned long get_capturable_pid(Capturable* c) { return c->pid; }
+
 void map_input_device_to_entire_screen(Display* disp, const char* device_name, int pen, Error* err)
 {
 
@@ -37,6 +37,10 @@ typedef struct Capturable
 {
 	CaptureType type;
 	char name[128];
+	// WM_CLASS of windows, empty for screens
+	char class_name[128];
+	// _NET_WM_PID of windows, 0 if unknown and for screens
+	unsigned long pid;
 	Display* disp;
 	Screen* screen;
 	union
@@ -0,0 +1,126 @@
+#include <libavcodec/avcodec.h>
+#include <libavformat/avformat.h>
+#include <libavformat/avio.h>
+#include <libavutil/dict.h>
+#include <libavutil/mem.h>
+
+#include <stdint.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "error.h"
+
+// mirrors Container in video.rs, raw Annex B is passed on by Rust without a MuxContext
+enum Container
+{
+	CONTAINER_MP4 = 0,
+	CONTAINER_MPEGTS = 1,
+};
+
+typedef struct MuxContext
+{
+	AVFormatContext* oc;
+	AVStream* st;
+	AVPacket* pkt;
+	size_t buf_size;
+	void* buf;
+	void* rust_ctx;
+	// time base of the timestamps of the encoder
+	AVRational time_base;
+	int initialized;
+} MuxContext;
+
+int write_muxed_data(void* rust_ctx, uint8_t* buf, int buf_size);
+
+// implemented in encode_video.c, the codec parameters of the stream are taken from there
+AVCodecContext* video_codec_context(void* encoder);
+
+MuxContext* init_muxer(void* rust_ctx)
+{
+	MuxContext* ctx = malloc(sizeof(MuxContext));
+	memset(ctx, 0, sizeof(MuxContext));
+	ctx->rust_ctx = rust_ctx;
+	return ctx;
+}
+
+void open_muxer(MuxContext* ctx, void* encoder, int container, Error* err)
+{
+	const char* format = container == CONTAINER_MPEGTS ? "mpegts" : "mp4";
+	avformat_alloc_output_context2(&ctx->oc, NULL, format, NULL);
+	if (!ctx->oc)
+		ERROR(err, 1, "Could not find output format %s.", format);
+
+	AVCodecContext* c = video_codec_context(encoder);
+	ctx->time_base = c->time_base;
+	ctx->st = avformat_new_stream(ctx->oc, NULL);
+	if (!ctx->st)
+		ERROR(err, 1, "Could not allocate stream");
+	int ret = avcodec_parameters_from_context(ctx->st->codecpar, c);
+	if (ret < 0)
+		ERROR(err, 1, "Could not copy codec parameters: %s", av_err2str(ret));
+
+	ctx->pkt = av_packet_alloc();
+	if (!ctx->pkt)
+		ERROR(err, 1, "Failed to allocate packet");
+
+	ctx->buf_size = 1024 * 1024;
+	ctx->buf = av_malloc(ctx->buf_size);
+	ctx->oc->pb = avio_alloc_context(
+		ctx->buf, ctx->buf_size, AVIO_FLAG_WRITE, ctx->rust_ctx, NULL, write_muxed_data, NULL);
+	if (!ctx->oc->pb)
+		ERROR(err, 1, "Failed to allocate avio context");
+
+	av_dump_format(ctx->oc, 0, NULL, 1);
+	AVDictionary* opt = NULL;
+	// enable writing fragmented mp4
+	if (container == CONTAINER_MP4)
+		av_dict_set(&opt, "movflags", "frag_custom+empty_moov+default_base_moof", 0);
+	ret = avformat_write_header(ctx->oc, &opt);
+	av_dict_free(&opt);
+	if (ret < 0)
+		ERROR(err, 1, "Could not write header: %s", av_err2str(ret));
+	// the header is sent on its own, this way clients can keep it to restart decoding
+	avio_flush(ctx->oc->pb);
+	ctx->initialized = 1;
+}
+
+void mux_packet(
+	MuxContext* ctx, const uint8_t* data, int size, int64_t pts, int64_t dts, int keyframe, Error* err)
+{
+	int ret = av_new_packet(ctx->pkt, size);
+	if (ret < 0)
+		ERROR(err, 1, "Failed to allocate packet: %s", av_err2str(ret));
+	memcpy(ctx->pkt->data, data, size);
+	ctx->pkt->pts = pts;
+	ctx->pkt->dts = dts;
+	ctx->pkt->stream_index = ctx->st->index;
+	if (keyframe)
+		ctx->pkt->flags |= AV_PKT_FLAG_KEY;
+	av_packet_rescale_ts(ctx->pkt, ctx->time_base, ctx->st->time_base);
+	ret = av_write_frame(ctx->oc, ctx->pkt);
+	av_packet_unref(ctx->pkt);
+	if (ret < 0)
+		ERROR(err, 1, "Failed to mux packet: %s", av_err2str(ret));
+
+	// new fragment on every frame for lowest latency, with MPEG-TS this passes on the packets
+	// right away
+	av_write_frame(ctx->oc, NULL);
+	avio_flush(ctx->oc->pb);
+}
+
+void destroy_muxer(MuxContext* ctx)
+{
+	if (ctx->initialized)
+		av_write_trailer(ctx->oc);
+	if (ctx->oc && ctx->oc->pb)
+	{
+		// avio may have replaced the buffer
+		av_freep(&ctx->oc->pb->buffer);
+		avio_context_free(&ctx->oc->pb);
+	}
+	else
+		av_free(ctx->buf);
+	av_packet_free(&ctx->pkt);
+	avformat_free_context(ctx->oc);
+	free(ctx);
+}
@@ -0,0 +1,122 @@
+//! Records who connected to Weylus and what came of it, to find out afterwards who controlled the
+//! machine. Entries are logged with their own target, they show up in the regular log and can be
+//! written to a separate file via AccessLogLayer.
+
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::io::{LineWriter, Write};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tracing::field::{Field, Visit};
+use tracing::{info, Event, Subscriber};
+use tracing_subscriber::layer::{Context, Layer};
+
+pub const TARGET: &str = "access";
+
+/// A request to the web server has been answered with status.
+pub fn http(client: SocketAddr, method: &str, path: &str, user_agent: Option<&str>, status: u16) {
+    info!(
+        target: TARGET,
+        client = %client.ip(),
+        user_agent = user_agent.unwrap_or("-"),
+        status,
+        "HTTP {} {}", method, path
+    );
+}
+
+/// Something happened on a websocket connection, e.g. "connected" or "authentication failed".
+pub fn websocket(client: SocketAddr, port: u16, user_agent: Option<&str>, outcome: &str) {
+    info!(
+        target: TARGET,
+        client = %client.ip(),
+        user_agent = user_agent.unwrap_or("-"),
+        outcome,
+        "Websocket on port {}", port
+    );
+}
+
+/// Appends the entries of the access log to a file, one line each.
+pub struct AccessLogLayer {
+    out: Mutex<LineWriter<File>>,
+}
+
+impl AccessLogLayer {
+    pub fn new(path: &Path) -> std::io::Result<Self> {
+        Ok(Self {
+            out: Mutex::new(LineWriter::new(
+                OpenOptions::new().create(true).append(true).open(path)?,
+            )),
+        })
+    }
+}
+
+impl<S: Subscriber> Layer<S> for AccessLogLayer {
+    fn on_event(&self, event: &Event<'_>, _ctx: Context<'_, S>) {
+        if event.metadata().target() != TARGET {
+            return;
+        }
+        let mut line = LineVisitor {
+            message: String::new(),
+            fields: String::new(),
+        };
+        event.record(&mut line);
+        let line = format!("{} {}{}\n", timestamp(), line.message, line.fields);
+        // errors are ignored, there is no sensible way to report them from within the logger
+        self.out.lock().unwrap().write_all(line.as_bytes()).ok();
+    }
+}
+
+struct LineVisitor {
+    message: String,
+    fields: String,
+}
+
+impl Visit for LineVisitor {
+    fn record_str(&mut self, field: &Field, value: &str) {
+        if field.name() == "message" {
+            self.message.push_str(value);
+        } else {
+            // quoted as user agents contain spaces
+            write!(self.fields, " {}={:?}", field.name(), value).ok();
+        }
+    }
+
+    fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+        if field.name() == "message" {
+            write!(self.message, "{:?}", value).ok();
+        } else {
+            write!(self.fields, " {}={:?}", field.name(), value).ok();
+        }
+    }
+}
+
+// current time in UTC as RFC 3339, e.g. 2020-06-01T12:30:00Z
+fn timestamp() -> String {
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0);
+    let (days, secs) = (secs.div_euclid(86400), secs.rem_euclid(86400));
+    // convert days since 1970-01-01 to a date, see http://howardhinnant.github.io/date_algorithms.html
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60
+    )
+}
@@ -0,0 +1,342 @@
+//! Audio for the clients, captured via the tools of PulseAudio which PipeWire provides as well
+//! (pipewire-pulse). Either the whole desktop mix is streamed or only the audio of the application
+//! whose window is captured, this keeps notification sounds of other applications off the tablet.
+//! PipeWire and PulseAudio tell which process plays a stream, so the streams of the application
+//! are found by the process id of its window.
+//!
+//! Audio is sent as raw PCM on the video socket: a text message "audio" announces that the next
+//! binary message contains audio instead of video.
+
+use std::io::Read;
+use std::process::{Child, Command, Stdio};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use tracing::{debug, info, warn};
+use websocket::{OwnedMessage, WebSocketError};
+
+use crate::send_queue::SendQueue;
+use crate::stream_handler::WsWriter;
+
+pub const SAMPLE_RATE: u32 = 48000;
+pub const CHANNELS: u32 = 2;
+// chunks of 20 ms of signed 16 bit samples, short enough to keep the latency low
+const CHUNK_SIZE: usize = (SAMPLE_RATE / 50 * CHANNELS * 2) as usize;
+// how often the streams of the captured application are looked up again, it may start playing
+// audio later or the captured window may change
+const SOURCE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Which audio is streamed to the clients.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AudioMode {
+    None,
+    // everything the default output device plays
+    Desktop,
+    // only the application of the captured window, the desktop mix if the whole screen is captured
+    Application,
+}
+
+impl FromStr for AudioMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" => Ok(Self::None),
+            "desktop" => Ok(Self::Desktop),
+            "application" => Ok(Self::Application),
+            _ => Err(format!(
+                "Unknown audio mode '{}', use none, desktop or application.",
+                s
+            )),
+        }
+    }
+}
+
+// what parec records
+#[derive(Clone, Debug, PartialEq)]
+enum Source {
+    Desktop,
+    // index of a sink input, i.e. a stream played by an application
+    SinkInput(u32),
+    // the application plays nothing at the moment
+    Silence,
+}
+
+// returns the process id of the captured window, None if the whole screen is captured
+type Target = Box<dyn Fn() -> Option<u32> + Send>;
+
+/// Captures audio as long as there are clients and sends it to all of them.
+pub struct AudioStream {
+    mode: AudioMode,
+    target: Mutex<Option<Target>>,
+    clients: Mutex<Vec<(u64, SendQueue)>>,
+    next_id: AtomicU64,
+    running: AtomicBool,
+}
+
+impl AudioStream {
+    pub fn new(mode: AudioMode) -> Option<Arc<Self>> {
+        if mode == AudioMode::None {
+            return None;
+        }
+        Some(Arc::new(Self {
+            mode,
+            target: Mutex::new(None),
+            clients: Mutex::new(Vec::new()),
+            next_id: AtomicU64::new(0),
+            running: AtomicBool::new(false),
+        }))
+    }
+
+    /// Tell the stream which process the captured window belongs to.
+    pub fn set_target(&self, target: impl Fn() -> Option<u32> + Send + 'static) {
+        *self.target.lock().unwrap() = Some(Box::new(target));
+    }
+
+    pub fn subscribe(self: &Arc<Self>, sender: WsWriter) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.clients
+            .lock()
+            .unwrap()
+            .push((id, SendQueue::new(sender, log_audio_error)));
+        // the thread checks for clients while holding the lock before it stops
+        if !self.running.swap(true, Ordering::SeqCst) {
+            let stream = self.clone();
+            std::thread::spawn(move || stream.run());
+        }
+        id
+    }
+
+    /// Continue sending audio on the new connection of a client that resumed its session.
+    pub fn resubscribe(&self, id: u64, sender: &WsWriter) {
+        if let Some((_, client)) = self
+            .clients
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .find(|(client_id, _)| *client_id == id)
+        {
+            *client = SendQueue::new(sender.clone(), log_audio_error);
+        }
+    }
+
+    pub fn unsubscribe(&self, id: u64) {
+        self.clients
+            .lock()
+            .unwrap()
+            .retain(|(client_id, _)| *client_id != id);
+    }
+
+    fn source(&self) -> Source {
+        let pid = match self.mode {
+            AudioMode::Application => self.target.lock().unwrap().as_ref().and_then(|f| f()),
+            _ => None,
+        };
+        match pid {
+            None => Source::Desktop,
+            Some(pid) => match sink_input_of(pid) {
+                Some(index) => Source::SinkInput(index),
+                None => Source::Silence,
+            },
+        }
+    }
+
+    fn run(self: Arc<Self>) {
+        info!("Starting to stream audio.");
+        let mut source = None;
+        let mut recorder: Option<(Child, mpsc::Receiver<Vec<u8>>)> = None;
+        loop {
+            {
+                let clients = self.clients.lock().unwrap();
+                if clients.is_empty() {
+                    self.running.store(false, Ordering::SeqCst);
+                    break;
+                }
+            }
+            let current = self.source();
+            if source.as_ref() != Some(&current) {
+                debug!("Recording audio from {:?}.", current);
+                if let Some((mut child, _)) = recorder.take() {
+                    child.kill().ok();
+                    child.wait().ok();
+                }
+                recorder = match record(&current) {
+                    Ok(recorder) => recorder,
+                    Err(err) => {
+                        warn!("Failed to record audio: {}", err);
+                        None
+                    }
+                };
+                source = Some(current);
+            }
+            let lost = match &recorder {
+                Some((_, chunks)) => self.forward(chunks),
+                None => {
+                    std::thread::sleep(SOURCE_CHECK_INTERVAL);
+                    continue;
+                }
+            };
+            if lost {
+                warn!("Recording audio stopped.");
+                if let Some((mut child, _)) = recorder.take() {
+                    child.wait().ok();
+                }
+                // try again once the source is checked next
+                source = None;
+                std::thread::sleep(SOURCE_CHECK_INTERVAL);
+            }
+        }
+        if let Some((mut child, _)) = recorder {
+            child.kill().ok();
+            child.wait().ok();
+        }
+        info!("Stopped streaming audio.");
+    }
+
+    // send the recorded chunks until it is time to check the source again, returns true if
+    // recording stopped
+    fn forward(&self, chunks: &mpsc::Receiver<Vec<u8>>) -> bool {
+        let started = Instant::now();
+        // parec blocks while the stream plays nothing, so chunks are waited for with a timeout
+        while started.elapsed() < SOURCE_CHECK_INTERVAL {
+            match chunks.recv_timeout(SOURCE_CHECK_INTERVAL) {
+                Ok(chunk) => self.send(chunk),
+                Err(mpsc::RecvTimeoutError::Timeout) => return false,
+                Err(mpsc::RecvTimeoutError::Disconnected) => return true,
+            }
+        }
+        false
+    }
+
+    // chunks for clients that do not keep up are dropped
+    fn send(&self, chunk: Vec<u8>) {
+        let announce = Arc::new(OwnedMessage::Text("audio".into()));
+        let data = Arc::new(OwnedMessage::Binary(chunk));
+        for (_, client) in self.clients.lock().unwrap().iter_mut() {
+            // both messages are sent as a batch, so no video gets in between
+            client.send(vec![announce.clone(), data.clone()]);
+        }
+    }
+}
+
+fn log_audio_error(err: WebSocketError) {
+    debug!("Error sending audio: {}", err);
+}
+
+/// Fails if the tools needed to stream audio in this mode are missing.
+pub fn check_tools(mode: AudioMode) -> Result<(), String> {
+    let tools: &[&str] = match mode {
+        AudioMode::None => &[],
+        AudioMode::Desktop => &["parec"],
+        AudioMode::Application => &["parec", "pactl"],
+    };
+    for tool in tools {
+        let found = Command::new(tool)
+            .arg("--version")
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .is_ok();
+        if !found {
+            return Err(format!(
+                "--audio requires {}, install the PulseAudio utilities (pulseaudio-utils) with \
+                PipeWire (pipewire-pulse) or PulseAudio.",
+                tools.join(" and ")
+            ));
+        }
+    }
+    Ok(())
+}
+
+// start parec, the chunks it records are passed on by a thread reading its output
+fn record(source: &Source) -> std::io::Result<Option<(Child, mpsc::Receiver<Vec<u8>>)>> {
+    let mut command = Command::new("parec");
+    command.args(&[
+        "--raw",
+        "--format=s16le",
+        &format!("--rate={}", SAMPLE_RATE),
+        &format!("--channels={}", CHANNELS),
+        "--latency-msec=20",
+        "--client-name=Weylus",
+    ]);
+    match source {
+        Source::Desktop => command.arg("--device=@DEFAULT_MONITOR@"),
+        Source::SinkInput(index) => command.arg(format!("--monitor-stream={}", index)),
+        Source::Silence => return Ok(None),
+    };
+    let mut child = command
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()?;
+    let mut stdout = child.stdout.take().unwrap();
+    let (sender, receiver) = mpsc::sync_channel(16);
+    std::thread::spawn(move || {
+        let mut chunk = vec![0; CHUNK_SIZE];
+        while stdout.read_exact(&mut chunk).is_ok() {
+            // chunks are dropped instead of piling up if sending falls behind
+            if let Err(mpsc::TrySendError::Disconnected(_)) = sender.try_send(chunk.clone()) {
+                return;
+            }
+        }
+    });
+    Ok(Some((child, receiver)))
+}
+
+// the stream of the application with this process id or of one of its child processes, browsers
+// for example play audio from a separate process
+fn sink_input_of(pid: u32) -> Option<u32> {
+    let output = match Command::new("pactl")
+        .args(&["--format=json", "list", "sink-inputs"])
+        .stderr(Stdio::null())
+        .output()
+    {
+        Ok(output) => output,
+        Err(err) => {
+            warn!("Failed to list audio streams via pactl: {}", err);
+            return None;
+        }
+    };
+    let inputs: serde_json::Value = serde_json::from_slice(&output.stdout).ok()?;
+    inputs.as_array()?.iter().find_map(|input| {
+        let index = input.get("index")?.as_u64()?;
+        let process = input
+            .get("properties")?
+            .get("application.process.id")?
+            .as_str()?
+            .parse()
+            .ok()?;
+        if descends_from(process, pid) {
+            Some(index as u32)
+        } else {
+            None
+        }
+    })
+}
+
+fn descends_from(mut pid: u32, ancestor: u32) -> bool {
+    // init is the ancestor of everything, the depth of process trees is limited just in case
+    for _ in 0..64 {
+        if pid == ancestor {
+            return true;
+        }
+        if pid <= 1 {
+            return false;
+        }
+        pid = match parent_pid(pid) {
+            Some(parent) => parent,
+            None => return false,
+        };
+    }
+    false
+}
+
+fn parent_pid(pid: u32) -> Option<u32> {
+    let stat = std::fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // the name of the process in parentheses may contain spaces, the parent follows the state
+    let (_, rest) = stat.rsplit_once(')')?;
+    rest.split_whitespace().nth(1)?.parse().ok()
+}
@@ -25,6 +25,23 @@ impl CError {
     pub fn code(&self) -> i32 {
         self.code as i32
     }
+
+    /// Create an error that did not originate from C code, the message is truncated to fit.
+    pub fn with_message(code: i32, message: &str) -> Self {
+        let mut err = Self::new();
+        err.code = code as c_int;
+        let len = message.len().min(err.error_str.len() - 1);
+        for (c, b) in err.error_str.iter_mut().zip(&message.as_bytes()[..len]) {
+            *c = *b as c_char;
+        }
+        err
+    }
+
+    pub fn message(&self) -> String {
+        unsafe { CStr::from_ptr(self.error_str.as_ptr()) }
+            .to_string_lossy()
+            .into_owned()
+    }
 }
 
 impl fmt::Display for CError {
@@ -0,0 +1,88 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{LineWriter, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use tracing::span::{Attributes, Id};
+use tracing::Subscriber;
+use tracing_subscriber::layer::{Context, Layer};
+
+static NEXT_THREAD_ID: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    static THREAD_ID: Cell<u64> = Cell::new(0);
+}
+
+// small stable id for the current thread, std::thread::ThreadId can not be converted to a number
+fn thread_id() -> u64 {
+    THREAD_ID.with(|id| {
+        if id.get() == 0 {
+            id.set(NEXT_THREAD_ID.fetch_add(1, Ordering::Relaxed));
+        }
+        id.get()
+    })
+}
+
+/// Writes every entered span as duration event in the trace event format, the resulting file can
+/// be loaded in chrome://tracing or https://ui.perfetto.dev. The closing bracket of the JSON array
+/// is never written, which both viewers accept, so the file stays valid if Weylus is killed.
+pub struct ChromeTraceLayer {
+    out: Mutex<LineWriter<File>>,
+    names: Mutex<HashMap<u64, &'static str>>,
+    start: Instant,
+}
+
+impl ChromeTraceLayer {
+    pub fn new(path: &Path) -> std::io::Result<Self> {
+        let mut out = LineWriter::new(File::create(path)?);
+        out.write_all(b"[\n")?;
+        Ok(Self {
+            out: Mutex::new(out),
+            names: Mutex::new(HashMap::new()),
+            start: Instant::now(),
+        })
+    }
+
+    fn event(&self, id: &Id, phase: char) {
+        let name = match self.names.lock().unwrap().get(&id.into_u64()) {
+            Some(name) => *name,
+            None => return,
+        };
+        let ts = self.start.elapsed().as_micros();
+        let line = format!(
+            "{{\"name\":\"{}\",\"ph\":\"{}\",\"ts\":{},\"pid\":{},\"tid\":{}}},\n",
+            name,
+            phase,
+            ts,
+            std::process::id(),
+            thread_id()
+        );
+        // errors are ignored, there is no sensible way to report them from within the logger
+        self.out.lock().unwrap().write_all(line.as_bytes()).ok();
+    }
+}
+
+impl<S: Subscriber> Layer<S> for ChromeTraceLayer {
+    fn new_span(&self, attrs: &Attributes<'_>, id: &Id, _ctx: Context<'_, S>) {
+        self.names
+            .lock()
+            .unwrap()
+            .insert(id.into_u64(), attrs.metadata().name());
+    }
+
+    fn on_enter(&self, id: &Id, _ctx: Context<'_, S>) {
+        self.event(id, 'B');
+    }
+
+    fn on_exit(&self, id: &Id, _ctx: Context<'_, S>) {
+        self.event(id, 'E');
+    }
+
+    fn on_close(&self, id: Id, _ctx: Context<'_, S>) {
+        self.names.lock().unwrap().remove(&id.into_u64());
+    }
+}
@@ -0,0 +1,276 @@
+use std::net::SocketAddr;
+#[cfg(target_os = "linux")]
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
+
+use qrcode::render::unicode;
+use qrcode::QrCode;
+use tokio::sync::mpsc as mpsc_tokio;
+use tracing::error;
+#[cfg(target_os = "linux")]
+use tracing::info;
+#[cfg(not(target_os = "linux"))]
+use tracing::warn;
+
+#[cfg(target_os = "linux")]
+use crate::config::State;
+use crate::config::{Config, ConfigFile, Options};
+use crate::input::mapping::InputMapping;
+use crate::logs::LogBuffer;
+use crate::metrics::Metrics;
+use crate::network;
+use crate::stream_handler::StreamMode;
+use crate::tcp_options::TcpOptions;
+use crate::web::{Gui2WebMessage, Web2GuiMessage};
+use crate::websocket::{Gui2WsMessage, Ws2GuiMessage};
+
+/// Run the servers without GUI using the settings from the command line and configuration file,
+/// returns once the web server has been shut down.
+pub fn run(options: &Options, config_file: &ConfigFile, logs: Arc<LogBuffer>) {
+    let resolve = |port: u16| -> Option<u16> {
+        if port != 0 {
+            return Some(port);
+        }
+        match network::free_port(options.bind_address) {
+            Ok(port) => Some(port),
+            Err(err) => {
+                error!("Failed to find a free port: {}", err);
+                None
+            }
+        }
+    };
+    let (web_port, ws_pointer_port, ws_video_port) = match (
+        resolve(options.web_port),
+        resolve(options.websocket_pointer_port),
+        resolve(options.websocket_video_port),
+    ) {
+        (Some(web), Some(pointer), Some(video)) => (web, pointer, video),
+        _ => return,
+    };
+
+    let config = Config {
+        password: options.password.clone(),
+        view_only_password: options.view_only_password.clone(),
+        basic_auth: options.basic_auth,
+        bind_address: options.bind_address,
+        web_port,
+        websocket_pointer_port: ws_pointer_port,
+        websocket_video_port: ws_video_port,
+        screen_update_interval: Duration::from_millis(0),
+        stylus_support: cfg!(target_os = "linux"),
+        faster_capture: cfg!(target_os = "linux"),
+        capture_cursor: options.capture_cursor,
+        enable_mouse: true,
+        enable_stylus: true,
+        enable_touch: true,
+        rotation: options.rotation,
+        scaling_filter: options.scaling_filter,
+        color_space: options.color_space,
+        color_range: options.color_range,
+        pointer_roi: options.pointer_roi,
+        stroke_smoothing: options.stroke_smoothing,
+        record_input: options.record_input.clone(),
+        shortcuts: config_file.shortcuts(),
+        long_press: if options.long_press {
+            Some(config_file.long_press)
+        } else {
+            None
+        },
+        touch_filter: config_file.touch_filter,
+        codec: options.encoder.unwrap_or_else(crate::video::best_encoder),
+        threads: options.threads,
+        scale_on_resize: options.scale_on_resize,
+        xcomposite: options.xcomposite,
+        hide_own_windows: options.hide_own_windows,
+        privacy: config_file.privacy.clone(),
+        v4l2_device: options.v4l2_device.clone(),
+        gpu_convert: options.gpu_convert,
+        inhibit_screensaver: !options.allow_screensaver,
+        wake_display: options.wake_display,
+        max_input_rate: options.max_input_rate,
+        input_mapping: InputMapping {
+            mirror_x: options.mirror_x,
+            mirror_y: options.mirror_y,
+            swap_buttons: options.swap_buttons,
+        },
+        max_resolution: options.max_resolution,
+        latency_budget: options.latency_budget.map(Duration::from_millis),
+        tcp_options: TcpOptions {
+            nodelay: !options.tcp_delay,
+            send_buffer_size: options.send_buffer_size.map(|kib| kib * 1024),
+        },
+        intra_refresh: options.intra_refresh,
+        cpu_limit: options.cpu_limit,
+        battery_frame_interval: options
+            .battery_max_fps
+            .map(|fps| Duration::from_secs_f64(1.0 / fps.max(0.1))),
+        stream_mode: options.stream_mode,
+        notify_connections: options.notify_connections,
+        reject_grace_period: options.reject_grace_period.map(Duration::from_secs),
+        require_approval: options.require_approval,
+        idle_timeout: options
+            .idle_timeout
+            .map(|minutes| Duration::from_secs(minutes * 60)),
+        audio: options.audio,
+        draft_while_drawing: options.draft_while_drawing,
+    };
+
+    let metrics = Metrics::new();
+
+    // the servers shut down once the channels to them are closed, besides stopping the servers
+    // after the idle timeout the messages meant for the GUI are ignored
+    let (sender_ws2gui, receiver_ws2gui) = mpsc::channel();
+    let (sender_gui2ws, receiver_gui2ws) = mpsc::channel();
+    #[cfg(target_os = "linux")]
+    let capture_source = if let Some(device) = drm_device(options) {
+        crate::websocket::run_drm(
+            sender_ws2gui,
+            receiver_gui2ws,
+            &config,
+            metrics.clone(),
+            device,
+        )
+    } else {
+        // the capturable selected in the GUI the last time, the desktop otherwise
+        let capturable = match crate::x11helper::X11Context::new()
+            .and_then(|mut x11ctx| x11ctx.capturables().ok())
+            .and_then(|capturables| {
+                let saved = State::load().capture.and_then(|id| id.find(&capturables));
+                capturables.into_iter().nth(saved.unwrap_or(0))
+            }) {
+            Some(capturable) => capturable,
+            None => {
+                error!("Failed to find anything to capture, is DISPLAY set?");
+                return;
+            }
+        };
+        crate::websocket::run(
+            sender_ws2gui,
+            receiver_gui2ws,
+            &config,
+            metrics.clone(),
+            crate::x11helper::CaptureTarget::new(capturable),
+        )
+    };
+    #[cfg(not(target_os = "linux"))]
+    let capture_source = {
+        if options.drm_device.is_some() {
+            warn!("Capturing via DRM is only supported on Linux.");
+        }
+        crate::websocket::run(sender_ws2gui, receiver_gui2ws, &config, metrics.clone())
+    };
+
+    let (sender_web2gui, receiver_web2gui) = mpsc::channel();
+    let (mut sender_gui2web, receiver_gui2web) = mpsc_tokio::channel(100);
+    let web_sock = SocketAddr::new(config.bind_address, config.web_port);
+    crate::web::run(
+        sender_web2gui,
+        receiver_gui2web,
+        &web_sock,
+        ws_pointer_port,
+        ws_video_port,
+        config.password.as_deref(),
+        config.view_only_password.as_deref(),
+        config.basic_auth,
+        metrics,
+        config.shortcuts.iter().map(|s| s.name.clone()).collect(),
+        config.stream_mode == StreamMode::PerClient,
+        logs,
+        capture_source,
+        config.encoder_options(),
+    );
+
+    // with basic authentication the browser asks for the password, it is left out of the URLs
+    if config.basic_auth {
+        print_urls(web_sock, None, "");
+    } else {
+        print_urls(web_sock, config.password.as_deref(), "");
+        if let Some(password) = &config.view_only_password {
+            print_urls(web_sock, Some(password), " to only watch");
+        }
+    }
+
+    std::thread::spawn(move || {
+        while let Ok(message) = receiver_ws2gui.recv() {
+            match message {
+                Ws2GuiMessage::IdleTimeout => {
+                    sender_gui2ws.send(Gui2WsMessage::Shutdown).ok();
+                    sender_gui2web.try_send(Gui2WebMessage::Shutdown).ok();
+                }
+                // without a window to ask in, a notification offering to approve is shown
+                Ws2GuiMessage::ApprovalRequested(addr, reply) => {
+                    std::thread::spawn(move || {
+                        let action = crate::notifications::ask(
+                            "Weylus: New device",
+                            &format!("{} asks to connect.", addr.ip()),
+                            &[("approve", "Approve"), ("deny", "Deny")],
+                            crate::websocket::APPROVAL_TIMEOUT,
+                        );
+                        reply.send(action.as_deref() == Some("approve")).ok();
+                    });
+                }
+                _ => (),
+            }
+        }
+    });
+
+    while let Ok(message) = receiver_web2gui.recv() {
+        match message {
+            Web2GuiMessage::Shutdown => return,
+            // the wake page is only served by the GUI
+            Web2GuiMessage::StartRequested(_) => (),
+        }
+    }
+}
+
+// the framebuffer is captured via DRM if requested or if there is no display server to capture
+#[cfg(target_os = "linux")]
+fn drm_device(options: &Options) -> Option<PathBuf> {
+    if options.drm_device.is_some() {
+        return options.drm_device.clone();
+    }
+    if crate::x11helper::display_server_available() {
+        return None;
+    }
+    info!(
+        "No display server found, capturing {} via DRM.",
+        crate::screen_capture::drm::DEFAULT_DEVICE
+    );
+    Some(PathBuf::from(crate::screen_capture::drm::DEFAULT_DEVICE))
+}
+
+fn print_urls(web_sock: SocketAddr, password: Option<&str>, purpose: &str) {
+    let socks = network::reachable_addresses(web_sock);
+    // the name comes first, so the QR code keeps working if the addresses change
+    let urls: Vec<String> = network::host_name(&socks)
+        .map(|name| network::named_server_url(&name, web_sock.port(), password))
+        .into_iter()
+        .chain(socks.iter().map(|sock| network::server_url(sock, password)))
+        .collect();
+    if urls.is_empty() {
+        println!(
+            "Connect your tablet{} to http://<your ip address>:{}",
+            purpose,
+            web_sock.port()
+        );
+        return;
+    }
+    println!("Connect your tablet{} to:", purpose);
+    for url in &urls {
+        println!("    {}", url);
+    }
+    match QrCode::new(&urls[0]) {
+        Ok(code) => {
+            // light modules are drawn as blocks, this way the code is readable in terminals with
+            // a dark background
+            let qr = code
+                .render::<unicode::Dense1x2>()
+                .dark_color(unicode::Dense1x2::Light)
+                .light_color(unicode::Dense1x2::Dark)
+                .build();
+            println!("\n{}\n", qr);
+        }
+        Err(err) => error!("Failed to create QR code: {}", err),
+    }
+}
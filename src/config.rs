@@ -0,0 +1,695 @@
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use structopt::StructOpt;
+use tracing::{error, warn};
+
+use crate::audio::AudioMode;
+use crate::input::long_press::LongPressOptions;
+use crate::input::mapping::InputMapping;
+use crate::input::shortcuts::{default_shortcuts, parse_key_sequence, Shortcut};
+use crate::input::smoothing::Smoothing;
+use crate::input::touch_filter::TouchFilterOptions;
+use crate::logs::LogFormat;
+#[cfg(target_os = "linux")]
+use crate::screen_capture::linux::X11CaptureOptions;
+use crate::screen_capture::privacy::PrivacyOptions;
+use crate::screen_capture::rotation::Rotation;
+use crate::slice_pool::thread_count;
+use crate::stream_handler::{PointerOptions, StreamMode};
+use crate::tcp_options::TcpOptions;
+use crate::video::{
+    Codec, ColorRange, ColorSpace, Container, EncoderOptions, Resolution, ScalingFilter,
+};
+#[cfg(target_os = "linux")]
+use crate::x11helper::CapturableId;
+
+/// Options passed on the command line.
+#[derive(StructOpt, Debug)]
+#[structopt(name = "weylus")]
+pub struct Options {
+    /// Record all messages received from clients to this file, useful to reproduce input bugs.
+    #[structopt(long, parse(from_os_str))]
+    pub record_input: Option<PathBuf>,
+
+    /// Replay input recorded with --record-input and exit.
+    #[structopt(long, parse(from_os_str))]
+    pub replay_input: Option<PathBuf>,
+
+    /// Path of the configuration file, defaults to weylus/weylus.toml in the user's configuration
+    /// directory.
+    #[structopt(long, parse(from_os_str))]
+    pub config: Option<PathBuf>,
+
+    /// Run without GUI and print the URL and a QR code to connect to the terminal. This is also
+    /// done if there is no display or the GUI fails to start.
+    #[structopt(long)]
+    pub no_gui: bool,
+
+    /// Password required to connect, none by default.
+    #[structopt(long)]
+    pub password: Option<String>,
+
+    /// Generate a new random password each time Weylus is launched, it is included in the URLs and
+    /// QR codes to connect. Stopping and starting the server in the GUI keeps the password. Takes
+    /// precedence over --password.
+    #[structopt(long)]
+    pub random_password: bool,
+
+    /// Second password for clients that may only watch, they receive the video but can not send
+    /// any input. This way a link mirroring the screen can be handed out while the password
+    /// allowing input stays private.
+    #[structopt(long)]
+    pub view_only_password: Option<String>,
+
+    /// Generate a new random view-only password each time Weylus is launched, like
+    /// --random-password. Takes precedence over --view-only-password.
+    #[structopt(long)]
+    pub random_view_only_password: bool,
+
+    /// Ask for the password via HTTP basic authentication instead of a page of Weylus, this way it
+    /// does not end up in the browser history. The user name is ignored. Browsers send it
+    /// unencrypted, so put a proxy providing TLS in front of Weylus.
+    #[structopt(long)]
+    pub basic_auth: bool,
+
+    /// Address the servers listen on, :: listens on all IPv6 and, except on Windows, all IPv4
+    /// addresses.
+    #[structopt(long, default_value = "0.0.0.0", parse(try_from_str = crate::network::parse_ip))]
+    pub bind_address: IpAddr,
+
+    /// Port of the web server, 0 picks any free port.
+    #[structopt(long, default_value = "1701")]
+    pub web_port: u16,
+
+    /// Port of the websocket for pointer events, 0 picks any free port.
+    #[structopt(long, default_value = "9001")]
+    pub websocket_pointer_port: u16,
+
+    /// Port of the websocket for the video, 0 picks any free port.
+    #[structopt(long, default_value = "9002")]
+    pub websocket_video_port: u16,
+
+    /// Encoder used for the video: x264, vaapi (Linux only), nvenc, amf (Windows only) or mjpeg.
+    /// Defaults to the first of nvenc, vaapi, amf and x264 that works.
+    #[structopt(long)]
+    pub encoder: Option<Codec>,
+
+    /// Number of threads used to convert and encode frames, 0 picks a number based on the
+    /// available cores.
+    #[structopt(long, default_value = "0")]
+    pub threads: usize,
+
+    /// Scale the video to its previous size while the captured window is being resized instead of
+    /// restarting the stream for every new size, the stream is restarted once the size settles.
+    #[structopt(long)]
+    pub scale_on_resize: bool,
+
+    /// Capture windows via XComposite, this way they can be captured while covered by other
+    /// windows or moved off screen. Popup menus of the window are not captured then, Linux only.
+    #[structopt(long)]
+    pub xcomposite: bool,
+
+    /// Black out the windows of Weylus itself when capturing a whole screen so neither the GUI nor
+    /// the URL and QR code it shows end up on the tablet, Linux only.
+    #[structopt(long)]
+    pub hide_own_windows: bool,
+
+    /// Also write the captured frames to this v4l2loopback device, e.g. /dev/video10, so video
+    /// calls can use them like a webcam, Linux only.
+    #[structopt(long, parse(from_os_str))]
+    pub v4l2_device: Option<PathBuf>,
+
+    /// Mirror the framebuffer of this DRM device, e.g. /dev/dri/card0, instead of capturing via
+    /// X11. Used for consoles and kiosk sessions without display server, in which case
+    /// /dev/dri/card0 is captured by default. Requires CAP_SYS_ADMIN, input is not supported,
+    /// implies --no-gui, Linux only.
+    #[structopt(long, parse(from_os_str))]
+    pub drm_device: Option<PathBuf>,
+
+    /// Have the input helper listening on this socket create the virtual input devices, see
+    /// `weylus input-helper`. Weylus itself then needs no access to /dev/uinput, Linux only.
+    #[structopt(long, parse(from_os_str))]
+    pub input_helper: Option<PathBuf>,
+
+    /// Largest resolution of the video, e.g. 1920x1080, larger screens and windows are scaled down
+    /// before encoding. This keeps streaming a 4K monitor usable on weak computers. The limit
+    /// applies rotated by 90 degrees to captures in portrait orientation.
+    #[structopt(long)]
+    pub max_resolution: Option<Resolution>,
+
+    /// Milliseconds a frame may take from encoding to being shown, e.g. 16, 33 or 66. x264 is then
+    /// configured to never buffer frames and to limit the size of each frame to what can be sent
+    /// within the budget, clients skip ahead once they lag behind by more than the budget.
+    #[structopt(long)]
+    pub latency_budget: Option<u64>,
+
+    /// Keep Nagle's algorithm enabled on the connections to clients. It combines small packets
+    /// but holds back small video frames and pointer events, which adds visible latency.
+    #[structopt(long)]
+    pub tcp_delay: bool,
+
+    /// Size of the send buffer of the connections to clients in KiB, chosen by the operating
+    /// system by default. A smaller buffer makes the video adapt faster to a dropping bandwidth,
+    /// e.g. 64 to 256 on Wi-Fi, as fewer frames queue up on the computer.
+    #[structopt(long)]
+    pub send_buffer_size: Option<usize>,
+
+    /// Refresh the picture with a column of intra coded blocks wandering across the frames instead
+    /// of sending a keyframe every few frames. This keeps the bitrate flat, which avoids regular
+    /// latency spikes on slow Wi-Fi. Only supported by x264.
+    #[structopt(long)]
+    pub intra_refresh: bool,
+
+    /// Capture frames less often if Weylus uses more CPU time than this, given in percent of a
+    /// single core. Useful to leave enough CPU time to other programs on laptops.
+    #[structopt(long)]
+    pub cpu_limit: Option<f64>,
+
+    /// Capture at most this many frames per second while the computer runs on battery, the frame
+    /// rate goes back to normal once it is plugged in.
+    #[structopt(long)]
+    pub battery_max_fps: Option<f64>,
+
+    /// Show the cursor in the video, Linux only.
+    #[structopt(long)]
+    pub capture_cursor: bool,
+
+    /// Rotate the video clockwise: none, 90, 180, 270 or auto, which rotates it if the orientation
+    /// of the tablet does not match the orientation of the captured screen or window.
+    #[structopt(long, default_value = "none")]
+    pub rotation: Rotation,
+
+    /// Filter used when scaling the captured image: fast, bicubic or lanczos. The latter keep text
+    /// more readable but need more CPU time.
+    #[structopt(long, default_value = "fast")]
+    pub scaling_filter: ScalingFilter,
+
+    /// Colorspace of the video: bt709 or bt601.
+    #[structopt(long, default_value = "bt709")]
+    pub color_space: ColorSpace,
+
+    /// Range of the video colors: limited or full. Try the other one if colors on the tablet look
+    /// washed out or too dark.
+    #[structopt(long, default_value = "limited")]
+    pub color_range: ColorRange,
+
+    /// Encode the area around the pen or cursor with higher quality at the expense of the rest of
+    /// the screen.
+    #[structopt(long)]
+    pub pointer_roi: bool,
+
+    /// Filter jitter from pen and touch input: off, low, medium or high. Stronger smoothing adds a
+    /// little lag at slow speeds.
+    #[structopt(long, default_value = "off")]
+    pub stroke_smoothing: Smoothing,
+
+    /// Turn holding a finger in place into a right click, delay and tolerance are read from the
+    /// configuration file.
+    #[structopt(long)]
+    pub long_press: bool,
+
+    /// Mirror pointer input horizontally, e.g. for tablets mounted upside down.
+    #[structopt(long)]
+    pub mirror_x: bool,
+
+    /// Mirror pointer input vertically.
+    #[structopt(long)]
+    pub mirror_y: bool,
+
+    /// Swap the left and right mouse button for left-handed use.
+    #[structopt(long)]
+    pub swap_buttons: bool,
+
+    /// How video is encoded for multiple clients: per-client runs an encoder for every client,
+    /// each adapts to the client and requests frames at its own pace. shared encodes the video
+    /// once and sends it to all clients, which is much cheaper if many clients watch the screen.
+    #[structopt(long, default_value = "per-client")]
+    pub stream_mode: StreamMode,
+
+    /// Stop the server after this many minutes without connected clients. The GUI keeps running
+    /// and the server can be started again, without GUI Weylus exits.
+    #[structopt(long)]
+    pub idle_timeout: Option<u64>,
+
+    /// Stream audio to the clients: none, desktop for everything the computer plays or
+    /// application for only the application whose window is captured, which keeps notification
+    /// sounds of other applications off the tablet. Requires pactl and parec as provided by
+    /// PipeWire (pipewire-pulse) or PulseAudio.
+    #[structopt(long, default_value = "none")]
+    pub audio: AudioMode,
+
+    /// Encode the video with lower quality while a pen or finger touches the tablet, this lowers
+    /// the latency when drawing. Once the stroke ends a frame in full quality is sent. Only the
+    /// x264 and MJPEG encoders support this.
+    #[structopt(long)]
+    pub draft_while_drawing: bool,
+
+    /// Scale and convert captured frames on the GPU before they are encoded, this saves CPU time
+    /// if the capture delivers RGB images in system memory. Requires the gpu-convert feature.
+    #[structopt(long)]
+    pub gpu_convert: bool,
+
+    /// Let the screensaver start and the display blank while video is streamed, by default this
+    /// is prevented.
+    #[structopt(long)]
+    pub allow_screensaver: bool,
+
+    /// Switch the display on if it has been blanked when a client starts streaming.
+    #[structopt(long)]
+    pub wake_display: bool,
+
+    /// Pointer events accepted from a client per second, excess events are dropped so a
+    /// misbehaving client can not flood the host with input. 0 disables the limit.
+    #[structopt(long, default_value = "2000")]
+    pub max_input_rate: u32,
+
+    /// Scale the GUI by this factor, e.g. 2 on HiDPI displays. Defaults to the scaling of the
+    /// desktop.
+    #[structopt(long)]
+    pub gui_scale: Option<f32>,
+
+    /// While the server is stopped, serve a page on the web port that lets a tablet ask for
+    /// Weylus to be started, starting has to be confirmed on this computer. GUI only.
+    #[structopt(long)]
+    pub wake_page: bool,
+
+    /// Show a desktop notification whenever a client connects or disconnects.
+    #[structopt(long)]
+    pub notify_connections: bool,
+
+    /// Every new device has to be approved on this computer before it receives video or may send
+    /// input. Approved devices are remembered.
+    #[structopt(long)]
+    pub require_approval: bool,
+
+    /// Ignore input from newly connected clients for this many seconds, meanwhile they can be
+    /// rejected from the notification shown on connecting. Requires notify-send on Linux.
+    #[structopt(long, requires = "notify_connections")]
+    pub reject_grace_period: Option<u64>,
+
+    /// Format of the log: text or json, which writes one JSON object per line.
+    #[structopt(long, default_value = "text")]
+    pub log_format: LogFormat,
+
+    /// Append the log to this file instead of writing it to stdout.
+    #[structopt(long, parse(from_os_str))]
+    pub log_file: Option<PathBuf>,
+
+    /// Also write the access log, which records every HTTP request and websocket connection with
+    /// the address of the client, its user agent and the outcome, to this file.
+    #[structopt(long, parse(from_os_str))]
+    pub access_log: Option<PathBuf>,
+
+    /// Write how long capturing, converting, encoding and sending each frame takes to this file,
+    /// it can be opened in chrome://tracing.
+    #[structopt(long, parse(from_os_str))]
+    pub trace_file: Option<PathBuf>,
+
+    #[structopt(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(StructOpt, Debug)]
+pub enum Command {
+    /// Give the current user write access to /dev/uinput, which is required for stylus and
+    /// multitouch support. Root privileges are requested via pkexec.
+    #[cfg(target_os = "linux")]
+    SetupUinput,
+    /// Internal, started by Weylus to clean up its input devices if it crashes.
+    #[cfg(target_os = "linux")]
+    #[structopt(setting = structopt::clap::AppSettings::Hidden)]
+    UinputGuard,
+    /// Create the virtual input devices for Weylus started with --input-helper. Run this in the
+    /// uinput group or as root, Weylus itself then needs no access to /dev/uinput and can be
+    /// started as an unprivileged user or in a sandbox, it does not drop privileges by itself.
+    #[cfg(target_os = "linux")]
+    InputHelper(InputHelperOptions),
+    /// Allow tablets to connect to the ports of Weylus through the Windows firewall, which
+    /// otherwise silently blocks them. Administrator privileges are requested via UAC.
+    #[cfg(target_os = "windows")]
+    SetupFirewall,
+    /// Connect to a running Weylus like a client, request frames at a fixed rate and send pointer
+    /// events, statistics about the latency are printed at the end. Note that the pointer events
+    /// are injected on the machine running Weylus.
+    Loadtest(LoadtestOptions),
+    /// Forward all connections to Weylus running on another machine, this way tablets can reach it
+    /// via a machine that is connected to both networks. The video and the input are passed on as
+    /// they are, so the relay needs next to no CPU time.
+    Relay(RelayOptions),
+    /// Send a wake-on-LAN packet to start the computer running Weylus. Run with --listen on a
+    /// machine that is always on, e.g. a router or a Raspberry Pi, every visit of its web page
+    /// then wakes the computer, so a bookmark on the tablet is enough.
+    Wake(WakeOptions),
+}
+
+#[derive(StructOpt, Debug)]
+pub struct LoadtestOptions {
+    /// Address of the machine running Weylus.
+    #[structopt(long, default_value = "127.0.0.1")]
+    pub host: IpAddr,
+
+    /// Port of the websocket for pointer events.
+    #[structopt(long, default_value = "9001")]
+    pub websocket_pointer_port: u16,
+
+    /// Port of the websocket for the video.
+    #[structopt(long, default_value = "9002")]
+    pub websocket_video_port: u16,
+
+    /// Password required to connect.
+    #[structopt(long)]
+    pub password: Option<String>,
+
+    /// Duration of the test in seconds.
+    #[structopt(long, default_value = "10")]
+    pub duration: u64,
+
+    /// Frames requested per second.
+    #[structopt(long, default_value = "60")]
+    pub fps: f64,
+
+    /// Pointer events sent per second, 0 disables sending pointer events.
+    #[structopt(long, default_value = "240")]
+    pub pointer_rate: f64,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct RelayOptions {
+    /// Host name or address of the machine running Weylus.
+    #[structopt(long)]
+    pub upstream: String,
+
+    /// Address the relay listens on.
+    #[structopt(long, default_value = "0.0.0.0")]
+    pub bind_address: IpAddr,
+
+    /// Port of the web server, the relay listens on the same ports Weylus does as the client
+    /// connects to the ports Weylus told it.
+    #[structopt(long, default_value = "1701")]
+    pub web_port: u16,
+
+    /// Port of the websocket for pointer events.
+    #[structopt(long, default_value = "9001")]
+    pub websocket_pointer_port: u16,
+
+    /// Port of the websocket for the video.
+    #[structopt(long, default_value = "9002")]
+    pub websocket_video_port: u16,
+}
+
+#[cfg(target_os = "linux")]
+#[derive(StructOpt, Debug)]
+pub struct InputHelperOptions {
+    /// Path of the Unix socket to listen on, everyone allowed to write to it can create input
+    /// devices. It is created readable and writable by owner and group only.
+    #[structopt(long, parse(from_os_str))]
+    pub socket: PathBuf,
+}
+
+#[derive(StructOpt, Debug)]
+pub struct WakeOptions {
+    /// MAC address of the computer to wake, e.g. 01:23:45:67:89:ab.
+    pub mac_address: String,
+
+    /// Address the packet is sent to, the broadcast address of the network of the computer.
+    #[structopt(long, default_value = "255.255.255.255")]
+    pub broadcast_address: IpAddr,
+
+    /// UDP port the packet is sent to, usually 9 or 7.
+    #[structopt(long, default_value = "9")]
+    pub port: u16,
+
+    /// Keep running and send a packet whenever the web page on this port is requested.
+    #[structopt(long)]
+    pub listen: Option<u16>,
+
+    /// Address to listen on with --listen.
+    #[structopt(long, default_value = "0.0.0.0")]
+    pub bind_address: IpAddr,
+
+    /// URL the web page opens once the computer had time to start, e.g. the URL of Weylus.
+    #[structopt(long)]
+    pub redirect: Option<String>,
+}
+
+/// Settings read from the configuration file, all of them are optional.
+#[derive(Deserialize, Default, Debug)]
+pub struct ConfigFile {
+    // buttons shown on the client
+    pub shortcuts: Option<Vec<Shortcut>>,
+    // used if right clicks on long presses are enabled in the GUI
+    #[serde(default)]
+    pub long_press: LongPressOptions,
+    // filter for noisy touch input, disabled by default
+    #[serde(default)]
+    pub touch_filter: TouchFilterOptions,
+    // windows blanked or pixelated wherever they show up in captured frames
+    #[serde(default)]
+    pub privacy: PrivacyOptions,
+}
+
+impl ConfigFile {
+    /// Load the configuration file, problems are logged and result in the default configuration.
+    pub fn load(path: Option<&Path>) -> Self {
+        let (path, explicit) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => match dirs::config_dir() {
+                Some(dir) => (dir.join("weylus").join("weylus.toml"), false),
+                None => return Self::default(),
+            },
+        };
+        let content = match std::fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(err) => {
+                // not having a configuration file at the default location is perfectly fine
+                if explicit || err.kind() != std::io::ErrorKind::NotFound {
+                    error!("Failed to read {}: {}", path.display(), err);
+                }
+                return Self::default();
+            }
+        };
+        match toml::from_str(&content) {
+            Ok(config) => config,
+            Err(err) => {
+                error!("Failed to parse {}: {}", path.display(), err);
+                Self::default()
+            }
+        }
+    }
+
+    pub fn shortcuts(&self) -> Vec<Shortcut> {
+        match &self.shortcuts {
+            Some(shortcuts) => shortcuts
+                .iter()
+                .filter(|s| match parse_key_sequence(&s.keys) {
+                    Ok(_) => true,
+                    Err(err) => {
+                        warn!("Ignoring shortcut {}: {}", s.name, err);
+                        false
+                    }
+                })
+                .cloned()
+                .collect(),
+            None => default_shortcuts(),
+        }
+    }
+}
+
+/// Remembered between runs of Weylus, unlike the configuration file this is written by Weylus
+/// itself.
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct State {
+    // what has been captured the last time the server was started
+    #[cfg(target_os = "linux")]
+    pub capture: Option<CapturableId>,
+    // the ports the user has been asked to open in the firewall for, asked again if they change
+    #[cfg(target_os = "windows")]
+    #[serde(default)]
+    pub firewall_ports: Vec<u16>,
+}
+
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+impl State {
+    /// Load the state, problems are logged and result in the default state.
+    pub fn load() -> Self {
+        load_state("state.toml")
+    }
+
+    pub fn save(&self) {
+        save_state("state.toml", self)
+    }
+}
+
+/// Devices that have been approved to connect, see --require-approval. Devices identify themselves
+/// with a random token the browser keeps.
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct ApprovedDevices {
+    pub tokens: Vec<String>,
+}
+
+impl ApprovedDevices {
+    pub fn load() -> Self {
+        load_state("devices.toml")
+    }
+
+    pub fn save(&self) {
+        save_state("devices.toml", self)
+    }
+}
+
+fn state_path(name: &str) -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("weylus").join(name))
+}
+
+// problems are logged and result in the default
+fn load_state<T: DeserializeOwned + Default>(name: &str) -> T {
+    let path = match state_path(name) {
+        Some(path) => path,
+        None => return T::default(),
+    };
+    let content = match std::fs::read_to_string(&path) {
+        Ok(content) => content,
+        Err(err) => {
+            if err.kind() != std::io::ErrorKind::NotFound {
+                error!("Failed to read {}: {}", path.display(), err);
+            }
+            return T::default();
+        }
+    };
+    match toml::from_str(&content) {
+        Ok(state) => state,
+        Err(err) => {
+            error!("Failed to parse {}: {}", path.display(), err);
+            T::default()
+        }
+    }
+}
+
+fn save_state<T: Serialize>(name: &str, state: &T) {
+    let path = match state_path(name) {
+        Some(path) => path,
+        None => return,
+    };
+    let res = toml::to_string(state)
+        .map_err(|err| err.to_string())
+        .and_then(|content| {
+            if let Some(dir) = path.parent() {
+                std::fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+            }
+            std::fs::write(&path, content).map_err(|err| err.to_string())
+        });
+    if let Err(err) = res {
+        error!("Failed to write {}: {}", path.display(), err);
+    }
+}
+
+#[derive(Clone)]
+pub struct Config {
+    pub password: Option<String>,
+    pub view_only_password: Option<String>,
+    pub basic_auth: bool,
+    pub bind_address: IpAddr,
+    pub web_port: u16,
+    pub websocket_pointer_port: u16,
+    pub websocket_video_port: u16,
+    pub screen_update_interval: Duration,
+    pub stylus_support: bool,
+    pub faster_capture: bool,
+    pub capture_cursor: bool,
+    pub enable_mouse: bool,
+    pub enable_stylus: bool,
+    pub enable_touch: bool,
+    pub rotation: Rotation,
+    pub scaling_filter: ScalingFilter,
+    pub color_space: ColorSpace,
+    pub color_range: ColorRange,
+    pub pointer_roi: bool,
+    pub stroke_smoothing: Smoothing,
+    pub record_input: Option<PathBuf>,
+    pub shortcuts: Vec<Shortcut>,
+    pub long_press: Option<LongPressOptions>,
+    pub touch_filter: TouchFilterOptions,
+    pub codec: Codec,
+    pub threads: usize,
+    pub scale_on_resize: bool,
+    pub xcomposite: bool,
+    pub hide_own_windows: bool,
+    pub privacy: PrivacyOptions,
+    pub v4l2_device: Option<PathBuf>,
+    pub gpu_convert: bool,
+    pub inhibit_screensaver: bool,
+    pub wake_display: bool,
+    pub max_input_rate: u32,
+    pub input_mapping: InputMapping,
+    pub max_resolution: Option<Resolution>,
+    pub latency_budget: Option<Duration>,
+    pub tcp_options: TcpOptions,
+    pub intra_refresh: bool,
+    pub cpu_limit: Option<f64>,
+    // minimal interval between frames while running on battery
+    pub battery_frame_interval: Option<Duration>,
+    pub stream_mode: StreamMode,
+    pub notify_connections: bool,
+    pub reject_grace_period: Option<Duration>,
+    pub require_approval: bool,
+    pub idle_timeout: Option<Duration>,
+    pub audio: AudioMode,
+    pub draft_while_drawing: bool,
+}
+
+impl Config {
+    pub fn encoder_options(&self) -> EncoderOptions {
+        EncoderOptions {
+            scaling_filter: self.scaling_filter,
+            color_space: self.color_space,
+            // browsers decode JPEGs as full range
+            color_range: if self.codec == Codec::Mjpeg {
+                ColorRange::Full
+            } else {
+                self.color_range
+            },
+            pointer_roi: self.pointer_roi,
+            codec: self.codec,
+            threads: thread_count(self.threads),
+            container: Container::Mp4,
+            gpu_convert: self.gpu_convert,
+            max_resolution: self.max_resolution,
+            latency_budget: self.latency_budget,
+            intra_refresh: self.intra_refresh,
+        }
+    }
+
+    #[cfg(target_os = "linux")]
+    pub fn x11_capture_options(&self) -> X11CaptureOptions {
+        X11CaptureOptions {
+            capture_cursor: self.capture_cursor,
+            xcomposite: self.xcomposite,
+            hide_own_windows: self.hide_own_windows,
+            privacy: self.privacy.clone(),
+        }
+    }
+
+    pub fn pointer_options(&self) -> PointerOptions {
+        PointerOptions {
+            smoothing: self.stroke_smoothing,
+            long_press: self.long_press,
+            touch_filter: self.touch_filter,
+            max_input_rate: self.max_input_rate,
+            mapping: self.input_mapping,
+        }
+    }
+}
+
+/// A password that is hard to guess but still possible to type on a tablet if needed.
+pub fn random_password() -> String {
+    use rand::Rng;
+    rand::thread_rng()
+        .sample_iter(&rand::distributions::Alphanumeric)
+        .take(16)
+        .collect()
+}
@@ -0,0 +1,125 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tracing::{debug, warn};
+
+// how often the CPU usage is measured
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+// frames are never captured at less than this fraction of the configured frame rate
+const MAX_SLOWDOWN: f64 = 8.0;
+
+/// Keeps the CPU usage of Weylus below a limit by capturing frames less often if it is exceeded.
+/// The usage is measured for the whole process, so input handling and all clients count as well.
+pub struct CpuLimiter {
+    // percent of a single core
+    limit: f64,
+    state: Mutex<LimiterState>,
+}
+
+struct LimiterState {
+    last_sample: Instant,
+    last_cpu_time: Duration,
+    // factor the interval between frames is stretched by
+    slowdown: f64,
+}
+
+impl CpuLimiter {
+    /// Returns None if the CPU usage can not be measured on this platform.
+    pub fn new(limit: f64) -> Option<Self> {
+        let cpu_time = match process_cpu_time() {
+            Some(cpu_time) => cpu_time,
+            None => {
+                warn!(
+                    "Measuring CPU usage is not supported on this platform, ignoring --cpu-limit."
+                );
+                return None;
+            }
+        };
+        Some(Self {
+            limit: limit.max(1.0),
+            state: Mutex::new(LimiterState {
+                last_sample: Instant::now(),
+                last_cpu_time: cpu_time,
+                slowdown: 1.0,
+            }),
+        })
+    }
+
+    /// Factor the interval between frames has to be multiplied with to stay within the limit.
+    pub fn slowdown(&self) -> f64 {
+        let mut state = self.state.lock().unwrap();
+        let elapsed = state.last_sample.elapsed();
+        if elapsed < SAMPLE_INTERVAL {
+            return state.slowdown;
+        }
+        let cpu_time = match process_cpu_time() {
+            Some(cpu_time) => cpu_time,
+            None => return state.slowdown,
+        };
+        let usage = (cpu_time - state.last_cpu_time).as_secs_f64() / elapsed.as_secs_f64() * 100.0;
+        // most of the CPU time is spent on capturing and encoding, so it is roughly proportional
+        // to the frame rate
+        let slowdown = (state.slowdown * usage / self.limit)
+            .max(1.0)
+            .min(MAX_SLOWDOWN);
+        if (slowdown - state.slowdown).abs() > 0.1 {
+            debug!(
+                "CPU usage is at {:.0}%, capturing frames {:.1} times slower.",
+                usage, slowdown
+            );
+        }
+        state.slowdown = slowdown;
+        state.last_sample = Instant::now();
+        state.last_cpu_time = cpu_time;
+        slowdown
+    }
+}
+
+// time spent by all threads of this process in user and kernel mode
+#[cfg(target_os = "linux")]
+fn process_cpu_time() -> Option<Duration> {
+    // the kernel reports times in ticks of 1/100 s to user space regardless of its configuration
+    const TICKS_PER_SECOND: u64 = 100;
+    let stat = std::fs::read_to_string("/proc/self/stat").ok()?;
+    // the name of the executable may contain spaces, the fields following it do not
+    let mut fields = stat[stat.rfind(')')? + 1..].split_whitespace();
+    // utime and stime are the 14th and 15th field, the state following the name is the 3rd
+    let utime: u64 = fields.nth(11)?.parse().ok()?;
+    let stime: u64 = fields.next()?.parse().ok()?;
+    Some(Duration::from_millis(
+        (utime + stime) * 1000 / TICKS_PER_SECOND,
+    ))
+}
+
+#[cfg(target_os = "windows")]
+fn process_cpu_time() -> Option<Duration> {
+    #[repr(C)]
+    #[derive(Default)]
+    struct FileTime {
+        low: u32,
+        high: u32,
+    }
+    extern "system" {
+        fn GetCurrentProcess() -> isize;
+        fn GetProcessTimes(
+            process: isize,
+            creation: *mut FileTime,
+            exit: *mut FileTime,
+            kernel: *mut FileTime,
+            user: *mut FileTime,
+        ) -> i32;
+    }
+    let mut times: [FileTime; 4] = Default::default();
+    let [creation, exit, kernel, user] = &mut times;
+    if unsafe { GetProcessTimes(GetCurrentProcess(), creation, exit, kernel, user) } == 0 {
+        return None;
+    }
+    // times are given in units of 100 ns
+    let to_nanos = |t: &FileTime| (((t.high as u64) << 32) | t.low as u64) * 100;
+    Some(Duration::from_nanos(to_nanos(kernel) + to_nanos(user)))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+fn process_cpu_time() -> Option<Duration> {
+    None
+}
@@ -0,0 +1,105 @@
+use std::backtrace::Backtrace;
+use std::fmt::Display;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::config::{ConfigFile, Options};
+use crate::logs::LogBuffer;
+
+// number of log lines included in crash reports
+const LOG_LINES: usize = 100;
+
+/// Settings Weylus has been started with, secrets are redacted so the summary can be shared.
+pub fn config_summary(options: &Options, config_file: &ConfigFile) -> String {
+    format!(
+        "password: {}\nbind address: {}\nweb port: {}\nwebsocket pointer port: {}\n\
+        websocket video port: {}\nencoder: {:?}\nno gui: {}\ngui scale: {:?}\nrecord input: {:?}\n\
+        config: {:?}\nconfig file: {:?}\n",
+        if options.password.is_some() {
+            "<redacted>"
+        } else {
+            "<none>"
+        },
+        options.bind_address,
+        options.web_port,
+        options.websocket_pointer_port,
+        options.websocket_video_port,
+        options.encoder,
+        options.no_gui,
+        options.gui_scale,
+        options.record_input,
+        options.config,
+        config_file,
+    )
+}
+
+/// Write a crash report to a file if any thread panics, if gui is set a dialog pointing to the
+/// report is shown as well.
+pub fn install_panic_hook(config_summary: String, logs: Arc<LogBuffer>, gui: bool) {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        let report = report(&info, &config_summary, &logs);
+        // logging is avoided here, the panic might have happened while logging
+        match write_report(&report) {
+            Ok(path) => {
+                eprintln!("A crash report has been written to {}.", path.display());
+                if gui {
+                    show_crash_dialog(path);
+                }
+            }
+            Err(err) => eprintln!("Failed to write crash report: {}", err),
+        }
+    }));
+}
+
+#[cfg(feature = "gui")]
+fn show_crash_dialog(path: PathBuf) {
+    fltk::app::awake(Box::new(move || {
+        let (width, height) = fltk::app::screen_size();
+        fltk::dialog::alert(
+            width as i32 / 2 - 200,
+            height as i32 / 2 - 100,
+            &format!(
+                "Weylus crashed, parts of it might not work anymore.\n\
+                A crash report has been written to {}, please attach it if you report this bug.",
+                path.display()
+            ),
+        );
+    }));
+}
+
+// there is no GUI to show a dialog in, the path has been printed already
+#[cfg(not(feature = "gui"))]
+fn show_crash_dialog(_path: PathBuf) {}
+
+fn report(info: &dyn Display, config_summary: &str, logs: &LogBuffer) -> String {
+    let thread = std::thread::current();
+    format!(
+        "Weylus {} on {} ({})\n\nThread '{}' {}\n\n{}\n\nConfiguration:\n{}\nLast log lines:\n{}",
+        env!("CARGO_PKG_VERSION"),
+        std::env::consts::OS,
+        std::env::consts::ARCH,
+        thread.name().unwrap_or("<unnamed>"),
+        info,
+        Backtrace::force_capture(),
+        config_summary,
+        logs.last_lines(LOG_LINES)
+            .unwrap_or_else(|| "<log is locked>\n".to_string()),
+    )
+}
+
+fn write_report(report: &str) -> std::io::Result<PathBuf> {
+    let dir = dirs::data_local_dir()
+        .map(|dir| dir.join("weylus"))
+        .unwrap_or_else(std::env::temp_dir);
+    std::fs::create_dir_all(&dir)?;
+    let secs = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    let path = dir.join(format!("crash-{}.txt", secs));
+    std::fs::write(&path, report)?;
+    Ok(path)
+}
@@ -0,0 +1,258 @@
+use std::fmt;
+use std::net::{IpAddr, SocketAddr};
+
+use tracing::{error, info, warn};
+
+use crate::network;
+use crate::video::Codec;
+#[cfg(target_os = "linux")]
+use crate::x11helper::X11Context;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Status {
+    Ok,
+    Info,
+    Warning,
+    Error,
+}
+
+/// Result of checking a single requirement.
+pub struct Check {
+    pub name: String,
+    pub status: Status,
+    pub message: String,
+}
+
+impl Check {
+    fn new(name: &str, status: Status, message: impl Into<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            status,
+            message: message.into(),
+        }
+    }
+
+    fn log(&self) {
+        match self.status {
+            Status::Ok | Status::Info => info!("{}", self),
+            Status::Warning => warn!("{}", self),
+            Status::Error => error!("{}", self),
+        }
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let status = match self.status {
+            Status::Ok => "[ OK ]",
+            Status::Info => "[INFO]",
+            Status::Warning => "[WARN]",
+            Status::Error => "[FAIL]",
+        };
+        write!(f, "{} {}: {}", status, self.name, self.message)
+    }
+}
+
+/// Check whether everything Weylus needs is available, ports are checked for the given address.
+/// The results are logged as well.
+pub fn run(bind_address: IpAddr, ports: &[(&str, u16)]) -> Vec<Check> {
+    let mut checks = Vec::new();
+    #[cfg(target_os = "linux")]
+    {
+        checks.push(check_uinput());
+        checks.extend(check_x11());
+    }
+    for (name, port) in ports {
+        checks.push(check_port(name, SocketAddr::new(bind_address, *port)));
+    }
+    checks.push(check_encoder());
+    checks.push(check_firewall(ports));
+    for check in &checks {
+        check.log();
+    }
+    checks
+}
+
+/// Summary of the checks that did not pass, None if there are no problems.
+pub fn problems(checks: &[Check]) -> Option<String> {
+    let problems: Vec<String> = checks
+        .iter()
+        .filter(|c| c.status == Status::Warning || c.status == Status::Error)
+        .map(|c| c.to_string())
+        .collect();
+    if problems.is_empty() {
+        None
+    } else {
+        Some(problems.join("\n"))
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn check_uinput() -> Check {
+    let name = "uinput";
+    if crate::input::uinput_helper::connected() {
+        return Check::new(
+            name,
+            Status::Ok,
+            "Input devices are created by the input helper.",
+        );
+    }
+    match std::fs::OpenOptions::new().write(true).open("/dev/uinput") {
+        Ok(_) => Check::new(name, Status::Ok, "/dev/uinput is writable."),
+        Err(err) => Check::new(
+            name,
+            Status::Error,
+            format!(
+                "Can not open /dev/uinput ({}), stylus and multitouch support will not work. \
+                Click \"Setup uinput\" or run `weylus setup-uinput` to give your user \
+                access.",
+                err
+            ),
+        ),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn check_x11() -> Vec<Check> {
+    let x11ctx = match X11Context::new() {
+        Some(x11ctx) => x11ctx,
+        None => {
+            return vec![Check::new(
+                "X11",
+                Status::Error,
+                "Failed to connect to the X server, is DISPLAY set?",
+            )]
+        }
+    };
+    let extensions = [
+        ("MIT-SHM", Status::Error, "required to capture the screen"),
+        ("XFIXES", Status::Warning, "required to capture the cursor"),
+        (
+            "XInputExtension",
+            Status::Warning,
+            "required to map input devices to the screen",
+        ),
+        (
+            "RANDR",
+            Status::Warning,
+            "required to handle multiple screens",
+        ),
+    ];
+    extensions
+        .iter()
+        .map(|(extension, status, purpose)| {
+            if x11ctx.has_extension(extension) {
+                Check::new(extension, Status::Ok, "X extension is available.")
+            } else {
+                Check::new(
+                    extension,
+                    *status,
+                    format!("X extension is missing, it is {}.", purpose),
+                )
+            }
+        })
+        .collect()
+}
+
+fn check_port(name: &str, addr: SocketAddr) -> Check {
+    match network::port_available(addr) {
+        Ok(_) => Check::new(name, Status::Ok, format!("{} is available.", addr)),
+        Err(err) => Check::new(
+            name,
+            Status::Error,
+            format!(
+                "Can not listen on {} ({}), choose another port or stop the program using it.",
+                addr, err
+            ),
+        ),
+    }
+}
+
+fn check_encoder() -> Check {
+    let available: Vec<String> = Codec::ALL
+        .iter()
+        .filter(|codec| crate::video::encoder_available(**codec))
+        .map(|codec| codec.to_string())
+        .collect();
+    if crate::video::encoder_available(Codec::X264) {
+        Check::new(
+            "Video encoder",
+            Status::Ok,
+            format!("Available encoders: {}.", available.join(", ")),
+        )
+    } else {
+        Check::new(
+            "Video encoder",
+            Status::Error,
+            format!(
+                "libx264 is not available, Weylus has been built without it. Available \
+                encoders: {}.",
+                available.join(", ")
+            ),
+        )
+    }
+}
+
+fn check_firewall(ports: &[(&str, u16)]) -> Check {
+    let ports = ports
+        .iter()
+        .map(|(_, port)| port.to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    #[cfg(target_os = "linux")]
+    {
+        let active: Vec<&str> = ["ufw", "firewalld"]
+            .iter()
+            .filter(|service| {
+                std::process::Command::new("systemctl")
+                    .args(&["is-active", "--quiet", service])
+                    .status()
+                    .map(|status| status.success())
+                    .unwrap_or(false)
+            })
+            .cloned()
+            .collect();
+        if active.is_empty() {
+            // other firewalls might still be in place
+            Check::new(
+                "Firewall",
+                Status::Info,
+                "Neither ufw nor firewalld is active.",
+            )
+        } else {
+            Check::new(
+                "Firewall",
+                Status::Warning,
+                format!(
+                    "{} is active, make sure it allows incoming connections on TCP ports {}.",
+                    active.join(" and "),
+                    ports
+                ),
+            )
+        }
+    }
+    #[cfg(target_os = "windows")]
+    {
+        Check::new(
+            "Firewall",
+            Status::Info,
+            format!(
+                "Allow Weylus to accept connections when Windows asks, otherwise make sure TCP \
+                ports {} are open.",
+                ports
+            ),
+        )
+    }
+    #[cfg(target_os = "macos")]
+    {
+        Check::new(
+            "Firewall",
+            Status::Info,
+            format!(
+                "If the firewall is enabled, allow incoming connections for Weylus on TCP \
+                ports {}.",
+                ports
+            ),
+        )
+    }
+}
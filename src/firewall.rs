@@ -0,0 +1,44 @@
+use std::process::Command;
+
+use tracing::info;
+
+// rules created by Weylus are replaced on every setup, so ports that are no longer used are closed
+const RULE_NAME: &str = "Weylus";
+
+/// Allow inbound TCP connections to the given ports through the Windows firewall, without such a
+/// rule the firewall silently drops the connections of tablets. Administrator privileges are
+/// requested via UAC.
+pub fn allow_ports(ports: &[u16]) -> Result<(), String> {
+    let ports: Vec<String> = ports.iter().map(|port| port.to_string()).collect();
+    let ports = ports.join(",");
+    info!(
+        "Allowing connections to ports {} through the firewall.",
+        ports
+    );
+    let netsh = format!(
+        "/c netsh advfirewall firewall delete rule name={name} >nul & \
+        netsh advfirewall firewall add rule name={name} dir=in action=allow protocol=TCP \
+        localport={ports}",
+        name = RULE_NAME,
+        ports = ports
+    );
+    // Start-Process fails if the UAC prompt is declined, this makes PowerShell exit with 1
+    let script = format!(
+        "$p = Start-Process -FilePath cmd -ArgumentList '{}' -Verb RunAs -Wait -PassThru \
+        -WindowStyle Hidden; exit $p.ExitCode",
+        netsh
+    );
+    let status = Command::new("powershell")
+        .args(&["-NoProfile", "-NonInteractive", "-Command", &script])
+        .status()
+        .map_err(|err| format!("Failed to run PowerShell: {}", err))?;
+    if !status.success() {
+        return Err(match status.code() {
+            Some(1) => "Authorization failed.".to_string(),
+            Some(code) => format!("Creating the firewall rule failed with exit code {}.", code),
+            None => "Creating the firewall rule has been terminated.".to_string(),
+        });
+    }
+    info!("Connections to ports {} are allowed now.", ports);
+    Ok(())
+}
@@ -0,0 +1,270 @@
+//! Scales and converts captured RGB frames to NV12 on the GPU via a wgpu compute shader. This
+//! saves a lot of CPU time on setups that encode in hardware but capture into system memory.
+
+use std::error::Error;
+use std::sync::mpsc;
+
+use crate::video::{PixelFormat, YUVCoefficients};
+
+// pixels every invocation of the shader handles and the size of the workgroups
+const BLOCK_WIDTH: usize = 4;
+const BLOCK_HEIGHT: usize = 2;
+const WORKGROUP_SIZE: usize = 8;
+
+pub struct GpuConverter {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    pipeline: wgpu::ComputePipeline,
+    sampler: wgpu::Sampler,
+    params: wgpu::Buffer,
+    frame: Option<FrameResources>,
+    nv12: Vec<u8>,
+}
+
+// everything that depends on the size or format of the frames
+struct FrameResources {
+    format: PixelFormat,
+    src_size: (usize, usize),
+    dst_size: (usize, usize),
+    texture: wgpu::Texture,
+    output: wgpu::Buffer,
+    readback: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+}
+
+impl GpuConverter {
+    pub fn new() -> Result<Self, Box<dyn Error>> {
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor::default());
+        let adapter = pollster::block_on(instance.request_adapter(&wgpu::RequestAdapterOptions {
+            power_preference: wgpu::PowerPreference::HighPerformance,
+            force_fallback_adapter: false,
+            compatible_surface: None,
+        }))
+        .ok_or("No GPU found.")?;
+        let (device, queue) = pollster::block_on(adapter.request_device(
+            &wgpu::DeviceDescriptor {
+                label: Some("weylus"),
+                required_features: wgpu::Features::empty(),
+                required_limits: wgpu::Limits::downlevel_defaults(),
+            },
+            None,
+        ))?;
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("rgb to nv12"),
+            source: wgpu::ShaderSource::Wgsl(include_str!("gpu_convert.wgsl").into()),
+        });
+        let pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("rgb to nv12"),
+            layout: None,
+            module: &shader,
+            entry_point: "main",
+        });
+        let sampler = device.create_sampler(&wgpu::SamplerDescriptor {
+            label: Some("rgb"),
+            mag_filter: wgpu::FilterMode::Linear,
+            min_filter: wgpu::FilterMode::Linear,
+            ..Default::default()
+        });
+        let params = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("params"),
+            size: 64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        tracing::info!("Converting frames on {}.", adapter.get_info().name);
+        Ok(Self {
+            device,
+            queue,
+            pipeline,
+            sampler,
+            params,
+            frame: None,
+            nv12: Vec::new(),
+        })
+    }
+
+    /// Whether frames can be converted to this size, every invocation of the shader writes whole
+    /// words of 4 luma samples.
+    pub fn supports(width: usize, height: usize) -> bool {
+        width % BLOCK_WIDTH == 0 && height % BLOCK_HEIGHT == 0 && width > 0 && height > 0
+    }
+
+    /// Scale a tightly packed BGRA or RGBx image to width x height and convert it to NV12.
+    /// Returns the luma plane and the plane of interleaved chroma samples, both are tightly
+    /// packed.
+    #[allow(clippy::too_many_arguments)]
+    pub fn convert(
+        &mut self,
+        src: &[u8],
+        format: PixelFormat,
+        src_width: usize,
+        src_height: usize,
+        width: usize,
+        height: usize,
+        coefficients: &YUVCoefficients,
+    ) -> Result<(&[u8], &[u8]), Box<dyn Error>> {
+        if !Self::supports(width, height) {
+            return Err(format!("Can not convert to {}x{}.", width, height).into());
+        }
+        let outdated = self.frame.as_ref().map_or(true, |frame| {
+            frame.format != format
+                || frame.src_size != (src_width, src_height)
+                || frame.dst_size != (width, height)
+        });
+        if outdated {
+            self.frame = Some(self.create_frame_resources(
+                format,
+                (src_width, src_height),
+                (width, height),
+            )?);
+        }
+        let frame = self.frame.as_ref().unwrap();
+
+        self.queue
+            .write_buffer(&self.params, 0, &params(width, height, coefficients));
+        self.queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &frame.texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            &src[..4 * src_width * src_height],
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * src_width as u32),
+                rows_per_image: Some(src_height as u32),
+            },
+            wgpu::Extent3d {
+                width: src_width as u32,
+                height: src_height as u32,
+                depth_or_array_layers: 1,
+            },
+        );
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: None,
+                timestamp_writes: None,
+            });
+            pass.set_pipeline(&self.pipeline);
+            pass.set_bind_group(0, &frame.bind_group, &[]);
+            let groups = |size: usize, block: usize| {
+                ((size / block + WORKGROUP_SIZE - 1) / WORKGROUP_SIZE) as u32
+            };
+            pass.dispatch_workgroups(groups(width, BLOCK_WIDTH), groups(height, BLOCK_HEIGHT), 1);
+        }
+        let size = nv12_size(width, height);
+        encoder.copy_buffer_to_buffer(&frame.output, 0, &frame.readback, 0, size as u64);
+        self.queue.submit(Some(encoder.finish()));
+
+        // wait for the GPU, the encoder needs the frame right away anyway
+        let slice = frame.readback.slice(..);
+        let (sender, receiver) = mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            sender.send(result).ok();
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+        receiver.recv()??;
+        self.nv12.clear();
+        self.nv12.extend_from_slice(&slice.get_mapped_range());
+        frame.readback.unmap();
+
+        Ok(self.nv12.split_at(width * height))
+    }
+
+    fn create_frame_resources(
+        &self,
+        format: PixelFormat,
+        src_size: (usize, usize),
+        dst_size: (usize, usize),
+    ) -> Result<FrameResources, Box<dyn Error>> {
+        let texture_format = match format {
+            PixelFormat::BGRA => wgpu::TextureFormat::Bgra8Unorm,
+            PixelFormat::RGBx => wgpu::TextureFormat::Rgba8Unorm,
+            _ => return Err(format!("{:?} is not a packed RGB format.", format).into()),
+        };
+        let texture = self.device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("rgb"),
+            size: wgpu::Extent3d {
+                width: src_size.0 as u32,
+                height: src_size.1 as u32,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: texture_format,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let size = nv12_size(dst_size.0, dst_size.1) as u64;
+        let output = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("nv12"),
+            size,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        });
+        let readback = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("nv12 readback"),
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let view = texture.create_view(&wgpu::TextureViewDescriptor::default());
+        let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &self.pipeline.get_bind_group_layout(0),
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Sampler(&self.sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.params.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: output.as_entire_binding(),
+                },
+            ],
+        });
+        Ok(FrameResources {
+            format,
+            src_size,
+            dst_size,
+            texture,
+            output,
+            readback,
+            bind_group,
+        })
+    }
+}
+
+// NV12 has a full resolution luma plane followed by chroma at half the resolution in each
+// dimension with two bytes per sample
+fn nv12_size(width: usize, height: usize) -> usize {
+    width * height * 3 / 2
+}
+
+// contents of the uniform buffer, see Params in gpu_convert.wgsl
+fn params(width: usize, height: usize, c: &YUVCoefficients) -> Vec<u8> {
+    let mut params = Vec::with_capacity(64);
+    for v in &[width, height, width * height / 4, 0] {
+        params.extend_from_slice(&(*v as u32).to_ne_bytes());
+    }
+    for (k, offset) in &[(c.y, c.y_offset), (c.u, 128), (c.v, 128)] {
+        for v in &[k[0], k[1], k[2], *offset] {
+            params.extend_from_slice(&(*v as f32).to_ne_bytes());
+        }
+    }
+    params
+}
@@ -0,0 +1,67 @@
+// Scales an RGB texture to the size of the video and converts it to NV12. Every invocation handles
+// a block of 4x2 pixels, this way it writes whole u32s of luma and chroma samples and no two
+// invocations touch the same word.
+
+struct Params {
+    // size of the output, the width is a multiple of 4 and the height a multiple of 2
+    width: u32,
+    height: u32,
+    // index of the first u32 of the chroma plane
+    uv_offset: u32,
+    padding: u32,
+    // coefficients scaled by 256 followed by the offset of the component, see YUVCoefficients
+    y: vec4<f32>,
+    u: vec4<f32>,
+    v: vec4<f32>,
+}
+
+@group(0) @binding(0) var src: texture_2d<f32>;
+@group(0) @binding(1) var src_sampler: sampler;
+@group(0) @binding(2) var<uniform> params: Params;
+@group(0) @binding(3) var<storage, read_write> dst: array<u32>;
+
+// bilinearly sampled RGB at the center of the output pixel x, y in 0..255
+fn rgb_at(x: u32, y: u32) -> vec3<f32> {
+    let size = vec2<f32>(f32(params.width), f32(params.height));
+    let pos = (vec2<f32>(f32(x), f32(y)) + 0.5) / size;
+    return floor(textureSampleLevel(src, src_sampler, pos, 0.0).rgb * 255.0 + 0.5);
+}
+
+// same rounding as the conversion on the CPU: ((k · rgb + 128) >> 8) + offset
+fn component(k: vec4<f32>, rgb: vec3<f32>) -> u32 {
+    return u32(clamp(floor((dot(k.xyz, rgb) + 128.0) / 256.0) + k.w, 0.0, 255.0));
+}
+
+@compute @workgroup_size(8, 8)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let x = id.x * 4u;
+    let y = id.y * 2u;
+    if (x >= params.width || y >= params.height) {
+        return;
+    }
+
+    // sums of the two 2x2 blocks that share chroma samples
+    var left = vec3<f32>(0.0);
+    var right = vec3<f32>(0.0);
+    for (var row = 0u; row < 2u; row = row + 1u) {
+        var luma = 0u;
+        for (var i = 0u; i < 4u; i = i + 1u) {
+            let rgb = rgb_at(x + i, y + row);
+            luma = luma | (component(params.y, rgb) << (8u * i));
+            if (i < 2u) {
+                left = left + rgb;
+            } else {
+                right = right + rgb;
+            }
+        }
+        dst[((y + row) * params.width + x) / 4u] = luma;
+    }
+
+    left = floor(left / 4.0);
+    right = floor(right / 4.0);
+    let chroma = component(params.u, left)
+        | (component(params.v, left) << 8u)
+        | (component(params.u, right) << 16u)
+        | (component(params.v, right) << 24u);
+    dst[params.uv_offset + (y / 2u * params.width + x) / 4u] = chroma;
+}
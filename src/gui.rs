@@ -1,18 +1,23 @@
 use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
 use std::iter::Iterator;
-use std::net::{IpAddr, SocketAddr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::rc::Rc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{mpsc, Arc, Mutex};
 use tokio::sync::mpsc as mpsc_tokio;
 use tracing::{error, info};
 
 use fltk::{
     app::App,
+    browser::HoldBrowser,
     button::{Button, CheckButton},
     enums::Shortcut,
     frame::Frame,
+    group::{Pack, PackType, Scroll},
     input::{Input, IntInput},
     menu::{Choice, MenuFlag},
     output::Output,
@@ -21,17 +26,47 @@ use fltk::{
     window::Window,
 };
 
-#[cfg(not(target_os = "windows"))]
-use pnet::datalink;
+use crate::config::{random_password, Config, ConfigFile, Options};
+use crate::diagnostics;
+use crate::input::mapping::InputMapping;
+use crate::input::smoothing::Smoothing;
+use crate::logs::LogBuffer;
+use crate::metrics::Metrics;
+use crate::network;
+use crate::screen_capture::rotation::Rotation;
+use crate::stream_handler::{StreamMode, VideoStats};
+use crate::tcp_options::TcpOptions;
+use crate::video::{Codec, ColorRange, ColorSpace, ScalingFilter};
+use crate::web::{Gui2WebMessage, WakePage, Web2GuiMessage};
+use crate::websocket::{Gui2WsMessage, Ws2GuiMessage};
 
-use crate::web::{Gui2WebMessage, Web2GuiMessage};
-use crate::websocket::Gui2WsMessage;
+#[cfg(target_os = "linux")]
+use tracing::warn;
 
+#[cfg(any(target_os = "linux", target_os = "windows"))]
+use crate::config::State;
+#[cfg(target_os = "linux")]
+use crate::screen_capture::linux::{ScreenCaptureX11, X11CaptureOptions};
+#[cfg(target_os = "linux")]
+use crate::screen_capture::{self, ScreenCapture};
 #[cfg(target_os = "linux")]
-use crate::x11helper::{Capturable, X11Context};
+use crate::x11helper::{Capturable, CapturableId, CaptureTarget, X11Context};
 
-pub fn run(log_receiver: mpsc::Receiver<String>) {
-    fltk::app::lock().unwrap();
+/// Run the GUI until it is closed, fails if FLTK can not be initialized.
+pub fn run(
+    log_receiver: mpsc::Receiver<String>,
+    options: &Options,
+    config_file: &ConfigFile,
+    log_buffer: Arc<LogBuffer>,
+) -> Result<(), Box<dyn Error>> {
+    // FLTK exits right away if it can not open the display, so this is checked first
+    #[cfg(target_os = "linux")]
+    X11Context::new().ok_or("Failed to open the X11 display.")?;
+    if let Some(scale) = options.gui_scale {
+        // read by FLTK when the screens are initialized
+        std::env::set_var("FLTK_SCALING_FACTOR", scale.to_string());
+    }
+    fltk::app::lock().map_err(|err| format!("Failed to initialize FLTK: {:?}", err))?;
     fltk::app::unlock();
     let width = 200;
     let height = 30;
@@ -39,52 +74,208 @@ pub fn run(log_receiver: mpsc::Receiver<String>) {
 
     let app = App::default();
     let mut wind = Window::default()
-        .with_size(660, 600)
+        .with_size(660, 980)
         .center_screen()
         .with_label(&format!("Weylus - {}", env!("CARGO_PKG_VERSION")));
 
+    // the settings are arranged in two columns, labels of the left column are drawn to the left of
+    // the inputs
+    let mut pack_left = Pack::new(200, 30, width, 0, "");
+    pack_left.set_spacing(padding);
+
+    let mut pack_password = Pack::default().with_size(width, height);
+    pack_password.set_type(PackType::Horizontal);
+    pack_password.set_spacing(4);
+
     let input_password = Input::default()
-        .with_pos(200, 30)
-        .with_size(width, height)
+        .with_size(width - height - 4, height)
         .with_label("Password");
+    if let Some(password) = &options.password {
+        input_password.set_value(password);
+    }
+
+    let mut but_generate_password = Button::default()
+        .with_size(height, height)
+        .with_label("@reload");
+    but_generate_password.set_tooltip("Generate a random password.");
+    {
+        let input_password = input_password.clone();
+        but_generate_password.set_callback(Box::new(move || {
+            input_password.set_value(&random_password());
+        }));
+    }
+
+    pack_password.end();
+
+    let mut choice_interface = Choice::default()
+        .with_size(width, height)
+        .with_label("Network interface");
+    choice_interface.set_tooltip(
+        "Pick the network your tablet is connected to, this sets the bind address. \
+        Localhost only is useful to access Weylus through a tunnel or a USB connection.",
+    );
+    let interfaces = interface_choices();
+    for (label, _) in &interfaces {
+        choice_interface.add(
+            &escape_menu_label(label),
+            Shortcut::None,
+            MenuFlag::Normal,
+            Box::new(|| ()),
+        );
+    }
 
     let input_bind_addr = Input::default()
         .with_size(width, height)
-        .below_of(&input_password, padding)
         .with_label("Bind Address");
-    input_bind_addr.set_value("0.0.0.0");
+    input_bind_addr.set_value(&options.bind_address.to_string());
+    select_interface(&mut choice_interface, &interfaces, &input_bind_addr.value());
+    {
+        let interfaces = interfaces.clone();
+        let choice = choice_interface.clone();
+        let mut input_bind_addr = input_bind_addr.clone();
+        choice_interface.set_callback(Box::new(move || {
+            if let Some((_, addr)) = interfaces.get(choice.value() as usize) {
+                input_bind_addr.set_value(&addr.to_string());
+                // validates the address
+                input_bind_addr.do_callback();
+            }
+        }));
+    }
 
     let input_port = IntInput::default()
         .with_size(width, height)
-        .below_of(&input_bind_addr, padding)
         .with_label("Port");
-    input_port.set_value("1701");
+    input_port.set_value(&options.web_port.to_string());
 
     let input_ws_pointer_port = IntInput::default()
         .with_size(width, height)
-        .below_of(&input_port, padding)
         .with_label("Websocket Pointer Port");
-    input_ws_pointer_port.set_value("9001");
+    input_ws_pointer_port.set_value(&options.websocket_pointer_port.to_string());
 
     let input_ws_video_port = IntInput::default()
         .with_size(width, height)
-        .below_of(&input_ws_pointer_port, padding)
         .with_label("Websocket Video Port");
-    input_ws_video_port.set_value("9002");
+    input_ws_video_port.set_value(&options.websocket_video_port.to_string());
 
     let input_limit_screen_updates = IntInput::default()
         .with_size(width, height)
-        .below_of(&input_ws_video_port, padding)
         .with_label("Limit screen updates\n(milliseconds)");
     input_limit_screen_updates.set_value("0");
 
+    let mut choice_rotation = Choice::default()
+        .with_size(width, height)
+        .with_label("Rotate video");
+    choice_rotation.set_tooltip(
+        "Rotate the video clockwise, this is useful if you hold your tablet in portrait mode. \
+        Auto rotates the video if the orientation of your tablet does not match the orientation \
+        of the captured screen or window. If all clients share the same video it follows the \
+        client that changed its orientation last, view-only clients are ignored.",
+    );
+    for label in &["0°", "90°", "180°", "270°", "Auto"] {
+        choice_rotation.add(label, Shortcut::None, MenuFlag::Normal, Box::new(|| ()));
+    }
+    choice_rotation.set_value(match options.rotation {
+        Rotation::None => 0,
+        Rotation::Rotate90 => 1,
+        Rotation::Rotate180 => 2,
+        Rotation::Rotate270 => 3,
+        Rotation::Auto => 4,
+    });
+
+    let mut choice_scaling_filter = Choice::default()
+        .with_size(width, height)
+        .with_label("Scaling quality");
+    choice_scaling_filter.set_tooltip(
+        "Filter used when converting and scaling the captured image. Fast saves CPU time, \
+        Bicubic and Lanczos keep text more readable.",
+    );
+    for label in &["Fast", "Bicubic", "Lanczos"] {
+        choice_scaling_filter.add(label, Shortcut::None, MenuFlag::Normal, Box::new(|| ()));
+    }
+    choice_scaling_filter.set_value(match options.scaling_filter {
+        ScalingFilter::FastBilinear => 0,
+        ScalingFilter::Bicubic => 1,
+        ScalingFilter::Lanczos => 2,
+    });
+
+    let mut choice_colors = Choice::default()
+        .with_size(width, height)
+        .with_label("Colors");
+    choice_colors.set_tooltip(
+        "Colorspace and range of the video. Try another one if colors on your tablet look \
+        washed out or too dark.",
+    );
+    for label in &[
+        "BT.709 limited range",
+        "BT.709 full range",
+        "BT.601 limited range",
+        "BT.601 full range",
+    ] {
+        choice_colors.add(label, Shortcut::None, MenuFlag::Normal, Box::new(|| ()));
+    }
+    choice_colors.set_value(match (options.color_space, options.color_range) {
+        (ColorSpace::BT709, ColorRange::Limited) => 0,
+        (ColorSpace::BT709, ColorRange::Full) => 1,
+        (ColorSpace::BT601, ColorRange::Limited) => 2,
+        (ColorSpace::BT601, ColorRange::Full) => 3,
+    });
+
+    let mut choice_encoder = Choice::default()
+        .with_size(width, height)
+        .with_label("Video encoder");
+    choice_encoder.set_tooltip(
+        "x264 encodes in software and works everywhere, VAAPI, NVENC and AMF use the GPU \
+        (Intel/AMD on Linux, Nvidia and AMD on Windows respectively) and save CPU time. MJPEG \
+        sends every frame as image, this needs a lot of bandwidth but is easy to decode for slow \
+        tablets.",
+    );
+    add_encoder_labels(&mut choice_encoder, "Auto");
+    choice_encoder.set_value(match options.encoder {
+        Some(encoder) => Codec::ALL
+            .iter()
+            .position(|codec| *codec == encoder)
+            .map_or(0, |i| i + 1) as i32,
+        None => 0,
+    });
+
+    let mut choice_smoothing = Choice::default()
+        .with_size(width, height)
+        .with_label("Smooth strokes");
+    choice_smoothing.set_tooltip(
+        "Filter jitter from pen and touch input, e.g. if strokes look shaky on an unreliable \
+        Wi-Fi connection. Stronger smoothing adds a little lag at slow speeds.",
+    );
+    for label in &["Off", "Low", "Medium", "High"] {
+        choice_smoothing.add(label, Shortcut::None, MenuFlag::Normal, Box::new(|| ()));
+    }
+    choice_smoothing.set_value(match options.stroke_smoothing {
+        Smoothing::Off => 0,
+        Smoothing::Low => 1,
+        Smoothing::Medium => 2,
+        Smoothing::High => 3,
+    });
+
+    // extra space above the start button
+    Frame::default().with_size(width, padding);
+
     let but_toggle = Button::default()
         .with_size(width, height)
-        .below_of(&input_limit_screen_updates, 3 * padding)
         .with_label("Start");
 
+    let mut but_export_logs = Button::default()
+        .with_size(width, height)
+        .with_label("Export logs");
+    but_export_logs.set_tooltip(
+        "Save recent log messages and information about your system to a zip file, \
+        please attach it to bug reports. Also available at /logs on the web server.",
+    );
+
+    pack_left.end();
+
+    let mut pack_right = Pack::new(430, 30, width, 0, "");
+    pack_right.set_spacing(padding);
+
     let mut label_enable_input = Frame::default()
-        .with_pos(430, 30)
         .with_size(width, 15)
         .with_label("Enabled input methods:");
     label_enable_input.set_tooltip(
@@ -93,34 +284,35 @@ pub fn run(log_receiver: mpsc::Receiver<String>) {
         want to use a pen/stylus.",
     );
 
+    let mut pack_enable_input = Pack::default().with_size(width, height);
+    pack_enable_input.set_type(PackType::Horizontal);
+    pack_enable_input.set_spacing(2);
+
     let check_enable_mouse = CheckButton::default()
         .with_size(64, height)
-        .below_of(&label_enable_input, 0)
         .with_label("Mouse");
     check_enable_mouse.set_checked(true);
 
     let check_enable_stylus = CheckButton::default()
         .with_size(64, height)
-        .right_of(&check_enable_mouse, 2)
         .with_label("Stylus");
     check_enable_stylus.set_checked(true);
 
     let check_enable_touch = CheckButton::default()
         .with_size(63, height)
-        .right_of(&check_enable_stylus, 2)
         .with_label("Touch");
     check_enable_touch.set_checked(true);
 
+    pack_enable_input.end();
+
     let mut label_only_linux = Frame::default()
         .with_size(width, 15)
-        .below_of(&check_enable_mouse, 5)
         .with_label("Available only on Linux:");
     #[cfg(target_os = "linux")]
     label_only_linux.hide();
 
     #[allow(unused_mut)]
     let mut check_stylus = CheckButton::default()
-        .with_pos(430, padding + 3 * height)
         .with_size(width, height)
         .with_label("Stylus && Touch Simulation");
     check_stylus.set_tooltip(
@@ -138,7 +330,6 @@ pub fn run(log_receiver: mpsc::Receiver<String>) {
 
     let mut check_faster_screencapture = CheckButton::default()
         .with_size(width, height)
-        .below_of(&check_stylus, padding)
         .with_label("Better screen capturing");
 
     check_faster_screencapture.set_tooltip(
@@ -149,12 +340,11 @@ pub fn run(log_receiver: mpsc::Receiver<String>) {
     #[allow(unused_mut)]
     let mut check_capture_cursor = CheckButton::default()
         .with_size(width, height)
-        .below_of(&check_faster_screencapture, padding)
         .with_label("Capture Cursor");
 
     #[cfg(target_os = "linux")]
     {
-        check_capture_cursor.set_checked(false);
+        check_capture_cursor.set_checked(options.capture_cursor);
         check_faster_screencapture.set_checked(true);
     }
     #[cfg(not(target_os = "linux"))]
@@ -163,48 +353,122 @@ pub fn run(log_receiver: mpsc::Receiver<String>) {
         check_capture_cursor.deactivate();
     }
 
-    let label_capturable_choice = Frame::default()
+    Frame::default()
         .with_size(width, height)
-        .below_of(&check_capture_cursor, padding)
         .with_label("Capture:");
 
     #[allow(unused_mut)]
-    let mut choice_capturable = Choice::default()
-        .with_size(width, height)
-        .below_of(&label_capturable_choice, 0);
-    #[cfg(not(target_os = "linux"))]
+    let mut input_capturable_filter = Input::default().with_size(width, height);
+    input_capturable_filter.set_tooltip(
+        "Only list windows and screens whose title, class or geometry contains this text.",
+    );
+    input_capturable_filter.set_trigger(fltk::CallbackTrigger::Changed);
+    #[cfg(not(any(target_os = "linux", target_os = "windows")))]
+    input_capturable_filter.deactivate();
+
+    #[allow(unused_mut)]
+    let mut choice_capturable = Choice::default().with_size(width, height);
+    #[cfg(not(any(target_os = "linux", target_os = "windows")))]
     choice_capturable.deactivate();
 
     let mut but_update_capturables = Button::default()
         .with_size(width, height)
-        .below_of(&choice_capturable, padding)
         .with_label("Refresh");
     but_update_capturables.set_tooltip(
         "Refresh list of capturable objects, e. g. if you opened a \
         new window after starting Weylus.",
     );
-    #[cfg(not(target_os = "linux"))]
+    #[cfg(not(any(target_os = "linux", target_os = "windows")))]
     but_update_capturables.deactivate();
 
-    let output_buf = TextBuffer::default();
-    let output = TextDisplay::default(output_buf)
-        .with_size(600, 6 * height)
-        .with_pos(30, 600 - 30 - 6 * height);
+    // separates the capture settings from the rest, the spacing of the pack is added twice
+    Frame::default().with_size(width, 0);
 
-    let mut output_server_addr = Output::default()
-        .with_size(500, height)
-        .with_pos(130, 600 - 30 - 7 * height - 3 * padding)
-        .with_label("Connect your\ntablet to:");
-    output_server_addr.hide();
+    let mut check_pointer_roi = CheckButton::default()
+        .with_size(width, height)
+        .with_label("Sharpen near pointer");
+    check_pointer_roi.set_tooltip(
+        "Encode the area around the pen or cursor with higher quality \
+        at the expense of the rest of the screen.",
+    );
+    check_pointer_roi.set_checked(options.pointer_roi);
+
+    let mut check_long_press = CheckButton::default()
+        .with_size(width, height)
+        .with_label("Long press: right click");
+    check_long_press.set_tooltip(
+        "Holding a finger in place and lifting it produces a right click, \
+        e. g. to open context menus. Delay and tolerance can be set in the \
+        configuration file.",
+    );
+    check_long_press.set_checked(options.long_press);
+
+    let frame_latency = Frame::default().with_size(width, height);
+
+    let mut pack_diagnostics = Pack::default().with_size(width, height);
+    pack_diagnostics.set_type(PackType::Horizontal);
+    pack_diagnostics.set_spacing(4);
+
+    let but_diagnostics = Button::default()
+        .with_size(width / 2 - 2, height)
+        .with_label("Diagnostics");
+
+    let mut but_setup_uinput = Button::default()
+        .with_size(width / 2 - 2, height)
+        .with_label("Setup uinput");
+    but_setup_uinput.set_tooltip(
+        "Give your user write access to /dev/uinput, which is required for \
+        stylus and touch simulation. Asks for your password.",
+    );
+    #[cfg(not(target_os = "linux"))]
+    but_setup_uinput.deactivate();
+
+    pack_diagnostics.end();
+
+    #[allow(unused_mut)]
+    let mut frame_preview = Frame::default()
+        .with_size(width, 100)
+        .with_label("No preview");
+    frame_preview.set_tooltip("Preview of the selected window or screen.");
+    #[cfg(not(target_os = "linux"))]
+    frame_preview.hide();
+
+    pack_right.end();
+
+    // below the columns, only the log grows if the window is resized
+    let mut browser_clients = HoldBrowser::default()
+        .with_size(390, 3 * height)
+        .with_pos(130, 630)
+        .with_label("Connected\nclients:");
+    browser_clients.set_align(fltk::Align::Left);
+    browser_clients.set_column_char('\t');
+    browser_clients.set_column_widths(&[120, 110, 110]);
 
-    let mut but_show_qr = Button::default()
-        .with_size(120, height)
-        .with_pos(but_toggle.x() - 165, but_toggle.y())
-        .with_label("Show QR Code");
+    let mut but_disconnect = Button::default()
+        .with_size(110, height)
+        .right_of(&browser_clients, padding)
+        .with_label("Disconnect");
+    but_disconnect.set_tooltip("Close all connections of the selected client.");
 
-    but_show_qr.hide();
+    // a row with the URL and buttons to copy it or show it as QR code for every address browsers
+    // can connect to, the rows are added when the server is started
+    let mut scroll_urls = Scroll::default()
+        .with_size(510, 2 * height + padding)
+        .below_of(&browser_clients, 2 * padding)
+        .with_label("Connect your\ntablet to:");
+    scroll_urls.set_align(fltk::Align::Left);
+    let mut pack_urls = Pack::new(scroll_urls.x(), scroll_urls.y(), 490, 0, "");
+    pack_urls.set_spacing(padding);
+    pack_urls.end();
+    scroll_urls.end();
+    scroll_urls.hide();
+
+    let output_buf = TextBuffer::default();
+    let mut output = TextDisplay::default(output_buf)
+        .with_size(600, 4 * height)
+        .with_pos(30, scroll_urls.y() + scroll_urls.height() + 2 * padding);
 
-    wind.make_resizable(true);
+    wind.resizable(&mut output);
     wind.end();
     wind.show();
 
@@ -215,14 +479,22 @@ pub fn run(log_receiver: mpsc::Receiver<String>) {
     let choice_capturable_ref = Rc::new(RefCell::new(choice_capturable));
     let check_faster_screencapture_ref = Rc::new(RefCell::new(check_faster_screencapture));
     let check_capture_cursor_ref = Rc::new(RefCell::new(check_capture_cursor));
-    let output_server_addr = Arc::new(Mutex::new(output_server_addr));
+    let scroll_urls = Arc::new(Mutex::new(scroll_urls));
     let output = Arc::new(Mutex::new(output));
+    let frame_latency = Arc::new(Mutex::new(frame_latency));
+    let choice_encoder = Arc::new(Mutex::new(choice_encoder));
+    let but_diagnostics_ref = Rc::new(RefCell::new(but_diagnostics));
+    let browser_clients = Arc::new(Mutex::new(browser_clients));
+    #[cfg(target_os = "linux")]
+    let frame_preview = Arc::new(Mutex::new(frame_preview));
+    // addresses of the clients in the order they are listed
+    let client_ips = Arc::new(Mutex::new(Vec::<IpAddr>::new()));
 
     let qr_popup_ref = Rc::new(RefCell::new(Window::default()));
     let qr_img_frame_ref = Rc::new(RefCell::new(Frame::new(0, 0, 0, 0, "")));
     qr_popup_ref.borrow().end();
 
-    let (sender_ws2gui, _receiver_ws2gui) = mpsc::channel();
+    let (sender_ws2gui, receiver_ws2gui) = mpsc::channel();
     let (sender_web2gui, receiver_web2gui) = mpsc::channel();
 
     std::thread::spawn(move || {
@@ -232,14 +504,134 @@ pub fn run(log_receiver: mpsc::Receiver<String>) {
         }
     });
 
+    // probing the encoders takes a moment, so it is done in the background, starting the servers
+    // waits for the result if Auto is selected
+    let best_encoder = Arc::new(Mutex::new(Option::<Codec>::None));
+    {
+        let best_encoder = best_encoder.clone();
+        let choice_encoder = choice_encoder.clone();
+        std::thread::spawn(move || {
+            let mut best_encoder = best_encoder.lock().unwrap();
+            let codec = *best_encoder.get_or_insert_with(crate::video::best_encoder);
+            let mut choice_encoder = choice_encoder.lock().unwrap();
+            let selected = choice_encoder.value();
+            choice_encoder.clear();
+            add_encoder_labels(
+                &mut choice_encoder,
+                &format!("Auto ({})", encoder_label(codec)),
+            );
+            choice_encoder.set_value(selected);
+            choice_encoder.redraw();
+        });
+    }
+
+    let metrics = Metrics::new();
+    {
+        let metrics = metrics.clone();
+        std::thread::spawn(move || loop {
+            std::thread::sleep(Duration::from_secs(1));
+            let label = match metrics.latency() {
+                Some(latency) => format!("Latency: {:.0} ms", latency.end_to_end),
+                None => "".to_string(),
+            };
+            let mut frame_latency = frame_latency.lock().unwrap();
+            if frame_latency.label() != label {
+                frame_latency.set_label(&label);
+                frame_latency.redraw();
+            }
+        });
+    }
+
+    let idle_timeout_reached = Arc::new(AtomicBool::new(false));
+    let approval_requests = Arc::new(Mutex::new(Vec::<(SocketAddr, mpsc::Sender<bool>)>::new()));
+    {
+        let browser_clients = browser_clients.clone();
+        let client_ips = client_ips.clone();
+        let idle_timeout_reached = idle_timeout_reached.clone();
+        let approval_requests = approval_requests.clone();
+        std::thread::spawn(move || {
+            let mut clients = HashMap::<IpAddr, ClientInfo>::new();
+            loop {
+                match receiver_ws2gui.recv_timeout(Duration::from_secs(1)) {
+                    Ok(Ws2GuiMessage::Connected(addr)) => {
+                        clients
+                            .entry(addr.ip())
+                            .or_insert_with(|| ClientInfo {
+                                connections: HashSet::new(),
+                                connected_since: Instant::now(),
+                                video: None,
+                            })
+                            .connections
+                            .insert(addr);
+                    }
+                    Ok(Ws2GuiMessage::Disconnected(addr)) => {
+                        if let Some(client) = clients.get_mut(&addr.ip()) {
+                            client.connections.remove(&addr);
+                            if client.connections.is_empty() {
+                                clients.remove(&addr.ip());
+                            }
+                        }
+                    }
+                    Ok(Ws2GuiMessage::VideoStats(addr, stats)) => {
+                        if let Some(client) = clients.get_mut(&addr.ip()) {
+                            client.video = Some(stats);
+                        }
+                    }
+                    Ok(Ws2GuiMessage::IdleTimeout) => {
+                        // the server is stopped by the main thread as if Stop had been pressed
+                        idle_timeout_reached.store(true, Ordering::Relaxed);
+                        fltk::app::awake(Box::new(|| ()));
+                    }
+                    Ok(Ws2GuiMessage::ApprovalRequested(addr, reply)) => {
+                        approval_requests.lock().unwrap().push((addr, reply));
+                        fltk::app::awake(Box::new(|| ()));
+                    }
+                    Err(mpsc::RecvTimeoutError::Timeout) => (),
+                    Err(mpsc::RecvTimeoutError::Disconnected) => return,
+                }
+                let mut ips: Vec<IpAddr> = clients.keys().cloned().collect();
+                ips.sort();
+                let mut browser_clients = browser_clients.lock().unwrap();
+                let selected = browser_clients.value();
+                // keep the selection if the list changes
+                let selected_ip = if selected > 0 {
+                    client_ips
+                        .lock()
+                        .unwrap()
+                        .get(selected as usize - 1)
+                        .cloned()
+                } else {
+                    None
+                };
+                browser_clients.clear();
+                for (i, ip) in ips.iter().enumerate() {
+                    browser_clients.add(&clients[ip].describe(ip));
+                    if Some(*ip) == selected_ip {
+                        browser_clients.select(i as i32 + 1);
+                    }
+                }
+                browser_clients.redraw();
+                *client_ips.lock().unwrap() = ips;
+            }
+        });
+    }
+
+    // set if a client asked to start the server via the wake page, the main thread asks for
+    // confirmation
+    let start_requested = Arc::new(Mutex::new(Option::<SocketAddr>::None));
     {
-        let output_server_addr = output_server_addr.clone();
+        let scroll_urls = scroll_urls.clone();
+        let start_requested = start_requested.clone();
         std::thread::spawn(move || {
             while let Ok(message) = receiver_web2gui.recv() {
                 match message {
                     Web2GuiMessage::Shutdown => {
-                        let mut output_server_addr = output_server_addr.lock().unwrap();
-                        output_server_addr.hide();
+                        let mut scroll_urls = scroll_urls.lock().unwrap();
+                        scroll_urls.hide();
+                    }
+                    Web2GuiMessage::StartRequested(addr) => {
+                        start_requested.lock().unwrap().replace(addr);
+                        fltk::app::awake(Box::new(|| ()));
                     }
                 }
             }
@@ -250,55 +642,138 @@ pub fn run(log_receiver: mpsc::Receiver<String>) {
     let mut x11_context = X11Context::new().unwrap();
     #[cfg(target_os = "linux")]
     let current_capturable = Rc::new(RefCell::new(Option::<Capturable>::None));
+    // what the running servers capture, a newly selected capturable is captured right away
+    #[cfg(target_os = "linux")]
+    let capture_target = Rc::new(RefCell::new(Option::<Arc<CaptureTarget>>::None));
+    // the preview thread picks up newly selected capturables from here
+    #[cfg(target_os = "linux")]
+    let preview_capturable = Arc::new(Mutex::new(Option::<Capturable>::None));
+
+    #[cfg(target_os = "linux")]
+    {
+        let preview_capturable = preview_capturable.clone();
+        let capture_options = X11CaptureOptions {
+            xcomposite: options.xcomposite,
+            hide_own_windows: options.hide_own_windows,
+            ..Default::default()
+        };
+        std::thread::spawn(move || {
+            let mut capture: Option<ScreenCaptureX11> = None;
+            loop {
+                std::thread::sleep(Duration::from_secs(1));
+                if let Some(capturable) = preview_capturable.lock().unwrap().take() {
+                    capture = match ScreenCaptureX11::new(capturable, capture_options.clone()) {
+                        Ok(capture) => Some(capture),
+                        Err(err) => {
+                            warn!("Failed to capture preview: {}", err);
+                            None
+                        }
+                    };
+                }
+                let thumbnail = capture.as_mut().and_then(|capture| {
+                    capture.capture();
+                    screen_capture::thumbnail(capture, 200, 100)
+                });
+                let mut frame_preview = frame_preview.lock().unwrap();
+                match thumbnail {
+                    Some((rgb, width, height)) => {
+                        let image =
+                            fltk::image::RgbImage::new(&rgb, width as u32, height as u32, 3);
+                        frame_preview.set_image(&image);
+                        frame_preview.set_label("");
+                    }
+                    None => frame_preview.set_label("No preview"),
+                }
+                frame_preview.redraw();
+            }
+        });
+    }
 
     #[cfg(target_os = "linux")]
     {
         let current_capturable = current_capturable.clone();
+        let capturables = Rc::new(RefCell::new(Vec::<Capturable>::new()));
+        // selected instead of the first capturable the next time the list is filled, initially
+        // this is the capturable used the last time Weylus ran
+        let saved_capturable = Rc::new(RefCell::new(State::load().capture));
 
-        {
+        let select_capturable = {
+            let current_capturable = current_capturable.clone();
+            let capture_target = capture_target.clone();
+            Rc::new(move |c: &Capturable| {
+                current_capturable.replace(Some(c.clone()));
+                preview_capturable.lock().unwrap().replace(c.clone());
+                if let Some(target) = capture_target.borrow().as_ref() {
+                    info!("Switching capture to {}.", c);
+                    target.set(c.clone());
+                    State {
+                        capture: Some(CapturableId::new(c)),
+                    }
+                    .save();
+                }
+            })
+        };
+
+        // fill the menu with the capturables matching the filter, if select_first is set the first
+        // match becomes the current capturable
+        let populate_capturables = {
             let choice_capturable_ref = choice_capturable_ref.clone();
+            let capturables = capturables.clone();
+            let saved_capturable = saved_capturable.clone();
+            let input_capturable_filter = input_capturable_filter.clone();
+            Rc::new(move |select_first: bool| {
+                let mut choice_capturable = choice_capturable_ref.borrow_mut();
+                choice_capturable.clear();
+                let filter = input_capturable_filter.value().to_lowercase();
+                let (labels, matching): (Vec<String>, Vec<Capturable>) = capturables
+                    .borrow()
+                    .iter()
+                    .map(|c| (capturable_label(c), c.clone()))
+                    .filter(|(label, _)| label.to_lowercase().contains(&filter))
+                    .unzip();
+                let selected = if select_first {
+                    saved_capturable
+                        .borrow_mut()
+                        .take()
+                        .and_then(|id| id.find(&matching))
+                        .unwrap_or(0)
+                } else {
+                    0
+                };
+                for (label, c) in labels.iter().zip(matching.iter()) {
+                    let select_capturable = select_capturable.clone();
+                    let c = c.clone();
+                    choice_capturable.add(
+                        &escape_menu_label(label),
+                        Shortcut::None,
+                        MenuFlag::Normal,
+                        Box::new(move || select_capturable(&c)),
+                    );
+                }
+                if select_first && selected < matching.len() {
+                    select_capturable(&matching[selected]);
+                    choice_capturable.set_value(selected as i32);
+                }
+            })
+        };
+
+        {
+            let populate_capturables = populate_capturables.clone();
             but_update_capturables_ref
                 .borrow_mut()
                 .set_callback(Box::new(move || {
-                    let mut choice_capturable = choice_capturable_ref.borrow_mut();
-                    choice_capturable.clear();
-                    let capturables = x11_context.capturables().unwrap();
-                    {
-                        let mut current_capturable = current_capturable.borrow_mut();
-                        if current_capturable.is_none() {
-                            let first_capturable = capturables[0].clone();
-                            current_capturable.replace(first_capturable);
-                        }
-                    }
-                    for c in capturables {
-                        let current_capturable = current_capturable.clone();
-                        let chars = c
-                            .name()
-                            .replace("\\", "\\\\")
-                            .replace("/", "\\/")
-                            .replace("_", "\\_")
-                            .replace("&", "\\&");
-                        let chars = chars.chars();
-                        let mut name = String::new();
-                        for (i, c) in chars.enumerate() {
-                            if i >= 32 {
-                                name.push_str("...");
-                                break;
-                            }
-                            name.push(c);
-                        }
-                        choice_capturable.add(
-                            &name,
-                            Shortcut::None,
-                            MenuFlag::Normal,
-                            Box::new(move || {
-                                current_capturable.replace(Some(c.clone()));
-                            }),
-                        );
+                    // windows may have been recreated since, the current capturable is looked up
+                    // again in the new list
+                    if let Some(current) = current_capturable.borrow().as_ref() {
+                        saved_capturable.replace(Some(CapturableId::new(current)));
                     }
+                    capturables.replace(x11_context.capturables().unwrap());
+                    populate_capturables(true);
                 }));
         }
 
+        input_capturable_filter.set_callback(Box::new(move || populate_capturables(true)));
+
         but_update_capturables_ref.borrow_mut().do_callback();
 
         let check_faster_screencapture_ref = check_faster_screencapture_ref.clone();
@@ -323,13 +798,401 @@ pub fn run(log_receiver: mpsc::Receiver<String>) {
             }));
     }
 
-    let mut sender_gui2ws: Option<mpsc::Sender<Gui2WsMessage>> = None;
+    // windows are captured via PrintWindow, the whole screen like everywhere else
+    #[cfg(target_os = "windows")]
+    {
+        use crate::screen_capture::windows;
+
+        let populate_windows = {
+            let choice_capturable_ref = choice_capturable_ref.clone();
+            let input_capturable_filter = input_capturable_filter.clone();
+            Rc::new(move || {
+                let mut choice_capturable = choice_capturable_ref.borrow_mut();
+                choice_capturable.clear();
+                choice_capturable.add(
+                    "Whole screen",
+                    Shortcut::None,
+                    MenuFlag::Normal,
+                    Box::new(|| windows::set_target(None)),
+                );
+                let filter = input_capturable_filter.value().to_lowercase();
+                let mut selected = None;
+                for (i, window) in windows::windows()
+                    .into_iter()
+                    .filter(|window| window.title.to_lowercase().contains(&filter))
+                    .enumerate()
+                {
+                    if Some(window.hwnd) == windows::target() {
+                        selected = Some(i + 1);
+                    }
+                    let label: String = window.title.chars().take(64).collect();
+                    choice_capturable.add(
+                        &escape_menu_label(&label),
+                        Shortcut::None,
+                        MenuFlag::Normal,
+                        Box::new(move || windows::set_target(Some(&window))),
+                    );
+                }
+                // the captured window is kept if it is still listed
+                match selected {
+                    Some(selected) => choice_capturable.set_value(selected as i32),
+                    None => {
+                        choice_capturable.set_value(0);
+                        if windows::target().is_some() {
+                            windows::set_target(None);
+                        }
+                    }
+                }
+            })
+        };
+
+        {
+            let populate_windows = populate_windows.clone();
+            but_update_capturables_ref
+                .borrow_mut()
+                .set_callback(Box::new(move || populate_windows()));
+        }
+        input_capturable_filter.set_callback(Box::new(move || populate_windows()));
+        but_update_capturables_ref.borrow_mut().do_callback();
+    }
+
+    // show problems found by the diagnostics, if always_show is set the results are shown even if
+    // everything is fine
+    let run_diagnostics = {
+        let wind_ref = wind_ref.clone();
+        let input_bind_addr = input_bind_addr.clone();
+        let input_port = input_port.clone();
+        let input_ws_pointer_port = input_ws_pointer_port.clone();
+        let input_ws_video_port = input_ws_video_port.clone();
+        Rc::new(move |always_show: bool| {
+            let bind_addr = input_bind_addr
+                .value()
+                .parse()
+                .unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+            let ports: Vec<(&str, u16)> = [
+                ("Web port", &input_port),
+                ("Websocket pointer port", &input_ws_pointer_port),
+                ("Websocket video port", &input_ws_video_port),
+            ]
+            .iter()
+            .filter_map(|(name, input)| input.value().parse().ok().map(|port| (*name, port)))
+            .collect();
+            let checks = diagnostics::run(bind_addr, &ports);
+            let text = match diagnostics::problems(&checks) {
+                Some(problems) => problems,
+                None if always_show => checks
+                    .iter()
+                    .map(|c| c.to_string())
+                    .collect::<Vec<_>>()
+                    .join("\n"),
+                None => return,
+            };
+            let wind = wind_ref.borrow();
+            fltk::dialog::message(
+                wind.x() + wind.width() / 2 - 200,
+                wind.y() + wind.height() / 2 - 100,
+                &text,
+            );
+        })
+    };
+    {
+        let run_diagnostics = run_diagnostics.clone();
+        but_diagnostics_ref
+            .borrow_mut()
+            .set_callback(Box::new(move || run_diagnostics(true)));
+    }
+
+    #[cfg(target_os = "linux")]
+    {
+        let wind_ref = wind_ref.clone();
+        but_setup_uinput.set_callback(Box::new(move || {
+            let message = match crate::input::uinput_setup::setup_uinput_access() {
+                Ok(()) => "Access to /dev/uinput has been set up, log out and back in to \
+                    complete the setup."
+                    .to_string(),
+                Err(err) => err,
+            };
+            let wind = wind_ref.borrow();
+            fltk::dialog::message(
+                wind.x() + wind.width() / 2 - 200,
+                wind.y() + wind.height() / 2 - 100,
+                &message,
+            );
+        }));
+    }
+
+    let sender_gui2ws: Rc<RefCell<Option<mpsc::Sender<Gui2WsMessage>>>> =
+        Rc::new(RefCell::new(None));
+
+    {
+        let sender_gui2ws = sender_gui2ws.clone();
+        but_disconnect.set_callback(Box::new(move || {
+            let selected = browser_clients.lock().unwrap().value();
+            if selected < 1 {
+                return;
+            }
+            let ip = client_ips
+                .lock()
+                .unwrap()
+                .get(selected as usize - 1)
+                .cloned();
+            if let (Some(ip), Some(sender)) = (ip, sender_gui2ws.borrow().as_ref()) {
+                if let Err(err) = sender.send(Gui2WsMessage::Disconnect(ip)) {
+                    error!("Failed to disconnect {}: {}", ip, err);
+                }
+            }
+        }));
+    }
+
+    // Start is only clickable if all inputs are valid, invalid ones are highlighted while typing
+    let inputs_valid = {
+        let input_bind_addr = input_bind_addr.clone();
+        let input_port = input_port.clone();
+        let input_ws_pointer_port = input_ws_pointer_port.clone();
+        let input_ws_video_port = input_ws_video_port.clone();
+        let input_limit_screen_updates = input_limit_screen_updates.clone();
+        Rc::new(move || {
+            let is_port = |v: &str| v.parse::<u16>().is_ok();
+            let valid = [
+                mark_input(&input_bind_addr, |v| network::parse_ip(v).is_ok()),
+                mark_input(&input_port, is_port),
+                mark_input(&input_ws_pointer_port, is_port),
+                mark_input(&input_ws_video_port, is_port),
+                mark_input(&input_limit_screen_updates, |v| v.parse::<u64>().is_ok()),
+            ];
+            valid.iter().all(|v| *v)
+        })
+    };
+
+    {
+        let validate = {
+            let inputs_valid = inputs_valid.clone();
+            let but_toggle_ref = but_toggle_ref.clone();
+            Rc::new(move || {
+                let valid = inputs_valid();
+                let mut but = but_toggle_ref.borrow_mut();
+                // never prevent stopping the server
+                if but.label() != "Start" {
+                    return;
+                }
+                if valid {
+                    but.activate();
+                } else {
+                    but.deactivate();
+                }
+            })
+        };
+        let mut input_bind_addr = input_bind_addr.clone();
+        input_bind_addr.set_trigger(fltk::CallbackTrigger::Changed);
+        {
+            let validate = validate.clone();
+            let input = input_bind_addr.clone();
+            input_bind_addr.set_callback(Box::new(move || {
+                select_interface(&mut choice_interface, &interfaces, &input.value());
+                validate()
+            }));
+        }
+        for input in &mut [
+            input_port.clone(),
+            input_ws_pointer_port.clone(),
+            input_ws_video_port.clone(),
+            input_limit_screen_updates.clone(),
+        ] {
+            let validate = validate.clone();
+            input.set_trigger(fltk::CallbackTrigger::Changed);
+            input.set_callback(Box::new(move || validate()));
+        }
+    }
+
+    {
+        let wind_ref = wind_ref.clone();
+        let log_buffer = log_buffer.clone();
+        but_export_logs.set_callback(Box::new(move || {
+            let secs = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            let path = dirs::home_dir()
+                .unwrap_or_default()
+                .join(format!("weylus-logs-{}.zip", secs));
+            let message = match std::fs::write(&path, log_buffer.bundle()) {
+                Ok(_) => {
+                    info!("Exported logs to {}.", path.display());
+                    format!("Logs have been saved to {}.", path.display())
+                }
+                Err(err) => {
+                    error!("Failed to export logs to {}: {}", path.display(), err);
+                    format!("Failed to save logs to {}: {}", path.display(), err)
+                }
+            };
+            let wind = wind_ref.borrow();
+            fltk::dialog::message(
+                wind.x() + wind.width() / 2 - 200,
+                wind.y() + wind.height() / 2 - 100,
+                &message,
+            );
+        }));
+    }
+
     let mut sender_gui2web: Option<mpsc_tokio::Sender<Gui2WebMessage>> = None;
 
     let mut is_server_running = false;
+    let record_input = options.record_input.clone();
+    let threads = options.threads;
+    let scale_on_resize = options.scale_on_resize;
+    let xcomposite = options.xcomposite;
+    let hide_own_windows = options.hide_own_windows;
+    let v4l2_device = options.v4l2_device.clone();
+    let gpu_convert = options.gpu_convert;
+    let inhibit_screensaver = !options.allow_screensaver;
+    let wake_display = options.wake_display;
+    let max_input_rate = options.max_input_rate;
+    let input_mapping = InputMapping {
+        mirror_x: options.mirror_x,
+        mirror_y: options.mirror_y,
+        swap_buttons: options.swap_buttons,
+    };
+    let max_resolution = options.max_resolution;
+    let latency_budget = options.latency_budget.map(Duration::from_millis);
+    let tcp_options = TcpOptions {
+        nodelay: !options.tcp_delay,
+        send_buffer_size: options.send_buffer_size.map(|kib| kib * 1024),
+    };
+    let intra_refresh = options.intra_refresh;
+    let cpu_limit = options.cpu_limit;
+    let battery_frame_interval = options
+        .battery_max_fps
+        .map(|fps| Duration::from_secs_f64(1.0 / fps.max(0.1)));
+    let stream_mode = options.stream_mode;
+    let notify_connections = options.notify_connections;
+    let reject_grace_period = options.reject_grace_period.map(Duration::from_secs);
+    let require_approval = options.require_approval;
+    let view_only_password = options.view_only_password.clone();
+    let basic_auth = options.basic_auth;
+    let idle_timeout = options
+        .idle_timeout
+        .map(|minutes| Duration::from_secs(minutes * 60));
+    let audio = options.audio;
+    let draft_while_drawing = options.draft_while_drawing;
+    let shortcuts = config_file.shortcuts();
+    let long_press = config_file.long_press;
+    let touch_filter = config_file.touch_filter;
+    let privacy = config_file.privacy.clone();
+
+    // serves the wake page on the web port while the server is stopped
+    let wake_page = Rc::new(RefCell::new(Option::<WakePage>::None));
+    let start_wake_page = {
+        let wake_page = wake_page.clone();
+        let input_bind_addr = input_bind_addr.clone();
+        let input_port = input_port.clone();
+        let sender_web2gui = sender_web2gui.clone();
+        let enabled = options.wake_page;
+        Rc::new(move || {
+            if !enabled || wake_page.borrow().is_some() {
+                return;
+            }
+            if let (Ok(bind_addr), Ok(port)) = (
+                network::parse_ip(&input_bind_addr.value()),
+                input_port.value().parse::<u16>(),
+            ) {
+                // a free port is only picked once the server starts, nobody knows where to find
+                // the wake page then
+                if port != 0 {
+                    wake_page.replace(Some(WakePage::start(
+                        SocketAddr::new(bind_addr, port),
+                        sender_web2gui.clone(),
+                    )));
+                }
+            }
+        })
+    };
+
+    // a row with the URL, a click on it opens the URL in the browser
+    let add_url_row = {
+        let wind_ref = wind_ref.clone();
+        let qr_popup_ref = qr_popup_ref.clone();
+        // the URL is shown without the password
+        move |shown_url: String, url: String, view_only: bool| {
+            let mut row = Pack::default().with_size(490, height);
+            row.set_type(PackType::Horizontal);
+            row.set_spacing(padding);
+
+            let mut output = Output::default().with_size(250, height);
+            if view_only {
+                output.set_value(&format!("{} (view only)", shown_url));
+            } else {
+                output.set_value(&shown_url);
+            }
+            output.set_tooltip("Click to open Weylus in your browser.");
+            {
+                let url = url.clone();
+                output.handle(Box::new(move |ev| match ev {
+                    fltk::Event::Push => {
+                        open_in_browser(&url);
+                        true
+                    }
+                    _ => false,
+                }));
+            }
+
+            let mut but_copy_url = Button::default()
+                .with_size(110, height)
+                .with_label("Copy URL");
+            but_copy_url.set_tooltip("Copy the URL including the password to the clipboard.");
+            {
+                let url = url.clone();
+                but_copy_url.set_callback(Box::new(move || {
+                    fltk::app::copy(&url);
+                    info!("Copied the URL to the clipboard.");
+                }));
+            }
+
+            let mut but_show_qr = Button::default()
+                .with_size(110, height)
+                .with_label("Show QR Code");
+            let wind_ref = wind_ref.clone();
+            let qr_popup_ref = qr_popup_ref.clone();
+            let qr_img_frame_ref = qr_img_frame_ref.clone();
+            but_show_qr.set_callback(Box::new(move || {
+                use image::Luma;
+                use qrcode::QrCode;
+                let code = QrCode::new(&url).unwrap();
+                let img_buf = code.render::<Luma<u8>>().build();
+                let width = img_buf.width() as i32;
+                let height = img_buf.height() as i32;
+                let image = image::DynamicImage::ImageLuma8(img_buf);
+                let mut buf = vec![];
+                image
+                    .write_to(&mut buf, image::ImageOutputFormat::Png)
+                    .unwrap();
+                let png = fltk::image::PngImage::from_data(&buf).unwrap();
+
+                let mut qr_popup = qr_popup_ref.borrow_mut();
+                let wind = wind_ref.borrow();
+                qr_popup.resize(
+                    wind.x() + (wind.width() - width) / 2,
+                    wind.y() + (wind.height() - height) / 2,
+                    width,
+                    height,
+                );
+                qr_popup.set_label(&format!("Weylus - QR Code for: {}", shown_url));
+                let mut qr_img_frame = qr_img_frame_ref.borrow_mut();
+                qr_img_frame.resize(0, 0, width, height);
+                qr_img_frame.set_image(&png);
+                qr_popup.show();
+                qr_popup.make_current();
+            }));
+
+            row.end();
+        }
+    };
 
     let but_toggle_ref2 = but_toggle_ref.clone();
+    let but_toggle_ref3 = but_toggle_ref.clone();
     let wind_ref2 = wind_ref.clone();
+    let wind_ref3 = wind_ref.clone();
+    let start_wake_page2 = start_wake_page.clone();
+    let but_diagnostics_ref2 = but_diagnostics_ref.clone();
 
     but_toggle_ref
         .clone()
@@ -340,69 +1203,208 @@ pub fn run(log_receiver: mpsc::Receiver<String>) {
                 let mut but = but_toggle_ref.try_borrow_mut()?;
 
                 let wind_ref = wind_ref.clone();
-                let qr_popup_ref = qr_popup_ref.clone();
-                let qr_img_frame_ref = qr_img_frame_ref.clone();
 
                 if !is_server_running {
-                    let password_string = input_password.value();
+                    let mut password_string = input_password.value();
+                    let bind_addr = network::parse_ip(&input_bind_addr.value())?;
+                    if password_string.is_empty() && !is_private(bind_addr) {
+                        let wind = wind_ref.borrow();
+                        let choice = fltk::dialog::choice(
+                            wind.x() + wind.width() / 2 - 200,
+                            wind.y() + wind.height() / 2 - 100,
+                            &format!(
+                                "Weylus is about to listen on {} without a password.\n\
+                                Anyone who can reach this computer over the network gets full \
+                                control over mouse, stylus and keyboard input.",
+                                bind_addr
+                            ),
+                            "Cancel",
+                            "Generate password",
+                            "Start anyway",
+                        );
+                        match choice {
+                            1 => {
+                                password_string = random_password();
+                                input_password.set_value(&password_string);
+                            }
+                            2 => (),
+                            _ => return Ok(()),
+                        }
+                    }
                     let password = match password_string.as_str() {
                         "" => None,
                         pw => Some(pw),
                     };
-                    let bind_addr: IpAddr = input_bind_addr.value().parse()?;
-                    let web_port: u16 = input_port.value().parse()?;
-                    let ws_pointer_port: u16 = input_ws_pointer_port.value().parse()?;
-                    let ws_video_port: u16 = input_ws_video_port.value().parse()?;
+                    // the web server takes over the port of the wake page
+                    if let Some(wake_page) = wake_page.borrow_mut().take() {
+                        wake_page.stop();
+                    }
+                    let web_port = resolve_port(
+                        bind_addr,
+                        input_port.value().parse()?,
+                        "web server",
+                        &input_port,
+                        &wind_ref.borrow(),
+                    )?;
+                    let ws_pointer_port = resolve_port(
+                        bind_addr,
+                        input_ws_pointer_port.value().parse()?,
+                        "pointer websocket",
+                        &input_ws_pointer_port,
+                        &wind_ref.borrow(),
+                    )?;
+                    let ws_video_port = resolve_port(
+                        bind_addr,
+                        input_ws_video_port.value().parse()?,
+                        "video websocket",
+                        &input_ws_video_port,
+                        &wind_ref.borrow(),
+                    )?;
+                    #[cfg(target_os = "windows")]
+                    offer_firewall_rule(
+                        &wind_ref.borrow(),
+                        &[web_port, ws_pointer_port, ws_video_port],
+                    );
                     let screen_update_interval: u64 = input_limit_screen_updates.value().parse()?;
                     let screen_update_interval = Duration::from_millis(screen_update_interval);
+                    let rotation = match choice_rotation.value() {
+                        1 => Rotation::Rotate90,
+                        2 => Rotation::Rotate180,
+                        3 => Rotation::Rotate270,
+                        4 => Rotation::Auto,
+                        _ => Rotation::None,
+                    };
+                    let scaling_filter = match choice_scaling_filter.value() {
+                        1 => ScalingFilter::Bicubic,
+                        2 => ScalingFilter::Lanczos,
+                        _ => ScalingFilter::FastBilinear,
+                    };
+                    let (color_space, color_range) = match choice_colors.value() {
+                        1 => (ColorSpace::BT709, ColorRange::Full),
+                        2 => (ColorSpace::BT601, ColorRange::Limited),
+                        3 => (ColorSpace::BT601, ColorRange::Full),
+                        _ => (ColorSpace::BT709, ColorRange::Limited),
+                    };
 
+                    // the first entry picks the best working encoder
+                    let selected_encoder = choice_encoder.lock().unwrap().value();
+                    let codec = match selected_encoder {
+                        0 => *best_encoder
+                            .lock()
+                            .unwrap()
+                            .get_or_insert_with(crate::video::best_encoder),
+                        i => Codec::ALL
+                            .get(i as usize - 1)
+                            .copied()
+                            .unwrap_or(Codec::X264),
+                    };
+
+                    let stroke_smoothing = match choice_smoothing.value() {
+                        1 => Smoothing::Low,
+                        2 => Smoothing::Medium,
+                        3 => Smoothing::High,
+                        _ => Smoothing::Off,
+                    };
+
+                    let config = Config {
+                        password: password.map(|pw| pw.to_string()),
+                        view_only_password: view_only_password.clone(),
+                        basic_auth,
+                        bind_address: bind_addr,
+                        web_port,
+                        websocket_pointer_port: ws_pointer_port,
+                        websocket_video_port: ws_video_port,
+                        screen_update_interval,
+                        stylus_support: check_stylus.is_checked(),
+                        faster_capture: check_faster_screencapture_ref.borrow().is_checked(),
+                        capture_cursor: check_capture_cursor_ref.borrow().is_checked(),
+                        enable_mouse: check_enable_mouse.is_checked(),
+                        enable_stylus: check_enable_stylus.is_checked(),
+                        enable_touch: check_enable_touch.is_checked(),
+                        rotation,
+                        scaling_filter,
+                        color_space,
+                        color_range,
+                        pointer_roi: check_pointer_roi.is_checked(),
+                        stroke_smoothing,
+                        record_input: record_input.clone(),
+                        shortcuts: shortcuts.clone(),
+                        long_press: if check_long_press.is_checked() {
+                            Some(long_press)
+                        } else {
+                            None
+                        },
+                        touch_filter,
+                        codec,
+                        threads,
+                        scale_on_resize,
+                        xcomposite,
+                        hide_own_windows,
+                        privacy: privacy.clone(),
+                        v4l2_device: v4l2_device.clone(),
+                        gpu_convert,
+                        inhibit_screensaver,
+                        wake_display,
+                        max_input_rate,
+                        input_mapping,
+                        max_resolution,
+                        latency_budget,
+                        tcp_options,
+                        intra_refresh,
+                        cpu_limit,
+                        battery_frame_interval,
+                        stream_mode,
+                        notify_connections,
+                        reject_grace_period,
+                        require_approval,
+                        idle_timeout,
+                        audio,
+                        draft_while_drawing,
+                    };
+
+                    metrics.reset();
                     let (sender_gui2ws_tmp, receiver_gui2ws) = mpsc::channel();
-                    sender_gui2ws = Some(sender_gui2ws_tmp);
+                    sender_gui2ws.replace(Some(sender_gui2ws_tmp));
                     #[cfg(target_os = "linux")]
-                    {
-                        let faster_screencapture =
-                            check_faster_screencapture_ref.borrow().is_checked();
-                        if !faster_screencapture {
+                    let capture_source = {
+                        if !config.faster_capture {
                             current_capturable.replace(None);
                             but_update_capturables_ref.borrow_mut().do_callback();
                         }
+                        let capturable = current_capturable
+                            .clone()
+                            .borrow()
+                            .as_ref()
+                            .unwrap()
+                            .clone();
+                        let target = CaptureTarget::new(capturable.clone());
+                        // without faster capture the whole desktop is captured anyway
+                        if config.faster_capture {
+                            State {
+                                capture: Some(CapturableId::new(&capturable)),
+                            }
+                            .save();
+                            capture_target.replace(Some(target.clone()));
+                        }
                         crate::websocket::run(
                             sender_ws2gui.clone(),
                             receiver_gui2ws,
-                            SocketAddr::new(bind_addr, ws_pointer_port),
-                            SocketAddr::new(bind_addr, ws_video_port),
-                            password,
-                            screen_update_interval,
-                            check_stylus.is_checked(),
-                            faster_screencapture,
-                            current_capturable
-                                .clone()
-                                .borrow()
-                                .as_ref()
-                                .unwrap()
-                                .clone(),
-                            check_capture_cursor_ref.borrow().is_checked(),
-                            check_enable_mouse.is_checked(),
-                            check_enable_stylus.is_checked(),
-                            check_enable_touch.is_checked(),
-                        );
-                    }
+                            &config,
+                            metrics.clone(),
+                            target,
+                        )
+                    };
                     #[cfg(not(target_os = "linux"))]
-                    crate::websocket::run(
+                    let capture_source = crate::websocket::run(
                         sender_ws2gui.clone(),
                         receiver_gui2ws,
-                        SocketAddr::new(bind_addr, ws_pointer_port),
-                        SocketAddr::new(bind_addr, ws_video_port),
-                        password,
-                        screen_update_interval,
-                        check_enable_mouse.is_checked(),
-                        check_enable_stylus.is_checked(),
-                        check_enable_touch.is_checked(),
+                        &config,
+                        metrics.clone(),
                     );
 
                     let (sender_gui2web_tmp, receiver_gui2web) = mpsc_tokio::channel(100);
                     sender_gui2web = Some(sender_gui2web_tmp);
-                    let mut web_sock = SocketAddr::new(bind_addr, web_port);
+                    let web_sock = SocketAddr::new(config.bind_address, config.web_port);
                     crate::web::run(
                         sender_web2gui.clone(),
                         receiver_gui2web,
@@ -410,113 +1412,95 @@ pub fn run(log_receiver: mpsc::Receiver<String>) {
                         ws_pointer_port,
                         ws_video_port,
                         password,
+                        config.view_only_password.as_deref(),
+                        config.basic_auth,
+                        metrics.clone(),
+                        config.shortcuts.iter().map(|s| s.name.clone()).collect(),
+                        config.stream_mode == StreamMode::PerClient,
+                        log_buffer.clone(),
+                        capture_source,
+                        config.encoder_options(),
                     );
 
-                    #[cfg(not(target_os = "windows"))]
-                    {
-                        if web_sock.ip().is_unspecified() {
-                            // try to guess an ip
-                            let mut ips = Vec::<IpAddr>::new();
-                            for iface in datalink::interfaces()
-                                .iter()
-                                .filter(|iface| iface.is_up() && !iface.is_loopback())
-                            {
-                                for ipnetw in &iface.ips {
-                                    if (ipnetw.is_ipv4() && web_sock.ip().is_ipv4())
-                                        || (ipnetw.is_ipv6() && web_sock.ip().is_ipv6())
-                                    {
-                                        // filtering ipv6 unicast requires nightly or more fiddling,
-                                        // lets wait for nightlies to stabilize...
-                                        ips.push(ipnetw.ip())
-                                    }
-                                }
-                            }
-                            if !ips.is_empty() {
-                                web_sock.set_ip(ips[0]);
-                            }
-                            if ips.len() > 1 {
-                                info!("Found more than one IP address for browsers to connect to,");
-                                info!("other urls are:");
-                                for ip in &ips[1..] {
-                                    info!("http://{}", SocketAddr::new(*ip, web_port));
-                                }
-                            }
-                        }
+                    let mut socks = network::reachable_addresses(web_sock);
+                    let mut scroll_urls = scroll_urls.lock()?;
+                    pack_urls.clear();
+                    pack_urls.begin();
+                    if socks.is_empty() {
+                        let output = Output::default().with_size(250, height);
+                        output.set_value(&format!("http://<your ip address>:{}", web_sock.port()));
                     }
-                    let mut output_server_addr = output_server_addr.lock()?;
-
-                    #[cfg(not(target_os = "windows"))]
-                    {
-                        use image::Luma;
-                        use qrcode::QrCode;
-                        let addr_string = format!("http://{}", web_sock.to_string());
-                        output_server_addr.set_value(&addr_string);
-                        let password = password.map(|pw| pw.to_string());
-                        but_show_qr.set_callback(Box::new(move || {
-                            let mut url_string = addr_string.clone();
-                            if let Some(password) = &password {
-                                url_string.push_str("?password=");
-                                url_string.push_str(
-                                    &percent_encoding::utf8_percent_encode(
-                                        &password,
-                                        percent_encoding::NON_ALPHANUMERIC,
-                                    )
-                                    .to_string(),
-                                );
-                                info!("{}", &url_string);
-                            }
-                            let code = QrCode::new(&url_string).unwrap();
-                            let img_buf = code.render::<Luma<u8>>().build();
-                            let width = img_buf.width() as i32;
-                            let height = img_buf.height() as i32;
-                            let image = image::DynamicImage::ImageLuma8(img_buf);
-                            let mut buf = vec![];
-                            image
-                                .write_to(&mut buf, image::ImageOutputFormat::Png)
-                                .unwrap();
-                            let png = fltk::image::PngImage::from_data(&buf).unwrap();
-
-                            let mut qr_popup = qr_popup_ref.borrow_mut();
-                            let wind = wind_ref.borrow();
-                            qr_popup.resize(
-                                wind.x() + (wind.width() - width) / 2,
-                                wind.y() + (wind.height() - height) / 2,
-                                width,
-                                height,
+                    // the browser on this computer can always connect via the loopback address
+                    if web_sock.ip().is_unspecified() {
+                        let localhost: IpAddr = if web_sock.is_ipv4() {
+                            Ipv4Addr::LOCALHOST.into()
+                        } else {
+                            Ipv6Addr::LOCALHOST.into()
+                        };
+                        socks.push(SocketAddr::new(localhost, web_sock.port()));
+                    }
+                    // with basic authentication the browser asks for the password, it is left out
+                    // of the URLs
+                    let url_password = password.filter(|_| !config.basic_auth);
+                    let view_only_password = config
+                        .view_only_password
+                        .as_deref()
+                        .filter(|_| !config.basic_auth);
+                    // a name keeps working if the addresses change with the next DHCP lease
+                    if let Some(name) = network::host_name(&socks) {
+                        let port = web_sock.port();
+                        add_url_row(
+                            network::named_server_url(&name, port, None),
+                            network::named_server_url(&name, port, url_password),
+                            false,
+                        );
+                        if view_only_password.is_some() {
+                            add_url_row(
+                                network::named_server_url(&name, port, None),
+                                network::named_server_url(&name, port, view_only_password),
+                                true,
                             );
-                            qr_popup.set_label(&format!(
-                                "Weylus - QR Code for: {}",
-                                web_sock.to_string(),
-                            ));
-                            let mut qr_img_frame = qr_img_frame_ref.borrow_mut();
-                            qr_img_frame.resize(0, 0, width, height);
-                            qr_img_frame.set_image(&png);
-                            qr_popup.show();
-                            qr_popup.make_current();
-                        }));
-                        but_show_qr.show();
+                        }
                     }
-                    #[cfg(target_os = "windows")]
-                    {
-                        if web_sock.ip().is_unspecified() {
-                            output_server_addr.set_value("http://<your ip address>");
-                        } else {
-                            output_server_addr
-                                .set_value(&format!("http://{}", web_sock.to_string()));
+                    for sock in &socks {
+                        add_url_row(
+                            network::server_url(sock, None),
+                            network::server_url(sock, url_password),
+                            false,
+                        );
+                    }
+                    if view_only_password.is_some() {
+                        for sock in &socks {
+                            add_url_row(
+                                network::server_url(sock, None),
+                                network::server_url(sock, view_only_password),
+                                true,
+                            );
                         }
                     }
-                    output_server_addr.show();
+                    pack_urls.end();
+                    scroll_urls.show();
+                    scroll_urls.redraw();
+                    // the ports are in use by Weylus itself now
+                    but_diagnostics_ref2.borrow_mut().deactivate();
                     but.set_label("Stop");
                 } else {
                     if let Some(mut sender_gui2web) = sender_gui2web.clone() {
                         sender_gui2web.try_send(Gui2WebMessage::Shutdown)?;
                     }
 
-                    if let Some(sender_gui2ws) = sender_gui2ws.clone() {
+                    if let Some(sender_gui2ws) = sender_gui2ws.borrow().as_ref() {
                         sender_gui2ws.send(Gui2WsMessage::Shutdown)?;
                     }
+                    #[cfg(target_os = "linux")]
+                    capture_target.replace(None);
                     but.set_label("Start");
-                    but_show_qr.hide();
+                    if !inputs_valid() {
+                        but.deactivate();
+                    }
+                    but_diagnostics_ref2.borrow_mut().activate();
+                    pack_urls.clear();
+                    scroll_urls.lock()?.hide();
                     qr_popup_ref.borrow_mut().hide();
                 }
                 is_server_running = !is_server_running;
@@ -524,6 +1508,10 @@ pub fn run(log_receiver: mpsc::Receiver<String>) {
             }() {
                 error!("{}", err);
             };
+            // also if starting failed
+            if !is_server_running {
+                start_wake_page();
+            }
         }));
 
     wind_ref2.borrow_mut().handle(Box::new(move |ev| match ev {
@@ -536,5 +1524,269 @@ pub fn run(log_receiver: mpsc::Receiver<String>) {
         _ => false,
     }));
 
-    app.run().expect("Failed to run Gui!");
+    run_diagnostics(false);
+    start_wake_page2();
+
+    while app.wait() {
+        let request = start_requested.lock().unwrap().take();
+        if let Some(addr) = request {
+            let mut but = but_toggle_ref3.borrow().clone();
+            if but.label() == "Start" {
+                let choice = {
+                    let wind = wind_ref3.borrow();
+                    fltk::dialog::choice(
+                        wind.x() + wind.width() / 2 - 200,
+                        wind.y() + wind.height() / 2 - 100,
+                        &format!("{} asks to start Weylus.", addr.ip()),
+                        "Ignore",
+                        "Start",
+                        "",
+                    )
+                };
+                if choice == 1 {
+                    but.do_callback();
+                }
+            }
+        }
+        let requests: Vec<_> = approval_requests.lock().unwrap().drain(..).collect();
+        for (addr, reply) in requests {
+            let choice = {
+                let wind = wind_ref3.borrow();
+                fltk::dialog::choice(
+                    wind.x() + wind.width() / 2 - 200,
+                    wind.y() + wind.height() / 2 - 100,
+                    &format!("{} asks to connect to Weylus.", addr.ip()),
+                    "Deny",
+                    "Approve",
+                    "",
+                )
+            };
+            // the client may have given up waiting already
+            reply.send(choice == 1).ok();
+        }
+        if idle_timeout_reached.swap(false, Ordering::Relaxed) {
+            // the callback borrows the button itself
+            let mut but = but_toggle_ref3.borrow().clone();
+            if but.label() == "Stop" {
+                but.do_callback();
+            }
+        }
+    }
+    Ok(())
+}
+
+struct ClientInfo {
+    // a client opens several websockets, it is shown as long as one of them is open
+    connections: HashSet<SocketAddr>,
+    connected_since: Instant,
+    video: Option<VideoStats>,
+}
+
+impl ClientInfo {
+    fn describe(&self, ip: &IpAddr) -> String {
+        let connected = self.connected_since.elapsed().as_secs();
+        let video = match &self.video {
+            Some(stats) => format!(
+                "{}x{} @ {:.0} fps\t{:.1} MB",
+                stats.width,
+                stats.height,
+                stats.fps,
+                stats.bytes_sent as f64 / 1e6
+            ),
+            None => "no video\t".to_string(),
+        };
+        format!(
+            "{}\tconnected {}:{:02}\t{}",
+            ip,
+            connected / 60,
+            connected % 60,
+            video
+        )
+    }
+}
+
+fn encoder_label(codec: Codec) -> &'static str {
+    match codec {
+        Codec::X264 => "x264",
+        Codec::Vaapi => "VAAPI",
+        Codec::Nvenc => "NVENC",
+        Codec::Amf => "AMF",
+        Codec::Mjpeg => "MJPEG",
+    }
+}
+
+// the entry picking the encoder automatically followed by the encoders in the order of Codec::ALL
+fn add_encoder_labels(choice: &mut Choice, auto: &str) {
+    choice.add(auto, Shortcut::None, MenuFlag::Normal, Box::new(|| ()));
+    for codec in Codec::ALL.iter() {
+        choice.add(
+            encoder_label(*codec),
+            Shortcut::None,
+            MenuFlag::Normal,
+            Box::new(|| ()),
+        );
+    }
+}
+
+// Title, class and geometry of a capturable to tell similar windows apart
+#[cfg(target_os = "linux")]
+fn capturable_label(capturable: &Capturable) -> String {
+    let name = capturable.name();
+    let mut label: String = name.chars().take(48).collect();
+    if label.len() < name.len() {
+        label.push_str("...");
+    }
+    let class = capturable.class();
+    if !class.is_empty() {
+        label.push_str(&format!(" [{}]", class));
+    }
+    if let Ok((x, y, width, height)) = capturable.pixel_geometry() {
+        label.push_str(&format!(" {}x{}+{}+{}", width, height, x, y));
+    }
+    label
+}
+
+// menus interpret slashes as submenus and underscores and ampersands as shortcuts
+fn escape_menu_label(label: &str) -> String {
+    label
+        .replace("\\", "\\\\")
+        .replace("/", "\\/")
+        .replace("_", "\\_")
+        .replace("&", "\\&")
+}
+
+// labels and addresses of the network interfaces to bind to
+fn interface_choices() -> Vec<(String, IpAddr)> {
+    let mut choices = vec![
+        ("All interfaces".to_string(), Ipv4Addr::UNSPECIFIED.into()),
+        ("Localhost only".to_string(), Ipv4Addr::LOCALHOST.into()),
+    ];
+    // sockets bound to the unspecified IPv6 address accept IPv4 connections too, except on Windows
+    if cfg!(not(target_os = "windows")) {
+        choices.push((
+            "All interfaces, IPv6 first".to_string(),
+            Ipv6Addr::UNSPECIFIED.into(),
+        ));
+    }
+    for (name, addr) in network::interface_addresses() {
+        choices.push((format!("{}: {}", name, addr), addr));
+    }
+    choices
+}
+
+// select the interface with the bind address, nothing is selected for addresses typed in that
+// belong to no interface
+fn select_interface(choice: &mut Choice, interfaces: &[(String, IpAddr)], bind_addr: &str) {
+    let index = network::parse_ip(bind_addr)
+        .ok()
+        .and_then(|addr| interfaces.iter().position(|(_, a)| *a == addr));
+    choice.set_value(index.map_or(-1, |i| i as i32));
+}
+
+// Whether only computers on a private network or this computer itself can connect to addr
+fn is_private(addr: IpAddr) -> bool {
+    match addr {
+        IpAddr::V4(addr) => addr.is_loopback() || addr.is_private() || addr.is_link_local(),
+        IpAddr::V6(addr) => {
+            let segment = addr.segments()[0];
+            // unique local (fc00::/7) and link local (fe80::/10) addresses
+            addr.is_loopback() || segment & 0xfe00 == 0xfc00 || segment & 0xffc0 == 0xfe80
+        }
+    }
+}
+
+// Highlight the input if its value is invalid, returns whether it is valid.
+fn mark_input<I: InputExt + Clone>(input: &I, is_valid: impl Fn(&str) -> bool) -> bool {
+    let valid = is_valid(&input.value());
+    let color = if valid {
+        fltk::Color::Foreground
+    } else {
+        fltk::Color::Red
+    };
+    let mut input = input.clone();
+    input.set_label_color(color);
+    input.set_text_color(color);
+    input.redraw();
+    valid
+}
+
+fn open_in_browser(url: &str) {
+    #[cfg(target_os = "linux")]
+    let result = std::process::Command::new("xdg-open").arg(url).spawn();
+    #[cfg(target_os = "macos")]
+    let result = std::process::Command::new("open").arg(url).spawn();
+    #[cfg(target_os = "windows")]
+    let result = std::process::Command::new("cmd")
+        .args(&["/C", "start", "", url])
+        .spawn();
+    if let Err(err) = result {
+        error!("Failed to open {} in the browser: {}", url, err);
+    }
+}
+
+// Make sure the port can be used before starting the servers. Port 0 means any free port, if the
+// port is taken the user can choose to use a free one instead, which is then shown in input.
+// the Windows firewall silently blocks tablets unless there is a rule for the ports, the user is
+// asked again only if the ports change
+#[cfg(target_os = "windows")]
+fn offer_firewall_rule(wind: &Window, ports: &[u16]) {
+    let mut state = State::load();
+    if state.firewall_ports == ports {
+        return;
+    }
+    let choice = fltk::dialog::choice(
+        wind.x() + wind.width() / 2 - 200,
+        wind.y() + wind.height() / 2 - 100,
+        "The Windows firewall may block tablets from connecting to Weylus.\n\
+        Allow connections to the ports of Weylus? This requires administrator privileges, it can \
+        also be done later by running weylus setup-firewall.",
+        "No",
+        "Allow",
+        "",
+    );
+    if choice == 1 {
+        if let Err(err) = crate::firewall::allow_ports(ports) {
+            fltk::dialog::message(
+                wind.x() + wind.width() / 2 - 200,
+                wind.y() + wind.height() / 2 - 100,
+                &err,
+            );
+        }
+    }
+    state.firewall_ports = ports.to_vec();
+    state.save();
+}
+
+fn resolve_port(
+    bind_addr: IpAddr,
+    port: u16,
+    name: &str,
+    input: &IntInput,
+    wind: &Window,
+) -> Result<u16, Box<dyn std::error::Error>> {
+    if port == 0 {
+        return Ok(network::free_port(bind_addr)?);
+    }
+    let err = match network::port_available(SocketAddr::new(bind_addr, port)) {
+        Ok(()) => return Ok(port),
+        Err(err) => err,
+    };
+    let choice = fltk::dialog::choice(
+        wind.x() + wind.width() / 2 - 200,
+        wind.y() + wind.height() / 2 - 100,
+        &format!(
+            "Port {} for the {} is not available: {}\nIs another instance of Weylus running?",
+            port, name, err
+        ),
+        "Cancel",
+        "Use a free port",
+        "",
+    );
+    if choice != 1 {
+        return Err(format!("Port {} for the {} is not available: {}", port, name, err).into());
+    }
+    let port = network::free_port(bind_addr)?;
+    info!("Using port {} for the {}.", port, name);
+    input.set_value(&port.to_string());
+    Ok(port)
 }
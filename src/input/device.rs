@@ -1,5 +1,56 @@
+use std::time::Duration;
+
+use crate::input::shortcuts::{tap_keys, KeyCombo};
 use crate::protocol::PointerEvent;
 
+// upper bound for the total delay added by spacing out coalesced events
+pub const MAX_COALESCED_DELAY: Duration = Duration::from_millis(8);
+
 pub trait InputDevice {
     fn send_event(&mut self, event: &PointerEvent);
+
+    /// Send events the browser coalesced into a single one, they are ordered by their timestamps.
+    fn send_events(&mut self, events: &[PointerEvent]) {
+        let mut budget = MAX_COALESCED_DELAY;
+        for (i, event) in events.iter().enumerate() {
+            if i > 0 {
+                pace_coalesced(&events[i - 1], event, &mut budget);
+            }
+            self.send_event(event);
+        }
+    }
+
+    /// Turn the mouse wheel with the pointer at x, y, positive values scroll up and right. If zoom
+    /// is set Ctrl is held while scrolling.
+    fn send_wheel(&mut self, x: f64, y: f64, horizontal: i32, vertical: i32, zoom: bool);
+
+    fn send_right_click(&mut self, x: f64, y: f64);
+
+    fn send_keys(&mut self, keys: &[KeyCombo]) {
+        tap_keys(keys);
+    }
+}
+
+/// Sleep for the time that passed between two coalesced events on the client, this way
+/// applications see the events with roughly their original timing instead of all at once. The
+/// total delay is bounded by budget, which is reduced accordingly.
+pub fn pace_coalesced(prev: &PointerEvent, next: &PointerEvent, budget: &mut Duration) {
+    let delta = Duration::from_micros(next.timestamp.saturating_sub(prev.timestamp)).min(*budget);
+    *budget -= delta;
+    if delta > Duration::from_millis(0) {
+        std::thread::sleep(delta);
+    }
+}
+
+/// Drops all input, used where Weylus can only mirror the screen.
+pub struct NoInput;
+
+impl InputDevice for NoInput {
+    fn send_event(&mut self, _event: &PointerEvent) {}
+
+    fn send_wheel(&mut self, _x: f64, _y: f64, _horizontal: i32, _vertical: i32, _zoom: bool) {}
+
+    fn send_right_click(&mut self, _x: f64, _y: f64) {}
+
+    fn send_keys(&mut self, _keys: &[KeyCombo]) {}
 }
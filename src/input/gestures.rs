@@ -0,0 +1,267 @@
+use std::collections::HashMap;
+
+use autopilot::key::{self, Code, KeyCode};
+
+use crate::protocol::{PointerEvent, PointerEventType, PointerType};
+
+// distance the fingers have to move for one click of the mouse wheel, relative to the size of the
+// screen
+const SCROLL_STEP: f64 = 0.03;
+// relative change of the distance between the fingers (on a logarithmic scale) for one zoom step
+const ZOOM_STEP: f64 = 0.1;
+
+#[derive(Clone, Copy, PartialEq)]
+enum Mode {
+    Undecided,
+    Scroll,
+    Zoom,
+}
+
+pub enum Gesture {
+    // a touch that has already been sent to the host became part of a gesture and has to be
+    // cancelled
+    Cancel(PointerEvent),
+    // turn the mouse wheel at x, y, positive values scroll up and right, zoom means Ctrl is held
+    // while scrolling
+    Wheel {
+        x: f64,
+        y: f64,
+        horizontal: i32,
+        vertical: i32,
+        zoom: bool,
+    },
+}
+
+/// Translates two finger gestures into mouse wheel events: Moving both fingers scrolls, pinching
+/// zooms. This is meant for applications that do not handle multitouch themselves.
+pub struct GestureRecognizer {
+    // positions of the touches that are currently down
+    touches: HashMap<i64, (f64, f64)>,
+    // last events of touches that have been sent to the host and are still down
+    forwarded: HashMap<i64, PointerEvent>,
+    // a gesture lasts until all fingers have been lifted
+    active: bool,
+    mode: Mode,
+    // center and distance of the fingers when the last wheel event has been sent
+    anchor: Option<(f64, f64, f64)>,
+}
+
+impl GestureRecognizer {
+    pub fn new() -> Self {
+        Self {
+            touches: HashMap::new(),
+            forwarded: HashMap::new(),
+            active: false,
+            mode: Mode::Undecided,
+            anchor: None,
+        }
+    }
+
+    /// Process an event and append recognized gestures, returns false if the event is consumed by
+    /// a gesture and must not be sent to the host.
+    pub fn process(&mut self, event: &PointerEvent, gestures: &mut Vec<Gesture>) -> bool {
+        match event.pointer_type {
+            PointerType::Touch => (),
+            _ => return true,
+        }
+        match event.event_type {
+            PointerEventType::DOWN => {
+                self.touches.insert(event.pointer_id, (event.x, event.y));
+                if !self.active && self.touches.len() == 2 {
+                    self.active = true;
+                    self.mode = Mode::Undecided;
+                    for (_, mut event) in self.forwarded.drain() {
+                        event.event_type = PointerEventType::CANCEL;
+                        gestures.push(Gesture::Cancel(event));
+                    }
+                }
+                // a finger has been added or lifted since the last event
+                self.anchor = None;
+            }
+            PointerEventType::MOVE => {
+                if let Some(touch) = self.touches.get_mut(&event.pointer_id) {
+                    *touch = (event.x, event.y);
+                }
+            }
+            PointerEventType::UP | PointerEventType::CANCEL | PointerEventType::LEAVE => {
+                self.touches.remove(&event.pointer_id);
+                self.anchor = None;
+                if self.active {
+                    self.active = !self.touches.is_empty();
+                    return false;
+                }
+            }
+        }
+        if self.active {
+            self.recognize(gestures);
+            return false;
+        }
+        match event.event_type {
+            PointerEventType::DOWN | PointerEventType::MOVE => {
+                if self.touches.contains_key(&event.pointer_id) {
+                    self.forwarded.insert(event.pointer_id, event.clone());
+                }
+            }
+            PointerEventType::UP | PointerEventType::CANCEL | PointerEventType::LEAVE => {
+                self.forwarded.remove(&event.pointer_id);
+            }
+        }
+        true
+    }
+
+    // center of and distance between the fingers, only defined for exactly two fingers
+    fn fingers(&self) -> Option<(f64, f64, f64)> {
+        if self.touches.len() != 2 {
+            return None;
+        }
+        let mut touches = self.touches.values();
+        let (x1, y1) = touches.next()?;
+        let (x2, y2) = touches.next()?;
+        let distance = ((x2 - x1).powi(2) + (y2 - y1).powi(2)).sqrt();
+        Some(((x1 + x2) / 2.0, (y1 + y2) / 2.0, distance.max(1e-6)))
+    }
+
+    fn recognize(&mut self, gestures: &mut Vec<Gesture>) {
+        let (x, y, distance) = match self.fingers() {
+            Some(fingers) => fingers,
+            None => return,
+        };
+        let (anchor_x, anchor_y, anchor_distance) = match self.anchor {
+            Some(anchor) => anchor,
+            None => {
+                self.anchor = Some((x, y, distance));
+                return;
+            }
+        };
+        let zoom = (distance / anchor_distance).ln() / ZOOM_STEP;
+        let dx = (x - anchor_x) / SCROLL_STEP;
+        let dy = (y - anchor_y) / SCROLL_STEP;
+        // whatever is recognized first is kept until the fingers are lifted, this avoids zooming
+        // accidentally while scrolling and vice versa
+        if self.mode == Mode::Undecided {
+            if zoom.abs() >= 1.0 {
+                self.mode = Mode::Zoom;
+            } else if dx.abs() >= 1.0 || dy.abs() >= 1.0 {
+                self.mode = Mode::Scroll;
+            }
+        }
+        match self.mode {
+            Mode::Zoom => {
+                let steps = zoom.trunc() as i32;
+                if steps != 0 {
+                    gestures.push(Gesture::Wheel {
+                        x,
+                        y,
+                        horizontal: 0,
+                        vertical: steps,
+                        zoom: true,
+                    });
+                    self.anchor = Some((x, y, anchor_distance * (steps as f64 * ZOOM_STEP).exp()));
+                }
+            }
+            Mode::Scroll => {
+                let (steps_x, steps_y) = (dx.trunc() as i32, dy.trunc() as i32);
+                if steps_x != 0 || steps_y != 0 {
+                    // the content follows the fingers like on touchscreens
+                    gestures.push(Gesture::Wheel {
+                        x,
+                        y,
+                        horizontal: -steps_x,
+                        vertical: steps_y,
+                        zoom: false,
+                    });
+                    self.anchor = Some((
+                        anchor_x + steps_x as f64 * SCROLL_STEP,
+                        anchor_y + steps_y as f64 * SCROLL_STEP,
+                        distance,
+                    ));
+                }
+            }
+            Mode::Undecided => (),
+        }
+    }
+}
+
+/// Press or release Ctrl, most applications zoom if the mouse wheel is turned while it is held.
+pub fn set_zoom_modifier(pressed: bool) {
+    key::toggle(&Code(KeyCode::Control), pressed, &[], 0);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::test_events::pointer_event;
+
+    fn touch(event_type: PointerEventType, pointer_id: i64, x: f64, y: f64) -> PointerEvent {
+        let mut event = pointer_event(event_type, PointerType::Touch, x, y, 0);
+        event.pointer_id = pointer_id;
+        event
+    }
+
+    fn wheels(gestures: &[Gesture]) -> Vec<(i32, i32, bool)> {
+        gestures
+            .iter()
+            .filter_map(|gesture| match gesture {
+                Gesture::Wheel {
+                    horizontal,
+                    vertical,
+                    zoom,
+                    ..
+                } => Some((*horizontal, *vertical, *zoom)),
+                Gesture::Cancel(_) => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn second_finger_cancels_first() {
+        let mut recognizer = GestureRecognizer::new();
+        let mut gestures = Vec::new();
+        let pen = pointer_event(PointerEventType::DOWN, PointerType::Pen, 0.5, 0.5, 0);
+        assert!(recognizer.process(&pen, &mut gestures));
+        assert!(recognizer.process(&touch(PointerEventType::DOWN, 1, 0.4, 0.5), &mut gestures));
+        assert!(gestures.is_empty());
+        assert!(!recognizer.process(&touch(PointerEventType::DOWN, 2, 0.6, 0.5), &mut gestures));
+        match gestures.as_slice() {
+            [Gesture::Cancel(event)] => {
+                assert_eq!(event.pointer_id, 1);
+                assert!(matches!(event.event_type, PointerEventType::CANCEL));
+            }
+            _ => panic!("Expected the first touch to be cancelled."),
+        }
+        // the gesture lasts until all fingers are lifted
+        assert!(!recognizer.process(&touch(PointerEventType::UP, 1, 0.4, 0.5), &mut gestures));
+        assert!(!recognizer.process(&touch(PointerEventType::MOVE, 2, 0.6, 0.5), &mut gestures));
+        assert!(!recognizer.process(&touch(PointerEventType::UP, 2, 0.6, 0.5), &mut gestures));
+        assert!(recognizer.process(&touch(PointerEventType::DOWN, 3, 0.5, 0.5), &mut gestures));
+    }
+
+    #[test]
+    fn moving_fingers_scrolls() {
+        let mut recognizer = GestureRecognizer::new();
+        let mut gestures = Vec::new();
+        recognizer.process(&touch(PointerEventType::DOWN, 1, 0.4, 0.5), &mut gestures);
+        recognizer.process(&touch(PointerEventType::DOWN, 2, 0.6, 0.5), &mut gestures);
+        recognizer.process(&touch(PointerEventType::MOVE, 1, 0.4, 0.54), &mut gestures);
+        assert!(wheels(&gestures).is_empty());
+        recognizer.process(&touch(PointerEventType::MOVE, 2, 0.6, 0.54), &mut gestures);
+        assert_eq!(wheels(&gestures), vec![(0, 1, false)]);
+        // pinching does not zoom once scrolling started
+        recognizer.process(&touch(PointerEventType::MOVE, 2, 0.9, 0.54), &mut gestures);
+        assert!(wheels(&gestures).iter().all(|(_, _, zoom)| !zoom));
+    }
+
+    #[test]
+    fn pinching_zooms() {
+        let mut recognizer = GestureRecognizer::new();
+        let mut gestures = Vec::new();
+        recognizer.process(&touch(PointerEventType::DOWN, 1, 0.4, 0.5), &mut gestures);
+        recognizer.process(&touch(PointerEventType::DOWN, 2, 0.6, 0.5), &mut gestures);
+        // twice the distance is ln(2) / ZOOM_STEP steps
+        recognizer.process(&touch(PointerEventType::MOVE, 2, 0.8, 0.5), &mut gestures);
+        assert_eq!(wheels(&gestures), vec![(0, 6, true)]);
+        gestures.clear();
+        recognizer.process(&touch(PointerEventType::MOVE, 2, 0.55, 0.5), &mut gestures);
+        assert_eq!(wheels(&gestures), vec![(0, -8, true)]);
+    }
+}
@@ -0,0 +1,237 @@
+use serde::Deserialize;
+
+use crate::protocol::{PointerEvent, PointerEventType, PointerType};
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(default)]
+pub struct LongPressOptions {
+    // milliseconds a touch has to be held to count as long press
+    pub delay: u64,
+    // distance a touch may move during a long press, relative to the size of the screen
+    pub tolerance: f64,
+}
+
+impl Default for LongPressOptions {
+    fn default() -> Self {
+        Self {
+            delay: 500,
+            tolerance: 0.02,
+        }
+    }
+}
+
+// touch that may turn out to be a long press
+struct Candidate {
+    pointer_id: i64,
+    x: f64,
+    y: f64,
+    // microseconds on the clock of the client
+    timestamp: u64,
+    // events withheld until it is clear whether this is a long press
+    events: Vec<PointerEvent>,
+}
+
+/// Turns touches that are held in place into right clicks, which is the only way to open context
+/// menus on many tablets. Like on touchscreens the click happens when the finger is lifted, until
+/// then the events of a touch that did not move are withheld.
+pub struct LongPressDetector {
+    options: LongPressOptions,
+    candidate: Option<Candidate>,
+}
+
+impl LongPressDetector {
+    pub fn new(options: LongPressOptions) -> Self {
+        Self {
+            options,
+            candidate: None,
+        }
+    }
+
+    /// Process an event, events that should be sent to the host are appended to out. Returns the
+    /// position of the right click if a long press has been recognized.
+    pub fn process(
+        &mut self,
+        event: &PointerEvent,
+        out: &mut Vec<PointerEvent>,
+    ) -> Option<(f64, f64)> {
+        match event.pointer_type {
+            PointerType::Touch => (),
+            _ => {
+                out.push(event.clone());
+                return None;
+            }
+        }
+        let mut candidate = match self.candidate.take() {
+            Some(candidate) => candidate,
+            None => {
+                match event.event_type {
+                    PointerEventType::DOWN if event.is_primary => {
+                        self.candidate = Some(Candidate {
+                            pointer_id: event.pointer_id,
+                            x: event.x,
+                            y: event.y,
+                            timestamp: event.timestamp,
+                            events: vec![event.clone()],
+                        })
+                    }
+                    _ => out.push(event.clone()),
+                }
+                return None;
+            }
+        };
+        if candidate.pointer_id != event.pointer_id {
+            // another finger, this is no long press
+            out.append(&mut candidate.events);
+            out.push(event.clone());
+            return None;
+        }
+        match event.event_type {
+            PointerEventType::MOVE => {
+                let distance =
+                    ((event.x - candidate.x).powi(2) + (event.y - candidate.y).powi(2)).sqrt();
+                if distance > self.options.tolerance {
+                    out.append(&mut candidate.events);
+                    out.push(event.clone());
+                } else {
+                    candidate.events.push(event.clone());
+                    self.candidate = Some(candidate);
+                }
+                None
+            }
+            PointerEventType::UP => {
+                let duration = event.timestamp.saturating_sub(candidate.timestamp);
+                if duration >= self.options.delay * 1000 {
+                    Some((candidate.x, candidate.y))
+                } else {
+                    out.append(&mut candidate.events);
+                    out.push(event.clone());
+                    None
+                }
+            }
+            // nothing has been sent to the host yet, so there is nothing to cancel
+            PointerEventType::CANCEL | PointerEventType::LEAVE => None,
+            PointerEventType::DOWN => {
+                out.append(&mut candidate.events);
+                out.push(event.clone());
+                None
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::test_events::pointer_event_ms;
+
+    fn detector() -> LongPressDetector {
+        LongPressDetector::new(LongPressOptions::default())
+    }
+
+    #[test]
+    fn holding_a_touch_right_clicks() {
+        let mut detector = detector();
+        let mut out = Vec::new();
+        assert_eq!(
+            detector.process(
+                &pointer_event_ms(PointerEventType::DOWN, PointerType::Touch, 0.5, 0.5, 0),
+                &mut out
+            ),
+            None
+        );
+        assert_eq!(
+            detector.process(
+                &pointer_event_ms(PointerEventType::MOVE, PointerType::Touch, 0.51, 0.5, 200),
+                &mut out
+            ),
+            None
+        );
+        assert_eq!(
+            detector.process(
+                &pointer_event_ms(PointerEventType::UP, PointerType::Touch, 0.51, 0.5, 600),
+                &mut out
+            ),
+            Some((0.5, 0.5))
+        );
+        // the touch itself never reaches the host
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn short_touches_are_passed_on() {
+        let mut detector = detector();
+        let mut out = Vec::new();
+        detector.process(
+            &pointer_event_ms(PointerEventType::DOWN, PointerType::Touch, 0.5, 0.5, 0),
+            &mut out,
+        );
+        detector.process(
+            &pointer_event_ms(PointerEventType::MOVE, PointerType::Touch, 0.5, 0.5, 100),
+            &mut out,
+        );
+        assert!(out.is_empty());
+        assert_eq!(
+            detector.process(
+                &pointer_event_ms(PointerEventType::UP, PointerType::Touch, 0.5, 0.5, 200),
+                &mut out
+            ),
+            None
+        );
+        let types: Vec<_> = out.iter().map(|event| event.event_type).collect();
+        assert!(matches!(
+            types.as_slice(),
+            [
+                PointerEventType::DOWN,
+                PointerEventType::MOVE,
+                PointerEventType::UP
+            ]
+        ));
+    }
+
+    #[test]
+    fn moving_or_second_finger_is_no_long_press() {
+        let mut detector = detector();
+        let mut out = Vec::new();
+        detector.process(
+            &pointer_event_ms(PointerEventType::DOWN, PointerType::Touch, 0.5, 0.5, 0),
+            &mut out,
+        );
+        detector.process(
+            &pointer_event_ms(PointerEventType::MOVE, PointerType::Touch, 0.6, 0.5, 100),
+            &mut out,
+        );
+        assert_eq!(out.len(), 2);
+        assert_eq!(
+            detector.process(
+                &pointer_event_ms(PointerEventType::UP, PointerType::Touch, 0.6, 0.5, 900),
+                &mut out
+            ),
+            None
+        );
+        assert_eq!(out.len(), 3);
+
+        out.clear();
+        detector.process(
+            &pointer_event_ms(PointerEventType::DOWN, PointerType::Touch, 0.5, 0.5, 1000),
+            &mut out,
+        );
+        let mut second =
+            pointer_event_ms(PointerEventType::DOWN, PointerType::Touch, 0.2, 0.2, 1100);
+        second.pointer_id = 2;
+        second.is_primary = false;
+        detector.process(&second, &mut out);
+        assert_eq!(out.len(), 2);
+        assert_eq!(out[1].pointer_id, 2);
+    }
+
+    #[test]
+    fn pens_are_passed_on() {
+        let mut detector = detector();
+        let mut out = Vec::new();
+        let pen = pointer_event_ms(PointerEventType::DOWN, PointerType::Pen, 0.5, 0.5, 0);
+        assert_eq!(detector.process(&pen, &mut out), None);
+        let up = pointer_event_ms(PointerEventType::UP, PointerType::Pen, 0.5, 0.5, 1000);
+        assert_eq!(detector.process(&up, &mut out), None);
+        assert_eq!(out.len(), 2);
+    }
+}
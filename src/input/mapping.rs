@@ -0,0 +1,76 @@
+use crate::protocol::{Button, PointerEvent, PointerType};
+
+/// Remaps pointer events for left-handed users and tablets mounted in unusual orientations.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct InputMapping {
+    pub mirror_x: bool,
+    pub mirror_y: bool,
+    // swaps the primary and secondary button of mice
+    pub swap_buttons: bool,
+}
+
+impl InputMapping {
+    /// Map an event given in coordinates relative to the video, that is in the range of 0 to 1.
+    pub fn apply(&self, event: &mut PointerEvent) {
+        // the values come straight from the client, negating i32::MIN would overflow
+        if self.mirror_x {
+            event.x = 1.0 - event.x;
+            event.movement_x = event.movement_x.saturating_neg();
+            event.tilt_x = event.tilt_x.saturating_neg();
+        }
+        if self.mirror_y {
+            event.y = 1.0 - event.y;
+            event.movement_y = event.movement_y.saturating_neg();
+            event.tilt_y = event.tilt_y.saturating_neg();
+        }
+        // mirroring along a single axis reverses the direction of rotations
+        if self.mirror_x != self.mirror_y {
+            event.twist = (360 - event.twist.rem_euclid(360)) % 360;
+        }
+        // the primary button of pens and touches means contact, swapping it makes no sense
+        if self.swap_buttons {
+            if let PointerType::Mouse = event.pointer_type {
+                event.button = swap_buttons(event.button);
+                event.buttons = swap_buttons(event.buttons);
+            }
+        }
+    }
+}
+
+fn swap_buttons(buttons: Button) -> Button {
+    let mut swapped = buttons - (Button::PRIMARY | Button::SECONDARY);
+    swapped.set(Button::SECONDARY, buttons.contains(Button::PRIMARY));
+    swapped.set(Button::PRIMARY, buttons.contains(Button::SECONDARY));
+    swapped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::test_events::pointer_event;
+    use crate::protocol::PointerEventType;
+
+    #[test]
+    fn mirroring_handles_extreme_values() {
+        let mapping = InputMapping {
+            mirror_x: true,
+            mirror_y: false,
+            swap_buttons: false,
+        };
+        let mut event = pointer_event(PointerEventType::MOVE, PointerType::Pen, 0.25, 0.5, 0);
+        event.movement_x = i64::MIN;
+        event.tilt_x = i32::MIN;
+        event.twist = i32::MIN;
+        mapping.apply(&mut event);
+        assert_eq!(event.x, 0.75);
+        assert_eq!(event.movement_x, i64::MAX);
+        assert_eq!(event.tilt_x, i32::MAX);
+        assert!((0..360).contains(&event.twist));
+
+        for (twist, mirrored) in &[(0, 0), (90, 270), (359, 1), (-90, 90), (720, 0)] {
+            event.twist = *twist;
+            mapping.apply(&mut event);
+            assert_eq!(event.twist, *mirrored);
+        }
+    }
+}
@@ -1,5 +1,22 @@
 pub mod device;
+pub mod gestures;
+pub mod long_press;
+pub mod mapping;
 pub mod mouse_device;
+pub mod pressure;
+pub mod rate_limit;
+pub mod recording;
+pub mod shortcuts;
+pub mod smoothing;
+#[cfg(test)]
+pub mod test_device;
+#[cfg(test)]
+pub mod test_events;
+pub mod touch_filter;
 
 #[cfg(target_os = "linux")]
 pub mod uinput_device;
+#[cfg(target_os = "linux")]
+pub mod uinput_helper;
+#[cfg(target_os = "linux")]
+pub mod uinput_setup;
@@ -1,20 +1,28 @@
+use autopilot::key::{self, Code, KeyCode};
 use autopilot::mouse;
 use autopilot::screen::size as screen_size;
 
 use tracing::warn;
 
 use crate::input::device::InputDevice;
+use crate::input::gestures::set_zoom_modifier;
 use crate::protocol::Button;
 use crate::protocol::PointerEvent;
 use crate::protocol::PointerEventType;
 use crate::protocol::PointerType;
 
 #[cfg(target_os = "linux")]
-use crate::x11helper::Capturable;
+use std::sync::Arc;
+
+#[cfg(target_os = "linux")]
+use crate::x11helper::{Capturable, CaptureTarget};
 
 #[cfg(target_os = "linux")]
 pub struct Mouse {
+    target: Arc<CaptureTarget>,
     capture: Capturable,
+    // generation of the capture target capture belongs to
+    capture_generation: usize,
     enable_mouse: bool,
     enable_stylus: bool,
     enable_touch: bool,
@@ -30,13 +38,16 @@ pub struct Mouse {
 #[cfg(target_os = "linux")]
 impl Mouse {
     pub fn new(
-        capture: Capturable,
+        target: Arc<CaptureTarget>,
         enable_mouse: bool,
         enable_stylus: bool,
         enable_touch: bool,
     ) -> Self {
+        let (capture, capture_generation) = target.get();
         Self {
+            target,
             capture,
+            capture_generation,
             enable_mouse,
             enable_stylus,
             enable_touch,
@@ -55,43 +66,27 @@ impl Mouse {
     }
 }
 
-impl InputDevice for Mouse {
-    fn send_event(&mut self, event: &PointerEvent) {
-        match event.pointer_type {
-            PointerType::Mouse | PointerType::Unknown => {
-                if !self.enable_mouse {
-                    return;
-                }
-            }
-            PointerType::Pen => {
-                if !self.enable_stylus {
-                    return;
-                }
-            }
-            PointerType::Touch => {
-                if !self.enable_touch {
-                    return;
-                }
-            }
-        }
-        if !event.is_primary {
-            return;
-        }
+impl Mouse {
+    // move the mouse to a position given relative to the captured area, returns false if no
+    // input should be sent
+    fn move_to(&mut self, x: f64, y: f64) -> bool {
         #[cfg(target_os = "linux")]
         {
+            self.target
+                .update(&mut self.capture, &mut self.capture_generation);
             if let Err(err) = self.capture.before_input() {
                 warn!("Failed to activate window, sending no input ({})", err);
-                return;
+                return false;
             }
             let geometry = self.capture.geometry();
             if let Err(err) = geometry {
                 warn!("Failed to get window geometry, sending no input ({})", err);
-                return;
+                return false;
             }
             let geometry = geometry.unwrap();
             if let Err(err) = mouse::move_to(autopilot::geometry::Point::new(
-                (event.x * geometry.width + geometry.x) * screen_size().width,
-                (event.y * geometry.height + geometry.y) * screen_size().height,
+                (x * geometry.width + geometry.x) * screen_size().width,
+                (y * geometry.height + geometry.y) * screen_size().height,
             )) {
                 warn!("Could not move mouse: {}", err);
             }
@@ -99,13 +94,48 @@ impl InputDevice for Mouse {
 
         #[cfg(not(target_os = "linux"))]
         {
+            // position and size relative to the screen
+            #[cfg(target_os = "windows")]
+            let (x0, y0, width, height) =
+                crate::screen_capture::windows::target_geometry().unwrap_or((0.0, 0.0, 1.0, 1.0));
+            #[cfg(not(target_os = "windows"))]
+            let (x0, y0, width, height) = (0.0, 0.0, 1.0, 1.0);
             if let Err(err) = mouse::move_to(autopilot::geometry::Point::new(
-                event.x * screen_size().width,
-                event.y * screen_size().height,
+                (x * width + x0) * screen_size().width,
+                (y * height + y0) * screen_size().height,
             )) {
                 warn!("Could not move mouse: {}", err);
             }
         }
+        true
+    }
+}
+
+impl InputDevice for Mouse {
+    fn send_event(&mut self, event: &PointerEvent) {
+        match event.pointer_type {
+            PointerType::Mouse | PointerType::Unknown => {
+                if !self.enable_mouse {
+                    return;
+                }
+            }
+            PointerType::Pen => {
+                if !self.enable_stylus {
+                    return;
+                }
+            }
+            PointerType::Touch => {
+                if !self.enable_touch {
+                    return;
+                }
+            }
+        }
+        if !event.is_primary {
+            return;
+        }
+        if !self.move_to(event.x, event.y) {
+            return;
+        }
         match event.event_type {
             PointerEventType::DOWN => match event.button {
                 Button::PRIMARY => mouse::toggle(mouse::Button::Left, true),
@@ -113,7 +143,7 @@ impl InputDevice for Mouse {
                 Button::SECONDARY => mouse::toggle(mouse::Button::Right, true),
                 _ => (),
             },
-            PointerEventType::UP => {
+            PointerEventType::UP | PointerEventType::CANCEL => {
                 mouse::toggle(mouse::Button::Left, false);
                 mouse::toggle(mouse::Button::Middle, false);
                 mouse::toggle(mouse::Button::Right, false);
@@ -121,4 +151,37 @@ impl InputDevice for Mouse {
             _ => (),
         }
     }
+    fn send_right_click(&mut self, x: f64, y: f64) {
+        if self.move_to(x, y) {
+            mouse::click(mouse::Button::Right, None);
+        }
+    }
+
+    fn send_wheel(&mut self, x: f64, y: f64, horizontal: i32, vertical: i32, zoom: bool) {
+        if !self.move_to(x, y) {
+            return;
+        }
+        if zoom {
+            set_zoom_modifier(true);
+        }
+        if vertical > 0 {
+            mouse::scroll(mouse::ScrollDirection::Up, vertical as u32);
+        } else if vertical < 0 {
+            mouse::scroll(mouse::ScrollDirection::Down, -vertical as u32);
+        }
+        // autopilot only supports vertical scrolling, most applications scroll horizontally if
+        // Shift is held
+        if horizontal != 0 {
+            key::toggle(&Code(KeyCode::Shift), true, &[], 0);
+            if horizontal > 0 {
+                mouse::scroll(mouse::ScrollDirection::Down, horizontal as u32);
+            } else {
+                mouse::scroll(mouse::ScrollDirection::Up, -horizontal as u32);
+            }
+            key::toggle(&Code(KeyCode::Shift), false, &[], 0);
+        }
+        if zoom {
+            set_zoom_modifier(false);
+        }
+    }
 }
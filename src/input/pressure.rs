@@ -0,0 +1,178 @@
+use tracing::info;
+
+use crate::protocol::{Button, PointerEvent, PointerEventType, PointerType, PressureRange};
+
+// calibrations covering less than this are most likely caused by a stroke that was too short
+const MIN_RANGE: f64 = 0.05;
+
+/// Rescales the pressure of pens from the range measured during a calibration stroke to the full
+/// range. The client starts a calibration and is sent the result to restore it after reconnecting.
+pub struct PressureCalibration {
+    range: Option<PressureRange>,
+    // range observed so far while calibrating
+    recording: Option<Option<PressureRange>>,
+    finished: Option<PressureRange>,
+}
+
+impl PressureCalibration {
+    pub fn new() -> Self {
+        Self {
+            range: None,
+            recording: None,
+            finished: None,
+        }
+    }
+
+    pub fn set_range(&mut self, range: Option<PressureRange>) {
+        self.range = range.filter(|range| range.max - range.min >= MIN_RANGE);
+    }
+
+    /// Measure the pressure of the next pen stroke, events are not rescaled until it is finished.
+    pub fn start(&mut self) {
+        self.recording = Some(None);
+    }
+
+    /// The result of a calibration once the stroke has been finished.
+    pub fn take_finished(&mut self) -> Option<PressureRange> {
+        self.finished.take()
+    }
+
+    pub fn process(&mut self, event: &mut PointerEvent) {
+        match event.pointer_type {
+            PointerType::Pen => (),
+            _ => return,
+        }
+        let recording = match &mut self.recording {
+            Some(recording) => recording,
+            None => {
+                if let Some(range) = self.range {
+                    if event.pressure > 0.0 {
+                        event.pressure = ((event.pressure - range.min) / (range.max - range.min))
+                            .max(0.0)
+                            .min(1.0);
+                    }
+                }
+                return;
+            }
+        };
+        if event.buttons.contains(Button::PRIMARY) && event.pressure > 0.0 {
+            *recording = Some(match *recording {
+                Some(range) => PressureRange {
+                    min: range.min.min(event.pressure),
+                    max: range.max.max(event.pressure),
+                },
+                None => PressureRange {
+                    min: event.pressure,
+                    max: event.pressure,
+                },
+            });
+        }
+        if let PointerEventType::UP = event.event_type {
+            match recording {
+                Some(range) if range.max - range.min >= MIN_RANGE => {
+                    info!(
+                        "Pen pressure calibrated to range from {:.2} to {:.2}.",
+                        range.min, range.max
+                    );
+                    self.range = Some(*range);
+                    self.finished = Some(*range);
+                    self.recording = None;
+                }
+                // try again with the next stroke
+                _ => *recording = None,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::test_events::pointer_event;
+
+    fn pen(event_type: PointerEventType, pressure: f64) -> PointerEvent {
+        let mut event = pointer_event(event_type, PointerType::Pen, 0.5, 0.5, 0);
+        event.pressure = pressure;
+        event
+    }
+
+    fn process(calibration: &mut PressureCalibration, mut event: PointerEvent) -> f64 {
+        calibration.process(&mut event);
+        event.pressure
+    }
+
+    #[test]
+    fn calibration_stroke_sets_range() {
+        let mut calibration = PressureCalibration::new();
+        calibration.start();
+        // not rescaled while calibrating
+        assert_eq!(
+            process(&mut calibration, pen(PointerEventType::DOWN, 0.2)),
+            0.2
+        );
+        assert_eq!(
+            process(&mut calibration, pen(PointerEventType::MOVE, 0.6)),
+            0.6
+        );
+        assert!(calibration.take_finished().is_none());
+        process(&mut calibration, pen(PointerEventType::UP, 0.0));
+        let range = calibration.take_finished().unwrap();
+        assert_eq!((range.min, range.max), (0.2, 0.6));
+        assert!(calibration.take_finished().is_none());
+
+        assert!((process(&mut calibration, pen(PointerEventType::MOVE, 0.4)) - 0.5).abs() < 1e-9);
+        assert_eq!(
+            process(&mut calibration, pen(PointerEventType::MOVE, 0.1)),
+            0.0
+        );
+        assert_eq!(
+            process(&mut calibration, pen(PointerEventType::MOVE, 0.9)),
+            1.0
+        );
+        // hovering stays at zero
+        assert_eq!(
+            process(&mut calibration, pen(PointerEventType::MOVE, 0.0)),
+            0.0
+        );
+        let mut touch = pointer_event(PointerEventType::MOVE, PointerType::Touch, 0.5, 0.5, 0);
+        touch.pressure = 0.4;
+        assert_eq!(process(&mut calibration, touch), 0.4);
+    }
+
+    #[test]
+    fn short_strokes_are_retried() {
+        let mut calibration = PressureCalibration::new();
+        calibration.start();
+        process(&mut calibration, pen(PointerEventType::DOWN, 0.5));
+        process(&mut calibration, pen(PointerEventType::UP, 0.0));
+        assert!(calibration.take_finished().is_none());
+        process(&mut calibration, pen(PointerEventType::DOWN, 0.3));
+        process(&mut calibration, pen(PointerEventType::MOVE, 0.7));
+        process(&mut calibration, pen(PointerEventType::UP, 0.0));
+        let range = calibration.take_finished().unwrap();
+        assert_eq!((range.min, range.max), (0.3, 0.7));
+    }
+
+    #[test]
+    fn narrow_ranges_are_ignored() {
+        let mut calibration = PressureCalibration::new();
+        calibration.set_range(Some(PressureRange {
+            min: 0.5,
+            max: 0.51,
+        }));
+        assert_eq!(
+            process(&mut calibration, pen(PointerEventType::MOVE, 0.505)),
+            0.505
+        );
+        calibration.set_range(Some(PressureRange { min: 0.0, max: 0.5 }));
+        assert_eq!(
+            process(&mut calibration, pen(PointerEventType::MOVE, 0.25)),
+            0.5
+        );
+        calibration.set_range(None);
+        assert_eq!(
+            process(&mut calibration, pen(PointerEventType::MOVE, 0.25)),
+            0.25
+        );
+    }
+}
@@ -0,0 +1,109 @@
+use std::time::{Duration, Instant};
+
+use tracing::warn;
+
+use crate::protocol::{PointerEvent, PointerEventType};
+
+// dropped events are reported at most this often to keep a flood out of the log
+const REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Caps the number of pointer events accepted from a client per second, a client may exceed the
+/// rate for up to a second before events are dropped. Moving the pointer only changes its
+/// position, so excess move events are dropped and later events take over their position. Events
+/// that press or release something keep being accepted for another second over the limit, this
+/// way strokes are ended properly while the client is throttled.
+pub struct RateLimiter {
+    // events per second, 0 disables the limit
+    max_rate: f64,
+    // events that may be accepted right now, negative if events that press or release something
+    // exceeded the limit
+    budget: f64,
+    last_update: Instant,
+    dropped: usize,
+    last_report: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(max_rate: u32) -> Self {
+        let max_rate = max_rate as f64;
+        Self {
+            max_rate,
+            budget: max_rate,
+            last_update: Instant::now(),
+            dropped: 0,
+            last_report: Instant::now(),
+        }
+    }
+
+    /// Whether the event should be processed or dropped.
+    pub fn accept(&mut self, event: &PointerEvent) -> bool {
+        if self.max_rate <= 0.0 {
+            return true;
+        }
+        let now = Instant::now();
+        self.budget = (self.budget
+            + now.duration_since(self.last_update).as_secs_f64() * self.max_rate)
+            .min(self.max_rate);
+        self.last_update = now;
+        let min_budget = match event.event_type {
+            PointerEventType::MOVE => 1.0,
+            _ => 1.0 - self.max_rate,
+        };
+        if self.budget >= min_budget {
+            self.budget -= 1.0;
+            return true;
+        }
+        self.dropped += 1;
+        if now.duration_since(self.last_report) >= REPORT_INTERVAL {
+            warn!(
+                "Client exceeds the limit of {} input events per second, dropped {} events.",
+                self.max_rate, self.dropped
+            );
+            self.dropped = 0;
+            self.last_report = now;
+        }
+        false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::test_events::pointer_event;
+    use crate::protocol::PointerType;
+
+    fn event(event_type: PointerEventType) -> PointerEvent {
+        pointer_event(event_type, PointerType::Pen, 0.5, 0.5, 0)
+    }
+
+    #[test]
+    fn moves_are_dropped_over_the_limit() {
+        let mut limiter = RateLimiter::new(10);
+        for _ in 0..10 {
+            assert!(limiter.accept(&event(PointerEventType::MOVE)));
+        }
+        assert!(!limiter.accept(&event(PointerEventType::MOVE)));
+        // events ending strokes are accepted for another second
+        for _ in 0..10 {
+            assert!(limiter.accept(&event(PointerEventType::UP)));
+        }
+        assert!(!limiter.accept(&event(PointerEventType::UP)));
+        assert!(!limiter.accept(&event(PointerEventType::MOVE)));
+    }
+
+    #[test]
+    fn budget_recovers() {
+        let mut limiter = RateLimiter::new(100);
+        while limiter.accept(&event(PointerEventType::MOVE)) {}
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(limiter.accept(&event(PointerEventType::MOVE)));
+    }
+
+    #[test]
+    fn zero_disables_the_limit() {
+        let mut limiter = RateLimiter::new(0);
+        for _ in 0..10000 {
+            assert!(limiter.accept(&event(PointerEventType::MOVE)));
+        }
+    }
+}
@@ -0,0 +1,77 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::input::device::InputDevice;
+use crate::protocol::NetMessage;
+
+#[derive(Serialize, Deserialize)]
+struct RecordedMessage {
+    // milliseconds since the recording has been started
+    time: f64,
+    message: NetMessage,
+}
+
+/// Writes messages received from clients to a file, one JSON object per line. Pointer events are
+/// recorded as they are sent to the input device, that is after rotation and smoothing have been
+/// applied.
+pub struct InputRecorder {
+    writer: BufWriter<File>,
+    start_time: Instant,
+}
+
+impl InputRecorder {
+    pub fn new(path: &Path) -> std::io::Result<Self> {
+        Ok(Self {
+            writer: BufWriter::new(File::create(path)?),
+            start_time: Instant::now(),
+        })
+    }
+
+    pub fn record(&mut self, message: NetMessage) {
+        let recorded = RecordedMessage {
+            time: self.start_time.elapsed().as_secs_f64() * 1000.0,
+            message,
+        };
+        let res = serde_json::to_writer(&mut self.writer, &recorded)
+            .map_err(std::io::Error::from)
+            .and_then(|_| self.writer.write_all(b"\n"))
+            .and_then(|_| self.writer.flush());
+        if let Err(err) = res {
+            warn!("Failed to record input: {}", err);
+        }
+    }
+}
+
+/// Send the pointer events of a recording to the input device, keeping their original timing.
+pub fn replay(
+    path: &Path,
+    device: &mut impl InputDevice,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let reader = BufReader::new(File::open(path)?);
+    let start_time = Instant::now();
+    for (n, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let recorded: RecordedMessage = serde_json::from_str(&line)
+            .map_err(|err| format!("{}:{}: {}", path.display(), n + 1, err))?;
+        let time = start_time + Duration::from_micros((recorded.time * 1000.0) as u64);
+        let now = Instant::now();
+        if time > now {
+            std::thread::sleep(time - now);
+        }
+        match recorded.message {
+            NetMessage::PointerEvent(event) => device.send_event(&event),
+            NetMessage::PointerEvents(events) => device.send_events(&events),
+            _ => (),
+        }
+    }
+    info!("Finished replaying {}.", path.display());
+    Ok(())
+}
@@ -0,0 +1,136 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use autopilot::key::{self, Character, Code, Flag, KeyCode};
+use serde::{Deserialize, Serialize};
+
+/// Button shown on the client, pressing it sends the keys to the host.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Shortcut {
+    pub name: String,
+    // key combinations separated by spaces, keys of a combination are joined by '+',
+    // e.g. "ctrl+shift+z" or "ctrl+s ctrl+w"
+    pub keys: String,
+}
+
+pub fn default_shortcuts() -> Vec<Shortcut> {
+    vec![
+        Shortcut {
+            name: "Undo".into(),
+            keys: "ctrl+z".into(),
+        },
+        Shortcut {
+            name: "Redo".into(),
+            keys: "ctrl+shift+z".into(),
+        },
+    ]
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Key {
+    Character(char),
+    Code(KeyCode),
+}
+
+#[derive(Clone, Debug)]
+pub struct KeyCombo {
+    key: Key,
+    modifiers: Vec<Flag>,
+}
+
+impl FromStr for KeyCombo {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut modifiers = Vec::new();
+        let mut key = None;
+        for part in s.split('+') {
+            if key.is_some() {
+                return Err(format!("'{}': only modifiers may precede the key", s));
+            }
+            let part = part.to_lowercase();
+            match part.as_str() {
+                "ctrl" | "control" => modifiers.push(Flag::Control),
+                "shift" => modifiers.push(Flag::Shift),
+                "alt" => modifiers.push(Flag::Alt),
+                "meta" | "super" | "cmd" => modifiers.push(Flag::Meta),
+                _ => key = Some(parse_key(&part).ok_or(format!("'{}': unknown key", s))?),
+            }
+        }
+        match key {
+            Some(key) => Ok(Self { key, modifiers }),
+            None => Err(format!("'{}': no key given", s)),
+        }
+    }
+}
+
+fn parse_key(s: &str) -> Option<Key> {
+    let mut chars = s.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Some(Key::Character(c));
+    }
+    let code = match s {
+        "f1" => KeyCode::F1,
+        "f2" => KeyCode::F2,
+        "f3" => KeyCode::F3,
+        "f4" => KeyCode::F4,
+        "f5" => KeyCode::F5,
+        "f6" => KeyCode::F6,
+        "f7" => KeyCode::F7,
+        "f8" => KeyCode::F8,
+        "f9" => KeyCode::F9,
+        "f10" => KeyCode::F10,
+        "f11" => KeyCode::F11,
+        "f12" => KeyCode::F12,
+        "left" => KeyCode::LeftArrow,
+        "right" => KeyCode::RightArrow,
+        "up" => KeyCode::UpArrow,
+        "down" => KeyCode::DownArrow,
+        "home" => KeyCode::Home,
+        "end" => KeyCode::End,
+        "pageup" => KeyCode::PageUp,
+        "pagedown" => KeyCode::PageDown,
+        "enter" | "return" => KeyCode::Return,
+        "delete" => KeyCode::Delete,
+        "backspace" => KeyCode::Backspace,
+        "escape" | "esc" => KeyCode::Escape,
+        "tab" => KeyCode::Tab,
+        "space" => KeyCode::Space,
+        _ => return None,
+    };
+    Some(Key::Code(code))
+}
+
+/// Parse a sequence of key combinations as described for Shortcut::keys.
+pub fn parse_key_sequence(s: &str) -> Result<Vec<KeyCombo>, String> {
+    let combos = s
+        .split_whitespace()
+        .map(KeyCombo::from_str)
+        .collect::<Result<Vec<_>, _>>()?;
+    if combos.is_empty() {
+        return Err("no keys given".into());
+    }
+    Ok(combos)
+}
+
+/// Press and release the key combinations one after another.
+pub fn tap_keys(combos: &[KeyCombo]) {
+    for combo in combos {
+        match combo.key {
+            Key::Character(c) => key::tap(&Character(c), &combo.modifiers, 0, 0),
+            Key::Code(code) => key::tap(&Code(code), &combo.modifiers, 0, 0),
+        }
+    }
+}
+
+/// Maps names of shortcuts to the keys they send, shortcuts with invalid keys are skipped.
+pub fn shortcut_table(shortcuts: &[Shortcut]) -> HashMap<String, Vec<KeyCombo>> {
+    shortcuts
+        .iter()
+        .filter_map(|s| {
+            parse_key_sequence(&s.keys)
+                .ok()
+                .map(|keys| (s.name.clone(), keys))
+        })
+        .collect()
+}
@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+use std::f64::consts::PI;
+use std::str::FromStr;
+
+use crate::protocol::{PointerEvent, PointerEventType};
+
+/// Strength of the filter applied to pointer coordinates to compensate for jitter, e.g. caused by
+/// an unreliable Wi-Fi connection.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Smoothing {
+    Off,
+    Low,
+    Medium,
+    High,
+}
+
+impl Smoothing {
+    // minimal cutoff frequency in Hz, lower values mean more smoothing but also more lag at low
+    // speeds
+    fn min_cutoff(self) -> Option<f64> {
+        match self {
+            Smoothing::Off => None,
+            Smoothing::Low => Some(3.0),
+            Smoothing::Medium => Some(1.5),
+            Smoothing::High => Some(0.7),
+        }
+    }
+}
+
+impl FromStr for Smoothing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "off" => Ok(Self::Off),
+            "low" => Ok(Self::Low),
+            "medium" => Ok(Self::Medium),
+            "high" => Ok(Self::High),
+            _ => Err(format!(
+                "Unknown smoothing '{}', use off, low, medium or high.",
+                s
+            )),
+        }
+    }
+}
+
+// increase of the cutoff frequency with speed (in screens per second), this keeps fast strokes
+// responsive
+const BETA: f64 = 5.0;
+// cutoff frequency used to filter the speed
+const DERIVATE_CUTOFF: f64 = 1.0;
+
+fn alpha(cutoff: f64, dt: f64) -> f64 {
+    let tau = 1.0 / (2.0 * PI * cutoff);
+    1.0 / (1.0 + tau / dt)
+}
+
+/// 1€ filter, see: Casiez, Roussel, Vogel: 1€ Filter: A Simple Speed-based Low-pass Filter for
+/// Noisy Input in Interactive Systems.
+struct OneEuroFilter {
+    min_cutoff: f64,
+    value: f64,
+    derivate: f64,
+}
+
+impl OneEuroFilter {
+    fn new(min_cutoff: f64, value: f64) -> Self {
+        Self {
+            min_cutoff,
+            value,
+            derivate: 0.0,
+        }
+    }
+
+    fn filter(&mut self, value: f64, dt: f64) -> f64 {
+        let derivate = (value - self.value) / dt;
+        self.derivate += alpha(DERIVATE_CUTOFF, dt) * (derivate - self.derivate);
+        let cutoff = self.min_cutoff + BETA * self.derivate.abs();
+        self.value += alpha(cutoff, dt) * (value - self.value);
+        self.value
+    }
+}
+
+struct PointerFilter {
+    x: OneEuroFilter,
+    y: OneEuroFilter,
+    // microseconds
+    timestamp: u64,
+}
+
+/// Smoothes the coordinates of pointer events, every pointer is filtered separately.
+pub struct PointerSmoother {
+    min_cutoff: Option<f64>,
+    filters: HashMap<i64, PointerFilter>,
+}
+
+impl PointerSmoother {
+    pub fn new(smoothing: Smoothing) -> Self {
+        Self {
+            min_cutoff: smoothing.min_cutoff(),
+            filters: HashMap::new(),
+        }
+    }
+
+    pub fn smooth(&mut self, event: &mut PointerEvent) {
+        let min_cutoff = match self.min_cutoff {
+            Some(min_cutoff) => min_cutoff,
+            None => return,
+        };
+        match event.event_type {
+            PointerEventType::DOWN => {
+                self.filters.insert(
+                    event.pointer_id,
+                    PointerFilter {
+                        x: OneEuroFilter::new(min_cutoff, event.x),
+                        y: OneEuroFilter::new(min_cutoff, event.y),
+                        timestamp: event.timestamp,
+                    },
+                );
+            }
+            PointerEventType::MOVE => {
+                // hovering pointers do not have a filter yet
+                let filter =
+                    self.filters
+                        .entry(event.pointer_id)
+                        .or_insert_with(|| PointerFilter {
+                            x: OneEuroFilter::new(min_cutoff, event.x),
+                            y: OneEuroFilter::new(min_cutoff, event.y),
+                            timestamp: event.timestamp,
+                        });
+                if event.timestamp <= filter.timestamp {
+                    return;
+                }
+                let dt = (event.timestamp - filter.timestamp) as f64 / 1_000_000.0;
+                filter.timestamp = event.timestamp;
+                event.x = filter.x.filter(event.x, dt);
+                event.y = filter.y.filter(event.y, dt);
+            }
+            PointerEventType::UP | PointerEventType::CANCEL | PointerEventType::LEAVE => {
+                // lifting the pointer at the filtered position avoids a jump at the end of strokes
+                if let Some(filter) = self.filters.remove(&event.pointer_id) {
+                    event.x = filter.x.value;
+                    event.y = filter.y.value;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::test_events::pointer_event_ms;
+    use crate::protocol::PointerType;
+
+    // the x coordinate after smoothing a pen event
+    fn smooth(
+        smoother: &mut PointerSmoother,
+        event_type: PointerEventType,
+        x: f64,
+        ms: u64,
+    ) -> f64 {
+        let mut event = pointer_event_ms(event_type, PointerType::Pen, x, 0.5, ms);
+        smoother.smooth(&mut event);
+        event.x
+    }
+
+    #[test]
+    fn off_keeps_events() {
+        let mut smoother = PointerSmoother::new(Smoothing::Off);
+        assert_eq!(smooth(&mut smoother, PointerEventType::DOWN, 0.5, 0), 0.5);
+        assert_eq!(smooth(&mut smoother, PointerEventType::MOVE, 0.6, 10), 0.6);
+        assert_eq!(smooth(&mut smoother, PointerEventType::UP, 0.7, 20), 0.7);
+    }
+
+    #[test]
+    fn jumps_are_smoothed() {
+        let mut smoother = PointerSmoother::new(Smoothing::Medium);
+        assert_eq!(smooth(&mut smoother, PointerEventType::DOWN, 0.5, 0), 0.5);
+        let x = smooth(&mut smoother, PointerEventType::MOVE, 0.6, 10);
+        assert!(x > 0.5 && x < 0.6, "{}", x);
+        // events that are not newer than the last one are left alone
+        assert_eq!(smooth(&mut smoother, PointerEventType::MOVE, 0.7, 10), 0.7);
+        // the pointer converges to where it rests
+        let mut x = 0.0;
+        for i in 2..200 {
+            x = smooth(&mut smoother, PointerEventType::MOVE, 0.6, i * 10);
+        }
+        assert!((x - 0.6).abs() < 1e-3, "{}", x);
+        // the pointer is lifted where it has been drawn last instead of jumping
+        assert_eq!(smooth(&mut smoother, PointerEventType::UP, 0.9, 2000), x);
+    }
+
+    #[test]
+    fn stronger_smoothing_lags_more() {
+        let lag = |smoothing| {
+            let mut smoother = PointerSmoother::new(smoothing);
+            smooth(&mut smoother, PointerEventType::DOWN, 0.5, 0);
+            0.6 - smooth(&mut smoother, PointerEventType::MOVE, 0.6, 10)
+        };
+        assert!(lag(Smoothing::Low) < lag(Smoothing::Medium));
+        assert!(lag(Smoothing::Medium) < lag(Smoothing::High));
+    }
+}
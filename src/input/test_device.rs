@@ -0,0 +1,75 @@
+use std::sync::{Arc, Mutex};
+
+use crate::input::device::InputDevice;
+use crate::input::shortcuts::KeyCombo;
+use crate::protocol::PointerEvent;
+
+/// Everything an input device has been asked to do.
+// not every test inspects all the fields
+#[allow(dead_code)]
+#[derive(Clone, Debug)]
+pub enum RecordedInput {
+    Event(PointerEvent),
+    Wheel {
+        x: f64,
+        y: f64,
+        horizontal: i32,
+        vertical: i32,
+        zoom: bool,
+    },
+    RightClick(f64, f64),
+    Keys(Vec<KeyCombo>),
+}
+
+/// Input device that records the input instead of sending it to the system. The recording is
+/// shared, so it can be inspected while the device is owned by a stream handler.
+pub struct TestInputDevice {
+    recorded: Arc<Mutex<Vec<RecordedInput>>>,
+}
+
+impl TestInputDevice {
+    pub fn new() -> (Self, Arc<Mutex<Vec<RecordedInput>>>) {
+        let recorded = Arc::new(Mutex::new(Vec::new()));
+        (
+            Self {
+                recorded: recorded.clone(),
+            },
+            recorded,
+        )
+    }
+
+    fn record(&self, input: RecordedInput) {
+        self.recorded.lock().unwrap().push(input);
+    }
+}
+
+impl InputDevice for TestInputDevice {
+    fn send_event(&mut self, event: &PointerEvent) {
+        self.record(RecordedInput::Event(event.clone()));
+    }
+
+    // events are recorded without the delays used to space them out
+    fn send_events(&mut self, events: &[PointerEvent]) {
+        for event in events {
+            self.send_event(event);
+        }
+    }
+
+    fn send_wheel(&mut self, x: f64, y: f64, horizontal: i32, vertical: i32, zoom: bool) {
+        self.record(RecordedInput::Wheel {
+            x,
+            y,
+            horizontal,
+            vertical,
+            zoom,
+        });
+    }
+
+    fn send_right_click(&mut self, x: f64, y: f64) {
+        self.record(RecordedInput::RightClick(x, y));
+    }
+
+    fn send_keys(&mut self, keys: &[KeyCombo]) {
+        self.record(RecordedInput::Keys(keys.to_vec()));
+    }
+}
@@ -0,0 +1,47 @@
+use crate::protocol::{Button, PenTool, PointerEvent, PointerEventType, PointerType};
+
+/// A pointer event as the web client sends it, with the primary button pressed while in contact.
+/// The timestamp is in microseconds like the one of the client.
+pub fn pointer_event(
+    event_type: PointerEventType,
+    pointer_type: PointerType,
+    x: f64,
+    y: f64,
+    timestamp: u64,
+) -> PointerEvent {
+    let buttons = match event_type {
+        PointerEventType::DOWN | PointerEventType::MOVE => Button::PRIMARY,
+        _ => Button::NONE,
+    };
+    PointerEvent {
+        event_type,
+        pointer_id: 1,
+        timestamp,
+        is_primary: true,
+        pointer_type,
+        button: Button::PRIMARY,
+        buttons,
+        x,
+        y,
+        movement_x: 0,
+        movement_y: 0,
+        pressure: 0.5,
+        tilt_x: 0,
+        tilt_y: 0,
+        twist: 0,
+        width: 1.0,
+        height: 1.0,
+        tool: PenTool::Pen,
+    }
+}
+
+/// Like pointer_event with the timestamp in milliseconds, which keeps delays in tests readable.
+pub fn pointer_event_ms(
+    event_type: PointerEventType,
+    pointer_type: PointerType,
+    x: f64,
+    y: f64,
+    millis: u64,
+) -> PointerEvent {
+    pointer_event(event_type, pointer_type, x, y, millis * 1000)
+}
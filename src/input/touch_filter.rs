@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::protocol::{PointerEvent, PointerEventType, PointerType};
+
+#[derive(Deserialize, Clone, Copy, Debug, Default)]
+#[serde(default)]
+pub struct TouchFilterOptions {
+    // movements of a touch shorter than this are ignored, relative to the size of the screen
+    pub dead_zone: f64,
+    // touches lifted within this many milliseconds are considered noise and ignored
+    pub debounce: u64,
+}
+
+impl TouchFilterOptions {
+    pub fn is_enabled(&self) -> bool {
+        self.dead_zone > 0.0 || self.debounce > 0
+    }
+}
+
+struct Touch {
+    // position of the last event that has not been dropped
+    x: f64,
+    y: f64,
+    // microseconds on the clock of the client
+    timestamp: u64,
+    // events withheld until the touch lasted longer than the debounce interval
+    pending: Vec<PointerEvent>,
+    released: bool,
+}
+
+/// Filters noise of touch input caused by digitizers of poor quality: Tiny movements are dropped
+/// and touches which are lifted right after going down are ignored.
+pub struct TouchFilter {
+    options: TouchFilterOptions,
+    touches: HashMap<i64, Touch>,
+}
+
+impl TouchFilter {
+    pub fn new(options: TouchFilterOptions) -> Self {
+        Self {
+            options,
+            touches: HashMap::new(),
+        }
+    }
+
+    /// Process an event, events that should be sent to the host are appended to out.
+    pub fn process(&mut self, event: &PointerEvent, out: &mut Vec<PointerEvent>) {
+        match event.pointer_type {
+            PointerType::Touch => (),
+            _ => {
+                out.push(event.clone());
+                return;
+            }
+        }
+        let debounce = self.options.debounce * 1000;
+        match event.event_type {
+            PointerEventType::DOWN => {
+                let mut touch = Touch {
+                    x: event.x,
+                    y: event.y,
+                    timestamp: event.timestamp,
+                    pending: vec![event.clone()],
+                    released: false,
+                };
+                if debounce == 0 {
+                    out.append(&mut touch.pending);
+                    touch.released = true;
+                }
+                self.touches.insert(event.pointer_id, touch);
+            }
+            PointerEventType::MOVE => {
+                let touch = match self.touches.get_mut(&event.pointer_id) {
+                    Some(touch) => touch,
+                    None => {
+                        out.push(event.clone());
+                        return;
+                    }
+                };
+                if !touch.released && event.timestamp.saturating_sub(touch.timestamp) >= debounce {
+                    out.append(&mut touch.pending);
+                    touch.released = true;
+                }
+                let distance = ((event.x - touch.x).powi(2) + (event.y - touch.y).powi(2)).sqrt();
+                if distance < self.options.dead_zone {
+                    return;
+                }
+                touch.x = event.x;
+                touch.y = event.y;
+                if touch.released {
+                    out.push(event.clone());
+                } else {
+                    touch.pending.push(event.clone());
+                }
+            }
+            PointerEventType::UP | PointerEventType::CANCEL | PointerEventType::LEAVE => {
+                match self.touches.remove(&event.pointer_id) {
+                    Some(mut touch) => {
+                        if !touch.released
+                            && event.timestamp.saturating_sub(touch.timestamp) >= debounce
+                        {
+                            out.append(&mut touch.pending);
+                            touch.released = true;
+                        }
+                        // nothing has been sent for touches that are too short
+                        if touch.released {
+                            out.push(event.clone());
+                        }
+                    }
+                    None => out.push(event.clone()),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::test_events::pointer_event_ms;
+
+    #[test]
+    fn short_touches_are_dropped() {
+        let options = TouchFilterOptions {
+            dead_zone: 0.0,
+            debounce: 50,
+        };
+        assert!(options.is_enabled());
+        let mut filter = TouchFilter::new(options);
+        let mut out = Vec::new();
+        filter.process(
+            &pointer_event_ms(PointerEventType::DOWN, PointerType::Touch, 0.5, 0.5, 0),
+            &mut out,
+        );
+        filter.process(
+            &pointer_event_ms(PointerEventType::MOVE, PointerType::Touch, 0.6, 0.5, 20),
+            &mut out,
+        );
+        filter.process(
+            &pointer_event_ms(PointerEventType::UP, PointerType::Touch, 0.6, 0.5, 30),
+            &mut out,
+        );
+        assert!(out.is_empty());
+
+        filter.process(
+            &pointer_event_ms(PointerEventType::DOWN, PointerType::Touch, 0.5, 0.5, 100),
+            &mut out,
+        );
+        filter.process(
+            &pointer_event_ms(PointerEventType::MOVE, PointerType::Touch, 0.6, 0.5, 120),
+            &mut out,
+        );
+        assert!(out.is_empty());
+        // withheld events are sent once the touch lasted long enough
+        filter.process(
+            &pointer_event_ms(PointerEventType::MOVE, PointerType::Touch, 0.7, 0.5, 160),
+            &mut out,
+        );
+        assert_eq!(out.len(), 3);
+        filter.process(
+            &pointer_event_ms(PointerEventType::UP, PointerType::Touch, 0.7, 0.5, 170),
+            &mut out,
+        );
+        assert_eq!(out.len(), 4);
+    }
+
+    #[test]
+    fn tiny_movements_are_dropped() {
+        let options = TouchFilterOptions {
+            dead_zone: 0.01,
+            debounce: 0,
+        };
+        let mut filter = TouchFilter::new(options);
+        let mut out = Vec::new();
+        filter.process(
+            &pointer_event_ms(PointerEventType::DOWN, PointerType::Touch, 0.5, 0.5, 0),
+            &mut out,
+        );
+        assert_eq!(out.len(), 1);
+        filter.process(
+            &pointer_event_ms(PointerEventType::MOVE, PointerType::Touch, 0.505, 0.5, 10),
+            &mut out,
+        );
+        filter.process(
+            &pointer_event_ms(PointerEventType::MOVE, PointerType::Touch, 0.509, 0.5, 20),
+            &mut out,
+        );
+        assert_eq!(out.len(), 1);
+        // distances are measured from the last event sent
+        filter.process(
+            &pointer_event_ms(PointerEventType::MOVE, PointerType::Touch, 0.511, 0.5, 30),
+            &mut out,
+        );
+        assert_eq!(out.len(), 2);
+        filter.process(
+            &pointer_event_ms(PointerEventType::UP, PointerType::Touch, 0.511, 0.5, 40),
+            &mut out,
+        );
+        assert_eq!(out.len(), 3);
+    }
+
+    #[test]
+    fn other_pointers_are_passed_on() {
+        assert!(!TouchFilterOptions::default().is_enabled());
+        let mut filter = TouchFilter::new(TouchFilterOptions {
+            dead_zone: 0.1,
+            debounce: 1000,
+        });
+        let mut out = Vec::new();
+        let pen = pointer_event_ms(PointerEventType::DOWN, PointerType::Pen, 0.5, 0.5, 0);
+        filter.process(&pen, &mut out);
+        // a touch that went down before the filter existed
+        filter.process(
+            &pointer_event_ms(PointerEventType::MOVE, PointerType::Touch, 0.5, 0.5, 0),
+            &mut out,
+        );
+        filter.process(
+            &pointer_event_ms(PointerEventType::UP, PointerType::Touch, 0.5, 0.5, 0),
+            &mut out,
+        );
+        assert_eq!(out.len(), 3);
+    }
+}
@@ -1,16 +1,23 @@
+use std::collections::HashMap;
 use std::ffi::CString;
 use std::os::raw::{c_char, c_int};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use crate::input::device::InputDevice;
+use crate::input::device::{pace_coalesced, InputDevice, MAX_COALESCED_DELAY};
+use crate::input::gestures::set_zoom_modifier;
+use crate::input::uinput_helper;
+use crate::input::shortcuts::{tap_keys, KeyCombo};
 use crate::protocol::Button;
+use crate::protocol::PenTool;
 use crate::protocol::PointerEvent;
 use crate::protocol::PointerEventType;
 use crate::protocol::PointerType;
-use crate::x11helper::{Capturable, X11Context};
+use crate::x11helper::{Capturable, CaptureTarget, X11Context};
 
 use crate::cerror::CError;
 
-use tracing::warn;
+use tracing::{info, warn};
 
 extern "C" {
     fn init_uinput_stylus(name: *const c_char, err: *mut CError) -> c_int;
@@ -20,16 +27,73 @@ extern "C" {
     fn send_uinput_event(device: c_int, typ: c_int, code: c_int, value: c_int, err: *mut CError);
 }
 
+/// The virtual devices created for every client.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DeviceKind {
+    Stylus,
+    Mouse,
+    Touch,
+}
+
+/// Create a device via /dev/uinput in this process.
+pub fn open_device(kind: DeviceKind, name: &str) -> Result<c_int, CError> {
+    let init = match kind {
+        DeviceKind::Stylus => init_uinput_stylus,
+        DeviceKind::Mouse => init_uinput_mouse,
+        DeviceKind::Touch => init_uinput_touch,
+    };
+    let mut err = CError::new();
+    let name_c_str = CString::new(name.as_bytes()).unwrap();
+    let fd = unsafe { init(name_c_str.as_ptr(), &mut err) };
+    if err.is_err() {
+        return Err(err);
+    }
+    Ok(fd)
+}
+
+// devices are created by the input helper if Weylus is connected to one, in any case the helper
+// cleans them up if Weylus exits without destroying them
+fn create_device(kind: DeviceKind, name: &str) -> Result<c_int, CError> {
+    let fd = if uinput_helper::connected() {
+        uinput_helper::create(kind, name)?
+    } else {
+        open_device(kind, name)?
+    };
+    uinput_helper::watch(fd);
+    Ok(fd)
+}
+
+fn destroy_device(fd: c_int) {
+    uinput_helper::forget(fd);
+    unsafe { destroy_uinput_device(fd) };
+}
+
+// devices that vanished are recreated at most this often, this avoids flooding the system if
+// creating devices fails
+const RECREATE_INTERVAL: Duration = Duration::from_secs(1);
+
 struct MultiTouch {
     id: i64,
+    tracking_id: i32,
 }
 
+// number of multitouch slots and the largest tracking id, these have to match the ranges of
+// ABS_MT_SLOT and ABS_MT_TRACKING_ID in uinput.c
+const NUM_TOUCH_SLOTS: usize = 10;
+const MAX_TRACKING_ID: i32 = 65535;
+
 pub struct GraphicTablet {
     stylus_fd: c_int,
     mouse_fd: c_int,
     touch_fd: c_int,
-    touches: [Option<MultiTouch>; 5],
+    touches: [Option<MultiTouch>; NUM_TOUCH_SLOTS],
+    next_tracking_id: i32,
+    // tool of the pen if it is in range of the tablet
+    pen_tool: Option<PenTool>,
+    target: Arc<CaptureTarget>,
     capture: Capturable,
+    // generation of the capture target capture belongs to
+    capture_generation: usize,
     x: f64,
     y: f64,
     width: f64,
@@ -44,45 +108,51 @@ pub struct GraphicTablet {
     num_stylus_mapping_tries: usize,
     num_touch_mapping_tries: usize,
     x11ctx: Option<X11Context>,
+    // keys and buttons currently pressed by device, required to restore the state of recreated
+    // devices
+    pressed_keys: HashMap<c_int, Vec<c_int>>,
+    touch_slot: c_int,
+    last_recreate: Option<Instant>,
 }
 
 impl GraphicTablet {
     pub fn new(
-        capture: Capturable,
+        target: Arc<CaptureTarget>,
         id: String,
         enable_mouse: bool,
         enable_stylus: bool,
         enable_touch: bool,
     ) -> Result<Self, CError> {
-        let mut err = CError::new();
+        let (capture, capture_generation) = target.get();
         let name_stylus = format!("Weylus Stylus - {}", id);
-        let name_stylus_c_str = CString::new(name_stylus.as_bytes()).unwrap();
-        let stylus_fd = unsafe { init_uinput_stylus(name_stylus_c_str.as_ptr(), &mut err) };
-        if err.is_err() {
-            return Err(err);
-        }
+        let stylus_fd = create_device(DeviceKind::Stylus, &name_stylus)?;
         let name_mouse = format!("Weylus Mouse - {}", id);
-        let name_mouse_c_str = CString::new(name_mouse.as_bytes()).unwrap();
-
-        let mouse_fd = unsafe { init_uinput_mouse(name_mouse_c_str.as_ptr(), &mut err) };
-        if err.is_err() {
-            unsafe { destroy_uinput_device(stylus_fd) };
-            return Err(err);
-        }
+        let mouse_fd = match create_device(DeviceKind::Mouse, &name_mouse) {
+            Ok(fd) => fd,
+            Err(err) => {
+                destroy_device(stylus_fd);
+                return Err(err);
+            }
+        };
         let name_touch = format!("Weylus Touch - {}", id);
-        let name_touch_c_str = CString::new(name_touch.as_bytes()).unwrap();
-        let touch_fd = unsafe { init_uinput_touch(name_touch_c_str.as_ptr(), &mut err) };
-        if err.is_err() {
-            unsafe { destroy_uinput_device(stylus_fd) };
-            unsafe { destroy_uinput_device(mouse_fd) };
-            return Err(err);
-        }
+        let touch_fd = match create_device(DeviceKind::Touch, &name_touch) {
+            Ok(fd) => fd,
+            Err(err) => {
+                destroy_device(stylus_fd);
+                destroy_device(mouse_fd);
+                return Err(err);
+            }
+        };
         let tblt = Self {
             stylus_fd,
             mouse_fd,
             touch_fd,
             touches: Default::default(),
+            next_tracking_id: 0,
+            pen_tool: None,
+            target,
             capture,
+            capture_generation,
             x: 0.0,
             y: 0.0,
             width: 1.0,
@@ -97,6 +167,9 @@ impl GraphicTablet {
             num_stylus_mapping_tries: 0,
             num_touch_mapping_tries: 0,
             x11ctx: X11Context::new(),
+            pressed_keys: HashMap::new(),
+            touch_slot: 0,
+            last_recreate: None,
         };
         Ok(tblt)
     }
@@ -135,31 +208,126 @@ impl GraphicTablet {
             })
     }
 
-    fn send(&self, fd: c_int, typ: c_int, code: c_int, value: c_int) {
+    fn num_touches(&self) -> usize {
+        self.touches.iter().filter(|mt| mt.is_some()).count()
+    }
+
+    // BTN_TOUCH and BTN_TOOL_FINGER, BTN_TOOL_DOUBLETAP, ... report how many fingers touch the
+    // screen to applications that do not understand multitouch slots
+    fn send_num_touches(&mut self, previous: usize) {
+        fn tool(num_touches: usize) -> Option<c_int> {
+            match num_touches {
+                0 => None,
+                1 => Some(EC_KEY_TOOL_FINGER),
+                2 => Some(EC_KEY_TOOL_DOUBLETAP),
+                3 => Some(EC_KEY_TOOL_TRIPLETAP),
+                4 => Some(EC_KEY_TOOL_QUADTAP),
+                _ => Some(EC_KEY_TOOL_QUINTTAP),
+            }
+        }
+        let num_touches = self.num_touches();
+        if (previous == 0) != (num_touches == 0) {
+            self.send(self.touch_fd, ET_KEY, EC_KEY_TOUCH, (num_touches > 0) as i32);
+        }
+        if tool(previous) != tool(num_touches) {
+            if let Some(key) = tool(previous) {
+                self.send(self.touch_fd, ET_KEY, key, 0);
+            }
+            if let Some(key) = tool(num_touches) {
+                self.send(self.touch_fd, ET_KEY, key, 1);
+            }
+        }
+    }
+
+    fn send(&mut self, fd: c_int, typ: c_int, code: c_int, value: c_int) {
         let mut err = CError::new();
         unsafe {
             send_uinput_event(fd, typ, code, value, &mut err);
         }
         if err.is_err() {
+            // the device vanished, for example after suspend or reloading the uinput module
+            if err.code() == 2 {
+                if let Some(fd) = self.recreate_device(fd) {
+                    self.send(fd, typ, code, value);
+                    return;
+                }
+            }
             warn!("{}", err);
+            return;
+        }
+        if typ == ET_KEY {
+            let keys = self.pressed_keys.entry(fd).or_default();
+            keys.retain(|key| *key != code);
+            if value != 0 {
+                keys.push(code);
+            }
+        } else if fd == self.touch_fd && typ == ET_ABSOLUTE && code == EC_ABS_MT_SLOT {
+            self.touch_slot = value;
+        }
+    }
+
+    // replace the device behind fd by a new one with the same name and restore the pressed keys
+    // and active touches, the event frame currently being written is completed on the new device
+    fn recreate_device(&mut self, fd: c_int) -> Option<c_int> {
+        if let Some(last_recreate) = self.last_recreate {
+            if last_recreate.elapsed() < RECREATE_INTERVAL {
+                return None;
+            }
+        }
+        self.last_recreate = Some(Instant::now());
+        let (kind, name): (DeviceKind, &str) = if fd == self.stylus_fd {
+            (DeviceKind::Stylus, &self.name_stylus_device)
+        } else if fd == self.mouse_fd {
+            (DeviceKind::Mouse, &self.name_mouse_device)
+        } else if fd == self.touch_fd {
+            (DeviceKind::Touch, &self.name_touch_device)
+        } else {
+            return None;
+        };
+        let new_fd = match create_device(kind, name) {
+            Ok(fd) => fd,
+            Err(err) => {
+                warn!("Failed to recreate vanished input device {}: {}", name, err);
+                return None;
+            }
+        };
+        info!("Input device {} vanished, recreated it.", name);
+        destroy_device(fd);
+        let keys = self.pressed_keys.remove(&fd).unwrap_or_default();
+        if fd == self.stylus_fd {
+            self.stylus_fd = new_fd;
+        } else if fd == self.mouse_fd {
+            self.mouse_fd = new_fd;
+        } else {
+            self.touch_fd = new_fd;
+            let touch_slot = self.touch_slot;
+            for slot in 0..NUM_TOUCH_SLOTS {
+                if let Some(tracking_id) = self.touches[slot].as_ref().map(|mt| mt.tracking_id) {
+                    self.send(new_fd, ET_ABSOLUTE, EC_ABS_MT_SLOT, slot as i32);
+                    self.send(new_fd, ET_ABSOLUTE, EC_ABS_MT_TRACKING_ID, tracking_id);
+                }
+            }
+            self.send(new_fd, ET_ABSOLUTE, EC_ABS_MT_SLOT, touch_slot);
+        }
+        for key in keys {
+            self.send(new_fd, ET_KEY, key, 1);
         }
+        Some(new_fd)
     }
 }
 
 impl Drop for GraphicTablet {
     fn drop(&mut self) {
-        unsafe {
-            destroy_uinput_device(self.stylus_fd);
-            destroy_uinput_device(self.mouse_fd);
-            destroy_uinput_device(self.touch_fd);
-        };
+        destroy_device(self.stylus_fd);
+        destroy_device(self.mouse_fd);
+        destroy_device(self.touch_fd);
     }
 }
 
 // Event Types
 const ET_SYNC: c_int = 0x00;
 const ET_KEY: c_int = 0x01;
-//const ET_RELATIVE: c_int = 0x02;
+const ET_RELATIVE: c_int = 0x02;
 const ET_ABSOLUTE: c_int = 0x03;
 const ET_MSC: c_int = 0x04;
 
@@ -170,6 +338,8 @@ const EC_KEY_MOUSE_LEFT: c_int = 0x110;
 const EC_KEY_MOUSE_RIGHT: c_int = 0x111;
 const EC_KEY_MOUSE_MIDDLE: c_int = 0x112;
 const EC_KEY_TOOL_PEN: c_int = 0x140;
+const EC_KEY_TOOL_RUBBER: c_int = 0x141;
+const EC_KEY_TOOL_BRUSH: c_int = 0x142;
 const EC_KEY_TOUCH: c_int = 0x14a;
 const EC_KEY_TOOL_FINGER: c_int = 0x145;
 const EC_KEY_TOOL_DOUBLETAP: c_int = 0x14d;
@@ -178,6 +348,8 @@ const EC_KEY_TOOL_QUADTAP: c_int = 0x14f; /* Four fingers on trackpad */
 const EC_KEY_TOOL_QUINTTAP: c_int = 0x148; /* Five fingers on trackpad */
 //const EC_RELATIVE_X: c_int = 0x00;
 //const EC_RELATIVE_Y: c_int = 0x01;
+const EC_RELATIVE_HWHEEL: c_int = 0x06;
+const EC_RELATIVE_WHEEL: c_int = 0x08;
 
 const EC_ABSOLUTE_X: c_int = 0x00;
 const EC_ABSOLUTE_Y: c_int = 0x01;
@@ -195,6 +367,15 @@ const EC_ABS_MT_PRESSURE: c_int = 0x3a; /* Pressure on contact area */
 
 const EC_MSC_TIMESTAMP: c_int = 0x05;
 
+// there is no key for highlighters, applications usually treat brushes as a tool of their own
+fn tool_key(tool: PenTool) -> c_int {
+    match tool {
+        PenTool::Pen => EC_KEY_TOOL_PEN,
+        PenTool::Eraser => EC_KEY_TOOL_RUBBER,
+        PenTool::Highlighter => EC_KEY_TOOL_BRUSH,
+    }
+}
+
 // This is choosen somewhat arbitrarily
 // describes maximum value for ABS_X, ABS_Y, ABS_...
 // This corresponds to PointerEvent values of 1.0
@@ -217,40 +398,39 @@ const ABS_MAX: f64 = 65535.0;
 // has been choosen. If anyone knows a better solution: PLEASE FIX THIS!
 const MAX_SCREEN_MAPPING_TRIES: usize = 100;
 
-impl InputDevice for GraphicTablet {
-    fn send_event(&mut self, event: &PointerEvent) {
-        match event.pointer_type {
-            PointerType::Mouse | PointerType::Unknown => {
-                if !self.enable_mouse {
-                    return;
-                }
-            }
-            PointerType::Pen => {
-                if !self.enable_stylus {
-                    return;
-                }
-            }
-            PointerType::Touch => {
-                if !self.enable_touch {
-                    return;
-                }
-            }
+impl GraphicTablet {
+    fn is_enabled(&self, pointer_type: &PointerType) -> bool {
+        match pointer_type {
+            PointerType::Mouse | PointerType::Unknown => self.enable_mouse,
+            PointerType::Pen => self.enable_stylus,
+            PointerType::Touch => self.enable_touch,
         }
+    }
 
+    // activate the captured window and update its geometry, returns false if no input should be
+    // sent
+    fn prepare_input(&mut self) -> bool {
+        self.target
+            .update(&mut self.capture, &mut self.capture_generation);
         if let Err(err) = self.capture.before_input() {
             warn!("Failed to activate window, sending no input ({})", err);
-            return;
+            return false;
         }
         let geometry = self.capture.geometry();
         if let Err(err) = geometry {
             warn!("Failed to get window geometry, sending no input ({})", err);
-            return;
+            return false;
         }
         let geometry = geometry.unwrap();
         self.x = geometry.x;
         self.y = geometry.y;
         self.width = geometry.width;
         self.height = geometry.height;
+        true
+    }
+
+    // write the event to the corresponding uinput device, every event ends with an EV_SYN
+    fn emit_event(&mut self, event: &PointerEvent) {
         match event.pointer_type {
             PointerType::Touch => {
                 if self.num_touch_mapping_tries < MAX_SCREEN_MAPPING_TRIES {
@@ -258,58 +438,39 @@ impl InputDevice for GraphicTablet {
                         x11ctx.map_input_device_to_entire_screen(&self.name_touch_device, false);
                     }
                 }
+                let num_touches = self.num_touches();
                 match event.event_type {
                     PointerEventType::DOWN | PointerEventType::MOVE => {
-                        let slot: usize;
-                        // check if this event is already assigned to one of our 10 multitouch slots
-                        if let Some(s) = self.find_slot(event.pointer_id) {
-                            slot = s;
-                        } else {
-                            // this event is not assigned to a slot, lets try to do so now
-                            // find the first unused slot
-                            if let Some(s) =
-                                self.touches
-                                    .iter()
-                                    .enumerate()
-                                    .find_map(|(slot, mt)| match mt {
-                                        None => Some(slot),
-                                        Some(_) => None,
-                                    })
-                            {
-                                slot = s;
+                        // every contact keeps its slot until it is lifted, new contacts take the
+                        // first free slot and get a new tracking id
+                        let slot = match self.find_slot(event.pointer_id) {
+                            Some(slot) => {
+                                self.send(self.touch_fd, ET_ABSOLUTE, EC_ABS_MT_SLOT, slot as i32);
+                                slot
+                            }
+                            None => {
+                                let slot = match self.touches.iter().position(Option::is_none) {
+                                    Some(slot) => slot,
+                                    // out of slots, ignore this contact
+                                    None => return,
+                                };
+                                let tracking_id = self.next_tracking_id;
+                                self.next_tracking_id = (tracking_id + 1) % (MAX_TRACKING_ID + 1);
                                 self.touches[slot] = Some(MultiTouch {
                                     id: event.pointer_id,
-                                })
-                            } else {
-                                // out of slots, do nothing
-                                return;
+                                    tracking_id,
+                                });
+                                self.send(self.touch_fd, ET_ABSOLUTE, EC_ABS_MT_SLOT, slot as i32);
+                                self.send(
+                                    self.touch_fd,
+                                    ET_ABSOLUTE,
+                                    EC_ABS_MT_TRACKING_ID,
+                                    tracking_id,
+                                );
+                                self.send_num_touches(num_touches);
+                                slot
                             }
                         };
-                        self.send(self.touch_fd, ET_ABSOLUTE, EC_ABS_MT_SLOT, slot as i32);
-                        self.send(
-                            self.touch_fd,
-                            ET_ABSOLUTE,
-                            EC_ABS_MT_TRACKING_ID,
-                            slot as i32,
-                        );
-
-                        if let PointerEventType::DOWN = event.event_type {
-                            self.send(self.touch_fd, ET_KEY, EC_KEY_TOUCH, 1);
-                            match slot {
-                                1 => self.send(self.touch_fd, ET_KEY, EC_KEY_TOOL_FINGER, 0),
-                                2 => self.send(self.touch_fd, ET_KEY, EC_KEY_TOOL_DOUBLETAP, 0),
-                                3 => self.send(self.touch_fd, ET_KEY, EC_KEY_TOOL_TRIPLETAP, 0),
-                                4 => self.send(self.touch_fd, ET_KEY, EC_KEY_TOOL_QUADTAP, 0),
-                                _ => self.send(self.touch_fd, ET_KEY, EC_KEY_TOOL_QUINTTAP, 0),
-                            }
-                            match slot {
-                                1 => self.send(self.touch_fd, ET_KEY, EC_KEY_TOOL_DOUBLETAP, 1),
-                                2 => self.send(self.touch_fd, ET_KEY, EC_KEY_TOOL_TRIPLETAP, 1),
-                                3 => self.send(self.touch_fd, ET_KEY, EC_KEY_TOOL_QUADTAP, 1),
-                                4 => self.send(self.touch_fd, ET_KEY, EC_KEY_TOOL_QUINTTAP, 1),
-                                _ => self.send(self.touch_fd, ET_KEY, EC_KEY_TOOL_FINGER, 1),
-                            }
-                        }
                         self.send(
                             self.touch_fd,
                             ET_ABSOLUTE,
@@ -347,18 +508,21 @@ impl InputDevice for GraphicTablet {
                             EC_ABS_MT_POSITION_Y,
                             self.transform_y(event.y),
                         );
-                        self.send(
-                            self.touch_fd,
-                            ET_ABSOLUTE,
-                            EC_ABSOLUTE_X,
-                            self.transform_x(event.x),
-                        );
-                        self.send(
-                            self.touch_fd,
-                            ET_ABSOLUTE,
-                            EC_ABSOLUTE_Y,
-                            self.transform_y(event.y),
-                        );
+                        // the single touch axes follow the contact in the first occupied slot
+                        if self.touches.iter().position(Option::is_some) == Some(slot) {
+                            self.send(
+                                self.touch_fd,
+                                ET_ABSOLUTE,
+                                EC_ABSOLUTE_X,
+                                self.transform_x(event.x),
+                            );
+                            self.send(
+                                self.touch_fd,
+                                ET_ABSOLUTE,
+                                EC_ABSOLUTE_Y,
+                                self.transform_y(event.y),
+                            );
+                        }
                         self.send(
                             self.touch_fd,
                             ET_MSC,
@@ -372,15 +536,8 @@ impl InputDevice for GraphicTablet {
                         if let Some(slot) = self.find_slot(event.pointer_id) {
                             self.send(self.touch_fd, ET_ABSOLUTE, EC_ABS_MT_SLOT, slot as i32);
                             self.send(self.touch_fd, ET_ABSOLUTE, EC_ABS_MT_TRACKING_ID, -1);
-                            self.send(self.touch_fd, ET_KEY, EC_KEY_TOUCH, 0);
-                            self.send(self.touch_fd, ET_KEY, EC_KEY_TOOL_FINGER, 0);
-                            match slot {
-                                1 => self.send(self.touch_fd, ET_KEY, EC_KEY_TOOL_DOUBLETAP, 0),
-                                2 => self.send(self.touch_fd, ET_KEY, EC_KEY_TOOL_TRIPLETAP, 0),
-                                3 => self.send(self.touch_fd, ET_KEY, EC_KEY_TOOL_QUADTAP, 0),
-                                4 => self.send(self.touch_fd, ET_KEY, EC_KEY_TOOL_QUINTTAP, 0),
-                                _ => (),
-                            }
+                            self.touches[slot] = None;
+                            self.send_num_touches(num_touches);
                             self.send(
                                 self.touch_fd,
                                 ET_MSC,
@@ -388,9 +545,9 @@ impl InputDevice for GraphicTablet {
                                 event.timestamp as i32,
                             );
                             self.send(self.touch_fd, ET_SYNC, EC_SYNC_REPORT, 0);
-                            self.touches[slot] = None;
                         }
                     }
+                    PointerEventType::LEAVE => (),
                 };
             }
             PointerType::Pen => {
@@ -401,8 +558,19 @@ impl InputDevice for GraphicTablet {
                 }
                 match event.event_type {
                     PointerEventType::DOWN | PointerEventType::MOVE => {
+                        // moving without touching the screen means the pen is hovering, this
+                        // lets applications show brush cursors before the pen touches
+                        if self.pen_tool != Some(event.tool) {
+                            // applications switch tools when the old one leaves and the new one
+                            // comes into range
+                            if let Some(tool) = self.pen_tool {
+                                self.send(self.stylus_fd, ET_KEY, tool_key(tool), 0);
+                            }
+                            self.send(self.stylus_fd, ET_KEY, tool_key(event.tool), 1);
+                            self.pen_tool = Some(event.tool);
+                        }
                         if let PointerEventType::DOWN = event.event_type {
-                            self.send(self.stylus_fd, ET_KEY, EC_KEY_TOOL_PEN, 1);
+                            self.send(self.stylus_fd, ET_KEY, EC_KEY_TOUCH, 1);
                         }
                         self.send(
                             self.stylus_fd,
@@ -435,8 +603,17 @@ impl InputDevice for GraphicTablet {
                             event.tilt_y,
                         );
                     }
-                    PointerEventType::UP | PointerEventType::CANCEL => {
-                        self.send(self.stylus_fd, ET_KEY, EC_KEY_TOOL_PEN, 0);
+                    // the pen keeps hovering after being lifted
+                    PointerEventType::UP => {
+                        self.send(self.stylus_fd, ET_ABSOLUTE, EC_ABSOLUTE_PRESSURE, 0);
+                        self.send(self.stylus_fd, ET_KEY, EC_KEY_TOUCH, 0);
+                    }
+                    PointerEventType::CANCEL | PointerEventType::LEAVE => {
+                        self.send(self.stylus_fd, ET_ABSOLUTE, EC_ABSOLUTE_PRESSURE, 0);
+                        self.send(self.stylus_fd, ET_KEY, EC_KEY_TOUCH, 0);
+                        if let Some(tool) = self.pen_tool.take() {
+                            self.send(self.stylus_fd, ET_KEY, tool_key(tool), 0);
+                        }
                     }
                 }
                 self.send(
@@ -492,6 +669,7 @@ impl InputDevice for GraphicTablet {
                         }
                         _ => (),
                     },
+                    PointerEventType::LEAVE => (),
                 }
                 self.send(
                     self.mouse_fd,
@@ -504,3 +682,70 @@ impl InputDevice for GraphicTablet {
         }
     }
 }
+
+impl InputDevice for GraphicTablet {
+    fn send_event(&mut self, event: &PointerEvent) {
+        if !self.is_enabled(&event.pointer_type) || !self.prepare_input() {
+            return;
+        }
+        self.emit_event(event);
+    }
+
+    fn send_events(&mut self, events: &[PointerEvent]) {
+        if events.is_empty()
+            || !self.is_enabled(&events[0].pointer_type)
+            || !self.prepare_input()
+        {
+            return;
+        }
+        let mut budget = MAX_COALESCED_DELAY;
+        for (i, event) in events.iter().enumerate() {
+            if i > 0 {
+                pace_coalesced(&events[i - 1], event, &mut budget);
+            }
+            self.emit_event(event);
+        }
+    }
+
+    fn send_right_click(&mut self, x: f64, y: f64) {
+        if !self.prepare_input() {
+            return;
+        }
+        self.send(self.mouse_fd, ET_ABSOLUTE, EC_ABSOLUTE_X, self.transform_x(x));
+        self.send(self.mouse_fd, ET_ABSOLUTE, EC_ABSOLUTE_Y, self.transform_y(y));
+        self.send(self.mouse_fd, ET_KEY, EC_KEY_MOUSE_RIGHT, 1);
+        self.send(self.mouse_fd, ET_SYNC, EC_SYNC_REPORT, 0);
+        self.send(self.mouse_fd, ET_KEY, EC_KEY_MOUSE_RIGHT, 0);
+        self.send(self.mouse_fd, ET_SYNC, EC_SYNC_REPORT, 0);
+    }
+
+    fn send_wheel(&mut self, x: f64, y: f64, horizontal: i32, vertical: i32, zoom: bool) {
+        if !self.prepare_input() {
+            return;
+        }
+        // position the pointer first, applications scroll or zoom whatever is below it
+        self.send(self.mouse_fd, ET_ABSOLUTE, EC_ABSOLUTE_X, self.transform_x(x));
+        self.send(self.mouse_fd, ET_ABSOLUTE, EC_ABSOLUTE_Y, self.transform_y(y));
+        self.send(self.mouse_fd, ET_SYNC, EC_SYNC_REPORT, 0);
+        if zoom {
+            set_zoom_modifier(true);
+        }
+        if vertical != 0 {
+            self.send(self.mouse_fd, ET_RELATIVE, EC_RELATIVE_WHEEL, vertical);
+        }
+        if horizontal != 0 {
+            self.send(self.mouse_fd, ET_RELATIVE, EC_RELATIVE_HWHEEL, horizontal);
+        }
+        self.send(self.mouse_fd, ET_SYNC, EC_SYNC_REPORT, 0);
+        if zoom {
+            set_zoom_modifier(false);
+        }
+    }
+
+    fn send_keys(&mut self, keys: &[KeyCombo]) {
+        // make sure the keys go to the captured window
+        if self.prepare_input() {
+            tap_keys(keys);
+        }
+    }
+}
@@ -0,0 +1,382 @@
+//! The input helper is a small process that creates the uinput devices of Weylus and passes their
+//! filedescriptors to it via a Unix socket. It keeps duplicates of the filedescriptors of all
+//! devices and if Weylus exits without destroying them, for example because it crashed or hung in
+//! the middle of a stroke and has been killed, it notices the closed socket, releases all pressed
+//! buttons and touch contacts and removes the devices. Otherwise applications could be left with a
+//! pen that is never lifted.
+//!
+//! By default Weylus starts a helper of its own that only does the cleanup, this is called the
+//! uinput guard. A helper started via `weylus input-helper` instead listens on a socket and creates
+//! devices for Weylus, so only the helper needs access to /dev/uinput and the network facing
+//! Weylus can run as an unprivileged user or in a sandbox.
+
+use std::collections::HashMap;
+use std::io;
+use std::os::raw::{c_int, c_void};
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd, RawFd};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::os::unix::process::CommandExt;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use tracing::{debug, info, warn};
+
+use crate::cerror::CError;
+use crate::input::uinput_device::{open_device, DeviceKind};
+
+extern "C" {
+    fn release_uinput_device(fd: c_int);
+    fn destroy_uinput_device(fd: c_int);
+    fn send_with_fd(sock: c_int, buf: *const c_void, len: usize, fd: c_int) -> c_int;
+    fn recv_with_fd(sock: c_int, buf: *mut c_void, len: usize, fd: *mut c_int) -> c_int;
+    fn uinput_guard_ignore_signals();
+    fn umask(mask: u32) -> u32;
+}
+
+// Every request starts with a header of three i32: the operation, its argument and the length of
+// the payload that follows. Only OP_CREATE is answered, by a header of two i32: the error code,
+// 0 on success, and the length of the error message that follows, the filedescriptor of the new
+// device is attached on success.
+
+// arg: the filedescriptor number in Weylus, the filedescriptor is attached
+const OP_WATCH: i32 = 1;
+// arg: the filedescriptor number in Weylus
+const OP_FORGET: i32 = 2;
+// arg: the kind of device, payload: its name
+const OP_CREATE: i32 = 3;
+
+// names of uinput devices are limited to 80 bytes anyway
+const MAX_PAYLOAD: i32 = 1024;
+
+// socket connected to the helper, None if there is none
+static HELPER: Mutex<Option<UnixStream>> = Mutex::new(None);
+// set if the helper creates devices, otherwise they are opened in Weylus
+static CREATES_DEVICES: AtomicBool = AtomicBool::new(false);
+
+/// Start the uinput guard, if this fails Weylus works as before, devices are only cleaned up by
+/// the kernel once Weylus exits.
+pub fn spawn_guard() {
+    let (sock, guard_sock) = match UnixStream::pair() {
+        Ok(pair) => pair,
+        Err(err) => {
+            warn!("Failed to create socket for uinput guard: {}", err);
+            return;
+        }
+    };
+    let exe = match std::env::current_exe() {
+        Ok(exe) => exe,
+        Err(err) => {
+            warn!("Failed to start uinput guard: {}", err);
+            return;
+        }
+    };
+    // the guard reads from stdin, its end of the socket is closed in Weylus once it is spawned
+    match Command::new(exe)
+        .arg0("weylus-uinput-guard")
+        .arg("uinput-guard")
+        .stdin(Stdio::from(OwnedFd::from(guard_sock)))
+        .spawn()
+    {
+        Ok(child) => {
+            debug!("Started uinput guard with pid {}.", child.id());
+            *HELPER.lock().unwrap() = Some(sock);
+        }
+        Err(err) => warn!("Failed to start uinput guard: {}", err),
+    }
+}
+
+/// Connect to an input helper listening on path, all devices are created by it from now on.
+pub fn connect(path: &Path) -> Result<(), String> {
+    let sock = UnixStream::connect(path).map_err(|err| {
+        format!(
+            "Failed to connect to input helper at {}: {}",
+            path.display(),
+            err
+        )
+    })?;
+    info!("Connected to input helper at {}.", path.display());
+    *HELPER.lock().unwrap() = Some(sock);
+    CREATES_DEVICES.store(true, Ordering::SeqCst);
+    Ok(())
+}
+
+/// Whether devices are created by the input helper.
+pub fn connected() -> bool {
+    CREATES_DEVICES.load(Ordering::SeqCst)
+}
+
+/// Have the input helper create a device, the filedescriptor returned belongs to Weylus.
+pub fn create(kind: DeviceKind, name: &str) -> Result<c_int, CError> {
+    let mut helper = HELPER.lock().unwrap();
+    let sock = match helper.as_ref() {
+        Some(sock) => sock,
+        None => return Err(CError::with_message(1, "Lost connection to input helper.")),
+    };
+    let kind = match kind {
+        DeviceKind::Stylus => 0,
+        DeviceKind::Mouse => 1,
+        DeviceKind::Touch => 2,
+    };
+    let res = send_message(sock, OP_CREATE, kind, name.as_bytes(), None).and_then(|_| {
+        let mut reply = [0i32; 2];
+        let fd = recv_message(sock, &mut reply)?;
+        let code = reply[0];
+        let message = recv_payload(sock, reply[1])?;
+        match (code, fd) {
+            (0, Some(fd)) => Ok(Ok(fd)),
+            (0, None) => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "no filedescriptor received",
+            )),
+            _ => Ok(Err(CError::with_message(
+                code,
+                &String::from_utf8_lossy(&message),
+            ))),
+        }
+    });
+    match res {
+        Ok(res) => res,
+        Err(err) => {
+            *helper = None;
+            Err(CError::with_message(
+                1,
+                &format!("Lost connection to input helper: {}", err),
+            ))
+        }
+    }
+}
+
+/// Have the helper clean up the device behind fd if Weylus exits without destroying it.
+pub fn watch(fd: c_int) {
+    send(OP_WATCH, fd, Some(fd));
+}
+
+/// Call before destroying the device behind fd, the number might be reused for another device.
+pub fn forget(fd: c_int) {
+    send(OP_FORGET, fd, None);
+}
+
+fn send(op: i32, arg: i32, fd: Option<RawFd>) {
+    let mut helper = HELPER.lock().unwrap();
+    if let Some(sock) = helper.as_ref() {
+        if let Err(err) = send_message(sock, op, arg, &[], fd) {
+            warn!(
+                "Lost connection to input helper, input devices might not be cleaned up after a \
+                crash: {}",
+                err
+            );
+            *helper = None;
+        }
+    }
+}
+
+fn send_message(
+    sock: &UnixStream,
+    op: i32,
+    arg: i32,
+    payload: &[u8],
+    fd: Option<RawFd>,
+) -> io::Result<()> {
+    let mut buf = Vec::with_capacity(12 + payload.len());
+    for v in &[op, arg, payload.len() as i32] {
+        buf.extend_from_slice(&v.to_ne_bytes());
+    }
+    buf.extend_from_slice(payload);
+    send_buf(sock, &buf, fd)
+}
+
+fn send_buf(sock: &UnixStream, buf: &[u8], fd: Option<RawFd>) -> io::Result<()> {
+    let res = unsafe {
+        send_with_fd(
+            sock.as_raw_fd(),
+            buf.as_ptr() as *const c_void,
+            buf.len(),
+            fd.unwrap_or(-1),
+        )
+    };
+    if res != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+// receive a header, returns a filedescriptor if one has been attached
+fn recv_message(sock: &UnixStream, header: &mut [i32]) -> io::Result<Option<RawFd>> {
+    let mut buf = vec![0u8; header.len() * 4];
+    let mut fd: c_int = -1;
+    let res = unsafe {
+        recv_with_fd(
+            sock.as_raw_fd(),
+            buf.as_mut_ptr() as *mut c_void,
+            buf.len(),
+            &mut fd,
+        )
+    };
+    match res {
+        1 => (),
+        0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+        _ => return Err(io::Error::last_os_error()),
+    }
+    for (v, bytes) in header.iter_mut().zip(buf.chunks(4)) {
+        *v = i32::from_ne_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+    }
+    Ok(if fd >= 0 { Some(fd) } else { None })
+}
+
+fn recv_payload(sock: &UnixStream, len: i32) -> io::Result<Vec<u8>> {
+    if len < 0 || len > MAX_PAYLOAD {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("invalid payload length {}", len),
+        ));
+    }
+    let mut payload = vec![0u8; len as usize];
+    if len > 0 {
+        let mut fd: c_int = -1;
+        let res = unsafe {
+            recv_with_fd(
+                sock.as_raw_fd(),
+                payload.as_mut_ptr() as *mut c_void,
+                payload.len(),
+                &mut fd,
+            )
+        };
+        if fd >= 0 {
+            drop(unsafe { OwnedFd::from_raw_fd(fd) });
+        }
+        match res {
+            1 => (),
+            0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+            _ => return Err(io::Error::last_os_error()),
+        }
+    }
+    Ok(payload)
+}
+
+/// Entry point of the uinput guard, the socket to Weylus is passed as stdin.
+pub fn run_guard() {
+    unsafe { uinput_guard_ignore_signals() };
+    let sock = unsafe { UnixStream::from_raw_fd(0) };
+    serve(&sock);
+}
+
+/// Entry point of `weylus input-helper`, create devices for every instance of Weylus connecting
+/// to the socket at path.
+pub fn run_helper(path: &Path) -> Result<(), String> {
+    // a socket left behind by a helper that has been killed
+    if path.exists() && UnixStream::connect(path).is_err() {
+        let _ = std::fs::remove_file(path);
+    }
+    // only the owner and group of the helper may create devices, the socket is created with these
+    // permissions right away so nobody else can connect before they are set
+    let mask = unsafe { umask(0o117) };
+    let listener = UnixListener::bind(path);
+    unsafe { umask(mask) };
+    let listener =
+        listener.map_err(|err| format!("Failed to listen on {}: {}", path.display(), err))?;
+    info!("Input helper listening on {}.", path.display());
+    for sock in listener.incoming() {
+        match sock {
+            Ok(sock) => {
+                std::thread::spawn(move || {
+                    debug!("Weylus connected to input helper.");
+                    serve(&sock);
+                    debug!("Weylus disconnected from input helper.");
+                });
+            }
+            Err(err) => warn!("Failed to accept connection: {}", err),
+        }
+    }
+    Ok(())
+}
+
+fn serve(sock: &UnixStream) {
+    // filedescriptor number in Weylus -> duplicate held by the helper
+    let mut devices: HashMap<c_int, OwnedFd> = HashMap::new();
+    loop {
+        let mut header = [0i32; 3];
+        let fd = match recv_message(sock, &mut header) {
+            Ok(fd) => fd.map(|fd| unsafe { OwnedFd::from_raw_fd(fd) }),
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => break,
+            Err(err) => {
+                warn!("Input helper failed to receive message: {}", err);
+                break;
+            }
+        };
+        let payload = match recv_payload(sock, header[2]) {
+            Ok(payload) => payload,
+            Err(err) => {
+                warn!("Input helper failed to receive message: {}", err);
+                break;
+            }
+        };
+        let res = match header[0] {
+            OP_WATCH => {
+                if let Some(fd) = fd {
+                    devices.insert(header[1], fd);
+                }
+                Ok(())
+            }
+            OP_FORGET => {
+                devices.remove(&header[1]);
+                Ok(())
+            }
+            OP_CREATE => create_for_client(sock, header[1], &payload),
+            op => {
+                warn!("Input helper received unknown operation {}.", op);
+                break;
+            }
+        };
+        if let Err(err) = res {
+            warn!("Input helper failed to send reply: {}", err);
+            break;
+        }
+    }
+    if !devices.is_empty() {
+        info!(
+            "Weylus exited without removing {} input device(s), releasing and removing them.",
+            devices.len()
+        );
+    }
+    for (_, fd) in devices {
+        // destroy_uinput_device closes the filedescriptor
+        let fd = fd.into_raw_fd();
+        unsafe {
+            release_uinput_device(fd);
+            destroy_uinput_device(fd);
+        }
+    }
+}
+
+fn create_for_client(sock: &UnixStream, kind: i32, name: &[u8]) -> io::Result<()> {
+    let kind = match kind {
+        0 => Ok(DeviceKind::Stylus),
+        1 => Ok(DeviceKind::Mouse),
+        2 => Ok(DeviceKind::Touch),
+        _ => Err(CError::with_message(1, "Unknown kind of device.")),
+    };
+    let name = String::from_utf8_lossy(name);
+    let res = kind.and_then(|kind| open_device(kind, &name));
+    let mut buf = Vec::new();
+    match res {
+        Ok(fd) => {
+            let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+            for v in &[0i32, 0] {
+                buf.extend_from_slice(&v.to_ne_bytes());
+            }
+            // Weylus sends a duplicate back to be watched, until then the device is removed by
+            // the kernel if Weylus crashes
+            send_buf(sock, &buf, Some(fd.as_raw_fd()))
+        }
+        Err(err) => {
+            let message = err.message();
+            for v in &[err.code(), message.len() as i32] {
+                buf.extend_from_slice(&v.to_ne_bytes());
+            }
+            buf.extend_from_slice(message.as_bytes());
+            send_buf(sock, &buf, None)
+        }
+    }
+}
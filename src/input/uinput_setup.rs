@@ -0,0 +1,41 @@
+use std::process::Command;
+
+use tracing::info;
+
+// executed as root by pkexec, the name of the user is passed as first argument
+const SETUP_SCRIPT: &str = r#"set -e
+groupadd -f uinput
+usermod -a -G uinput "$1"
+echo 'KERNEL=="uinput", MODE="0660", GROUP="uinput", OPTIONS+="static_node=uinput"' \
+    > /etc/udev/rules.d/60-weylus.rules
+echo uinput > /etc/modules-load.d/weylus.conf
+modprobe uinput
+udevadm control --reload-rules
+udevadm trigger --sysname-match=uinput
+"#;
+
+/// Give the current user write access to /dev/uinput: A udev rule makes /dev/uinput accessible to
+/// the group uinput, which the user is added to. The uinput module is loaded on boot from now on.
+/// Root privileges are requested via pkexec.
+pub fn setup_uinput_access() -> Result<(), String> {
+    let user = std::env::var("USER").map_err(|_| "Failed to determine the current user.")?;
+    info!("Setting up access to /dev/uinput for {}.", user);
+    let status = Command::new("pkexec")
+        .args(&["sh", "-c", SETUP_SCRIPT, "sh", &user])
+        .status()
+        .map_err(|err| format!("Failed to run pkexec: {}", err))?;
+    if !status.success() {
+        return Err(match status.code() {
+            // pkexec returns 126 if authorization failed or the dialog has been dismissed
+            Some(126) | Some(127) => "Authorization failed.".to_string(),
+            Some(code) => format!("Setup failed with exit code {}.", code),
+            None => "Setup has been terminated by a signal.".to_string(),
+        });
+    }
+    info!(
+        "Access to /dev/uinput has been set up, log out and back in for the group membership of \
+        {} to take effect.",
+        user
+    );
+    Ok(())
+}
@@ -0,0 +1,528 @@
+//! Drives complete websocket sessions against the servers, capture and input are replaced by
+//! TestScreenCapture and TestInputDevice so no screen or input devices are required.
+
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, TcpStream};
+use std::sync::atomic::AtomicBool;
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::spawn;
+use std::time::{Duration, Instant};
+
+use websocket::sync::Client;
+use websocket::{ClientBuilder, Message, OwnedMessage};
+
+use crate::audio::AudioMode;
+use crate::config::Config;
+use crate::input::smoothing::Smoothing;
+use crate::input::test_device::{RecordedInput, TestInputDevice};
+use crate::metrics::Metrics;
+use crate::network;
+use crate::protocol::{ClockProbe, NetMessage, PointerEventType, Viewport};
+use crate::screen_capture::rotation::Rotation;
+use crate::screen_capture::synthetic::TestScreenCapture;
+use crate::stream_handler::{
+    PointerStreamHandler, ScreenStreamHandler, StatsReporter, StreamMode, StreamState,
+};
+use crate::video::{Codec, ColorRange, ColorSpace, ScalingFilter};
+use crate::websocket::{listen_websocket, ClientAccess, Clients, Ws2GuiMessage};
+
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+struct TestServer {
+    pointer_addr: SocketAddr,
+    video_addr: SocketAddr,
+    recorded: Arc<Mutex<Vec<RecordedInput>>>,
+    gui_receiver: mpsc::Receiver<Ws2GuiMessage>,
+    shutdown: Arc<AtomicBool>,
+    stream_state: Arc<StreamState>,
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.shutdown
+            .store(true, std::sync::atomic::Ordering::Relaxed);
+    }
+}
+
+fn test_config(password: Option<&str>) -> Config {
+    let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    Config {
+        password: password.map(String::from),
+        view_only_password: None,
+        basic_auth: false,
+        bind_address: localhost,
+        web_port: 0,
+        websocket_pointer_port: network::free_port(localhost).unwrap(),
+        websocket_video_port: network::free_port(localhost).unwrap(),
+        screen_update_interval: Duration::from_millis(0),
+        stylus_support: false,
+        faster_capture: false,
+        capture_cursor: false,
+        enable_mouse: true,
+        enable_stylus: true,
+        enable_touch: true,
+        rotation: Rotation::None,
+        scaling_filter: ScalingFilter::FastBilinear,
+        color_space: ColorSpace::BT709,
+        color_range: ColorRange::Limited,
+        pointer_roi: false,
+        stroke_smoothing: Smoothing::Off,
+        record_input: None,
+        shortcuts: Vec::new(),
+        long_press: None,
+        touch_filter: Default::default(),
+        codec: Codec::X264,
+        threads: 1,
+        scale_on_resize: false,
+        xcomposite: false,
+        hide_own_windows: false,
+        privacy: Default::default(),
+        v4l2_device: None,
+        gpu_convert: false,
+        inhibit_screensaver: false,
+        wake_display: false,
+        max_input_rate: 0,
+        input_mapping: Default::default(),
+        max_resolution: None,
+        latency_budget: None,
+        tcp_options: Default::default(),
+        intra_refresh: false,
+        cpu_limit: None,
+        battery_frame_interval: None,
+        stream_mode: StreamMode::PerClient,
+        notify_connections: false,
+        reject_grace_period: None,
+        require_approval: false,
+        idle_timeout: None,
+        audio: AudioMode::None,
+        draft_while_drawing: false,
+    }
+}
+
+fn start_server(config: &Config) -> TestServer {
+    let clients: Clients = Default::default();
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let (gui_sender, gui_receiver) = mpsc::channel();
+    let access = ClientAccess::new(config, &clients, &gui_sender);
+    let stream_state = StreamState::new(config, Metrics::new());
+    let (device, recorded) = TestInputDevice::new();
+    // all pointer connections share one device, this way the recording can be inspected
+    let device = Arc::new(Mutex::new(Some(device)));
+
+    let pointer_addr = SocketAddr::new(config.bind_address, config.websocket_pointer_port);
+    let video_addr = SocketAddr::new(config.bind_address, config.websocket_video_port);
+    let tcp_options = config.tcp_options;
+    {
+        let (access, clients, shutdown, gui_sender) = (
+            access.clone(),
+            clients.clone(),
+            shutdown.clone(),
+            gui_sender.clone(),
+        );
+        let stream_state = stream_state.clone();
+        let pointer_options = config.pointer_options();
+        spawn(move || {
+            listen_websocket(
+                pointer_addr,
+                tcp_options,
+                access,
+                clients,
+                shutdown,
+                gui_sender,
+                move |_, session| {
+                    let device = device
+                        .lock()
+                        .unwrap()
+                        .take()
+                        .ok_or("only a single pointer connection is supported")?;
+                    Ok(PointerStreamHandler::new(
+                        device,
+                        stream_state.clone(),
+                        stream_state.view(session),
+                        pointer_options,
+                    ))
+                },
+            )
+        });
+    }
+    {
+        let shutdown = shutdown.clone();
+        let stream_state = stream_state.clone();
+        let (update_interval, encoder_options) =
+            (config.screen_update_interval, config.encoder_options());
+        let stats_sender = gui_sender.clone();
+        spawn(move || {
+            listen_websocket(
+                video_addr,
+                tcp_options,
+                access,
+                clients,
+                shutdown,
+                gui_sender,
+                move |client_addr, session| {
+                    Ok(ScreenStreamHandler::new(
+                        TestScreenCapture::new(320, 240),
+                        update_interval,
+                        encoder_options,
+                        stream_state.clone(),
+                        stream_state.view(session),
+                        StatsReporter::new(*client_addr, stats_sender.clone()),
+                    ))
+                },
+            )
+        });
+    }
+    TestServer {
+        pointer_addr,
+        video_addr,
+        recorded,
+        gui_receiver,
+        shutdown,
+        stream_state,
+    }
+}
+
+// the servers are started in the background, retry until they accept connections
+fn connect(addr: SocketAddr) -> Client<TcpStream> {
+    connect_to(&format!("ws://{}", addr))
+}
+
+fn connect_to(url: &str) -> Client<TcpStream> {
+    let start = Instant::now();
+    loop {
+        let client = ClientBuilder::new(url).unwrap().connect_insecure();
+        match client {
+            Ok(client) => {
+                client.stream_ref().set_read_timeout(Some(TIMEOUT)).unwrap();
+                return client;
+            }
+            Err(err) if start.elapsed() > TIMEOUT => panic!("Failed to connect: {}", err),
+            Err(_) => std::thread::sleep(Duration::from_millis(20)),
+        }
+    }
+}
+
+// the JSON the web client sends for pointer events
+fn pointer_event(event_type: &str, x: f64, y: f64, timestamp: u64) -> Message<'static> {
+    Message::text(format!(
+        r#"{{"PointerEvent":{{"event_type":"{}","pointer_id":1,"timestamp":{},"is_primary":true,
+        "pointer_type":"pen","button":1,"buttons":1,"x":{},"y":{},"movement_x":0,
+        "movement_y":0,"pressure":0.5,"tilt_x":0,"tilt_y":0,"twist":0,"width":1,"height":1}}}}"#,
+        event_type, timestamp, x, y
+    ))
+}
+
+fn wait_for(recorded: &Mutex<Vec<RecordedInput>>, count: usize) -> Vec<RecordedInput> {
+    let start = Instant::now();
+    while start.elapsed() < TIMEOUT {
+        let recorded = recorded.lock().unwrap();
+        if recorded.len() >= count {
+            return recorded.clone();
+        }
+        drop(recorded);
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    panic!("Timed out waiting for {} inputs.", count);
+}
+
+// messages are processed in order, once the clock probe is answered the ones sent before it have
+// been handled
+fn probe_clock(client: &mut Client<TcpStream>) {
+    let probe = NetMessage::ClockProbe(ClockProbe {
+        client_time: 42.0,
+        server_time: None,
+    });
+    client
+        .send_message(&Message::text(serde_json::to_string(&probe).unwrap()))
+        .unwrap();
+    match client.recv_message().unwrap() {
+        OwnedMessage::Text(text) => match serde_json::from_str(&text).unwrap() {
+            NetMessage::ClockProbe(reply) => {
+                assert_eq!(reply.client_time, 42.0);
+                assert!(reply.server_time.is_some());
+            }
+            other => panic!("Unexpected reply: {:?}", other),
+        },
+        other => panic!("Unexpected reply: {:?}", other),
+    }
+}
+
+fn viewport_of(server: &TestServer, session: Option<&str>) -> Option<Viewport> {
+    server
+        .stream_state
+        .view(session)
+        .rotation
+        .lock()
+        .unwrap()
+        .viewport()
+}
+
+#[test]
+fn pointer_events_reach_device() {
+    let server = start_server(&test_config(None));
+    let mut client = connect(server.pointer_addr);
+    client
+        .send_message(&pointer_event("pointerdown", 0.25, 0.5, 1000))
+        .unwrap();
+    client
+        .send_message(&pointer_event("pointermove", 0.3, 0.5, 2000))
+        .unwrap();
+    client
+        .send_message(&pointer_event("pointerup", 0.3, 0.5, 3000))
+        .unwrap();
+
+    let recorded = wait_for(&server.recorded, 3);
+    let types: Vec<_> = recorded
+        .iter()
+        .map(|input| match input {
+            RecordedInput::Event(event) => event.event_type,
+            other => panic!("Unexpected input: {:?}", other),
+        })
+        .collect();
+    assert!(matches!(
+        types.as_slice(),
+        [
+            PointerEventType::DOWN,
+            PointerEventType::MOVE,
+            PointerEventType::UP
+        ]
+    ));
+    match &recorded[0] {
+        RecordedInput::Event(event) => assert_eq!((event.x, event.y), (0.25, 0.5)),
+        _ => unreachable!(),
+    }
+}
+
+#[test]
+fn resumed_session_keeps_input_device() {
+    let server = start_server(&test_config(None));
+    let url = format!("ws://{}/?session=abc123", server.pointer_addr);
+    let mut client = connect_to(&url);
+    client
+        .send_message(&pointer_event("pointerdown", 0.25, 0.5, 1000))
+        .unwrap();
+    wait_for(&server.recorded, 1);
+
+    // the test server has a single input device, creating a new handler would fail
+    let mut resumed = connect_to(&url);
+    resumed
+        .send_message(&pointer_event("pointermove", 0.3, 0.5, 2000))
+        .unwrap();
+    wait_for(&server.recorded, 2);
+    // the connection that has been replaced is closed
+    assert!(client.recv_message().is_err());
+}
+
+#[test]
+fn clock_probe_is_echoed() {
+    let server = start_server(&test_config(None));
+    let mut client = connect(server.pointer_addr);
+    probe_clock(&mut client);
+}
+
+#[test]
+fn viewports_are_per_client() {
+    let server = start_server(&test_config(None));
+    let mut client = connect_to(&format!("ws://{}/?session=abc123", server.pointer_addr));
+    client
+        .send_message(&Message::text(
+            r#"{"Viewport":{"x":0.5,"y":0.5,"width":0.5,"height":0.5}}"#,
+        ))
+        .unwrap();
+    probe_clock(&mut client);
+    assert!(viewport_of(&server, Some("abc123")).is_some());
+    // other clients keep seeing the whole screen
+    assert!(viewport_of(&server, Some("def456")).is_none());
+    assert!(viewport_of(&server, None).is_none());
+}
+
+#[test]
+fn view_only_clients_keep_the_viewport() {
+    let mut config = test_config(Some("secret"));
+    config.view_only_password = Some("look".to_string());
+    let server = start_server(&config);
+    let mut client = connect_to(&format!("ws://{}/?session=abc123", server.pointer_addr));
+    client.send_message(&Message::text("look")).unwrap();
+    client
+        .send_message(&Message::text(
+            r#"{"Viewport":{"x":0.5,"y":0.5,"width":0.5,"height":0.5}}"#,
+        ))
+        .unwrap();
+    probe_clock(&mut client);
+    assert!(viewport_of(&server, Some("abc123")).is_none());
+}
+
+#[test]
+fn video_frames_are_sent() {
+    let server = start_server(&test_config(None));
+    let mut client = connect(server.video_addr);
+    client.send_message(&Message::text("")).unwrap();
+
+    let mut texts = Vec::new();
+    let mut chunks = 0;
+    // the header of the stream is followed by the first frame
+    while chunks < 2 {
+        match client.recv_message().unwrap() {
+            OwnedMessage::Text(text) => texts.push(text),
+            OwnedMessage::Binary(data) => {
+                assert!(!data.is_empty());
+                chunks += 1;
+            }
+            other => panic!("Unexpected message: {:?}", other),
+        }
+    }
+    // a new stream is announced before the first frame, which comes with a timestamp
+    assert_eq!(texts[0], "new");
+    assert!(texts[1].starts_with('#'));
+    assert!(texts[1][1..].parse::<f64>().is_ok());
+}
+
+#[test]
+fn mjpeg_frames_are_jpegs() {
+    let mut config = test_config(None);
+    config.codec = Codec::Mjpeg;
+    let server = start_server(&config);
+    let mut client = connect(server.video_addr);
+    client.send_message(&Message::text("")).unwrap();
+
+    assert!(
+        matches!(client.recv_message().unwrap(), OwnedMessage::Text(text) if text == "new mjpeg")
+    );
+    loop {
+        match client.recv_message().unwrap() {
+            OwnedMessage::Text(_) => (),
+            OwnedMessage::Binary(data) => {
+                // start of image marker
+                assert_eq!(&data[..2], &[0xff, 0xd8]);
+                break;
+            }
+            other => panic!("Unexpected message: {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn webcodecs_clients_get_annexb() {
+    let server = start_server(&test_config(None));
+    let mut client = connect(server.video_addr);
+    client
+        .send_message(&Message::text(
+            serde_json::to_string(&NetMessage::WebCodecs).unwrap(),
+        ))
+        .unwrap();
+    client.send_message(&Message::text("")).unwrap();
+
+    assert!(
+        matches!(client.recv_message().unwrap(), OwnedMessage::Text(text) if text == "new annexb")
+    );
+    loop {
+        match client.recv_message().unwrap() {
+            OwnedMessage::Text(_) => (),
+            OwnedMessage::Binary(data) => {
+                // there is no header, the first frame starts with the start code of a NAL unit
+                assert!(data.starts_with(&[0, 0, 0, 1]) || data.starts_with(&[0, 0, 1]));
+                break;
+            }
+            other => panic!("Unexpected message: {:?}", other),
+        }
+    }
+}
+
+#[test]
+fn wrong_password_is_rejected() {
+    let server = start_server(&test_config(Some("secret")));
+    let mut client = connect(server.pointer_addr);
+    client.send_message(&Message::text("wrong")).unwrap();
+    client
+        .send_message(&pointer_event("pointerdown", 0.5, 0.5, 1000))
+        .ok();
+    // the server closes the connection instead of answering
+    assert!(client.recv_message().is_err());
+    assert!(server.recorded.lock().unwrap().is_empty());
+    assert!(server.gui_receiver.try_recv().is_err());
+}
+
+#[test]
+fn resuming_a_session_requires_the_password() {
+    let server = start_server(&test_config(Some("secret")));
+    let url = format!("ws://{}/?session=abc123", server.pointer_addr);
+    let mut client = connect_to(&url);
+    client.send_message(&Message::text("secret")).unwrap();
+    client
+        .send_message(&pointer_event("pointerdown", 0.25, 0.5, 1000))
+        .unwrap();
+    wait_for(&server.recorded, 1);
+
+    // knowing the token is not enough to take over the session
+    let mut intruder = connect_to(&url);
+    intruder.send_message(&Message::text("wrong")).unwrap();
+    assert!(intruder.recv_message().is_err());
+    client
+        .send_message(&pointer_event("pointermove", 0.3, 0.5, 2000))
+        .unwrap();
+    wait_for(&server.recorded, 2);
+}
+
+#[test]
+fn binary_messages_close_the_connection() {
+    let server = start_server(&test_config(None));
+    let mut client = connect(server.pointer_addr);
+    client.send_message(&Message::binary(vec![0; 16])).unwrap();
+    client
+        .send_message(&pointer_event("pointerdown", 0.5, 0.5, 1000))
+        .ok();
+    // the server answers with a close message and does not process any further input
+    assert!(matches!(client.recv_message(), Ok(OwnedMessage::Close(_))));
+    std::thread::sleep(Duration::from_millis(100));
+    assert!(server.recorded.lock().unwrap().is_empty());
+}
+
+#[test]
+fn password_authenticates() {
+    let server = start_server(&test_config(Some("secret")));
+    let mut client = connect(server.pointer_addr);
+    client.send_message(&Message::text("secret")).unwrap();
+    match server.gui_receiver.recv_timeout(TIMEOUT) {
+        Ok(Ws2GuiMessage::Connected(_)) => (),
+        _ => panic!("Client has not been reported as connected."),
+    }
+    client
+        .send_message(&pointer_event("pointerdown", 0.5, 0.5, 1000))
+        .unwrap();
+    wait_for(&server.recorded, 1);
+}
+
+#[test]
+fn paused_video_is_not_sent() {
+    let server = start_server(&test_config(None));
+    let mut pointer = connect(server.pointer_addr);
+    pointer
+        .send_message(&Message::text(r#"{"Pause":true}"#))
+        .unwrap();
+    let mut video = connect(server.video_addr);
+    // the pause might not have been processed yet, request frames until it has
+    let start = Instant::now();
+    loop {
+        assert!(start.elapsed() < TIMEOUT, "Video has not been paused.");
+        video.send_message(&Message::text("")).unwrap();
+        match video.recv_message().unwrap() {
+            OwnedMessage::Text(text) if text == "paused blank" => break,
+            _ => std::thread::sleep(Duration::from_millis(10)),
+        }
+    }
+    // instead of frames the client is told to ask again later
+    assert!(
+        matches!(video.recv_message().unwrap(), OwnedMessage::Text(text) if text.starts_with('@'))
+    );
+
+    pointer.send_message(&Message::text(r#""Resume""#)).unwrap();
+    loop {
+        assert!(start.elapsed() < TIMEOUT, "Video has not been resumed.");
+        match video.recv_message().unwrap() {
+            OwnedMessage::Text(text) if text == "resumed" => break,
+            OwnedMessage::Text(text) if text.starts_with('@') => {
+                video.send_message(&Message::text("")).unwrap()
+            }
+            _ => (),
+        }
+    }
+}
@@ -0,0 +1,248 @@
+use std::error::Error;
+use std::net::{SocketAddr, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread::spawn;
+use std::time::{Duration, Instant};
+
+use tracing::{info, warn};
+use websocket::sync::Client;
+use websocket::{ClientBuilder, Message, OwnedMessage};
+
+use crate::config::LoadtestOptions;
+use crate::protocol::{ClockProbe, NetMessage};
+
+// interval between clock probes used to measure the round trip time of the pointer socket
+const PROBE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Samples in milliseconds.
+#[derive(Default)]
+struct Samples {
+    values: Vec<f64>,
+}
+
+impl Samples {
+    fn add(&mut self, value: f64) {
+        self.values.push(value);
+    }
+
+    fn print(&mut self, name: &str) {
+        if self.values.is_empty() {
+            println!("{}: no samples", name);
+            return;
+        }
+        self.values
+            .sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+        let percentile = |p: f64| {
+            let i = ((self.values.len() - 1) as f64 * p).round() as usize;
+            self.values[i]
+        };
+        let mean = self.values.iter().sum::<f64>() / self.values.len() as f64;
+        println!(
+            "{}: {} samples, min {:.1} ms, mean {:.1} ms, median {:.1} ms, p95 {:.1} ms, \
+            p99 {:.1} ms, max {:.1} ms",
+            name,
+            self.values.len(),
+            percentile(0.0),
+            mean,
+            percentile(0.5),
+            percentile(0.95),
+            percentile(0.99),
+            percentile(1.0),
+        );
+    }
+}
+
+struct VideoResult {
+    frames: usize,
+    bytes: usize,
+    // time between requesting a frame and receiving it
+    latency: Samples,
+}
+
+struct PointerResult {
+    events: usize,
+    round_trip: Samples,
+}
+
+fn connect(addr: SocketAddr, password: Option<&str>) -> Result<Client<TcpStream>, Box<dyn Error>> {
+    let mut client = ClientBuilder::new(&format!("ws://{}", addr))?.connect_insecure()?;
+    if let Some(password) = password {
+        client.send_message(&Message::text(password))?;
+    }
+    Ok(client)
+}
+
+/// Connect to a running Weylus, request frames at the configured rate and flood it with pointer
+/// events, then print statistics about throughput and latency.
+pub fn run(options: &LoadtestOptions) -> Result<(), Box<dyn Error>> {
+    let duration = Duration::from_secs(options.duration);
+    let video_addr = SocketAddr::new(options.host, options.websocket_video_port);
+    let pointer_addr = SocketAddr::new(options.host, options.websocket_pointer_port);
+    info!(
+        "Running load test against {} and {} for {} s.",
+        video_addr, pointer_addr, options.duration
+    );
+
+    let video_client = connect(video_addr, options.password.as_deref())?;
+    let pointer = if options.pointer_rate > 0.0 {
+        let pointer_client = connect(pointer_addr, options.password.as_deref())?;
+        let rate = options.pointer_rate;
+        Some(spawn(move || run_pointer(pointer_client, rate, duration)))
+    } else {
+        None
+    };
+    let mut video = run_video(video_client, options.fps, duration)?;
+
+    let seconds = duration.as_secs_f64();
+    println!(
+        "Video: {} frames ({:.1} fps), {:.1} kB/s",
+        video.frames,
+        video.frames as f64 / seconds,
+        video.bytes as f64 / seconds / 1000.0
+    );
+    video.latency.print("Frame latency");
+    if let Some(pointer) = pointer {
+        let mut pointer = pointer
+            .join()
+            .map_err(|_| "Pointer thread panicked.")?
+            .map_err(|err| err.to_string())?;
+        println!(
+            "Pointer: {} events ({:.1} events/s)",
+            pointer.events,
+            pointer.events as f64 / seconds
+        );
+        pointer.round_trip.print("Pointer round trip");
+    }
+    Ok(())
+}
+
+// behaves like the web client, except that frames are requested at a fixed rate
+fn run_video(
+    mut client: Client<TcpStream>,
+    fps: f64,
+    duration: Duration,
+) -> Result<VideoResult, Box<dyn Error>> {
+    client
+        .stream_ref()
+        .set_read_timeout(Some(Duration::from_secs(5)))?;
+    let frame_interval = Duration::from_secs_f64(1.0 / fps.max(0.1));
+    let mut result = VideoResult {
+        frames: 0,
+        bytes: 0,
+        latency: Samples::default(),
+    };
+    let start = Instant::now();
+    let mut requested = Instant::now();
+    // the header of a new mp4 stream is sent on its own before the first frame
+    let mut header_pending = false;
+    client.send_message(&Message::text(""))?;
+    while start.elapsed() < duration {
+        match client.recv_message()? {
+            OwnedMessage::Text(text) => {
+                // the server asks to wait before requesting the next frame
+                if let Some(wait) = text.strip_prefix('@') {
+                    let wait: u64 = wait.parse().unwrap_or(0);
+                    std::thread::sleep(Duration::from_millis(wait));
+                    requested = Instant::now();
+                    client.send_message(&Message::text(""))?;
+                } else if text.starts_with("new") {
+                    header_pending = text == "new";
+                    requested = Instant::now();
+                    client.send_message(&Message::text(""))?;
+                }
+            }
+            OwnedMessage::Binary(data) if header_pending => {
+                header_pending = false;
+                result.bytes += data.len();
+            }
+            OwnedMessage::Binary(data) => {
+                result
+                    .latency
+                    .add(requested.elapsed().as_secs_f64() * 1000.0);
+                result.frames += 1;
+                result.bytes += data.len();
+                let next = requested + frame_interval;
+                let now = Instant::now();
+                if next > now {
+                    std::thread::sleep(next - now);
+                }
+                requested = Instant::now();
+                client.send_message(&Message::text(""))?;
+            }
+            OwnedMessage::Close(_) => return Err("Server closed the connection.".into()),
+            _ => (),
+        }
+    }
+    client.shutdown().ok();
+    Ok(result)
+}
+
+// the JSON the web client sends for pointer events
+fn pointer_event(event_type: &str, x: f64, y: f64, timestamp: u64) -> String {
+    format!(
+        r#"{{"PointerEvent":{{"event_type":"{}","pointer_id":1,"timestamp":{},"is_primary":true,"pointer_type":"pen","button":0,"buttons":1,"x":{},"y":{},"movement_x":0,"movement_y":0,"pressure":0.5,"tilt_x":0,"tilt_y":0,"twist":0,"width":1,"height":1}}}}"#,
+        event_type, timestamp, x, y
+    )
+}
+
+// draws circles in the center of the screen and sends clock probes to measure the round trip time
+fn run_pointer(
+    client: Client<TcpStream>,
+    rate: f64,
+    duration: Duration,
+) -> Result<PointerResult, String> {
+    let (mut receiver, mut sender) = client.split().map_err(|err| err.to_string())?;
+    let start = Instant::now();
+    let round_trip = Arc::new(Mutex::new(Samples::default()));
+    {
+        let round_trip = round_trip.clone();
+        spawn(move || {
+            for message in receiver.incoming_messages() {
+                let text = match message {
+                    Ok(OwnedMessage::Text(text)) => text,
+                    Ok(_) => continue,
+                    Err(_) => return,
+                };
+                if let Ok(NetMessage::ClockProbe(probe)) = serde_json::from_str(&text) {
+                    let now = start.elapsed().as_secs_f64() * 1000.0;
+                    round_trip.lock().unwrap().add(now - probe.client_time);
+                }
+            }
+        });
+    }
+
+    let interval = Duration::from_secs_f64(1.0 / rate);
+    let micros = || start.elapsed().as_micros() as u64;
+    let mut send = |text: String| sender.send_message(&Message::text(text));
+    let mut events = 0;
+    let mut last_probe = Instant::now();
+    let mut next = Instant::now();
+    let mut result = send(pointer_event("pointerdown", 0.5, 0.5, micros()));
+    while result.is_ok() && start.elapsed() < duration {
+        let angle = start.elapsed().as_secs_f64() * std::f64::consts::PI;
+        let (x, y) = (0.5 + 0.1 * angle.cos(), 0.5 + 0.1 * angle.sin());
+        result = send(pointer_event("pointermove", x, y, micros()));
+        events += 1;
+        if last_probe.elapsed() >= PROBE_INTERVAL {
+            last_probe = Instant::now();
+            let probe = NetMessage::ClockProbe(ClockProbe {
+                client_time: start.elapsed().as_secs_f64() * 1000.0,
+                server_time: None,
+            });
+            result = result.and_then(|_| send(serde_json::to_string(&probe).unwrap()));
+        }
+        next += interval;
+        let now = Instant::now();
+        if next > now {
+            std::thread::sleep(next - now);
+        }
+    }
+    if let Err(err) = result {
+        warn!("Failed to send pointer event: {}", err);
+    }
+    send(pointer_event("pointerup", 0.5, 0.5, micros())).ok();
+    sender.shutdown_all().ok();
+
+    let round_trip = std::mem::take(&mut *round_trip.lock().unwrap());
+    Ok(PointerResult { events, round_trip })
+}
@@ -0,0 +1,246 @@
+use std::collections::VecDeque;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+// number of lines kept, older ones are dropped
+const CAPACITY: usize = 2000;
+
+/// Keeps the most recent log lines in memory so they can be exported for bug reports.
+pub struct LogBuffer {
+    lines: Mutex<VecDeque<String>>,
+}
+
+impl LogBuffer {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            lines: Mutex::new(VecDeque::with_capacity(CAPACITY)),
+        })
+    }
+
+    fn push(&self, line: String) {
+        let mut lines = self.lines.lock().unwrap();
+        if lines.len() >= CAPACITY {
+            lines.pop_front();
+        }
+        lines.push_back(line);
+    }
+
+    pub fn text(&self) -> String {
+        join(self.lines.lock().unwrap().iter())
+    }
+
+    /// The last count lines, None if the buffer is locked. This does not block and can be used
+    /// while panicking.
+    pub fn last_lines(&self, count: usize) -> Option<String> {
+        let lines = self.lines.try_lock().ok()?;
+        Some(join(lines.iter().skip(lines.len().saturating_sub(count))))
+    }
+
+    /// Zip archive containing the log and information about the system.
+    pub fn bundle(&self) -> Vec<u8> {
+        let mut zip = ZipWriter::new();
+        zip.add("weylus.log", self.text().as_bytes());
+        zip.add("system.txt", system_info().as_bytes());
+        zip.finish()
+    }
+}
+
+fn join<'a>(lines: impl Iterator<Item = &'a String>) -> String {
+    let mut text = String::new();
+    for line in lines {
+        text.push_str(line);
+        if !line.ends_with('\n') {
+            text.push('\n');
+        }
+    }
+    text
+}
+
+pub struct LogBufferWriter {
+    buffer: Arc<LogBuffer>,
+}
+
+impl Write for LogBufferWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buffer.push(String::from_utf8_lossy(buf).into());
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+pub struct LogBufferWriterFactory {
+    pub buffer: Arc<LogBuffer>,
+}
+
+impl tracing_subscriber::fmt::MakeWriter for LogBufferWriterFactory {
+    type Writer = LogBufferWriter;
+    fn make_writer(&self) -> Self::Writer {
+        Self::Writer {
+            buffer: self.buffer.clone(),
+        }
+    }
+}
+
+/// Format of the log written to stdout or the log file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LogFormat {
+    Text,
+    /// One JSON object per line, for log collectors like journald or Logstash.
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("Unknown log format '{}', use text or json.", s)),
+        }
+    }
+}
+
+pub struct LogFileWriter {
+    file: Arc<File>,
+}
+
+impl Write for LogFileWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        (&*self.file).write(buf)
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        (&*self.file).flush()
+    }
+}
+
+/// Writes the log to stdout or appends it to a file.
+#[derive(Clone)]
+pub enum LogWriterFactory {
+    Stdout,
+    File(Arc<File>),
+}
+
+impl LogWriterFactory {
+    pub fn new(path: Option<&Path>) -> std::io::Result<Self> {
+        match path {
+            Some(path) => Ok(Self::File(Arc::new(
+                OpenOptions::new().create(true).append(true).open(path)?,
+            ))),
+            None => Ok(Self::Stdout),
+        }
+    }
+}
+
+impl tracing_subscriber::fmt::MakeWriter for LogWriterFactory {
+    type Writer = Box<dyn Write>;
+    fn make_writer(&self) -> Self::Writer {
+        match self {
+            Self::Stdout => Box::new(std::io::stdout()),
+            Self::File(file) => Box::new(LogFileWriter { file: file.clone() }),
+        }
+    }
+}
+
+fn system_info() -> String {
+    let mut info = format!(
+        "Weylus {}\nOS: {}\nArchitecture: {}\n",
+        env!("CARGO_PKG_VERSION"),
+        std::env::consts::OS,
+        std::env::consts::ARCH
+    );
+    for var in &[
+        "XDG_SESSION_TYPE",
+        "XDG_CURRENT_DESKTOP",
+        "DISPLAY",
+        "WAYLAND_DISPLAY",
+    ] {
+        if let Ok(value) = std::env::var(var) {
+            info.push_str(&format!("{}: {}\n", var, value));
+        }
+    }
+    #[cfg(not(target_os = "windows"))]
+    {
+        if let Ok(output) = std::process::Command::new("uname").arg("-a").output() {
+            info.push_str(&format!(
+                "uname: {}\n",
+                String::from_utf8_lossy(&output.stdout).trim()
+            ));
+        }
+    }
+    info
+}
+
+// Minimal writer for zip archives, files are stored without compression.
+struct ZipWriter {
+    data: Vec<u8>,
+    central_directory: Vec<u8>,
+    entries: u16,
+}
+
+impl ZipWriter {
+    fn new() -> Self {
+        Self {
+            data: Vec::new(),
+            central_directory: Vec::new(),
+            entries: 0,
+        }
+    }
+
+    fn add(&mut self, name: &str, content: &[u8]) {
+        let crc = crc32fast::hash(content);
+        let size = content.len() as u32;
+        let offset = self.data.len() as u32;
+
+        // local file header
+        self.data.extend_from_slice(&0x04034b50u32.to_le_bytes());
+        // version needed to extract, flags, compression method, modification time and date
+        self.data
+            .extend_from_slice(&[20, 0, 0, 0, 0, 0, 0, 0, 0x21, 0]);
+        self.data.extend_from_slice(&crc.to_le_bytes());
+        self.data.extend_from_slice(&size.to_le_bytes());
+        self.data.extend_from_slice(&size.to_le_bytes());
+        self.data
+            .extend_from_slice(&(name.len() as u16).to_le_bytes());
+        self.data.extend_from_slice(&0u16.to_le_bytes());
+        self.data.extend_from_slice(name.as_bytes());
+        self.data.extend_from_slice(content);
+
+        // central directory file header
+        let cd = &mut self.central_directory;
+        cd.extend_from_slice(&0x02014b50u32.to_le_bytes());
+        // version made by, version needed to extract, flags, compression method, modification
+        // time and date
+        cd.extend_from_slice(&[20, 0, 20, 0, 0, 0, 0, 0, 0, 0, 0x21, 0]);
+        cd.extend_from_slice(&crc.to_le_bytes());
+        cd.extend_from_slice(&size.to_le_bytes());
+        cd.extend_from_slice(&size.to_le_bytes());
+        cd.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        // extra field length, comment length, disk number, internal and external attributes
+        cd.extend_from_slice(&[0; 12]);
+        cd.extend_from_slice(&offset.to_le_bytes());
+        cd.extend_from_slice(name.as_bytes());
+
+        self.entries += 1;
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        let cd_offset = self.data.len() as u32;
+        let cd_size = self.central_directory.len() as u32;
+        self.data.extend_from_slice(&self.central_directory);
+        // end of central directory record
+        self.data.extend_from_slice(&0x06054b50u32.to_le_bytes());
+        self.data.extend_from_slice(&[0; 4]);
+        self.data.extend_from_slice(&self.entries.to_le_bytes());
+        self.data.extend_from_slice(&self.entries.to_le_bytes());
+        self.data.extend_from_slice(&cd_size.to_le_bytes());
+        self.data.extend_from_slice(&cd_offset.to_le_bytes());
+        self.data.extend_from_slice(&0u16.to_le_bytes());
+        self.data
+    }
+}
@@ -1,4 +1,6 @@
 #![cfg_attr(feature = "bench", feature(test))]
+// many settings can only be changed from the GUI, without it they are never touched
+#![cfg_attr(not(feature = "gui"), allow(dead_code))]
 #[cfg(feature = "bench")]
 extern crate test;
 
@@ -6,20 +8,65 @@ extern crate test;
 extern crate bitflags;
 
 use std::io::Write;
+use std::path::Path;
 use std::sync::mpsc;
+use structopt::StructOpt;
+#[cfg(target_os = "linux")]
+use tracing::info;
+use tracing::error;
+#[cfg(any(
+    all(target_os = "linux", not(feature = "xcomposite")),
+    not(feature = "gpu-convert"),
+    feature = "gui"
+))]
+use tracing::warn;
 use tracing_subscriber::layer::SubscriberExt;
 
+use config::{ConfigFile, Options};
+
+mod access_log;
+mod audio;
 mod cerror;
+mod chrome_trace;
+mod cli;
+mod config;
+mod cpu_limit;
+mod crash;
+mod diagnostics;
+#[cfg(target_os = "windows")]
+mod firewall;
+#[cfg(feature = "gpu-convert")]
+mod gpu_convert;
+#[cfg(feature = "gui")]
 mod gui;
 mod input;
+#[cfg(test)]
+mod integration_tests;
+mod loadtest;
+mod logs;
+mod metrics;
+mod mpegts;
+mod mux;
+mod network;
+mod notifications;
+mod power;
 mod protocol;
+mod relay;
 mod screen_capture;
+mod screensaver;
+mod send_queue;
+mod slice_pool;
 mod stream_handler;
+mod tcp_options;
+#[cfg(target_os = "linux")]
+mod v4l2loopback;
 mod video;
+mod wake_on_lan;
 mod web;
 mod websocket;
 #[cfg(target_os = "linux")]
 mod x11helper;
+mod yuv;
 
 struct GuiTracingWriter {
     gui_sender: mpsc::SyncSender<String>,
@@ -50,7 +97,34 @@ impl tracing_subscriber::fmt::MakeWriter for GuiTracingWriterFactory {
     }
 }
 
+fn replay_input(path: &Path) {
+    #[cfg(target_os = "linux")]
+    let device = {
+        let mut x11ctx = x11helper::X11Context::new().unwrap();
+        let root = x11ctx.capturables().unwrap()[0].clone();
+        input::uinput_device::GraphicTablet::new(
+            x11helper::CaptureTarget::new(root),
+            "Replay".into(),
+            true,
+            true,
+            true,
+        )
+    };
+    #[cfg(not(target_os = "linux"))]
+    let device: Result<_, cerror::CError> = Ok(input::mouse_device::Mouse::new(true, true, true));
+
+    match device {
+        Ok(mut device) => {
+            if let Err(err) = input::recording::replay(path, &mut device) {
+                error!("Failed to replay input: {}", err);
+            }
+        }
+        Err(err) => error!("Failed to create input device: {}", err),
+    }
+}
+
 fn main() {
+    let mut options = Options::from_args();
     let (sender, receiver) = mpsc::sync_channel::<String>(100);
     #[cfg(debug_assertions)]
     let mut level = tracing::Level::TRACE;
@@ -65,9 +139,40 @@ fn main() {
         }
     }
 
-    let logger = tracing_subscriber::fmt()
-        .with_max_level(level)
-        .finish()
+    let log_writer = match logs::LogWriterFactory::new(options.log_file.as_deref()) {
+        Ok(log_writer) => log_writer,
+        Err(err) => {
+            eprintln!(
+                "Failed to open log file {}: {}",
+                options.log_file.as_ref().unwrap().display(),
+                err
+            );
+            logs::LogWriterFactory::Stdout
+        }
+    };
+    let json = options.log_format == logs::LogFormat::Json;
+
+    let log_buffer = logs::LogBuffer::new();
+    let logger = tracing_subscriber::registry()
+        .with(tracing_subscriber::filter::LevelFilter::from_level(level))
+        .with(if json {
+            None
+        } else {
+            Some(
+                tracing_subscriber::fmt::Layer::default()
+                    .with_ansi(options.log_file.is_none())
+                    .with_writer(log_writer.clone()),
+            )
+        })
+        .with(if json {
+            Some(
+                tracing_subscriber::fmt::Layer::default()
+                    .json()
+                    .with_writer(log_writer),
+            )
+        } else {
+            None
+        })
         .with(
             tracing_subscriber::fmt::Layer::default()
                 .with_ansi(false)
@@ -75,9 +180,168 @@ fn main() {
                 .with_target(false)
                 .compact()
                 .with_writer(GuiTracingWriterFactory { sender }),
-        );
+        )
+        .with(
+            tracing_subscriber::fmt::Layer::default()
+                .with_ansi(false)
+                .with_writer(logs::LogBufferWriterFactory {
+                    buffer: log_buffer.clone(),
+                }),
+        )
+        .with(options.access_log.as_ref().and_then(|path| {
+            match access_log::AccessLogLayer::new(path) {
+                Ok(layer) => Some(layer),
+                Err(err) => {
+                    eprintln!("Failed to open access log {}: {}", path.display(), err);
+                    None
+                }
+            }
+        }))
+        .with(options.trace_file.as_ref().and_then(|path| {
+            match chrome_trace::ChromeTraceLayer::new(path) {
+                Ok(layer) => Some(layer),
+                Err(err) => {
+                    eprintln!("Failed to create trace file {}: {}", path.display(), err);
+                    None
+                }
+            }
+        }));
     tracing::subscriber::set_global_default(logger).expect("Failed to setup logger!");
-    gui::run(receiver);
+    match &options.command {
+        #[cfg(target_os = "linux")]
+        Some(config::Command::SetupUinput) => {
+            if let Err(err) = input::uinput_setup::setup_uinput_access() {
+                error!("{}", err);
+                std::process::exit(1);
+            }
+            return;
+        }
+        #[cfg(target_os = "linux")]
+        Some(config::Command::UinputGuard) => {
+            input::uinput_helper::run_guard();
+            return;
+        }
+        #[cfg(target_os = "linux")]
+        Some(config::Command::InputHelper(helper_options)) => {
+            if let Err(err) = input::uinput_helper::run_helper(&helper_options.socket) {
+                error!("{}", err);
+                std::process::exit(1);
+            }
+            return;
+        }
+        #[cfg(target_os = "windows")]
+        Some(config::Command::SetupFirewall) => {
+            if let Err(err) = firewall::allow_ports(&[
+                options.web_port,
+                options.websocket_pointer_port,
+                options.websocket_video_port,
+            ]) {
+                error!("{}", err);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(config::Command::Loadtest(loadtest_options)) => {
+            if let Err(err) = loadtest::run(loadtest_options) {
+                error!("Load test failed: {}", err);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(config::Command::Relay(relay_options)) => {
+            if let Err(err) = relay::run(relay_options) {
+                error!("Relay failed: {}", err);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(config::Command::Wake(wake_options)) => {
+            if let Err(err) = wake_on_lan::run(wake_options) {
+                error!("Failed to wake computer: {}", err);
+                std::process::exit(1);
+            }
+            return;
+        }
+        None => (),
+    }
+    #[cfg(target_os = "linux")]
+    match &options.input_helper {
+        Some(path) => {
+            if let Err(err) = input::uinput_helper::connect(path) {
+                error!("{}", err);
+                std::process::exit(1);
+            }
+        }
+        None => input::uinput_helper::spawn_guard(),
+    }
+    if let Some(path) = &options.replay_input {
+        replay_input(path);
+        return;
+    }
+    #[cfg(all(target_os = "linux", not(feature = "xcomposite")))]
+    {
+        if options.xcomposite {
+            warn!("Weylus has been built without XComposite support, ignoring --xcomposite.");
+        }
+    }
+    #[cfg(not(feature = "gpu-convert"))]
+    {
+        if options.gpu_convert {
+            warn!("Weylus has been built without GPU conversion, ignoring --gpu-convert.");
+        }
+    }
+    if let Err(err) = audio::check_tools(options.audio) {
+        error!("{}", err);
+        std::process::exit(1);
+    }
+    if options.random_password {
+        options.password = Some(config::random_password());
+    }
+    if options.random_view_only_password {
+        options.view_only_password = Some(config::random_password());
+    }
+    if options.view_only_password.is_some() && options.password.is_none() {
+        warn!(
+            "No password has been set, everyone can connect with input despite \
+            --view-only-password."
+        );
+    }
+    let config_file = ConfigFile::load(options.config.as_deref());
+    // builds without GUI always run from the command line, so does capturing via DRM
+    let no_gui = options.no_gui
+        || options.drm_device.is_some()
+        || !cfg!(feature = "gui")
+        || !display_available();
+    crash::install_panic_hook(
+        crash::config_summary(&options, &config_file),
+        log_buffer.clone(),
+        !no_gui,
+    );
+    if no_gui {
+        // nobody shows the logs meant for the GUI
+        drop(receiver);
+        cli::run(&options, &config_file, log_buffer);
+    } else {
+        #[cfg(feature = "gui")]
+        {
+            if let Err(err) = gui::run(receiver, &options, &config_file, log_buffer.clone()) {
+                warn!("{} Running without GUI.", err);
+                cli::run(&options, &config_file, log_buffer);
+            }
+        }
+    }
+}
+
+// over SSH or in containers there is nothing to show the GUI on
+fn display_available() -> bool {
+    #[cfg(target_os = "linux")]
+    {
+        if !x11helper::display_server_available() {
+            info!("No display found, running without GUI.");
+            return false;
+        }
+    }
+    true
 }
 
 #[cfg(feature = "bench")]
@@ -92,7 +356,8 @@ mod tests {
     fn bench_capture_x11(b: &mut Bencher) {
         let mut x11ctx = x11helper::X11Context::new().unwrap();
         let root = x11ctx.capturables().unwrap()[0].clone();
-        let mut sc = screen_capture::linux::ScreenCaptureX11::new(root, false).unwrap();
+        let mut sc =
+            screen_capture::linux::ScreenCaptureX11::new(root, Default::default()).unwrap();
         b.iter(|| sc.capture());
     }
 
@@ -102,14 +367,29 @@ mod tests {
         let mut x11ctx = x11helper::X11Context::new().unwrap();
         let root = x11ctx.capturables().unwrap()[0].clone();
         use screen_capture::ScreenCapture;
-        let mut sc = screen_capture::linux::ScreenCaptureX11::new(root, false).unwrap();
+        use video::VideoEncoder;
+        let mut sc =
+            screen_capture::linux::ScreenCaptureX11::new(root, Default::default()).unwrap();
         sc.capture();
         let (width, height) = sc.size();
 
-        let mut encoder = video::VideoEncoder::new(width, height, |_| {}).unwrap();
+        let options = video::EncoderOptions {
+            scaling_filter: video::ScalingFilter::FastBilinear,
+            color_space: video::ColorSpace::BT709,
+            color_range: video::ColorRange::Limited,
+            pointer_roi: false,
+            codec: video::Codec::X264,
+            threads: slice_pool::thread_count(0),
+            container: video::Container::Mp4,
+            gpu_convert: false,
+            max_resolution: None,
+            latency_budget: None,
+            intra_refresh: false,
+        };
+        let mut encoder = video::FfmpegEncoder::new(width, height, options, |_| {}).unwrap();
         b.iter(|| {
             sc.capture();
-            encoder.encode(sc.pixel_provider())
+            encoder.encode(sc.pixel_provider(), width, height)
         });
     }
 }
@@ -0,0 +1,113 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+// weight of a new sample in the moving averages
+const SMOOTHING: f64 = 0.2;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Latency {
+    // time from capturing a frame until the client displayed it, if the clock of the client has
+    // not been synchronized this includes the time it took the report to reach the server
+    pub end_to_end: f64,
+    // time between receiving and displaying a frame on the client
+    pub client: f64,
+}
+
+/// Statistics collected while streaming, they are shown in the GUI and served at /metrics.
+pub struct Metrics {
+    latency: Mutex<Option<Latency>>,
+    // time from the browser dispatching a pointer event until it reaches the server
+    input_latency: Mutex<Option<f64>>,
+    frames: AtomicU64,
+}
+
+impl Metrics {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            latency: Mutex::new(None),
+            input_latency: Mutex::new(None),
+            frames: AtomicU64::new(0),
+        })
+    }
+
+    pub fn reset(&self) {
+        *self.latency.lock().unwrap() = None;
+        *self.input_latency.lock().unwrap() = None;
+        self.frames.store(0, Ordering::Relaxed);
+    }
+
+    pub fn frame_encoded(&self) {
+        self.frames.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Add a latency sample, all values are in milliseconds.
+    pub fn record_latency(&self, sample: Latency) {
+        let mut latency = self.latency.lock().unwrap();
+        *latency = Some(match *latency {
+            Some(avg) => Latency {
+                end_to_end: avg.end_to_end + SMOOTHING * (sample.end_to_end - avg.end_to_end),
+                client: avg.client + SMOOTHING * (sample.client - avg.client),
+            },
+            None => sample,
+        });
+    }
+
+    pub fn latency(&self) -> Option<Latency> {
+        *self.latency.lock().unwrap()
+    }
+
+    pub fn record_input_latency(&self, sample: f64) {
+        let mut latency = self.input_latency.lock().unwrap();
+        *latency = Some(match *latency {
+            Some(avg) => avg + SMOOTHING * (sample - avg),
+            None => sample,
+        });
+    }
+
+    pub fn input_latency(&self) -> Option<f64> {
+        *self.input_latency.lock().unwrap()
+    }
+
+    /// Render metrics in the text format understood by Prometheus.
+    pub fn render(&self) -> String {
+        let mut s = String::new();
+        s.push_str("# HELP weylus_frames_total Number of encoded video frames.\n");
+        s.push_str("# TYPE weylus_frames_total counter\n");
+        s.push_str(&format!(
+            "weylus_frames_total {}\n",
+            self.frames.load(Ordering::Relaxed)
+        ));
+        if let Some(latency) = self.latency() {
+            s.push_str(
+                "# HELP weylus_latency_milliseconds Time from capturing a frame until it is \
+                displayed by the client.\n",
+            );
+            s.push_str("# TYPE weylus_latency_milliseconds gauge\n");
+            s.push_str(&format!(
+                "weylus_latency_milliseconds {:.1}\n",
+                latency.end_to_end
+            ));
+            s.push_str(
+                "# HELP weylus_client_latency_milliseconds Time between receiving and displaying \
+                a frame on the client.\n",
+            );
+            s.push_str("# TYPE weylus_client_latency_milliseconds gauge\n");
+            s.push_str(&format!(
+                "weylus_client_latency_milliseconds {:.1}\n",
+                latency.client
+            ));
+        }
+        if let Some(latency) = self.input_latency() {
+            s.push_str(
+                "# HELP weylus_input_latency_milliseconds Time from the client dispatching a \
+                pointer event until it reaches the server.\n",
+            );
+            s.push_str("# TYPE weylus_input_latency_milliseconds gauge\n");
+            s.push_str(&format!(
+                "weylus_input_latency_milliseconds {:.1}\n",
+                latency
+            ));
+        }
+        s
+    }
+}
@@ -0,0 +1,100 @@
+//! Streams the screen as H.264 in MPEG-TS, this can be played by VLC, mpv or smart TVs without the
+//! web client.
+
+use std::cell::{Cell, RefCell};
+use std::error::Error;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tracing::{info, warn};
+
+use crate::screen_capture::CaptureSource;
+use crate::video::{create_encoder, Codec, Container, EncoderOptions, VideoEncoder};
+
+// media players expect a constant frame rate, frames are captured at about 30 fps
+const FRAME_INTERVAL: Duration = Duration::from_millis(33);
+// chunks of encoded data waiting to be sent, if the player does not keep up the capture stalls
+const BUFFERED_CHUNKS: usize = 64;
+
+pub type Chunk = Result<Vec<u8>, std::io::Error>;
+
+/// Capture and encode on a new thread, the stream ends once the receiver has been dropped.
+pub fn start(source: CaptureSource, options: EncoderOptions) -> mpsc::Receiver<Chunk> {
+    let (sender, receiver) = mpsc::channel(BUFFERED_CHUNKS);
+    std::thread::spawn(move || {
+        info!("MPEG-TS stream started.");
+        match run(source, options, sender) {
+            Ok(()) => info!("MPEG-TS stream stopped."),
+            Err(err) => warn!("MPEG-TS stream failed: {}", err),
+        }
+    });
+    receiver
+}
+
+fn run(
+    source: CaptureSource,
+    mut options: EncoderOptions,
+    sender: mpsc::Sender<Chunk>,
+) -> Result<(), Box<dyn Error>> {
+    // JPEGs can not be muxed into MPEG-TS
+    if options.codec == Codec::Mjpeg {
+        options.codec = Codec::X264;
+    }
+    options.container = Container::MpegTs;
+    // there is no pointer to focus on
+    options.pointer_roi = false;
+
+    let mut capture = source.create()?;
+    let sender = Rc::new(RefCell::new(sender));
+    let closed = Rc::new(Cell::new(false));
+    let mut encoder: Option<Box<dyn VideoEncoder>> = None;
+    while !closed.get() {
+        let start = Instant::now();
+        capture.capture();
+        let (width, height) = capture.size();
+        let (video_width, video_height) = options.video_size(width, height);
+        if encoder.as_ref().map_or(true, |encoder| {
+            !encoder.check_size(video_width, video_height)
+        }) {
+            // the new muxer repeats the program tables and starts with a keyframe, so players
+            // simply continue with the new resolution
+            let (sender, closed) = (sender.clone(), closed.clone());
+            encoder = Some(create_encoder(
+                video_width,
+                video_height,
+                options,
+                move |data| send(&mut sender.borrow_mut(), data, &closed),
+            )?);
+        }
+        if let Some(encoder) = &mut encoder {
+            encoder.encode(capture.pixel_provider(), width, height);
+        }
+        let elapsed = start.elapsed();
+        if elapsed < FRAME_INTERVAL {
+            std::thread::sleep(FRAME_INTERVAL - elapsed);
+        }
+    }
+    Ok(())
+}
+
+// blocks while the buffer is full, closed is set once the player disconnected
+fn send(sender: &mut mpsc::Sender<Chunk>, data: &[u8], closed: &Cell<bool>) {
+    if closed.get() {
+        return;
+    }
+    let mut chunk = Ok(data.to_vec());
+    loop {
+        match sender.try_send(chunk) {
+            Ok(()) => return,
+            Err(TrySendError::Full(c)) => {
+                chunk = c;
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            Err(TrySendError::Closed(_)) => {
+                closed.set(true);
+                return;
+            }
+        }
+    }
+}
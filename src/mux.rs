@@ -0,0 +1,146 @@
+//! Muxers pack the packets of a video encoder into the format a transport needs, so the encoding
+//! itself is shared by all of them: fragmented mp4 for Media Source Extensions, MPEG-TS for media
+//! players and raw Annex B NAL units for decoders that take H.264 as it is.
+
+use std::os::raw::{c_int, c_uchar, c_void};
+
+use tracing::warn;
+
+use crate::cerror::CError;
+use crate::video::{Codec, Container};
+
+extern "C" {
+    fn init_muxer(rust_ctx: *mut c_void) -> *mut c_void;
+    fn open_muxer(handle: *mut c_void, encoder: *mut c_void, container: c_int, err: *mut CError);
+    fn mux_packet(
+        handle: *mut c_void,
+        data: *const u8,
+        size: c_int,
+        pts: i64,
+        dts: i64,
+        keyframe: c_int,
+        err: *mut CError,
+    );
+    fn destroy_muxer(handle: *mut c_void);
+}
+
+/// A packet produced by the encoder, timestamps are in milliseconds.
+pub struct Packet<'a> {
+    pub data: &'a [u8],
+    pub pts: i64,
+    pub dts: i64,
+    pub keyframe: bool,
+}
+
+/// Packs encoded packets into a container, the result is passed to the callback given on
+/// creation.
+pub trait Muxer {
+    fn write_packet(&mut self, packet: &Packet);
+}
+
+impl Container {
+    /// Whether the encoder has to put the parameter sets into the codec parameters instead of in
+    /// front of every keyframe, fragmented mp4 stores them in its header.
+    pub fn global_header(self) -> bool {
+        match self {
+            Container::Mp4 => true,
+            Container::MpegTs | Container::AnnexB => false,
+        }
+    }
+}
+
+/// Create the muxer for container, encoder is the handle of the opened ffmpeg encoder the codec
+/// parameters are taken from. JPEGs are never muxed.
+pub fn create_muxer(
+    container: Container,
+    codec: Codec,
+    encoder: *mut c_void,
+    write_data: Box<dyn Fn(&[u8])>,
+) -> Result<Box<dyn Muxer>, CError> {
+    if codec == Codec::Mjpeg {
+        return Ok(Box::new(RawMuxer { write_data }));
+    }
+    match container {
+        Container::Mp4 | Container::MpegTs => {
+            FfmpegMuxer::new(container, encoder, write_data).map(|muxer| muxer as Box<dyn Muxer>)
+        }
+        Container::AnnexB => Ok(Box::new(RawMuxer { write_data })),
+    }
+}
+
+/// Passes packets on as they are, H.264 encoders output NAL units in Annex B format, every
+/// keyframe is preceded by the parameter sets. Packets of MJPEG are complete JPEGs.
+pub struct RawMuxer {
+    write_data: Box<dyn Fn(&[u8])>,
+}
+
+impl Muxer for RawMuxer {
+    fn write_packet(&mut self, packet: &Packet) {
+        (self.write_data)(packet.data);
+    }
+}
+
+/// Muxes into fragmented mp4 or MPEG-TS via libavformat, every packet is flushed right away.
+pub struct FfmpegMuxer {
+    handle: *mut c_void,
+    write_data: Box<dyn Fn(&[u8])>,
+}
+
+impl FfmpegMuxer {
+    pub fn new(
+        container: Container,
+        encoder: *mut c_void,
+        write_data: Box<dyn Fn(&[u8])>,
+    ) -> Result<Box<Self>, CError> {
+        let mut muxer = Box::new(Self {
+            handle: std::ptr::null_mut(),
+            write_data,
+        });
+        muxer.handle = unsafe { init_muxer(muxer.as_mut() as *mut _ as *mut c_void) };
+        let container = match container {
+            Container::MpegTs => 1,
+            _ => 0,
+        };
+        let mut err = CError::new();
+        unsafe { open_muxer(muxer.handle, encoder, container, &mut err) };
+        if err.is_err() {
+            return Err(err);
+        }
+        Ok(muxer)
+    }
+}
+
+#[no_mangle]
+fn write_muxed_data(muxer: *mut c_void, buf: *const c_uchar, buf_size: c_int) -> c_int {
+    let muxer = unsafe { (muxer as *mut FfmpegMuxer).as_mut().unwrap() };
+    (muxer.write_data)(unsafe { std::slice::from_raw_parts(buf as *const u8, buf_size as usize) });
+    0
+}
+
+impl Muxer for FfmpegMuxer {
+    fn write_packet(&mut self, packet: &Packet) {
+        let mut err = CError::new();
+        unsafe {
+            mux_packet(
+                self.handle,
+                packet.data.as_ptr(),
+                packet.data.len() as c_int,
+                packet.pts,
+                packet.dts,
+                packet.keyframe.into(),
+                &mut err,
+            )
+        };
+        if err.is_err() {
+            warn!("Failed to mux packet: {}", err);
+        }
+    }
+}
+
+impl Drop for FfmpegMuxer {
+    fn drop(&mut self) {
+        if !self.handle.is_null() {
+            unsafe { destroy_muxer(self.handle) }
+        }
+    }
+}
@@ -0,0 +1,219 @@
+use std::io;
+use std::net::{AddrParseError, IpAddr, Ipv6Addr, SocketAddr, TcpListener, ToSocketAddrs};
+use std::sync::mpsc;
+use std::time::Duration;
+
+// names announced via mDNS resolve quickly, looking up unknown ones may take several seconds
+const RESOLVE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Check whether it is possible to listen on addr.
+pub fn port_available(addr: SocketAddr) -> io::Result<()> {
+    TcpListener::bind(addr).map(|_| ())
+}
+
+/// Ask the operating system for a port that is currently free.
+pub fn free_port(ip: IpAddr) -> io::Result<u16> {
+    Ok(TcpListener::bind(SocketAddr::new(ip, 0))?
+        .local_addr()?
+        .port())
+}
+
+/// URL of the web interface, the password is passed as query parameter so it does not have to be
+/// entered on the tablet.
+pub fn server_url(addr: &SocketAddr, password: Option<&str>) -> String {
+    named_server_url(&url_host(addr.ip()), addr.port(), password)
+}
+
+/// Like server_url but with a host name instead of an address.
+pub fn named_server_url(host: &str, port: u16, password: Option<&str>) -> String {
+    let mut url = format!("http://{}:{}", host, port);
+    if let Some(password) = password {
+        url.push_str("?password=");
+        url.push_str(
+            &percent_encoding::utf8_percent_encode(password, percent_encoding::NON_ALPHANUMERIC)
+                .to_string(),
+        );
+    }
+    url
+}
+
+/// Parse an IP address, IPv6 addresses may be given in brackets as in URLs.
+pub fn parse_ip(s: &str) -> Result<IpAddr, AddrParseError> {
+    let s = s.trim();
+    match s.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+        Some(ip) => ip.parse::<Ipv6Addr>().map(IpAddr::V6),
+        None => s.parse(),
+    }
+}
+
+/// The host part of a URL, IPv6 addresses are put in brackets. Scope ids are left out as
+/// browsers hardly support them.
+pub fn url_host(ip: IpAddr) -> String {
+    match ip {
+        IpAddr::V4(ip) => ip.to_string(),
+        IpAddr::V6(ip) => format!("[{}]", ip),
+    }
+}
+
+/// A name of this computer that resolves to one of addrs, unlike the addresses it survives DHCP
+/// assigning new ones. This is the host name with .local appended if it is announced via mDNS,
+/// e.g. by Avahi, or the fully qualified host name if it is known to DNS. The name is resolved on
+/// this computer, if that works, tablets in the same network most likely can resolve it too.
+pub fn host_name(addrs: &[SocketAddr]) -> Option<String> {
+    let name = system_host_name()?;
+    let mut candidates = Vec::new();
+    if name.ends_with(".local") {
+        candidates.push(name);
+    } else {
+        if name.contains('.') {
+            candidates.push(name.clone());
+        }
+        let short = name.split('.').next().unwrap_or_default();
+        candidates.push(format!("{}.local", short));
+    }
+    candidates.into_iter().find(|candidate| {
+        let (sender, receiver) = mpsc::channel();
+        let lookup = candidate.clone();
+        std::thread::spawn(move || {
+            let ips: Vec<IpAddr> = (lookup.as_str(), 0)
+                .to_socket_addrs()
+                .map(|addrs| addrs.map(|addr| addr.ip()).collect())
+                .unwrap_or_default();
+            sender.send(ips).ok();
+        });
+        receiver.recv_timeout(RESOLVE_TIMEOUT).map_or(false, |ips| {
+            // many systems map their own name to a loopback address
+            addrs
+                .iter()
+                .any(|addr| !addr.ip().is_loopback() && ips.contains(&addr.ip()))
+        })
+    })
+}
+
+#[cfg(not(target_os = "windows"))]
+fn system_host_name() -> Option<String> {
+    extern "C" {
+        fn gethostname(name: *mut std::os::raw::c_char, len: usize) -> i32;
+    }
+    let mut buf = [0u8; 256];
+    if unsafe { gethostname(buf.as_mut_ptr() as *mut _, buf.len()) } != 0 {
+        return None;
+    }
+    let len = buf.iter().position(|b| *b == 0)?;
+    let name = String::from_utf8_lossy(&buf[..len]).to_string();
+    // localhost is what hosts without a configured name report
+    if name.is_empty() || name == "localhost" {
+        return None;
+    }
+    Some(name)
+}
+
+#[cfg(target_os = "windows")]
+fn system_host_name() -> Option<String> {
+    std::env::var("COMPUTERNAME")
+        .ok()
+        .filter(|name| !name.is_empty())
+}
+
+/// Addresses of this computer browsers can use to connect if bound to the unspecified address
+/// addr, loopback interfaces are skipped. Sockets bound to the unspecified IPv6 address accept
+/// IPv4 connections too, the IPv6 addresses come first then, the stable unique local ones
+/// before global ones, which may change with the prefix assigned by the provider.
+#[cfg(not(target_os = "windows"))]
+pub fn local_addresses(addr: IpAddr) -> Vec<IpAddr> {
+    let temporary = temporary_addresses();
+    let mut ips: Vec<IpAddr> = pnet::datalink::interfaces()
+        .iter()
+        .filter(|iface| iface.is_up() && !iface.is_loopback())
+        .flat_map(|iface| iface.ips.iter().map(|ipnetw| ipnetw.ip()))
+        .filter(|ip| addr.is_ipv6() || ip.is_ipv4())
+        .filter(|ip| reachable(*ip, &temporary))
+        .collect();
+    ips.sort_by_key(|ip| match ip {
+        IpAddr::V6(ip) if is_unique_local(ip) => 0,
+        IpAddr::V6(_) => 1,
+        IpAddr::V4(_) => 2,
+    });
+    ips
+}
+
+// link local IPv6 addresses require a scope id, temporary ones change regularly
+#[cfg(not(target_os = "windows"))]
+fn reachable(ip: IpAddr, temporary: &[Ipv6Addr]) -> bool {
+    match ip {
+        IpAddr::V4(_) => true,
+        IpAddr::V6(ip) => ip.segments()[0] & 0xffc0 != 0xfe80 && !temporary.contains(&ip),
+    }
+}
+
+#[cfg(not(target_os = "windows"))]
+fn is_unique_local(ip: &Ipv6Addr) -> bool {
+    ip.segments()[0] & 0xfe00 == 0xfc00
+}
+
+// temporary addresses for privacy (RFC 4941) and deprecated ones, which are about to be replaced
+#[cfg(target_os = "linux")]
+fn temporary_addresses() -> Vec<Ipv6Addr> {
+    // every line lists address, interface index, prefix length, scope, flags and interface name
+    std::fs::read_to_string("/proc/net/if_inet6")
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let ip = u128::from_str_radix(fields.next()?, 16).ok()?;
+            let flags = u32::from_str_radix(fields.nth(3)?, 16).ok()?;
+            // IFA_F_TEMPORARY and IFA_F_DEPRECATED
+            if flags & (0x01 | 0x20) == 0 {
+                return None;
+            }
+            Some(Ipv6Addr::from(ip))
+        })
+        .collect()
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+fn temporary_addresses() -> Vec<Ipv6Addr> {
+    Vec::new()
+}
+
+/// Addresses browsers can use to connect to a server listening on addr, these are the addresses of
+/// all network interfaces if addr is unspecified. On Windows they are unknown in that case and
+/// nothing is returned.
+pub fn reachable_addresses(addr: SocketAddr) -> Vec<SocketAddr> {
+    if !addr.ip().is_unspecified() {
+        return vec![addr];
+    }
+    #[cfg(not(target_os = "windows"))]
+    return local_addresses(addr.ip())
+        .into_iter()
+        .map(|ip| SocketAddr::new(ip, addr.port()))
+        .collect();
+    #[cfg(target_os = "windows")]
+    Vec::new()
+}
+
+/// Names of the network interfaces that are up together with their addresses, loopback interfaces,
+/// link local and temporary IPv6 addresses are skipped as they can not be reached from a tablet
+/// or change regularly.
+#[cfg(not(target_os = "windows"))]
+pub fn interface_addresses() -> Vec<(String, IpAddr)> {
+    let temporary = temporary_addresses();
+    let mut addresses = Vec::new();
+    for iface in pnet::datalink::interfaces()
+        .iter()
+        .filter(|iface| iface.is_up() && !iface.is_loopback())
+    {
+        for ipnetw in &iface.ips {
+            let ip = ipnetw.ip();
+            if reachable(ip, &temporary) {
+                addresses.push((iface.name.clone(), ip));
+            }
+        }
+    }
+    addresses
+}
+
+#[cfg(target_os = "windows")]
+pub fn interface_addresses() -> Vec<(String, IpAddr)> {
+    Vec::new()
+}
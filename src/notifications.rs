@@ -0,0 +1,86 @@
+//! Desktop notifications shown on this computer, e.g. when a client connects. They are sent via
+//! the command line tools of the platform so that no notification library is needed, if these are
+//! missing the notification is only logged.
+
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+use std::process::Command;
+use std::time::Duration;
+
+use tracing::debug;
+
+/// Show a notification, this does not block.
+pub fn notify(summary: &str, body: &str) {
+    let summary = summary.to_string();
+    let body = body.to_string();
+    std::thread::spawn(move || show(&summary, &body));
+}
+
+/// Show a notification offering the given actions as (id, label) and block until one is chosen or
+/// the timeout passes. Returns the id of the chosen action, None if the notification timed out,
+/// got dismissed or actions are not supported.
+pub fn ask(
+    summary: &str,
+    body: &str,
+    actions: &[(&str, &str)],
+    timeout: Duration,
+) -> Option<String> {
+    #[cfg(target_os = "linux")]
+    {
+        let mut command = Command::new("notify-send");
+        command
+            .arg("--app-name=Weylus")
+            .arg("--wait")
+            .arg(format!("--expire-time={}", timeout.as_millis()));
+        for (id, label) in actions {
+            command.arg(format!("--action={}={}", id, label));
+        }
+        match command.arg(summary).arg(body).output() {
+            Ok(output) if output.status.success() => {
+                let action = String::from_utf8_lossy(&output.stdout).trim().to_string();
+                return actions.iter().find(|(id, _)| *id == action).map(|_| action);
+            }
+            // notify-send only supports actions since libnotify 0.7.10
+            Ok(output) => debug!(
+                "notify-send failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            ),
+            Err(err) => debug!("Failed to run notify-send: {}", err),
+        }
+    }
+    let _ = (actions, timeout);
+    show(summary, body);
+    None
+}
+
+#[cfg(target_os = "linux")]
+fn show(summary: &str, body: &str) {
+    if let Err(err) = Command::new("notify-send")
+        .arg("--app-name=Weylus")
+        .arg(summary)
+        .arg(body)
+        .status()
+    {
+        debug!("Failed to run notify-send: {}", err);
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn show(summary: &str, body: &str) {
+    let quote = |s: &str| format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""));
+    let script = format!(
+        "display notification {} with title {}",
+        quote(body),
+        quote(summary)
+    );
+    if let Err(err) = Command::new("osascript").arg("-e").arg(script).status() {
+        debug!("Failed to run osascript: {}", err);
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos")))]
+fn show(summary: &str, _body: &str) {
+    debug!(
+        "Desktop notifications are not supported on this platform: {}",
+        summary
+    );
+}
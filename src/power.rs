@@ -0,0 +1,114 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use tracing::{info, warn};
+
+// how often the power source is checked
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Tracks whether the computer runs on battery, the power source is looked up at most every
+/// CHECK_INTERVAL.
+pub struct PowerMonitor {
+    state: Mutex<(bool, Instant)>,
+}
+
+impl PowerMonitor {
+    /// Returns None if the power source can not be determined on this platform.
+    pub fn new() -> Option<Self> {
+        let battery = match on_battery() {
+            Some(battery) => battery,
+            None => {
+                warn!("Unable to find out whether the computer runs on battery.");
+                return None;
+            }
+        };
+        if battery {
+            info!("Running on battery, switching to low power mode.");
+        }
+        Some(Self {
+            state: Mutex::new((battery, Instant::now())),
+        })
+    }
+
+    pub fn on_battery(&self) -> bool {
+        let mut state = self.state.lock().unwrap();
+        let (battery, last_check) = &mut *state;
+        if last_check.elapsed() < CHECK_INTERVAL {
+            return *battery;
+        }
+        *last_check = Instant::now();
+        if let Some(b) = on_battery() {
+            if b != *battery {
+                if b {
+                    info!("Running on battery, switching to low power mode.");
+                } else {
+                    info!("Plugged in, leaving low power mode.");
+                }
+                *battery = b;
+            }
+        }
+        *battery
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn on_battery() -> Option<bool> {
+    let read = |path: &std::path::Path, name: &str| {
+        std::fs::read_to_string(path.join(name))
+            .map(|s| s.trim().to_string())
+            .unwrap_or_default()
+    };
+    let mut discharging = false;
+    for entry in std::fs::read_dir("/sys/class/power_supply").ok()? {
+        let path = entry.ok()?.path();
+        match read(&path, "type").as_str() {
+            // desktops without battery have a mains supply too
+            "Mains" => return Some(read(&path, "online") == "0"),
+            "Battery" => discharging |= read(&path, "status") == "Discharging",
+            // the batteries of mice and other peripherals
+            _ => (),
+        }
+    }
+    Some(discharging)
+}
+
+#[cfg(target_os = "windows")]
+fn on_battery() -> Option<bool> {
+    #[repr(C)]
+    #[derive(Default)]
+    struct SystemPowerStatus {
+        ac_line_status: u8,
+        battery_flag: u8,
+        battery_life_percent: u8,
+        system_status_flag: u8,
+        battery_life_time: u32,
+        battery_full_life_time: u32,
+    }
+    extern "system" {
+        fn GetSystemPowerStatus(status: *mut SystemPowerStatus) -> i32;
+    }
+    let mut status = SystemPowerStatus::default();
+    if unsafe { GetSystemPowerStatus(&mut status) } == 0 {
+        return None;
+    }
+    // 0 is offline, 1 online and 255 unknown
+    match status.ac_line_status {
+        0 => Some(true),
+        1 => Some(false),
+        _ => None,
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn on_battery() -> Option<bool> {
+    let output = std::process::Command::new("pmset")
+        .args(&["-g", "batt"])
+        .output()
+        .ok()?;
+    Some(String::from_utf8_lossy(&output.stdout).contains("'Battery Power'"))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "windows", target_os = "macos")))]
+fn on_battery() -> Option<bool> {
+    None
+}
@@ -3,9 +3,89 @@ use serde::{Deserialize, Deserializer, Serialize};
 #[derive(Serialize, Deserialize, Debug)]
 pub enum NetMessage {
     PointerEvent(PointerEvent),
+    // coalesced events of a single pointermove, ordered by their timestamps
+    PointerEvents(Vec<PointerEvent>),
+    Orientation(Orientation),
+    FrameTiming(FrameTiming),
+    ClockProbe(ClockProbe),
+    ClockOffset(ClockOffset),
+    // name of a shortcut configured on the server, the server sends the corresponding keys
+    Shortcut(String),
+    // enable or disable translating two finger gestures into scrolling and zooming
+    Gestures(bool),
+    // stop capturing until resumed, clients hide the video while paused if set
+    Pause(bool),
+    Resume,
+    // the decoder of the client lost sync, the next frame is encoded as keyframe
+    Keyframe,
+    // the client decodes H.264 via WebCodecs, it is sent NAL units instead of fragmented mp4
+    WebCodecs,
+    // zoom into this part of the video, None shows everything again
+    Viewport(Option<Viewport>),
+    // measure the pressure range of the pen during the next stroke
+    CalibratePressure,
+    // sent by the server once the calibration is finished, clients send it back after
+    // reconnecting, None disables the calibration
+    PressureRange(Option<PressureRange>),
 }
 
+/// Range of pressure a pen actually reports, many pens never reach a pressure of 1, see
+/// input/pressure.rs.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct PressureRange {
+    pub min: f64,
+    pub max: f64,
+}
+
+/// Part of the video a client zoomed into, relative to the whole video as it is shown without zoom:
+/// x and y give the top left corner, all values range from 0 to 1.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct Viewport {
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+/// Sent by the client for some of the frames, times are in milliseconds: capture_timestamp is the
+/// timestamp the server sent with the frame, received and displayed are measured on the client.
+/// The clock of the client is synchronized on the pointer socket, so the client passes on the
+/// offset it estimated there, see ClockOffset.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FrameTiming {
+    pub capture_timestamp: f64,
+    pub received: f64,
+    pub displayed: f64,
+    // older clients do not send it
+    #[serde(default)]
+    pub clock_offset: Option<f64>,
+}
+
+/// Used to estimate the offset between the clocks of client and server: The client sends its
+/// current time, the server echoes the probe with server_time set to its own time.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ClockProbe {
+    pub client_time: f64,
+    pub server_time: Option<f64>,
+}
+
+/// Result of the clock probes as estimated by the client, server time is client time plus offset.
+/// All values are in milliseconds.
 #[derive(Serialize, Deserialize, Debug)]
+pub struct ClockOffset {
+    pub offset: f64,
+    pub round_trip: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub enum Orientation {
+    #[serde(rename = "portrait")]
+    Portrait,
+    #[serde(rename = "landscape")]
+    Landscape,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
 pub enum PointerType {
     #[serde(rename = "")]
     Unknown,
@@ -17,7 +97,24 @@ pub enum PointerType {
     Touch,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+/// Tool of a pen, either selected on the client or reported by pens with an eraser tip.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum PenTool {
+    #[serde(rename = "pen")]
+    Pen,
+    #[serde(rename = "eraser")]
+    Eraser,
+    #[serde(rename = "highlighter")]
+    Highlighter,
+}
+
+impl Default for PenTool {
+    fn default() -> Self {
+        PenTool::Pen
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
 pub enum PointerEventType {
     #[serde(rename = "pointerdown")]
     DOWN,
@@ -27,6 +124,9 @@ pub enum PointerEventType {
     CANCEL,
     #[serde(rename = "pointermove")]
     MOVE,
+    // only sent for pens, the pen left the range in which the tablet detects it
+    #[serde(rename = "pointerleave")]
+    LEAVE,
 }
 
 bitflags! {
@@ -46,13 +146,17 @@ fn from_str<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Button, D::Err
     Ok(Button::from_bits_truncate(bits))
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct PointerEvent {
     pub event_type: PointerEventType,
     pub pointer_id: i64,
-    pub timestamp: u32,
+    // microseconds on the clock of the client
+    pub timestamp: u64,
     pub is_primary: bool,
     pub pointer_type: PointerType,
+    // only meaningful for pens, older clients do not send it
+    #[serde(default)]
+    pub tool: PenTool,
     #[serde(deserialize_with = "from_str")]
     pub button: Button,
     #[serde(deserialize_with = "from_str")]
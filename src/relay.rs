@@ -0,0 +1,75 @@
+use std::error::Error;
+use std::io;
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::thread::spawn;
+use std::time::Duration;
+
+use tracing::{debug, info, warn};
+
+use crate::config::RelayOptions;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Listen on the ports of the web server and both websockets and pass every connection on to the
+/// same port of the upstream Weylus, runs until the process is killed.
+pub fn run(options: &RelayOptions) -> Result<(), Box<dyn Error>> {
+    let mut threads = Vec::new();
+    for &port in &[
+        options.web_port,
+        options.websocket_pointer_port,
+        options.websocket_video_port,
+    ] {
+        let listener = TcpListener::bind(SocketAddr::new(options.bind_address, port))
+            .map_err(|err| format!("Failed to listen on port {}: {}", port, err))?;
+        info!("Relaying port {} to {}.", port, options.upstream);
+        let upstream = options.upstream.clone();
+        threads.push(spawn(move || accept(listener, upstream, port)));
+    }
+    for thread in threads {
+        thread.join().ok();
+    }
+    Ok(())
+}
+
+fn accept(listener: TcpListener, upstream: String, port: u16) {
+    for stream in listener.incoming() {
+        match stream {
+            Ok(client) => {
+                let upstream = upstream.clone();
+                spawn(move || {
+                    if let Err(err) = relay(client, &upstream, port) {
+                        warn!("Failed to relay connection to {}: {}", upstream, err);
+                    }
+                });
+            }
+            Err(err) => warn!("Failed to accept connection: {}", err),
+        }
+    }
+}
+
+fn relay(client: TcpStream, upstream: &str, port: u16) -> io::Result<()> {
+    let peer_addr = client.peer_addr()?;
+    // resolved for every connection, the upstream may have got a new address in the meantime
+    let upstream_addr = (upstream, port)
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "Upstream has no address."))?;
+    let server = TcpStream::connect_timeout(&upstream_addr, CONNECT_TIMEOUT)?;
+    // pointer events and frames should not wait for more data to fill a packet
+    client.set_nodelay(true)?;
+    server.set_nodelay(true)?;
+    info!("Relaying connection of {} to {}.", peer_addr, upstream_addr);
+
+    let mut client_reader = client.try_clone()?;
+    let mut server_writer = server.try_clone()?;
+    let to_server = spawn(move || {
+        io::copy(&mut client_reader, &mut server_writer).ok();
+        server_writer.shutdown(Shutdown::Write).ok();
+    });
+    let (mut server_reader, mut client_writer) = (server, client);
+    io::copy(&mut server_reader, &mut client_writer).ok();
+    client_writer.shutdown(Shutdown::Write).ok();
+    to_server.join().ok();
+    debug!("Connection of {} to {} closed.", peer_addr, upstream_addr);
+    Ok(())
+}
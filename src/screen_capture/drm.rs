@@ -0,0 +1,74 @@
+use std::error::Error;
+use std::ffi::CString;
+use std::os::raw::{c_char, c_void};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+use tracing::{trace, warn};
+
+use crate::cerror::CError;
+use crate::screen_capture::linux::CImage;
+use crate::screen_capture::ScreenCapture;
+
+/// Device captured if there is no display server and none has been given via --drm-device.
+pub const DEFAULT_DEVICE: &str = "/dev/dri/card0";
+
+extern "C" {
+    fn start_drm_capture(path: *const c_char, err: *mut CError) -> *mut c_void;
+    fn capture_drm(handle: *mut c_void, img: *mut CImage, err: *mut CError);
+    fn stop_drm_capture(handle: *mut c_void);
+}
+
+/// Captures the framebuffer shown by a DRM/KMS device, this way the Linux console or kiosk
+/// sessions running without X11 or Wayland can be mirrored. Requires CAP_SYS_ADMIN.
+pub struct ScreenCaptureDrm {
+    handle: *mut c_void,
+    img: CImage,
+    // errors are only logged once until capturing works again
+    failing: bool,
+}
+
+impl ScreenCaptureDrm {
+    pub fn new(device: &Path) -> Result<Self, Box<dyn Error>> {
+        let path = CString::new(device.as_os_str().as_bytes())?;
+        let mut err = CError::new();
+        let handle = unsafe { start_drm_capture(path.as_ptr(), &mut err) };
+        if err.is_err() {
+            return Err(err.into());
+        }
+        Ok(Self {
+            handle,
+            img: CImage::new(),
+            failing: false,
+        })
+    }
+}
+
+impl Drop for ScreenCaptureDrm {
+    fn drop(&mut self) {
+        unsafe { stop_drm_capture(self.handle) };
+    }
+}
+
+impl ScreenCapture for ScreenCaptureDrm {
+    fn capture(&mut self) {
+        let mut err = CError::new();
+        unsafe { capture_drm(self.handle, &mut self.img, &mut err) };
+        if err.is_err() {
+            if err.code() == 1 && !self.failing {
+                warn!("Failed to capture framebuffer: {}", err);
+            } else {
+                trace!("Failed to capture framebuffer: {}", err);
+            }
+        }
+        self.failing = err.is_err();
+    }
+
+    fn pixel_provider(&self) -> crate::video::PixelProvider {
+        crate::video::PixelProvider::BGRA(self.img.data())
+    }
+
+    fn size(&self) -> (usize, usize) {
+        self.img.dimensions()
+    }
+}
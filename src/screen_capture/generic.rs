@@ -1,24 +1,53 @@
 use image_autopilot::GenericImageView;
-use image_autopilot::Pixel;
 
+#[cfg(target_os = "windows")]
+use crate::screen_capture::windows::WindowCapture;
 use crate::screen_capture::ScreenCapture;
+use crate::video::{PixelFormat, YUVCoefficients};
+use crate::yuv::{packed_rgb_to_yuv420p, YUV420PPlanes};
 
 pub struct ScreenCaptureGeneric {
     img: Option<autopilot::bitmap::Bitmap>,
+    coefficients: YUVCoefficients,
+    // captures the window chosen in the GUI instead of the screen
+    #[cfg(target_os = "windows")]
+    window: WindowCapture,
 }
 
 impl ScreenCaptureGeneric {
-    pub fn new() -> Self {
-        Self { img: None }
+    pub fn new(coefficients: YUVCoefficients) -> Self {
+        Self {
+            img: None,
+            coefficients,
+            #[cfg(target_os = "windows")]
+            window: WindowCapture::new(),
+        }
     }
 }
 
 impl ScreenCapture for ScreenCaptureGeneric {
     fn capture(&mut self) {
+        #[cfg(target_os = "windows")]
+        {
+            if self.window.capture() {
+                return;
+            }
+        }
         self.img = Some(autopilot::bitmap::capture_screen().unwrap());
     }
 
     fn pixel_provider(&self) -> crate::video::PixelProvider {
+        #[cfg(target_os = "windows")]
+        {
+            if self.window.active() {
+                return crate::video::PixelProvider::BGRA(self.window.bgra());
+            }
+        }
+        // most platforms capture RGBA which can be handed to the encoder as it is, everything else
+        // is converted to RGBA first
+        if let Some(rgba) = self.img.as_ref().and_then(|img| img.image.as_rgba8()) {
+            return crate::video::PixelProvider::RGBx(rgba);
+        }
         crate::video::PixelProvider::FillYUV420P(Box::new(
             move |y: &mut [u8],
                   u: &mut [u8],
@@ -32,53 +61,40 @@ impl ScreenCapture for ScreenCaptureGeneric {
                     .expect("capture has to be called before invoking fill_yuv");
 
                 let (width, height) = self.size();
-
-                let img = &img.image;
-                // Y
-                // YUV402p requires that width and height are even, so we just clip a row or column
-                // if necessary
-                for yy in 0..height - height % 2 {
-                    for xx in 0..width - width % 2 {
-                        let p = img.get_pixel(xx as u32, yy as u32).to_rgb();
-                        let p = p.channels();
-                        let r = p[0] as i32;
-                        let g = p[1] as i32;
-                        let b = p[2] as i32;
-                        y[y_line_size * yy + xx] =
-                            (((66 * r + 129 * g + 25 * b + 128) >> 8) + 16) as u8;
-                    }
-                }
-
-                // Cb and Cr
-                for yy in 0..(height / 2) {
-                    for xx in 0..(width / 2) {
-                        let p11 = img.get_pixel(2 * xx as u32, 2 * yy as u32).to_rgb();
-                        let p11 = p11.channels();
-                        let p12 = img.get_pixel(2 * xx as u32 + 1, 2 * yy as u32).to_rgb();
-                        let p12 = p12.channels();
-                        let p21 = img.get_pixel(2 * xx as u32, 2 * yy as u32 + 1).to_rgb();
-                        let p21 = p21.channels();
-                        let p22 = img.get_pixel(2 * xx as u32 + 1, 2 * yy as u32 + 1).to_rgb();
-                        let p22 = p22.channels();
-                        let mut r = p11[0] as i32 + p12[0] as i32 + p21[0] as i32 + p22[0] as i32;
-                        let mut g = p11[1] as i32 + p12[1] as i32 + p21[1] as i32 + p22[1] as i32;
-                        let mut b = p11[2] as i32 + p12[2] as i32 + p21[2] as i32 + p22[2] as i32;
-                        r >>= 2;
-                        g >>= 2;
-                        b >>= 2;
-                        u[yy * u_line_size + xx] =
-                            (((128 + 112 * b - 38 * r - 74 * g) >> 8) + 128) as u8;
-                        v[yy * v_line_size + xx] =
-                            (((128 + 112 * r - 94 * g - 18 * b) >> 8) + 128) as u8;
-                    }
-                }
+                let rgba = img.image.to_rgba();
+                packed_rgb_to_yuv420p(
+                    &rgba,
+                    PixelFormat::RGBx,
+                    width,
+                    height,
+                    &self.coefficients,
+                    &mut YUV420PPlanes {
+                        y,
+                        u,
+                        v,
+                        y_stride: y_line_size,
+                        u_stride: u_line_size,
+                        v_stride: v_line_size,
+                    },
+                );
             },
         ))
     }
 
     fn size(&self) -> (usize, usize) {
+        #[cfg(target_os = "windows")]
+        {
+            if self.window.active() {
+                return self.window.size();
+            }
+        }
         self.img.as_ref().map_or((0, 0), |img| {
             (img.image.width() as usize, img.image.height() as usize)
         })
     }
+
+    #[cfg(target_os = "windows")]
+    fn switched_target(&mut self) -> bool {
+        self.window.switched()
+    }
 }
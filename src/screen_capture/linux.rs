@@ -1,11 +1,18 @@
+use std::error::Error;
 use std::os::raw::{c_int, c_uint, c_void};
-use std::slice::from_raw_parts;
+use std::slice::{from_raw_parts, from_raw_parts_mut};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
-use tracing::{trace, warn};
+use tracing::{debug, trace, warn};
 
 use crate::cerror::CError;
+use crate::screen_capture::privacy::{redact, PrivacyOptions};
 use crate::screen_capture::ScreenCapture;
-use crate::x11helper::Capturable;
+use crate::x11helper::{lock_x11, unlock_x11, Capturable, CaptureTarget, X11Context};
+
+// how often the list of private windows is updated
+const PRIVATE_WINDOWS_UPDATE_INTERVAL: Duration = Duration::from_secs(1);
 
 extern "C" {
     fn start_capture(handle: *const c_void, ctx: *mut c_void, err: *mut CError) -> *mut c_void;
@@ -13,13 +20,16 @@ extern "C" {
         handle: *mut c_void,
         img: *mut CImage,
         capture_cursor: c_int,
+        xcomposite: c_int,
+        hide_own_windows: c_int,
         err: *mut CError,
     );
     fn stop_capture(handle: *mut c_void, err: *mut CError);
 }
 
+/// Image filled by the capture code in C.
 #[repr(C)]
-struct CImage {
+pub struct CImage {
     data: *const u8,
     width: c_uint,
     height: c_uint,
@@ -38,30 +48,139 @@ impl CImage {
         (self.width * self.height * 4) as usize
     }
 
+    pub fn dimensions(&self) -> (usize, usize) {
+        (self.width as usize, self.height as usize)
+    }
+
     pub fn data(&self) -> &[u8] {
         unsafe { from_raw_parts(self.data, self.size()) }
     }
+
+    pub fn data_mut(&mut self) -> &mut [u8] {
+        unsafe { from_raw_parts_mut(self.data as *mut u8, self.size()) }
+    }
+}
+
+/// Redacts windows matching the privacy options wherever they overlap the captured area.
+struct PrivacyFilter {
+    options: PrivacyOptions,
+    // what is captured, needed to locate private windows relative to the captured image
+    capturable: Capturable,
+    x11_context: X11Context,
+    windows: Vec<Capturable>,
+    last_update: Option<Instant>,
+}
+
+impl PrivacyFilter {
+    fn update_windows(&mut self) {
+        if let Some(last_update) = self.last_update {
+            if last_update.elapsed() < PRIVATE_WINDOWS_UPDATE_INTERVAL {
+                return;
+            }
+        }
+        self.last_update = Some(Instant::now());
+        match self.x11_context.capturables() {
+            Ok(capturables) => {
+                let options = &self.options;
+                self.windows = capturables
+                    .into_iter()
+                    .filter(|c| options.is_private(&c.name(), &c.class()))
+                    .collect();
+            }
+            Err(err) => warn!("Failed to list private windows: {}", err),
+        }
+    }
+
+    fn apply(&mut self, img: &mut CImage) {
+        self.update_windows();
+        if self.windows.is_empty() || img.data.is_null() {
+            return;
+        }
+        let (x, y) = match self.capturable.pixel_geometry() {
+            Ok((x, y, _, _)) => (x as i64, y as i64),
+            Err(err) => {
+                debug!("Failed to get geometry of captured area: {}", err);
+                return;
+            }
+        };
+        let (width, height) = (img.width as i64, img.height as i64);
+        for window in &self.windows {
+            // covered windows are redacted as well, better safe than sorry
+            if !window.is_visible_window() {
+                continue;
+            }
+            let (wx, wy, ww, wh) = match window.pixel_geometry() {
+                Ok((wx, wy, ww, wh)) => (wx as i64 - x, wy as i64 - y, ww as i64, wh as i64),
+                Err(_) => continue,
+            };
+            let (x0, y0) = (wx.max(0).min(width), wy.max(0).min(height));
+            let (x1, y1) = ((wx + ww).max(0).min(width), (wy + wh).max(0).min(height));
+            if x0 < x1 && y0 < y1 {
+                redact(
+                    img.data_mut(),
+                    width as usize,
+                    x0 as usize,
+                    y0 as usize,
+                    (x1 - x0) as usize,
+                    (y1 - y0) as usize,
+                    self.options.mode,
+                );
+            }
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct X11CaptureOptions {
+    pub capture_cursor: bool,
+    // capture windows from their XComposite pixmap, this includes parts covered by other windows
+    pub xcomposite: bool,
+    // black out the windows of Weylus when capturing a screen
+    pub hide_own_windows: bool,
+    pub privacy: PrivacyOptions,
 }
 
 pub struct ScreenCaptureX11 {
     handle: *mut c_void,
     img: CImage,
     capture_cursor: bool,
+    xcomposite: bool,
+    hide_own_windows: bool,
+    privacy: Option<PrivacyFilter>,
 }
 
 impl ScreenCaptureX11 {
-    pub fn new(mut capture: Capturable, capture_cursor: bool) -> Result<Self, CError> {
+    pub fn new(
+        mut capture: Capturable,
+        options: X11CaptureOptions,
+    ) -> Result<Self, Box<dyn Error>> {
+        let privacy = if options.privacy.is_enabled() {
+            let x11_context =
+                X11Context::new().ok_or("Failed to open display to find private windows.")?;
+            Some(PrivacyFilter {
+                options: options.privacy,
+                capturable: capture.clone(),
+                x11_context,
+                windows: Vec::new(),
+                last_update: None,
+            })
+        } else {
+            None
+        };
         let mut err = CError::new();
-        fltk::app::lock().unwrap();
+        lock_x11();
         let handle = unsafe { start_capture(capture.handle(), std::ptr::null_mut(), &mut err) };
-        fltk::app::unlock();
+        unlock_x11();
         if err.is_err() {
-            Err(err)
+            Err(err.into())
         } else {
             Ok(Self {
                 handle,
                 img: CImage::new(),
-                capture_cursor,
+                capture_cursor: options.capture_cursor,
+                xcomposite: options.xcomposite,
+                hide_own_windows: options.hide_own_windows,
+                privacy,
             })
         }
     }
@@ -70,27 +189,32 @@ impl ScreenCaptureX11 {
 impl Drop for ScreenCaptureX11 {
     fn drop(&mut self) {
         let mut err = CError::new();
-        fltk::app::lock().unwrap();
+        lock_x11();
         unsafe {
             stop_capture(self.handle, &mut err);
         }
-        fltk::app::unlock();
+        unlock_x11();
     }
 }
 
 impl ScreenCapture for ScreenCaptureX11 {
     fn capture(&mut self) {
         let mut err = CError::new();
-        fltk::app::lock().unwrap();
+        lock_x11();
         unsafe {
             capture_sceen(
                 self.handle,
                 &mut self.img,
                 self.capture_cursor.into(),
+                self.xcomposite.into(),
+                self.hide_own_windows.into(),
                 &mut err,
             );
         }
-        fltk::app::unlock();
+        unlock_x11();
+        if let Some(privacy) = &mut self.privacy {
+            privacy.apply(&mut self.img);
+        }
         if err.is_err() {
             if err.code() == 1 {
                 warn!("Failed to capture screen: {}", err);
@@ -108,3 +232,64 @@ impl ScreenCapture for ScreenCaptureX11 {
         (self.img.width as usize, self.img.height as usize)
     }
 }
+
+/// Captures the capturable of a CaptureTarget, if another capturable is chosen the capture is
+/// recreated for it.
+pub struct TargetedScreenCaptureX11 {
+    target: Arc<CaptureTarget>,
+    capturable: Capturable,
+    generation: usize,
+    options: X11CaptureOptions,
+    screen_capture: ScreenCaptureX11,
+    switched: bool,
+}
+
+impl TargetedScreenCaptureX11 {
+    pub fn new(
+        target: Arc<CaptureTarget>,
+        options: X11CaptureOptions,
+    ) -> Result<Self, Box<dyn Error>> {
+        let (capturable, generation) = target.get();
+        let screen_capture = ScreenCaptureX11::new(capturable.clone(), options.clone())?;
+        Ok(Self {
+            target,
+            capturable,
+            generation,
+            options,
+            screen_capture,
+            switched: false,
+        })
+    }
+}
+
+impl ScreenCapture for TargetedScreenCaptureX11 {
+    fn capture(&mut self) {
+        if self
+            .target
+            .update(&mut self.capturable, &mut self.generation)
+        {
+            // keep capturing the old capturable if the new one can not be captured
+            match ScreenCaptureX11::new(self.capturable.clone(), self.options.clone()) {
+                Ok(screen_capture) => {
+                    debug!("Switched capture to {}.", self.capturable);
+                    self.screen_capture = screen_capture;
+                    self.switched = true;
+                }
+                Err(err) => warn!("Failed to switch capture to {}: {}", self.capturable, err),
+            }
+        }
+        self.screen_capture.capture();
+    }
+
+    fn pixel_provider(&self) -> crate::video::PixelProvider {
+        self.screen_capture.pixel_provider()
+    }
+
+    fn size(&self) -> (usize, usize) {
+        self.screen_capture.size()
+    }
+
+    fn switched_target(&mut self) -> bool {
+        std::mem::replace(&mut self.switched, false)
+    }
+}
@@ -1,8 +1,24 @@
+use std::error::Error;
+use std::sync::Arc;
+
+#[cfg(target_os = "linux")]
+pub mod drm;
+
 pub mod generic;
 
 #[cfg(target_os = "linux")]
 pub mod linux;
 
+pub mod privacy;
+
+pub mod rotation;
+
+#[cfg(test)]
+pub mod synthetic;
+
+#[cfg(target_os = "windows")]
+pub mod windows;
+
 pub trait ScreenCapture {
     /// capture screen
     fn capture(&mut self);
@@ -11,4 +27,108 @@ pub trait ScreenCapture {
 
     /// width and size of captured image
     fn size(&self) -> (usize, usize);
+
+    /// Returns true once after the capture switched to another capturable, the next frame should
+    /// be encoded as keyframe then.
+    fn switched_target(&mut self) -> bool {
+        false
+    }
+}
+
+impl ScreenCapture for Box<dyn ScreenCapture> {
+    fn capture(&mut self) {
+        self.as_mut().capture()
+    }
+
+    fn pixel_provider(&self) -> crate::video::PixelProvider {
+        self.as_ref().pixel_provider()
+    }
+
+    fn size(&self) -> (usize, usize) {
+        self.as_ref().size()
+    }
+
+    fn switched_target(&mut self) -> bool {
+        self.as_mut().switched_target()
+    }
+}
+
+/// Creates captures of whatever the servers currently capture, used by the web server for
+/// screenshots and the MPEG-TS stream. Captures have to be used on the thread that created them.
+#[derive(Clone)]
+pub struct CaptureSource {
+    create: Arc<dyn Fn() -> Result<Box<dyn ScreenCapture>, String> + Send + Sync>,
+}
+
+impl CaptureSource {
+    pub fn new(
+        create: impl Fn() -> Result<Box<dyn ScreenCapture>, String> + Send + Sync + 'static,
+    ) -> Self {
+        Self {
+            create: Arc::new(create),
+        }
+    }
+
+    pub fn create(&self) -> Result<Box<dyn ScreenCapture>, String> {
+        (self.create)()
+    }
+
+    /// Capture a single frame and return it encoded as PNG.
+    pub fn screenshot(&self) -> Result<Vec<u8>, String> {
+        let mut capture = self.create()?;
+        screenshot(capture.as_mut()).map_err(|err| err.to_string())
+    }
+}
+
+/// Scale the last captured image down to fit into max_width x max_height by sampling the nearest
+/// pixels. Returns RGB data, width and height of the thumbnail. Only captures that provide BGRA
+/// data are supported.
+pub fn thumbnail(
+    capture: &dyn ScreenCapture,
+    max_width: usize,
+    max_height: usize,
+) -> Option<(Vec<u8>, usize, usize)> {
+    let (width, height) = capture.size();
+    if width == 0 || height == 0 {
+        return None;
+    }
+    // offsets of red, green and blue within a pixel
+    let (data, channels) = match capture.pixel_provider() {
+        crate::video::PixelProvider::BGRA(data) => (data, [2, 1, 0]),
+        crate::video::PixelProvider::RGBx(data) => (data, [0, 1, 2]),
+        _ => return None,
+    };
+    let scale = (max_width as f64 / width as f64)
+        .min(max_height as f64 / height as f64)
+        .min(1.0);
+    let thumb_width = ((width as f64 * scale) as usize).max(1);
+    let thumb_height = ((height as f64 * scale) as usize).max(1);
+    let mut rgb = Vec::with_capacity(thumb_width * thumb_height * 3);
+    for y in 0..thumb_height {
+        let src_y = y * height / thumb_height;
+        for x in 0..thumb_width {
+            let i = 4 * (src_y * width + x * width / thumb_width);
+            rgb.extend_from_slice(&[
+                data[i + channels[0]],
+                data[i + channels[1]],
+                data[i + channels[2]],
+            ]);
+        }
+    }
+    Some((rgb, thumb_width, thumb_height))
+}
+
+/// Capture a frame in full resolution and encode it as PNG.
+pub fn screenshot(capture: &mut dyn ScreenCapture) -> Result<Vec<u8>, Box<dyn Error>> {
+    capture.capture();
+    let (rgb, width, height) = thumbnail(capture, usize::MAX, usize::MAX)
+        .ok_or("Nothing has been captured or the pixel format is unsupported.")?;
+    let mut png = Vec::new();
+    image::png::PngEncoder::new(&mut png).encode(
+        &rgb,
+        width as u32,
+        height as u32,
+        image::ColorType::Rgb8,
+    )?;
+    Ok(png)
 }
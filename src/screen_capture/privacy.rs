@@ -0,0 +1,169 @@
+use serde::Deserialize;
+
+// edge length of the blocks private windows are pixelated with
+const BLOCK_SIZE: usize = 16;
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RedactionMode {
+    Blank,
+    Pixelate,
+}
+
+impl Default for RedactionMode {
+    fn default() -> Self {
+        Self::Blank
+    }
+}
+
+/// Window considered private, patterns may contain * to match any number of characters and are
+/// case insensitive. A window is private if all given patterns match.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct PrivateWindow {
+    pub title: Option<String>,
+    pub class: Option<String>,
+}
+
+impl PrivateWindow {
+    pub fn matches(&self, title: &str, class: &str) -> bool {
+        if self.title.is_none() && self.class.is_none() {
+            return false;
+        }
+        let matches = |pattern: &Option<String>, s: &str| match pattern {
+            Some(pattern) => wildcard_match(&pattern.to_lowercase(), &s.to_lowercase()),
+            None => true,
+        };
+        matches(&self.title, title) && matches(&self.class, class)
+    }
+}
+
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct PrivacyOptions {
+    pub windows: Vec<PrivateWindow>,
+    pub mode: RedactionMode,
+}
+
+impl PrivacyOptions {
+    pub fn is_enabled(&self) -> bool {
+        !self.windows.is_empty()
+    }
+
+    pub fn is_private(&self, title: &str, class: &str) -> bool {
+        self.windows.iter().any(|w| w.matches(title, class))
+    }
+}
+
+fn wildcard_match(pattern: &str, s: &str) -> bool {
+    let mut parts = pattern.split('*');
+    // there is always at least one part, the text before the first *
+    let first = parts.next().unwrap_or("");
+    if !s.starts_with(first) {
+        return false;
+    }
+    let mut rest = &s[first.len()..];
+    let parts: Vec<&str> = parts.collect();
+    let last = match parts.split_last() {
+        Some((last, middle)) => {
+            for part in middle {
+                match rest.find(part) {
+                    Some(i) => rest = &rest[i + part.len()..],
+                    None => return false,
+                }
+            }
+            last
+        }
+        // no * at all
+        None => return rest.is_empty(),
+    };
+    rest.ends_with(last)
+}
+
+/// Blank or pixelate the rectangle x, y, width, height of an image with 4 bytes per pixel, the
+/// rectangle has to be inside the image.
+pub fn redact(
+    data: &mut [u8],
+    image_width: usize,
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+    mode: RedactionMode,
+) {
+    let stride = 4 * image_width;
+    match mode {
+        RedactionMode::Blank => {
+            for row in y..y + height {
+                let start = row * stride + 4 * x;
+                for b in &mut data[start..start + 4 * width] {
+                    *b = 0;
+                }
+            }
+        }
+        RedactionMode::Pixelate => {
+            for block_y in (y..y + height).step_by(BLOCK_SIZE) {
+                let block_height = BLOCK_SIZE.min(y + height - block_y);
+                for block_x in (x..x + width).step_by(BLOCK_SIZE) {
+                    let block_width = BLOCK_SIZE.min(x + width - block_x);
+                    let mut sum = [0usize; 4];
+                    for row in block_y..block_y + block_height {
+                        let start = row * stride + 4 * block_x;
+                        for pixel in data[start..start + 4 * block_width].chunks_exact(4) {
+                            for (s, b) in sum.iter_mut().zip(pixel) {
+                                *s += *b as usize;
+                            }
+                        }
+                    }
+                    let count = block_width * block_height;
+                    let mut average = [0u8; 4];
+                    for (a, s) in average.iter_mut().zip(&sum) {
+                        *a = (s / count) as u8;
+                    }
+                    for row in block_y..block_y + block_height {
+                        let start = row * stride + 4 * block_x;
+                        for pixel in data[start..start + 4 * block_width].chunks_exact_mut(4) {
+                            pixel.copy_from_slice(&average);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn patterns_match() {
+        assert!(wildcard_match("keepassxc", "keepassxc"));
+        assert!(!wildcard_match("keepassxc", "keepassxc2"));
+        assert!(wildcard_match("*keepass*", "passwords - keepassxc"));
+        assert!(wildcard_match("*- signal", "chat - signal"));
+        assert!(wildcard_match("a*b*c", "aXbYbc"));
+        assert!(!wildcard_match("a*b*c", "aXcYb"));
+        let window = PrivateWindow {
+            title: None,
+            class: Some("Signal".into()),
+        };
+        assert!(window.matches("Chat", "signal"));
+        assert!(!window.matches("Signal", "Firefox"));
+        assert!(!PrivateWindow::default().matches("Signal", "Signal"));
+    }
+
+    #[test]
+    fn pixelate_averages_blocks() {
+        let mut data: Vec<u8> = (0..20 * 4)
+            .flat_map(|i| vec![i as u8 % 2 * 100; 4])
+            .collect();
+        redact(&mut data, 20, 2, 1, 18, 2, RedactionMode::Pixelate);
+        // outside of the rectangle
+        assert_eq!(&data[..4], &[0, 0, 0, 0]);
+        assert_eq!(&data[80..84], &[0, 0, 0, 0]);
+        // inside of the rectangle, every other pixel is 100 so the average of a block is 50
+        assert_eq!(&data[88..92], &[50, 50, 50, 50]);
+        assert_eq!(&data[80 + 4 * 17..80 + 4 * 18], &[50, 50, 50, 50]);
+    }
+}
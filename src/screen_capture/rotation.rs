@@ -0,0 +1,529 @@
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+use crate::protocol::{Orientation, PointerEvent, Viewport};
+use crate::screen_capture::ScreenCapture;
+use crate::video::{PixelFormat, PixelProvider};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rotation {
+    None,
+    // all rotations are clockwise
+    Rotate90,
+    Rotate180,
+    Rotate270,
+    // rotate by 90 degrees if the orientation reported by the client does not match the
+    // orientation of the captured image
+    Auto,
+}
+
+impl FromStr for Rotation {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "none" | "0" => Ok(Self::None),
+            "90" => Ok(Self::Rotate90),
+            "180" => Ok(Self::Rotate180),
+            "270" => Ok(Self::Rotate270),
+            "auto" => Ok(Self::Auto),
+            _ => Err(format!(
+                "Unknown rotation '{}', use none, 90, 180, 270 or auto.",
+                s
+            )),
+        }
+    }
+}
+
+impl Rotation {
+    fn swaps_dimensions(self) -> bool {
+        match self {
+            Rotation::Rotate90 | Rotation::Rotate270 => true,
+            _ => false,
+        }
+    }
+
+    /// Map a point relative to the rotated video back to the captured image.
+    fn transform_point(self, x: f64, y: f64) -> (f64, f64) {
+        match self {
+            Rotation::None | Rotation::Auto => (x, y),
+            Rotation::Rotate90 => (y, 1.0 - x),
+            Rotation::Rotate180 => (1.0 - x, 1.0 - y),
+            Rotation::Rotate270 => (1.0 - y, x),
+        }
+    }
+
+    /// Map a PointerEvent from the coordinate system of the rotated video back to the coordinate
+    /// system of the captured image. Tilt and movement come straight from the client, they are
+    /// negated saturating so i32::MIN and i64::MIN do not overflow.
+    pub fn transform_pointer_event(self, event: &mut PointerEvent) {
+        let (tilt_x, tilt_y) = (event.tilt_x, event.tilt_y);
+        let (movement_x, movement_y) = (event.movement_x, event.movement_y);
+        let (x, y) = self.transform_point(event.x, event.y);
+        event.x = x;
+        event.y = y;
+        match self {
+            Rotation::None | Rotation::Auto => return,
+            Rotation::Rotate90 => {
+                event.tilt_x = tilt_y;
+                event.tilt_y = tilt_x.saturating_neg();
+                event.movement_x = movement_y;
+                event.movement_y = movement_x.saturating_neg();
+            }
+            Rotation::Rotate180 => {
+                event.tilt_x = tilt_x.saturating_neg();
+                event.tilt_y = tilt_y.saturating_neg();
+                event.movement_x = movement_x.saturating_neg();
+                event.movement_y = movement_y.saturating_neg();
+            }
+            Rotation::Rotate270 => {
+                event.tilt_x = tilt_y.saturating_neg();
+                event.tilt_y = tilt_x;
+                event.movement_x = movement_y.saturating_neg();
+                event.movement_y = movement_x;
+            }
+        }
+        if self.swaps_dimensions() {
+            std::mem::swap(&mut event.width, &mut event.height);
+        }
+    }
+}
+
+/// Rotation state shared between the video and the pointer stream of a client: The pointer stream
+/// receives the orientation of the client, the video stream decides on the actual rotation, which
+/// then is used by the pointer stream to map coordinates back. The same goes for the viewport the
+/// client zoomed into and the part of the captured image it has been cropped to.
+pub struct RotationState {
+    mode: Rotation,
+    client_orientation: Option<Orientation>,
+    current: Rotation,
+    viewport: Option<Viewport>,
+    // the viewport mapped onto the captured image as it has been cropped for the last frame,
+    // relative to the captured image
+    crop: Option<Viewport>,
+}
+
+impl RotationState {
+    pub fn new(mode: Rotation) -> Arc<Mutex<Self>> {
+        Arc::new(Mutex::new(Self {
+            mode,
+            client_orientation: None,
+            current: Rotation::None,
+            viewport: None,
+            crop: None,
+        }))
+    }
+
+    pub fn set_viewport(&mut self, viewport: Option<Viewport>) {
+        self.viewport = viewport;
+    }
+
+    pub fn viewport(&self) -> Option<Viewport> {
+        self.viewport
+    }
+
+    pub fn current_crop(&self) -> Option<Viewport> {
+        self.crop
+    }
+
+    pub fn set_client_orientation(&mut self, orientation: Orientation) {
+        self.client_orientation = Some(orientation);
+    }
+
+    pub fn current(&self) -> Rotation {
+        self.current
+    }
+
+    fn resolve(&mut self, width: usize, height: usize) -> Rotation {
+        self.current = match self.mode {
+            Rotation::Auto => match self.client_orientation {
+                Some(Orientation::Portrait) if width > height => Rotation::Rotate90,
+                Some(Orientation::Landscape) if height > width => Rotation::Rotate90,
+                _ => Rotation::None,
+            },
+            rotation => rotation,
+        };
+        self.current
+    }
+
+    // x, y, width and height in pixels of the part of a captured image of the given size the
+    // viewport shows, offsets are even so chroma planes can be cropped the same way
+    fn resolve_crop(
+        &mut self,
+        width: usize,
+        height: usize,
+    ) -> Option<(usize, usize, usize, usize)> {
+        self.crop = None;
+        let viewport = self.viewport?;
+        if width < 2 || height < 2 {
+            return None;
+        }
+        let (x0, y0) = self.current.transform_point(viewport.x, viewport.y);
+        let (x1, y1) = self
+            .current
+            .transform_point(viewport.x + viewport.width, viewport.y + viewport.height);
+        let to_pixels = |a: f64, b: f64, size: usize| {
+            let start = ((a.min(b).max(0.0) * size as f64) as usize).min(size - 2) & !1;
+            let end = ((a.max(b).min(1.0) * size as f64).ceil() as usize)
+                .max(start + 2)
+                .min(size);
+            (start, end - start)
+        };
+        let (x, crop_width) = to_pixels(x0, x1, width);
+        let (y, crop_height) = to_pixels(y0, y1, height);
+        if crop_width == width && crop_height == height {
+            return None;
+        }
+        self.crop = Some(Viewport {
+            x: x as f64 / width as f64,
+            y: y as f64 / height as f64,
+            width: crop_width as f64 / width as f64,
+            height: crop_height as f64 / height as f64,
+        });
+        Some((x, y, crop_width, crop_height))
+    }
+}
+
+fn rotate_plane(
+    src: &[u8],
+    src_stride: usize,
+    width: usize,
+    height: usize,
+    bytes_per_pixel: usize,
+    rotation: Rotation,
+    dst: &mut Vec<u8>,
+) {
+    let dst_width = if rotation.swaps_dimensions() {
+        height
+    } else {
+        width
+    };
+    let dst_stride = dst_width * bytes_per_pixel;
+    dst.resize(width * height * bytes_per_pixel, 0);
+    for sy in 0..height {
+        for sx in 0..width {
+            let (dx, dy) = match rotation {
+                Rotation::Rotate90 => (height - 1 - sy, sx),
+                Rotation::Rotate180 => (width - 1 - sx, height - 1 - sy),
+                Rotation::Rotate270 => (sy, width - 1 - sx),
+                Rotation::None | Rotation::Auto => (sx, sy),
+            };
+            let s = sy * src_stride + sx * bytes_per_pixel;
+            let d = dy * dst_stride + dx * bytes_per_pixel;
+            dst[d..d + bytes_per_pixel].copy_from_slice(&src[s..s + bytes_per_pixel]);
+        }
+    }
+}
+
+// rotated planes of a yuv420p image, width and height are those of the Y plane
+struct YUV420PImage {
+    y: Vec<u8>,
+    u: Vec<u8>,
+    v: Vec<u8>,
+    width: usize,
+    height: usize,
+}
+
+// rotated image in the format provided by the wrapped ScreenCapture
+enum RotatedImage {
+    // BGRA or RGBx
+    Packed(PixelFormat, Vec<u8>),
+    NV12(Vec<u8>, Vec<u8>),
+    I420(Vec<u8>, Vec<u8>, Vec<u8>),
+    // filled via FillYUV420P, dimensions are clipped to be even
+    YUV420P(YUV420PImage),
+}
+
+/// Wraps a ScreenCapture and rotates the captured images before they are handed to the encoder,
+/// if the client zoomed in they are cropped to the viewport as well.
+pub struct RotatedScreenCapture<T: ScreenCapture> {
+    screen_capture: T,
+    state: Arc<Mutex<RotationState>>,
+    rotation: Rotation,
+    // x, y, width and height in pixels
+    crop: Option<(usize, usize, usize, usize)>,
+    image: Option<RotatedImage>,
+}
+
+impl<T: ScreenCapture> RotatedScreenCapture<T> {
+    pub fn new(screen_capture: T, state: Arc<Mutex<RotationState>>) -> Self {
+        Self {
+            screen_capture,
+            state,
+            rotation: Rotation::None,
+            crop: None,
+            image: None,
+        }
+    }
+
+    // buffers of the previously rotated image so they can be reused
+    fn take_buffers(&mut self) -> [Vec<u8>; 3] {
+        match self.image.take() {
+            Some(RotatedImage::Packed(_, buf)) => [buf, Vec::new(), Vec::new()],
+            Some(RotatedImage::NV12(y, uv)) => [y, uv, Vec::new()],
+            Some(RotatedImage::I420(y, u, v)) => [y, u, v],
+            Some(RotatedImage::YUV420P(img)) => [img.y, img.u, img.v],
+            None => Default::default(),
+        }
+    }
+}
+
+impl<T: ScreenCapture> ScreenCapture for RotatedScreenCapture<T> {
+    fn capture(&mut self) {
+        self.screen_capture.capture();
+        let (full_width, full_height) = self.screen_capture.size();
+        {
+            let mut state = self.state.lock().unwrap();
+            self.rotation = state.resolve(full_width, full_height);
+            self.crop = state.resolve_crop(full_width, full_height);
+        }
+        if self.rotation == Rotation::None && self.crop.is_none() {
+            return;
+        }
+        let rotation = self.rotation;
+        let (x, y, width, height) = self.crop.unwrap_or((0, 0, full_width, full_height));
+        let [mut buf0, mut buf1, mut buf2] = self.take_buffers();
+        // chroma planes of NV12 and I420 images, crops start at even offsets so they begin at a
+        // chroma sample
+        let full_chroma_width = (full_width + 1) / 2;
+        let chroma_offset = y / 2 * full_chroma_width + x / 2;
+        let (chroma_width, chroma_height) = ((width + 1) / 2, (height + 1) / 2);
+        let pixel_provider = self.screen_capture.pixel_provider();
+        let format = pixel_provider.format();
+        let image = match pixel_provider {
+            PixelProvider::BGRA(data) | PixelProvider::RGBx(data) => {
+                rotate_plane(
+                    &data[4 * (y * full_width + x)..],
+                    full_width * 4,
+                    width,
+                    height,
+                    4,
+                    rotation,
+                    &mut buf0,
+                );
+                RotatedImage::Packed(format.unwrap(), buf0)
+            }
+            PixelProvider::NV12(y_plane, uv) => {
+                rotate_plane(
+                    &y_plane[y * full_width + x..],
+                    full_width,
+                    width,
+                    height,
+                    1,
+                    rotation,
+                    &mut buf0,
+                );
+                rotate_plane(
+                    &uv[2 * chroma_offset..],
+                    2 * full_chroma_width,
+                    chroma_width,
+                    chroma_height,
+                    2,
+                    rotation,
+                    &mut buf1,
+                );
+                RotatedImage::NV12(buf0, buf1)
+            }
+            PixelProvider::I420(y_plane, u, v) => {
+                rotate_plane(
+                    &y_plane[y * full_width + x..],
+                    full_width,
+                    width,
+                    height,
+                    1,
+                    rotation,
+                    &mut buf0,
+                );
+                rotate_plane(
+                    &u[chroma_offset..],
+                    full_chroma_width,
+                    chroma_width,
+                    chroma_height,
+                    1,
+                    rotation,
+                    &mut buf1,
+                );
+                rotate_plane(
+                    &v[chroma_offset..],
+                    full_chroma_width,
+                    chroma_width,
+                    chroma_height,
+                    1,
+                    rotation,
+                    &mut buf2,
+                );
+                RotatedImage::I420(buf0, buf1, buf2)
+            }
+            PixelProvider::FillYUV420P(fill_yuv) => {
+                // yuv420p requires even dimensions, so the last row or column may be clipped
+                let full_width = full_width - full_width % 2;
+                let full_height = full_height - full_height % 2;
+                let width = width.min(full_width.saturating_sub(x)) & !1;
+                let height = height.min(full_height.saturating_sub(y)) & !1;
+                let mut y_plane = vec![0; full_width * full_height];
+                let mut u = vec![0; full_width / 2 * full_height / 2];
+                let mut v = vec![0; full_width / 2 * full_height / 2];
+                fill_yuv(
+                    &mut y_plane,
+                    &mut u,
+                    &mut v,
+                    full_width,
+                    full_width / 2,
+                    full_width / 2,
+                );
+                let chroma_offset = y / 2 * full_width / 2 + x / 2;
+                rotate_plane(
+                    &y_plane[y * full_width + x..],
+                    full_width,
+                    width,
+                    height,
+                    1,
+                    rotation,
+                    &mut buf0,
+                );
+                rotate_plane(
+                    &u[chroma_offset..],
+                    full_width / 2,
+                    width / 2,
+                    height / 2,
+                    1,
+                    rotation,
+                    &mut buf1,
+                );
+                rotate_plane(
+                    &v[chroma_offset..],
+                    full_width / 2,
+                    width / 2,
+                    height / 2,
+                    1,
+                    rotation,
+                    &mut buf2,
+                );
+                let (width, height) = if rotation.swaps_dimensions() {
+                    (height, width)
+                } else {
+                    (width, height)
+                };
+                RotatedImage::YUV420P(YUV420PImage {
+                    y: buf0,
+                    u: buf1,
+                    v: buf2,
+                    width,
+                    height,
+                })
+            }
+        };
+        self.image = Some(image);
+    }
+
+    fn pixel_provider(&self) -> PixelProvider {
+        if self.rotation == Rotation::None && self.crop.is_none() {
+            return self.screen_capture.pixel_provider();
+        }
+        let image = self
+            .image
+            .as_ref()
+            .expect("capture has to be called before invoking pixel_provider");
+        let img = match image {
+            RotatedImage::Packed(PixelFormat::RGBx, data) => return PixelProvider::RGBx(data),
+            RotatedImage::Packed(_, data) => return PixelProvider::BGRA(data),
+            RotatedImage::NV12(y, uv) => return PixelProvider::NV12(y, uv),
+            RotatedImage::I420(y, u, v) => return PixelProvider::I420(y, u, v),
+            RotatedImage::YUV420P(img) => img,
+        };
+        PixelProvider::FillYUV420P(Box::new(
+            move |y: &mut [u8],
+                  u: &mut [u8],
+                  v: &mut [u8],
+                  y_line_size: usize,
+                  u_line_size: usize,
+                  v_line_size: usize| {
+                for row in 0..img.height {
+                    y[row * y_line_size..row * y_line_size + img.width]
+                        .copy_from_slice(&img.y[row * img.width..(row + 1) * img.width]);
+                }
+                let (width, height) = (img.width / 2, img.height / 2);
+                for row in 0..height {
+                    u[row * u_line_size..row * u_line_size + width]
+                        .copy_from_slice(&img.u[row * width..(row + 1) * width]);
+                    v[row * v_line_size..row * v_line_size + width]
+                        .copy_from_slice(&img.v[row * width..(row + 1) * width]);
+                }
+            },
+        ))
+    }
+
+    fn size(&self) -> (usize, usize) {
+        // images filled via FillYUV420P are clipped to even dimensions and already rotated
+        if self.rotation != Rotation::None || self.crop.is_some() {
+            if let Some(RotatedImage::YUV420P(img)) = &self.image {
+                return (img.width, img.height);
+            }
+        }
+        let (width, height) = match self.crop {
+            Some((_, _, width, height)) => (width, height),
+            None => self.screen_capture.size(),
+        };
+        if self.rotation.swaps_dimensions() {
+            (height, width)
+        } else {
+            (width, height)
+        }
+    }
+
+    fn switched_target(&mut self) -> bool {
+        self.screen_capture.switched_target()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::test_events::pointer_event;
+    use crate::protocol::{PointerEventType, PointerType};
+
+    // provides a yuv420p image of odd dimensions
+    struct OddCapture;
+
+    impl ScreenCapture for OddCapture {
+        fn capture(&mut self) {}
+
+        fn pixel_provider(&self) -> PixelProvider {
+            PixelProvider::FillYUV420P(Box::new(|y, u, v, _, _, _| {
+                y.iter_mut().for_each(|p| *p = 1);
+                u.iter_mut().for_each(|p| *p = 2);
+                v.iter_mut().for_each(|p| *p = 3);
+            }))
+        }
+
+        fn size(&self) -> (usize, usize) {
+            (5, 3)
+        }
+    }
+
+    #[test]
+    fn size_matches_clipped_yuv420p_image() {
+        let mut capture =
+            RotatedScreenCapture::new(OddCapture, RotationState::new(Rotation::Rotate90));
+        capture.capture();
+        assert_eq!(capture.size(), (2, 4));
+        let mut y = vec![0; 8];
+        let (mut u, mut v) = (vec![0; 2], vec![0; 2]);
+        match capture.pixel_provider() {
+            PixelProvider::FillYUV420P(fill) => fill(&mut y, &mut u, &mut v, 2, 1, 1),
+            _ => panic!("Expected a yuv420p image."),
+        }
+        assert_eq!((y, u, v), (vec![1; 8], vec![2; 2], vec![3; 2]));
+    }
+
+    #[test]
+    fn rotating_handles_extreme_values() {
+        let mut event = pointer_event(PointerEventType::MOVE, PointerType::Pen, 0.25, 0.5, 0);
+        event.tilt_x = i32::MIN;
+        event.movement_x = i64::MIN;
+        Rotation::Rotate90.transform_pointer_event(&mut event);
+        assert_eq!((event.x, event.y), (0.5, 0.75));
+        assert_eq!(event.tilt_y, i32::MAX);
+        assert_eq!(event.movement_y, i64::MAX);
+    }
+}
@@ -0,0 +1,54 @@
+use crate::screen_capture::ScreenCapture;
+use crate::video::PixelProvider;
+
+/// Screen capture producing a synthetic image: A gradient with a vertical bar that moves by a few
+/// pixels on every capture, so consecutive frames always differ. Used to test streaming without
+/// access to a real screen.
+pub struct TestScreenCapture {
+    width: usize,
+    height: usize,
+    frame: usize,
+    data: Vec<u8>,
+}
+
+impl TestScreenCapture {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            frame: 0,
+            data: vec![0; width * height * 4],
+        }
+    }
+}
+
+impl ScreenCapture for TestScreenCapture {
+    fn capture(&mut self) {
+        let bar = (self.frame * 4) % self.width.max(1);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let i = 4 * (y * self.width + x);
+                let pixel = if x >= bar && x < bar + 8 {
+                    [255, 255, 255, 255]
+                } else {
+                    [
+                        (x * 255 / self.width) as u8,
+                        (y * 255 / self.height) as u8,
+                        128,
+                        255,
+                    ]
+                };
+                self.data[i..i + 4].copy_from_slice(&pixel);
+            }
+        }
+        self.frame += 1;
+    }
+
+    fn pixel_provider(&self) -> PixelProvider {
+        PixelProvider::BGRA(&self.data)
+    }
+
+    fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+}
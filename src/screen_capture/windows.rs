@@ -0,0 +1,347 @@
+//! Capturing single windows on Windows via PrintWindow. The window is asked to render itself into
+//! a bitmap, so it is captured even if other windows cover it. DWM thumbnails would avoid the
+//! rendering but can only be shown inside windows of Weylus, not read back, so they are of no use
+//! here. Everything else is captured by the generic screen capture.
+
+use std::os::raw::c_void;
+use std::sync::atomic::{AtomicIsize, Ordering};
+
+use tracing::{info, warn};
+
+type Hwnd = isize;
+type Hdc = isize;
+type Hgdiobj = isize;
+
+#[repr(C)]
+#[derive(Default)]
+struct Rect {
+    left: i32,
+    top: i32,
+    right: i32,
+    bottom: i32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct Point {
+    x: i32,
+    y: i32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct BitmapInfo {
+    size: u32,
+    width: i32,
+    height: i32,
+    planes: u16,
+    bit_count: u16,
+    compression: u32,
+    size_image: u32,
+    x_pels_per_meter: i32,
+    y_pels_per_meter: i32,
+    clr_used: u32,
+    clr_important: u32,
+    colors: [u32; 1],
+}
+
+#[link(name = "user32")]
+extern "system" {
+    fn EnumWindows(callback: extern "system" fn(Hwnd, isize) -> i32, param: isize) -> i32;
+    fn IsWindow(hwnd: Hwnd) -> i32;
+    fn IsWindowVisible(hwnd: Hwnd) -> i32;
+    fn IsIconic(hwnd: Hwnd) -> i32;
+    fn GetWindowTextW(hwnd: Hwnd, text: *mut u16, max_count: i32) -> i32;
+    fn GetWindowLongW(hwnd: Hwnd, index: i32) -> i32;
+    fn GetWindowThreadProcessId(hwnd: Hwnd, process_id: *mut u32) -> u32;
+    fn GetClientRect(hwnd: Hwnd, rect: *mut Rect) -> i32;
+    fn ClientToScreen(hwnd: Hwnd, point: *mut Point) -> i32;
+    fn GetSystemMetrics(index: i32) -> i32;
+    fn GetDC(hwnd: Hwnd) -> Hdc;
+    fn ReleaseDC(hwnd: Hwnd, hdc: Hdc) -> i32;
+    fn PrintWindow(hwnd: Hwnd, hdc: Hdc, flags: u32) -> i32;
+}
+
+#[link(name = "gdi32")]
+extern "system" {
+    fn CreateCompatibleDC(hdc: Hdc) -> Hdc;
+    fn CreateCompatibleBitmap(hdc: Hdc, width: i32, height: i32) -> Hgdiobj;
+    fn SelectObject(hdc: Hdc, object: Hgdiobj) -> Hgdiobj;
+    fn DeleteObject(object: Hgdiobj) -> i32;
+    fn DeleteDC(hdc: Hdc) -> i32;
+    fn GetDIBits(
+        hdc: Hdc,
+        bitmap: Hgdiobj,
+        start: u32,
+        lines: u32,
+        bits: *mut c_void,
+        info: *mut BitmapInfo,
+        usage: u32,
+    ) -> i32;
+}
+
+#[link(name = "dwmapi")]
+extern "system" {
+    fn DwmGetWindowAttribute(hwnd: Hwnd, attribute: u32, value: *mut c_void, size: u32) -> i32;
+}
+
+const GWL_EXSTYLE: i32 = -20;
+const WS_EX_TOOLWINDOW: i32 = 0x80;
+const DWMWA_CLOAKED: u32 = 14;
+const SM_CXSCREEN: i32 = 0;
+const SM_CYSCREEN: i32 = 1;
+const PW_CLIENTONLY: u32 = 1;
+// makes windows drawn via DirectX, e.g. browsers, render their content too, Windows 8.1 and later
+const PW_RENDERFULLCONTENT: u32 = 2;
+
+// the window captured instead of the whole screen, 0 if there is none
+static TARGET_WINDOW: AtomicIsize = AtomicIsize::new(0);
+
+/// A top level window that can be captured.
+#[derive(Clone, Debug)]
+pub struct Window {
+    pub hwnd: isize,
+    pub title: String,
+}
+
+/// Visible top level windows with a title, windows of Weylus itself are left out.
+pub fn windows() -> Vec<Window> {
+    extern "system" fn add_window(hwnd: Hwnd, param: isize) -> i32 {
+        let windows = unsafe { &mut *(param as *mut Vec<Window>) };
+        if let Some(window) = capturable_window(hwnd) {
+            windows.push(window);
+        }
+        1
+    }
+    let mut windows: Vec<Window> = Vec::new();
+    unsafe { EnumWindows(add_window, &mut windows as *mut Vec<Window> as isize) };
+    windows
+}
+
+fn capturable_window(hwnd: Hwnd) -> Option<Window> {
+    unsafe {
+        if IsWindowVisible(hwnd) == 0 || GetWindowLongW(hwnd, GWL_EXSTYLE) & WS_EX_TOOLWINDOW != 0 {
+            return None;
+        }
+        // windows of apps that are suspended are visible but hidden by DWM
+        let mut cloaked: u32 = 0;
+        if DwmGetWindowAttribute(
+            hwnd,
+            DWMWA_CLOAKED,
+            &mut cloaked as *mut u32 as *mut c_void,
+            std::mem::size_of::<u32>() as u32,
+        ) == 0
+            && cloaked != 0
+        {
+            return None;
+        }
+        let mut process_id: u32 = 0;
+        GetWindowThreadProcessId(hwnd, &mut process_id);
+        if process_id == std::process::id() {
+            return None;
+        }
+    }
+    let title = window_title(hwnd);
+    if title.is_empty() {
+        return None;
+    }
+    Some(Window { hwnd, title })
+}
+
+fn window_title(hwnd: Hwnd) -> String {
+    let mut title = [0u16; 512];
+    let len = unsafe { GetWindowTextW(hwnd, title.as_mut_ptr(), title.len() as i32) };
+    String::from_utf16_lossy(&title[..len.max(0) as usize])
+}
+
+/// Capture the window instead of the whole screen, None captures the whole screen again.
+pub fn set_target(window: Option<&Window>) {
+    match window {
+        Some(window) => info!("Capturing window {}.", window.title),
+        None => info!("Capturing the whole screen."),
+    }
+    TARGET_WINDOW.store(window.map_or(0, |window| window.hwnd), Ordering::SeqCst);
+}
+
+/// The captured window, None if the whole screen is captured.
+pub fn target() -> Option<isize> {
+    match TARGET_WINDOW.load(Ordering::SeqCst) {
+        0 => None,
+        hwnd => Some(hwnd),
+    }
+}
+
+/// Position and size of the client area of the captured window relative to the screen, None if
+/// the whole screen is captured. Used to map input to the window.
+pub fn target_geometry() -> Option<(f64, f64, f64, f64)> {
+    let hwnd = target()?;
+    let mut rect = Rect::default();
+    let mut origin = Point::default();
+    unsafe {
+        if GetClientRect(hwnd, &mut rect) == 0 || ClientToScreen(hwnd, &mut origin) == 0 {
+            return None;
+        }
+        let screen_width = GetSystemMetrics(SM_CXSCREEN) as f64;
+        let screen_height = GetSystemMetrics(SM_CYSCREEN) as f64;
+        if screen_width <= 0.0 || screen_height <= 0.0 {
+            return None;
+        }
+        Some((
+            origin.x as f64 / screen_width,
+            origin.y as f64 / screen_height,
+            (rect.right - rect.left) as f64 / screen_width,
+            (rect.bottom - rect.top) as f64 / screen_height,
+        ))
+    }
+}
+
+/// Captures the target window, if there is none the caller captures the whole screen.
+pub struct WindowCapture {
+    hwnd: Option<Hwnd>,
+    bgra: Vec<u8>,
+    width: usize,
+    height: usize,
+    // set if the last capture is of a window
+    active: bool,
+    switched: bool,
+}
+
+impl WindowCapture {
+    pub fn new() -> Self {
+        Self {
+            hwnd: None,
+            bgra: Vec::new(),
+            width: 0,
+            height: 0,
+            active: false,
+            switched: false,
+        }
+    }
+
+    /// Returns false if the whole screen should be captured instead.
+    pub fn capture(&mut self) -> bool {
+        self.active = self.capture_target();
+        self.active
+    }
+
+    fn capture_target(&mut self) -> bool {
+        let hwnd = target();
+        if hwnd != self.hwnd {
+            self.hwnd = hwnd;
+            self.bgra.clear();
+            self.switched = true;
+        }
+        let hwnd = match hwnd {
+            Some(hwnd) => hwnd,
+            None => return false,
+        };
+        if unsafe { IsWindow(hwnd) } == 0 {
+            warn!("The captured window has been closed, capturing the whole screen.");
+            let _ = TARGET_WINDOW.compare_exchange(hwnd, 0, Ordering::SeqCst, Ordering::SeqCst);
+            self.hwnd = None;
+            return false;
+        }
+        // minimized windows have no content, the last frame is shown until they are restored
+        if unsafe { IsIconic(hwnd) } != 0 && !self.bgra.is_empty() {
+            return true;
+        }
+        if let Err(err) = self.print_window(hwnd) {
+            warn!("Failed to capture window: {}", err);
+            return !self.bgra.is_empty();
+        }
+        true
+    }
+
+    fn print_window(&mut self, hwnd: Hwnd) -> Result<(), String> {
+        let mut rect = Rect::default();
+        if unsafe { GetClientRect(hwnd, &mut rect) } == 0 {
+            return Err("GetClientRect failed".into());
+        }
+        // the encoder needs a size of at least 2x2
+        let width = (rect.right - rect.left).max(2);
+        let height = (rect.bottom - rect.top).max(2);
+        unsafe {
+            let window_dc = GetDC(hwnd);
+            if window_dc == 0 {
+                return Err("GetDC failed".into());
+            }
+            let dc = CreateCompatibleDC(window_dc);
+            let bitmap = CreateCompatibleBitmap(window_dc, width, height);
+            let res = if dc == 0 || bitmap == 0 {
+                Err("Failed to create bitmap".to_string())
+            } else {
+                let previous = SelectObject(dc, bitmap);
+                let printed = PrintWindow(hwnd, dc, PW_CLIENTONLY | PW_RENDERFULLCONTENT);
+                SelectObject(dc, previous);
+                if printed == 0 {
+                    Err("PrintWindow failed".to_string())
+                } else {
+                    self.read_bitmap(dc, bitmap, width, height)
+                }
+            };
+            if bitmap != 0 {
+                DeleteObject(bitmap);
+            }
+            if dc != 0 {
+                DeleteDC(dc);
+            }
+            ReleaseDC(hwnd, window_dc);
+            res
+        }
+    }
+
+    unsafe fn read_bitmap(
+        &mut self,
+        dc: Hdc,
+        bitmap: Hgdiobj,
+        width: i32,
+        height: i32,
+    ) -> Result<(), String> {
+        let mut info = BitmapInfo {
+            size: (std::mem::size_of::<BitmapInfo>() - std::mem::size_of::<[u32; 1]>()) as u32,
+            width,
+            // negative heights give rows from top to bottom
+            height: -height,
+            planes: 1,
+            bit_count: 32,
+            ..Default::default()
+        };
+        self.bgra.resize(width as usize * height as usize * 4, 0);
+        let lines = GetDIBits(
+            dc,
+            bitmap,
+            0,
+            height as u32,
+            self.bgra.as_mut_ptr() as *mut c_void,
+            &mut info,
+            0,
+        );
+        if lines != height {
+            return Err("GetDIBits failed".into());
+        }
+        if self.width != width as usize || self.height != height as usize {
+            self.width = width as usize;
+            self.height = height as usize;
+            self.switched = true;
+        }
+        Ok(())
+    }
+
+    /// Whether the last capture is of a window.
+    pub fn active(&self) -> bool {
+        self.active
+    }
+
+    pub fn bgra(&self) -> &[u8] {
+        &self.bgra
+    }
+
+    pub fn size(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /// Returns true once after another window has been chosen.
+    pub fn switched(&mut self) -> bool {
+        std::mem::replace(&mut self.switched, false)
+    }
+}
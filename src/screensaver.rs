@@ -0,0 +1,104 @@
+//! Keeps the display of this computer awake while video is streamed, otherwise the stream goes
+//! black as soon as the screensaver starts or the display is blanked. Instead of disabling the
+//! screensaver, which would stay disabled if Weylus crashed, the idle timer of the system is reset
+//! periodically just like user input would do.
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use tracing::debug;
+
+// screensavers and DPMS can not be set to less than a minute on most systems
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(20);
+
+// number of inhibitors alive
+static INHIBITORS: AtomicUsize = AtomicUsize::new(0);
+// whether the thread resetting the idle timer is running
+static HEARTBEAT_RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Keeps the display awake as long as it exists.
+pub struct ScreenSaverInhibitor {
+    // only created via new
+    _private: (),
+}
+
+impl ScreenSaverInhibitor {
+    /// If wake_display is set a display that has been blanked already is switched on again.
+    pub fn new(wake_display: bool) -> Self {
+        if wake_display {
+            std::thread::spawn(|| keep_awake(true));
+        }
+        if INHIBITORS.fetch_add(1, Ordering::SeqCst) == 0
+            && !HEARTBEAT_RUNNING.swap(true, Ordering::SeqCst)
+        {
+            debug!("Inhibiting the screensaver.");
+            std::thread::spawn(heartbeat);
+        }
+        Self { _private: () }
+    }
+}
+
+impl Drop for ScreenSaverInhibitor {
+    fn drop(&mut self) {
+        INHIBITORS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+fn heartbeat() {
+    loop {
+        keep_awake(false);
+        // checked more often than the timer is reset to stop soon after the last stream ended
+        let start = Instant::now();
+        while start.elapsed() < HEARTBEAT_INTERVAL {
+            std::thread::sleep(Duration::from_secs(1));
+            if INHIBITORS.load(Ordering::SeqCst) > 0 {
+                continue;
+            }
+            HEARTBEAT_RUNNING.store(false, Ordering::SeqCst);
+            // an inhibitor created just now may have seen this thread as running and relies on
+            // it, unless another thread has been started already
+            if INHIBITORS.load(Ordering::SeqCst) == 0
+                || HEARTBEAT_RUNNING.swap(true, Ordering::SeqCst)
+            {
+                debug!("Allowing the screensaver again.");
+                return;
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn keep_awake(wake: bool) {
+    // capturing via DRM works without X server, there is nothing to do then
+    match crate::x11helper::X11Context::new() {
+        Some(mut x11_context) => x11_context.reset_screensaver(wake),
+        None => debug!("Failed to open the X11 display to reset the screensaver."),
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn keep_awake(_wake: bool) {
+    // ES_SYSTEM_REQUIRED | ES_DISPLAY_REQUIRED, this resets the idle timers and switches the
+    // display on
+    const FLAGS: u32 = 0x0000_0001 | 0x0000_0002;
+    extern "system" {
+        fn SetThreadExecutionState(flags: u32) -> u32;
+    }
+    if unsafe { SetThreadExecutionState(FLAGS) } == 0 {
+        debug!("Failed to reset the idle timer.");
+    }
+}
+
+#[cfg(target_os = "macos")]
+fn keep_awake(_wake: bool) {
+    // declares that the user is active, this switches the display on as well
+    if let Err(err) = std::process::Command::new("caffeinate")
+        .args(&["-u", "-t", "1"])
+        .status()
+    {
+        debug!("Failed to run caffeinate: {}", err);
+    }
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "windows", target_os = "macos")))]
+fn keep_awake(_wake: bool) {}
@@ -0,0 +1,99 @@
+//! Messages pushed to clients, e.g. by the shared video stream or the audio stream, are sent by a
+//! thread per client. Queueing never blocks, so a client on a slow connection does not hold up
+//! the others: once its queue is full further messages are dropped and if it does not take any
+//! messages for a while it is disconnected.
+
+use std::net::{Shutdown, TcpStream};
+use std::sync::mpsc::{self, TrySendError};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tracing::warn;
+use websocket::{OwnedMessage, WebSocketError};
+
+use crate::stream_handler::WsWriter;
+
+// batches of messages waiting to be sent to a client, further ones are dropped
+const QUEUE_LENGTH: usize = 16;
+// a client whose queue stays full for this long is disconnected
+const STALL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Messages in a batch are sent together, no other message on the same connection gets in
+/// between.
+pub type Batch = Vec<Arc<OwnedMessage>>;
+
+pub struct SendQueue {
+    queue: mpsc::SyncSender<Batch>,
+    // a clone of the connection to disconnect a stalled client, the writer itself is locked by
+    // the sending thread while it is blocked
+    stream: Option<TcpStream>,
+    // since when batches are dropped because the queue is full
+    full_since: Option<Instant>,
+}
+
+impl SendQueue {
+    /// Starts the thread sending to the client, errors on sending are passed to on_error and stop
+    /// the thread.
+    pub fn new(sender: WsWriter, on_error: fn(WebSocketError)) -> Self {
+        let stream = sender.lock().unwrap().stream.try_clone().ok();
+        let (queue, batches) = mpsc::sync_channel::<Batch>(QUEUE_LENGTH);
+        std::thread::spawn(move || {
+            for batch in batches {
+                let mut sender = sender.lock().unwrap();
+                for msg in batch {
+                    if let Err(err) = sender.send_message(&*msg) {
+                        on_error(err);
+                        return;
+                    }
+                }
+            }
+        });
+        Self {
+            queue,
+            stream,
+            full_since: None,
+        }
+    }
+
+    /// Returns false if the batch has been dropped because the client does not keep up or the
+    /// connection is broken.
+    pub fn send(&mut self, batch: Batch) -> bool {
+        match self.queue.try_send(batch) {
+            Ok(()) => {
+                self.full_since = None;
+                true
+            }
+            Err(TrySendError::Full(_)) => {
+                let full_since = *self.full_since.get_or_insert_with(Instant::now);
+                if full_since.elapsed() >= STALL_TIMEOUT {
+                    self.disconnect();
+                }
+                false
+            }
+            Err(TrySendError::Disconnected(_)) => false,
+        }
+    }
+
+    /// Whether batches are dropped because the queue is full.
+    pub fn is_full(&self) -> bool {
+        self.full_since.is_some()
+    }
+
+    fn disconnect(&mut self) {
+        if let Some(stream) = self.stream.take() {
+            match stream.peer_addr() {
+                Ok(addr) => warn!(
+                    "Disconnecting {}, it did not receive anything for {} seconds.",
+                    addr,
+                    STALL_TIMEOUT.as_secs()
+                ),
+                Err(_) => warn!(
+                    "Disconnecting a client, it did not receive anything for {} seconds.",
+                    STALL_TIMEOUT.as_secs()
+                ),
+            }
+            // the blocked sending thread fails and the connection is closed like any broken one
+            stream.shutdown(Shutdown::Both).ok();
+        }
+    }
+}
@@ -0,0 +1,97 @@
+//! Small pool of threads working on horizontal slices of a frame in parallel, the threads are kept
+//! alive for the lifetime of the pool so no threads have to be spawned per frame.
+
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::sync::mpsc;
+use std::thread;
+
+use tracing::warn;
+
+// upper bound for the number of threads if it is picked automatically, more threads hardly help
+// with conversion and make x264 split frames into many small slices which hurts compression
+const MAX_AUTO_THREADS: usize = 8;
+
+/// Number of threads to use if the user asked for `threads`, 0 picks a number based on the
+/// available cores.
+pub fn thread_count(threads: usize) -> usize {
+    if threads == 0 {
+        num_cpus::get().min(MAX_AUTO_THREADS)
+    } else {
+        threads
+    }
+}
+
+struct Job {
+    // only valid until the job has been reported as done, see SlicePool::run
+    f: &'static (dyn Fn(usize) + Sync),
+    slice: usize,
+}
+
+pub struct SlicePool {
+    jobs: Vec<mpsc::Sender<Job>>,
+    done: mpsc::Receiver<thread::Result<()>>,
+}
+
+impl SlicePool {
+    /// Create a pool that splits work into `threads` slices, the calling thread works on one of
+    /// them itself.
+    pub fn new(threads: usize) -> Self {
+        let (done_sender, done) = mpsc::channel();
+        let mut jobs = Vec::new();
+        for i in 1..threads.max(1) {
+            let (job_sender, job_receiver) = mpsc::channel::<Job>();
+            let done_sender = done_sender.clone();
+            let spawned = thread::Builder::new()
+                .name(format!("slice-{}", i))
+                .spawn(move || {
+                    // ends once the pool is dropped
+                    for job in job_receiver {
+                        let result = catch_unwind(AssertUnwindSafe(|| (job.f)(job.slice)));
+                        if done_sender.send(result).is_err() {
+                            return;
+                        }
+                    }
+                });
+            match spawned {
+                Ok(_) => jobs.push(job_sender),
+                Err(err) => {
+                    warn!("Failed to spawn thread, using {} threads: {}", i, err);
+                    break;
+                }
+            }
+        }
+        Self { jobs, done }
+    }
+
+    /// Number of slices work is split into.
+    pub fn slices(&self) -> usize {
+        self.jobs.len() + 1
+    }
+
+    /// Call f for every slice in 0..slices() in parallel and return once all calls have returned.
+    pub fn run(&self, f: &(dyn Fn(usize) + Sync)) {
+        // f is borrowed for the duration of this function only, this is fine as it does not
+        // return before every job has been reported as done, even if one of them panicked
+        let f: &'static (dyn Fn(usize) + Sync) = unsafe { std::mem::transmute(f) };
+        let mut sent = 0;
+        // slices of threads that are gone are processed by the calling thread
+        let mut remaining = vec![0];
+        for (i, jobs) in self.jobs.iter().enumerate() {
+            match jobs.send(Job { f, slice: i + 1 }) {
+                Ok(()) => sent += 1,
+                Err(_) => remaining.push(i + 1),
+            }
+        }
+        let mut panicked =
+            catch_unwind(AssertUnwindSafe(|| remaining.iter().for_each(|i| f(*i)))).is_err();
+        for _ in 0..sent {
+            match self.done.recv() {
+                Ok(Ok(())) => (),
+                _ => panicked = true,
+            }
+        }
+        if panicked {
+            panic!("Processing a slice of the frame panicked.");
+        }
+    }
+}
@@ -1,40 +1,837 @@
-use std::sync::{Arc, Mutex};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex, Weak};
 use std::time::{Duration, Instant};
 use websocket::{Message, OwnedMessage, WebSocketError};
 
-use tracing::{trace, warn};
+use serde::Serialize;
+use tracing::{debug, error, info, info_span, trace, warn};
 
+use crate::audio::AudioStream;
+use crate::config::Config;
+use crate::cpu_limit::CpuLimiter;
 use crate::input::device::InputDevice;
-use crate::protocol::NetMessage;
-use crate::screen_capture::ScreenCapture;
+use crate::input::gestures::{Gesture, GestureRecognizer};
+use crate::input::long_press::{LongPressDetector, LongPressOptions};
+use crate::input::mapping::InputMapping;
+use crate::input::pressure::PressureCalibration;
+use crate::input::rate_limit::RateLimiter;
+use crate::input::recording::InputRecorder;
+use crate::input::shortcuts::{shortcut_table, KeyCombo};
+use crate::input::smoothing::{PointerSmoother, Smoothing};
+use crate::input::touch_filter::{TouchFilter, TouchFilterOptions};
+use crate::metrics::{Latency, Metrics};
+use crate::power::PowerMonitor;
+use crate::protocol::{Button, ClockProbe, FrameTiming, NetMessage, PointerEvent};
+use crate::screen_capture::rotation::{RotatedScreenCapture, Rotation, RotationState};
+use crate::screen_capture::{CaptureSource, ScreenCapture};
+use crate::screensaver::ScreenSaverInhibitor;
+use crate::send_queue::{Batch, SendQueue};
 
-use crate::video::VideoEncoder;
+use crate::video::{create_encoder, padded_size, Codec, Container, EncoderOptions, VideoEncoder};
+use crate::websocket::Ws2GuiMessage;
 
-type WsWriter = Arc<Mutex<websocket::sender::Writer<std::net::TcpStream>>>;
+pub type WsWriter = Arc<Mutex<websocket::sender::Writer<std::net::TcpStream>>>;
+
+// the pointer is considered to be the focus of the user only if it moved recently
+const POINTER_FOCUS_TIMEOUT: Duration = Duration::from_secs(3);
+
+// frame interval used if the screen did not change for a while
+const IDLE_FRAME_INTERVAL: Duration = Duration::from_secs(1);
+// the maximal time the client is told to wait before requesting the next frame, keeping this
+// short allows switching back to a high frame rate quickly as soon as the pointer moves
+const MAX_FRAME_WAIT: Duration = Duration::from_millis(50);
+// capture at the highest rate as long as this fraction of the frame changes
+const FRAME_CHANGE_THRESHOLD: f64 = 0.001;
+// pointer activity means the user likely is drawing and expects immediate feedback
+const POINTER_ACTIVITY_TIMEOUT: Duration = Duration::from_millis(500);
+// with scale_on_resize the stream is restarted once the size did not change for this long, until
+// then frames are scaled to the size of the running stream
+const RESIZE_SETTLE_TIME: Duration = Duration::from_secs(2);
+// how long clients are told to wait before requesting another frame while the video is paused
+const PAUSED_FRAME_WAIT: Duration = Duration::from_millis(200);
+// how often the shared stream checks for new clients and pointer activity between frames
+const SHARED_STREAM_POLL_INTERVAL: Duration = Duration::from_millis(5);
+// a stroke is considered finished if no pointer events arrived for this long, e.g. because the
+// event lifting the pen got lost or the pen rests on the tablet
+const STROKE_TIMEOUT: Duration = Duration::from_secs(1);
+// how often the frame counts of a client are logged
+const FRAME_COUNTS_LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+fn create_input_recorder(config: &Config) -> Option<InputRecorder> {
+    let path = config.record_input.as_ref()?;
+    match InputRecorder::new(path) {
+        Ok(recorder) => {
+            info!("Recording input to {}.", path.display());
+            Some(recorder)
+        }
+        Err(err) => {
+            error!("Failed to record input to {}: {}", path.display(), err);
+            None
+        }
+    }
+}
+
+/// What a client sees of the captured screen, shared between its pointer and its video stream:
+/// the rotation, the viewport it zoomed into and the padding of its video.
+pub struct ClientView {
+    pub rotation: Arc<Mutex<RotationState>>,
+    // fraction of the width and height of the video covered by the captured image, the rest is
+    // padding added to get even dimensions
+    content_fraction: Mutex<(f64, f64)>,
+    // only a client with a video of its own may zoom, otherwise it would zoom for others as well
+    zoomable: bool,
+}
+
+impl ClientView {
+    fn new(rotation: Rotation, zoomable: bool) -> Arc<Self> {
+        Arc::new(Self {
+            rotation: RotationState::new(rotation),
+            content_fraction: Mutex::new((1.0, 1.0)),
+            zoomable,
+        })
+    }
+
+    fn set_video_size(&self, width: usize, height: usize) {
+        let (padded_width, padded_height) = padded_size(width, height);
+        *self.content_fraction.lock().unwrap() = (
+            width as f64 / padded_width as f64,
+            height as f64 / padded_height as f64,
+        );
+    }
+}
+
+/// State shared between the pointer and the video stream of a server.
+pub struct StreamState {
+    rotation: Rotation,
+    // the view of all clients of the shared stream and of clients without a session token, with
+    // Rotation::Auto it follows the orientation reported last by a client with control
+    shared_view: Arc<ClientView>,
+    // views of clients with a video of their own by their session token, the pointer and the
+    // video connection of a client use the same token. None if all clients share the stream.
+    views: Option<Mutex<HashMap<String, Weak<ClientView>>>>,
+    // last position of the pointer in coordinates of the video (before undoing any rotation)
+    pointer: Mutex<Option<(f64, f64, Instant)>>,
+    pub metrics: Arc<Metrics>,
+    // reference for all timestamps exchanged with the client
+    start_time: Instant,
+    input_recorder: Option<Mutex<InputRecorder>>,
+    shortcuts: HashMap<String, Vec<KeyCombo>>,
+    scale_on_resize: bool,
+    // set while a client paused the video, true if the video should be blanked. Pausing stops
+    // capturing, so it affects all clients; only clients allowed to control the computer may
+    // pause or resume, see PointerStreamHandler::process.
+    paused: Mutex<Option<bool>>,
+    inhibit_screensaver: bool,
+    wake_display: bool,
+    // None unless --audio is given
+    pub audio: Option<Arc<AudioStream>>,
+    draft_while_drawing: bool,
+    // time of the last event of a stroke in progress, that is a pen, finger or button is down
+    stroke: Mutex<Option<Instant>>,
+    cpu_limiter: Option<CpuLimiter>,
+    // frames are captured at most this often while running on battery
+    battery_frame_interval: Option<(Duration, PowerMonitor)>,
+}
+
+impl StreamState {
+    pub fn new(config: &Config, metrics: Arc<Metrics>) -> Arc<Self> {
+        Arc::new(Self {
+            rotation: config.rotation,
+            shared_view: ClientView::new(config.rotation, false),
+            views: match config.stream_mode {
+                StreamMode::PerClient => Some(Mutex::new(HashMap::new())),
+                StreamMode::Shared => None,
+            },
+            pointer: Mutex::new(None),
+            metrics,
+            start_time: Instant::now(),
+            input_recorder: create_input_recorder(config).map(Mutex::new),
+            shortcuts: shortcut_table(&config.shortcuts),
+            scale_on_resize: config.scale_on_resize,
+            paused: Mutex::new(None),
+            inhibit_screensaver: config.inhibit_screensaver,
+            wake_display: config.wake_display,
+            audio: AudioStream::new(config.audio),
+            draft_while_drawing: config.draft_while_drawing,
+            stroke: Mutex::new(None),
+            cpu_limiter: config.cpu_limit.and_then(CpuLimiter::new),
+            battery_frame_interval: config
+                .battery_frame_interval
+                .and_then(|interval| Some((interval, PowerMonitor::new()?))),
+        })
+    }
+
+    // clients of the video socket are sent audio along with the video, returns the id to
+    // unsubscribe with if audio is streamed
+    fn subscribe_audio(&self, sender: &WsWriter) -> Option<u64> {
+        self.audio
+            .as_ref()
+            .map(|audio| audio.subscribe(sender.clone()))
+    }
+
+    fn resubscribe_audio(&self, id: Option<u64>, sender: &WsWriter) {
+        if let (Some(audio), Some(id)) = (&self.audio, id) {
+            audio.resubscribe(id, sender);
+        }
+    }
+
+    fn unsubscribe_audio(&self, id: Option<u64>) {
+        if let (Some(audio), Some(id)) = (&self.audio, id) {
+            audio.unsubscribe(id);
+        }
+    }
+
+    fn record_input(&self, message: NetMessage) {
+        if let Some(recorder) = &self.input_recorder {
+            recorder.lock().unwrap().record(message);
+        }
+    }
+
+    /// Milliseconds since the server has been started.
+    fn timestamp(&self) -> f64 {
+        self.start_time.elapsed().as_secs_f64() * 1000.0
+    }
+
+    fn set_pointer_position(&self, x: f64, y: f64) {
+        *self.pointer.lock().unwrap() = Some((x, y, Instant::now()));
+    }
+
+    /// The view of the client with this session token.
+    pub fn view(&self, session: Option<&str>) -> Arc<ClientView> {
+        let (views, session) = match (&self.views, session) {
+            (Some(views), Some(session)) => (views, session),
+            _ => return self.shared_view.clone(),
+        };
+        let mut views = views.lock().unwrap();
+        if let Some(view) = views.get(session).and_then(Weak::upgrade) {
+            return view;
+        }
+        // views are dropped with the connections of their client
+        views.retain(|_, view| view.strong_count() > 0);
+        let view = ClientView::new(self.rotation, true);
+        views.insert(session.into(), Arc::downgrade(&view));
+        view
+    }
+
+    fn set_paused(&self, paused: Option<bool>) {
+        *self.paused.lock().unwrap() = paused;
+    }
+
+    fn paused(&self) -> Option<bool> {
+        *self.paused.lock().unwrap()
+    }
+
+    fn pointer_focus(&self) -> Option<(f64, f64)> {
+        match *self.pointer.lock().unwrap() {
+            Some((x, y, time)) if time.elapsed() < POINTER_FOCUS_TIMEOUT => Some((x, y)),
+            _ => None,
+        }
+    }
+
+    fn record_frame_timing(&self, timing: FrameTiming) {
+        // without a synchronized clock the time of arrival of the report is the best estimate
+        let displayed = match timing.clock_offset {
+            Some(offset) => timing.displayed + offset,
+            None => self.timestamp(),
+        };
+        let latency = Latency {
+            end_to_end: displayed - timing.capture_timestamp,
+            client: timing.displayed - timing.received,
+        };
+        debug!(
+            "Latency: {:.1} ms from capture to display, {:.1} ms on the client.",
+            latency.end_to_end, latency.client
+        );
+        self.metrics.record_latency(latency);
+    }
+
+    fn set_stroke_active(&self, active: bool) {
+        *self.stroke.lock().unwrap() = if active { Some(Instant::now()) } else { None };
+    }
+
+    // whether frames should be encoded in draft quality as the user is drawing
+    fn draft(&self) -> bool {
+        self.draft_while_drawing
+            && match *self.stroke.lock().unwrap() {
+                Some(time) => time.elapsed() < STROKE_TIMEOUT,
+                None => false,
+            }
+    }
+
+    // stretch the interval between frames to stay within the CPU limit and to save power while
+    // running on battery
+    fn limit_frame_interval(&self, interval: Duration) -> Duration {
+        let interval = match &self.cpu_limiter {
+            Some(cpu_limiter) => interval.mul_f64(cpu_limiter.slowdown()),
+            None => interval,
+        };
+        match &self.battery_frame_interval {
+            Some((min_interval, power)) if power.on_battery() => interval.max(*min_interval),
+            _ => interval,
+        }
+    }
+
+    fn pointer_moved_within(&self, duration: Duration) -> bool {
+        match *self.pointer.lock().unwrap() {
+            Some((_, _, time)) => time.elapsed() < duration,
+            None => false,
+        }
+    }
+}
+
+/// Decides how long to wait before capturing the next frame: Frames are captured as fast as
+/// allowed while the screen changes or the pointer moves, if the screen is static the interval
+/// is gradually increased up to IDLE_FRAME_INTERVAL.
+struct FramePacer {
+    min_interval: Duration,
+    max_interval: Duration,
+    interval: Duration,
+}
+
+impl FramePacer {
+    fn new(min_interval: Duration) -> Self {
+        Self {
+            min_interval,
+            max_interval: min_interval.max(IDLE_FRAME_INTERVAL),
+            interval: min_interval,
+        }
+    }
+
+    fn interval(&self, pointer_active: bool) -> Duration {
+        if pointer_active {
+            self.min_interval
+        } else {
+            self.interval
+        }
+    }
+
+    /// Update the interval given the fraction of the last frame that changed.
+    fn update(&mut self, change: f64) {
+        if change > FRAME_CHANGE_THRESHOLD {
+            self.interval = self.min_interval;
+        } else {
+            self.interval = (self.interval + self.interval / 2 + Duration::from_millis(1))
+                .min(self.max_interval);
+        }
+    }
+}
+
+/// Statistics about the video sent to a client, they are sent to the client as well so it can show
+/// them in an overlay.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct VideoStats {
+    pub width: usize,
+    pub height: usize,
+    pub fps: f64,
+    pub bytes_sent: u64,
+    // average time it took to encode a frame in milliseconds
+    pub encode_time: f64,
+    // bits per second
+    pub bitrate: f64,
+    // frames that could not be sent to the client since it connected
+    pub dropped_frames: u64,
+}
+
+// what happened to the frames of a client, logged to make sense of reports about lag
+#[derive(Clone, Copy, Default)]
+struct FrameCounts {
+    captured: u64,
+    encoded: u64,
+    sent: u64,
+    dropped: u64,
+    // requests for frames the client was told to repeat later because frames are paced or the
+    // video is paused
+    deferred: u64,
+}
+
+impl FrameCounts {
+    fn since(&self, earlier: &FrameCounts) -> FrameCounts {
+        FrameCounts {
+            captured: self.captured - earlier.captured,
+            encoded: self.encoded - earlier.encoded,
+            sent: self.sent - earlier.sent,
+            dropped: self.dropped - earlier.dropped,
+            deferred: self.deferred - earlier.deferred,
+        }
+    }
+}
+
+impl fmt::Display for FrameCounts {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "captured {}, encoded {}, sent {}, dropped {}, deferred {}",
+            self.captured, self.encoded, self.sent, self.dropped, self.deferred
+        )
+    }
+}
+
+/// Reports statistics of the video stream of a client to the GUI once per second and logs how
+/// many frames have been captured, encoded, sent and dropped every few seconds and once the
+/// client disconnects.
+pub struct StatsReporter {
+    addr: SocketAddr,
+    sender: mpsc::Sender<Ws2GuiMessage>,
+    bytes_sent: Arc<AtomicU64>,
+    // sent and dropped frames are counted while sending, possibly from the thread of another
+    // client
+    sent_frames: Arc<AtomicU64>,
+    dropped_frames: Arc<AtomicU64>,
+    frames: u32,
+    encode_time: Duration,
+    // bytes sent at the time of the last report
+    bytes_reported: u64,
+    last_report: Instant,
+    counts: FrameCounts,
+    counts_logged: FrameCounts,
+    last_counts_log: Instant,
+    connected: Instant,
+}
+
+impl StatsReporter {
+    pub fn new(addr: SocketAddr, sender: mpsc::Sender<Ws2GuiMessage>) -> Self {
+        Self {
+            addr,
+            sender,
+            bytes_sent: Arc::new(AtomicU64::new(0)),
+            sent_frames: Arc::new(AtomicU64::new(0)),
+            dropped_frames: Arc::new(AtomicU64::new(0)),
+            frames: 0,
+            encode_time: Duration::from_secs(0),
+            bytes_reported: 0,
+            last_report: Instant::now(),
+            counts: FrameCounts::default(),
+            counts_logged: FrameCounts::default(),
+            last_counts_log: Instant::now(),
+            connected: Instant::now(),
+        }
+    }
+
+    fn frame_counts(&self) -> FrameCounts {
+        FrameCounts {
+            sent: self.sent_frames.load(Ordering::Relaxed),
+            dropped: self.dropped_frames.load(Ordering::Relaxed),
+            ..self.counts
+        }
+    }
+
+    fn log_frame_counts(&mut self) {
+        let elapsed = self.last_counts_log.elapsed();
+        if elapsed < FRAME_COUNTS_LOG_INTERVAL {
+            return;
+        }
+        let counts = self.frame_counts();
+        info!(
+            "Video of {} in the last {:.0} s: {}",
+            self.addr,
+            elapsed.as_secs_f64(),
+            counts.since(&self.counts_logged)
+        );
+        self.counts_logged = counts;
+        self.last_counts_log = Instant::now();
+    }
+
+    fn frame_captured(&mut self) {
+        self.counts.captured += 1;
+    }
+
+    fn frame_deferred(&mut self) {
+        self.counts.deferred += 1;
+        self.log_frame_counts();
+    }
+
+    /// Returns the statistics once per second, they are meant to be forwarded to the client.
+    fn frame_sent(
+        &mut self,
+        width: usize,
+        height: usize,
+        encode_time: Duration,
+    ) -> Option<VideoStats> {
+        self.frames += 1;
+        self.encode_time += encode_time;
+        self.counts.encoded += 1;
+        self.log_frame_counts();
+        let elapsed = self.last_report.elapsed();
+        if elapsed < Duration::from_secs(1) {
+            return None;
+        }
+        let bytes_sent = self.bytes_sent.load(Ordering::Relaxed);
+        let stats = VideoStats {
+            width,
+            height,
+            fps: self.frames as f64 / elapsed.as_secs_f64(),
+            bytes_sent,
+            encode_time: self.encode_time.as_secs_f64() * 1000.0 / self.frames as f64,
+            bitrate: (bytes_sent - self.bytes_reported) as f64 * 8.0 / elapsed.as_secs_f64(),
+            dropped_frames: self.dropped_frames.load(Ordering::Relaxed),
+        };
+        self.sender
+            .send(Ws2GuiMessage::VideoStats(self.addr, stats))
+            .ok();
+        self.frames = 0;
+        self.encode_time = Duration::from_secs(0);
+        self.bytes_reported = bytes_sent;
+        self.last_report = Instant::now();
+        Some(stats)
+    }
+}
+
+impl Drop for StatsReporter {
+    fn drop(&mut self) {
+        // the client never requested any frames
+        if self.counts.captured == 0 && self.counts.deferred == 0 {
+            return;
+        }
+        info!(
+            "Video of {} during {:.0} s: {}",
+            self.addr,
+            self.connected.elapsed().as_secs_f64(),
+            self.frame_counts()
+        );
+    }
+}
+
+// the client shows these in its stats overlay
+fn stats_message(stats: &VideoStats) -> OwnedMessage {
+    OwnedMessage::Text(format!("stats {}", serde_json::to_string(stats).unwrap()))
+}
+
+fn send_stats(sender: &WsWriter, stats: &VideoStats) {
+    if let Err(err) = sender.lock().unwrap().send_message(&stats_message(stats)) {
+        log_video_error(err);
+    }
+}
 
 pub trait StreamHandler {
     fn process(&mut self, sender: WsWriter, message: &OwnedMessage);
+
+    /// Called when the client resumes the session of this handler on a new connection after the
+    /// old one broke.
+    fn resume(&mut self, _sender: &WsWriter) {}
+
+    /// Input is ignored while blocked, e.g. during the grace period of a new client.
+    fn block_input(&mut self, _blocked: bool) {}
+}
+
+#[derive(Clone, Copy)]
+pub struct PointerOptions {
+    pub smoothing: Smoothing,
+    pub long_press: Option<LongPressOptions>,
+    pub touch_filter: TouchFilterOptions,
+    pub max_input_rate: u32,
+    pub mapping: InputMapping,
 }
 
 pub struct PointerStreamHandler<T: InputDevice> {
     device: T,
+    stream_state: Arc<StreamState>,
+    view: Arc<ClientView>,
+    smoother: PointerSmoother,
+    touch_filter: Option<TouchFilter>,
+    long_press: Option<LongPressDetector>,
+    // only present if the client enabled gestures
+    gestures: Option<GestureRecognizer>,
+    rate_limiter: RateLimiter,
+    mapping: InputMapping,
+    pressure: PressureCalibration,
+    input_blocked: bool,
+    // offset in milliseconds to map the clock of the client onto StreamState::timestamp(), every
+    // client has a clock of its own
+    clock_offset: Option<f64>,
 }
 
 impl<T: InputDevice> PointerStreamHandler<T> {
-    pub fn new(device: T) -> Self {
-        PointerStreamHandler { device }
+    pub fn new(
+        device: T,
+        stream_state: Arc<StreamState>,
+        view: Arc<ClientView>,
+        options: PointerOptions,
+    ) -> Self {
+        PointerStreamHandler {
+            device,
+            stream_state,
+            view,
+            smoother: PointerSmoother::new(options.smoothing),
+            touch_filter: if options.touch_filter.is_enabled() {
+                Some(TouchFilter::new(options.touch_filter))
+            } else {
+                None
+            },
+            long_press: options.long_press.map(LongPressDetector::new),
+            gestures: None,
+            rate_limiter: RateLimiter::new(options.max_input_rate),
+            mapping: options.mapping,
+            pressure: PressureCalibration::new(),
+            input_blocked: false,
+            clock_offset: None,
+        }
+    }
+
+    // update the shared state and map events onto the captured image, events have to be ordered
+    // by their timestamps
+    fn preprocess(&mut self, events: &mut [PointerEvent]) {
+        if let Some(event) = events.last() {
+            self.stream_state.set_pointer_position(event.x, event.y);
+            self.stream_state
+                .set_stroke_active(event.buttons != Button::NONE);
+            // timestamps of the client are mapped onto the clock of the server once the client
+            // reported the offset between the clocks
+            if let Some(time) = self
+                .clock_offset
+                .map(|offset| event.timestamp as f64 / 1000.0 + offset)
+            {
+                self.stream_state
+                    .metrics
+                    .record_input_latency(self.stream_state.timestamp() - time);
+            }
+        }
+        let (rotation, crop) = {
+            let state = self.view.rotation.lock().unwrap();
+            (state.current(), state.current_crop())
+        };
+        let (content_width, content_height) = *self.view.content_fraction.lock().unwrap();
+        for event in events.iter_mut() {
+            // the padding of the video is not part of the captured image
+            event.x = (event.x / content_width).min(1.0);
+            event.y = (event.y / content_height).min(1.0);
+            self.mapping.apply(event);
+            self.pressure.process(event);
+            rotation.transform_pointer_event(event);
+            // the video only shows the part of the captured image the client zoomed into
+            if let Some(crop) = crop {
+                event.x = crop.x + event.x * crop.width;
+                event.y = crop.y + event.y * crop.height;
+            }
+            self.smoother.smooth(event);
+        }
+    }
+
+    // send events to the device after filtering noise
+    fn dispatch(&mut self, events: &[PointerEvent]) {
+        match &mut self.touch_filter {
+            Some(filter) => {
+                let mut filtered = Vec::new();
+                for event in events {
+                    filter.process(event, &mut filtered);
+                }
+                self.dispatch_long_press(&filtered);
+            }
+            None => self.dispatch_long_press(events),
+        }
+    }
+
+    // send events to the device, long presses are turned into right clicks
+    fn dispatch_long_press(&mut self, events: &[PointerEvent]) {
+        let mut detector = match self.long_press.take() {
+            Some(detector) => detector,
+            None => {
+                self.dispatch_gestures(events);
+                return;
+            }
+        };
+        let mut forwarded = Vec::new();
+        for event in events {
+            if let Some((x, y)) = detector.process(event, &mut forwarded) {
+                self.dispatch_gestures(&forwarded);
+                forwarded.clear();
+                self.device.send_right_click(x, y);
+            }
+        }
+        self.dispatch_gestures(&forwarded);
+        self.long_press = Some(detector);
+    }
+
+    // send events to the device unless they are part of a gesture, in which case the gesture is
+    // sent instead
+    fn dispatch_gestures(&mut self, events: &[PointerEvent]) {
+        let recognizer = match &mut self.gestures {
+            Some(recognizer) => recognizer,
+            None => {
+                send_to_device(&mut self.device, events);
+                return;
+            }
+        };
+        let mut pending = Vec::new();
+        let mut gestures = Vec::new();
+        for event in events {
+            if recognizer.process(event, &mut gestures) {
+                pending.push(event.clone());
+            }
+            if gestures.is_empty() {
+                continue;
+            }
+            send_to_device(&mut self.device, &pending);
+            pending.clear();
+            for gesture in gestures.drain(..) {
+                match gesture {
+                    Gesture::Cancel(event) => self.device.send_event(&event),
+                    Gesture::Wheel {
+                        x,
+                        y,
+                        horizontal,
+                        vertical,
+                        zoom,
+                    } => self.device.send_wheel(x, y, horizontal, vertical, zoom),
+                }
+            }
+        }
+        send_to_device(&mut self.device, &pending);
+    }
+}
+
+// the client remembers the calibration to restore it after reconnecting
+fn send_pressure_calibration(sender: &WsWriter, pressure: &mut PressureCalibration) {
+    if let Some(range) = pressure.take_finished() {
+        let msg = NetMessage::PressureRange(Some(range));
+        let msg = Message::text(serde_json::to_string(&msg).unwrap());
+        if let Err(err) = sender.lock().unwrap().send_message(&msg) {
+            warn!("Error sending pressure calibration: {}", err);
+        }
+    }
+}
+
+fn send_to_device(device: &mut impl InputDevice, events: &[PointerEvent]) {
+    match events.len() {
+        0 => (),
+        1 => device.send_event(&events[0]),
+        _ => device.send_events(events),
     }
 }
 
 impl<Device: InputDevice> StreamHandler for PointerStreamHandler<Device> {
-    fn process(&mut self, _: WsWriter, message: &OwnedMessage) {
+    fn process(&mut self, sender: WsWriter, message: &OwnedMessage) {
         match message {
             OwnedMessage::Text(s) => {
                 trace!("Pointerevent: {}", &s);
                 let message: Result<NetMessage, _> = serde_json::from_str(&s);
                 match message {
                     Ok(message) => match message {
-                        NetMessage::PointerEvent(event) => self.device.send_event(&event),
+                        NetMessage::PointerEvent(_)
+                        | NetMessage::PointerEvents(_)
+                        | NetMessage::Shortcut(_)
+                            if self.input_blocked =>
+                        {
+                            trace!("Ignoring input of a client within its grace period.");
+                        }
+                        // view-only clients are blocked for good, they must not stop the video
+                        // for everyone else
+                        NetMessage::Pause(_) | NetMessage::Resume if self.input_blocked => {
+                            debug!("Ignoring pause or resume of a client without control.");
+                        }
+                        NetMessage::Viewport(_) | NetMessage::Orientation(_)
+                            if self.input_blocked =>
+                        {
+                            debug!("Ignoring the view of a client without control.");
+                        }
+                        NetMessage::PointerEvent(mut event) => {
+                            if !self.rate_limiter.accept(&event) {
+                                return;
+                            }
+                            self.preprocess(std::slice::from_mut(&mut event));
+                            self.dispatch(std::slice::from_ref(&event));
+                            send_pressure_calibration(&sender, &mut self.pressure);
+                            self.stream_state
+                                .record_input(NetMessage::PointerEvent(event));
+                        }
+                        NetMessage::PointerEvents(mut events) => {
+                            let rate_limiter = &mut self.rate_limiter;
+                            events.retain(|event| rate_limiter.accept(event));
+                            self.preprocess(&mut events);
+                            self.dispatch(&events);
+                            send_pressure_calibration(&sender, &mut self.pressure);
+                            self.stream_state
+                                .record_input(NetMessage::PointerEvents(events));
+                        }
+                        NetMessage::Orientation(orientation) => {
+                            self.view
+                                .rotation
+                                .lock()
+                                .unwrap()
+                                .set_client_orientation(orientation);
+                            self.stream_state
+                                .record_input(NetMessage::Orientation(orientation));
+                        }
+                        NetMessage::Viewport(_) if !self.view.zoomable => {
+                            debug!("Ignoring the viewport of a client sharing its video.");
+                        }
+                        NetMessage::Viewport(viewport) => {
+                            self.view.rotation.lock().unwrap().set_viewport(viewport);
+                            self.stream_state
+                                .record_input(NetMessage::Viewport(viewport));
+                        }
+                        NetMessage::ClockProbe(probe) => {
+                            let reply = NetMessage::ClockProbe(ClockProbe {
+                                client_time: probe.client_time,
+                                server_time: Some(self.stream_state.timestamp()),
+                            });
+                            let msg = Message::text(serde_json::to_string(&reply).unwrap());
+                            if let Err(err) = sender.lock().unwrap().send_message(&msg) {
+                                warn!("Error sending clock probe: {}", err);
+                            }
+                            self.stream_state
+                                .record_input(NetMessage::ClockProbe(probe));
+                        }
+                        NetMessage::ClockOffset(offset) => {
+                            debug!(
+                                "Client clock offset: {:.1} ms (round trip: {:.1} ms).",
+                                offset.offset, offset.round_trip
+                            );
+                            self.clock_offset = Some(offset.offset);
+                            self.stream_state
+                                .record_input(NetMessage::ClockOffset(offset));
+                        }
+                        NetMessage::Shortcut(name) => {
+                            match self.stream_state.shortcuts.get(&name) {
+                                Some(keys) => self.device.send_keys(keys),
+                                None => warn!("Unknown shortcut: {}", name),
+                            }
+                            self.stream_state.record_input(NetMessage::Shortcut(name));
+                        }
+                        NetMessage::Gestures(enabled) => {
+                            debug!("Gestures {}.", if enabled { "enabled" } else { "disabled" });
+                            self.gestures = if enabled {
+                                Some(GestureRecognizer::new())
+                            } else {
+                                None
+                            };
+                            self.stream_state
+                                .record_input(NetMessage::Gestures(enabled));
+                        }
+                        NetMessage::Pause(blank) => {
+                            info!(
+                                "Video paused by client{}.",
+                                if blank { " and blanked" } else { "" }
+                            );
+                            self.stream_state.set_paused(Some(blank));
+                            self.stream_state.record_input(NetMessage::Pause(blank));
+                        }
+                        NetMessage::Resume => {
+                            info!("Video resumed by client.");
+                            self.stream_state.set_paused(None);
+                            self.stream_state.record_input(NetMessage::Resume);
+                        }
+                        NetMessage::CalibratePressure => {
+                            info!("Calibrating pen pressure with the next stroke.");
+                            self.pressure.start();
+                            self.stream_state
+                                .record_input(NetMessage::CalibratePressure);
+                        }
+                        NetMessage::PressureRange(range) => {
+                            self.pressure.set_range(range);
+                            self.stream_state
+                                .record_input(NetMessage::PressureRange(range));
+                        }
+                        NetMessage::FrameTiming(_)
+                        | NetMessage::Keyframe
+                        | NetMessage::WebCodecs => {
+                            warn!("Unexpected message on pointer socket: {}", &s)
+                        }
                     },
                     Err(err) => warn!("Unable to parse message: {}", err),
                 }
@@ -42,69 +839,251 @@ impl<Device: InputDevice> StreamHandler for PointerStreamHandler<Device> {
             _ => (),
         }
     }
+
+    fn block_input(&mut self, blocked: bool) {
+        self.input_blocked = blocked;
+    }
+}
+
+// tell a client that a new stream starts, with a latency budget the client also learns how far its
+// playback may lag behind before it skips ahead
+// tell the client that a new stream starts
+fn new_stream_messages(options: &EncoderOptions) -> Vec<OwnedMessage> {
+    let mut messages = vec![OwnedMessage::Text(
+        options.codec.new_stream_message(options.container).into(),
+    )];
+    if let Some(budget) = options.latency_budget {
+        messages.push(OwnedMessage::Text(format!("budget {}", budget.as_millis())));
+    }
+    messages
+}
+
+fn send_new_stream(
+    sender: &mut websocket::sender::Writer<std::net::TcpStream>,
+    options: &EncoderOptions,
+) -> Result<(), WebSocketError> {
+    for msg in new_stream_messages(options) {
+        sender.send_message(&msg)?;
+    }
+    Ok(())
+}
+
+fn log_video_error(err: WebSocketError) {
+    match err {
+        WebSocketError::IoError(err) => {
+            // ignore broken pipe errors as those are caused by intentionally shutting down the
+            // websocket
+            if err.kind() == std::io::ErrorKind::BrokenPipe {
+                trace!("Error sending video: {}", err);
+            } else {
+                warn!("Error sending video: {}", err);
+            }
+        }
+        _ => warn!("Error sending video: {}", err),
+    }
 }
 
 pub struct ScreenStreamHandler<T: ScreenCapture> {
     screen_capture: T,
-    video_encoder: Option<Box<VideoEncoder>>,
-    update_interval: Duration,
+    video_encoder: Option<Box<dyn VideoEncoder>>,
+    encoder_options: EncoderOptions,
+    frame_pacer: FramePacer,
     last_update: Instant,
+    stream_state: Arc<StreamState>,
+    view: Arc<ClientView>,
+    stats: StatsReporter,
+    // size the capture has been resized to and when it changed last
+    resize: Option<((usize, usize), Instant)>,
+    // whether the client has been told that the video is paused and if it is blanked
+    paused: Option<bool>,
+    // connection the encoder sends to, it changes if the session is resumed
+    connection: Arc<Mutex<Option<WsWriter>>>,
+    // keeps the display awake once the client started streaming
+    screensaver_inhibitor: Option<ScreenSaverInhibitor>,
+    // the client is subscribed to the audio on its first request for a frame
+    audio: Option<u64>,
 }
 
 impl<T: ScreenCapture> ScreenStreamHandler<T> {
-    pub fn new(screen_capture: T, update_interval: Duration) -> Self {
+    pub fn new(
+        screen_capture: T,
+        update_interval: Duration,
+        encoder_options: EncoderOptions,
+        stream_state: Arc<StreamState>,
+        view: Arc<ClientView>,
+        stats: StatsReporter,
+    ) -> Self {
         Self {
             screen_capture,
             video_encoder: None,
-            update_interval,
+            encoder_options,
+            frame_pacer: FramePacer::new(update_interval),
             last_update: Instant::now(),
+            stream_state,
+            view,
+            stats,
+            resize: None,
+            paused: None,
+            connection: Arc::new(Mutex::new(None)),
+            screensaver_inhibitor: None,
+            audio: None,
         }
     }
+
+    // whether the encoder has to be recreated for a capture of this size
+    fn needs_restart(&mut self, width: usize, height: usize) -> bool {
+        let video_encoder = match &self.video_encoder {
+            Some(video_encoder) => video_encoder,
+            None => return true,
+        };
+        if video_encoder.check_size(width, height) {
+            self.resize = None;
+            return false;
+        }
+        if !self.stream_state.scale_on_resize {
+            return true;
+        }
+        match self.resize {
+            Some((size, since)) if size == (width, height) => since.elapsed() > RESIZE_SETTLE_TIME,
+            _ => {
+                debug!("Capture resized to {}x{}, scaling frames.", width, height);
+                self.resize = Some(((width, height), Instant::now()));
+                false
+            }
+        }
+    }
+
+    // tell the client whether the video is paused if this changed, returns true while paused
+    fn update_paused(&mut self, sender: &WsWriter) -> bool {
+        let paused = self.stream_state.paused();
+        if paused != self.paused {
+            let msg = Message::text(match paused {
+                Some(true) => "paused blank",
+                Some(false) => "paused",
+                None => "resumed",
+            });
+            if let Err(err) = sender.lock().unwrap().send_message(&msg) {
+                warn!("Error sending video: {}", err);
+            }
+            self.paused = paused;
+        }
+        if paused.is_none() {
+            return false;
+        }
+        let msg = Message::text(format!("@{}", PAUSED_FRAME_WAIT.as_millis()));
+        if let Err(err) = sender.lock().unwrap().send_message(&msg) {
+            warn!("Error sending video: {}", err);
+        }
+        true
+    }
 }
 
 impl<T: ScreenCapture> StreamHandler for ScreenStreamHandler<T> {
     fn process(&mut self, sender: WsWriter, message: &OwnedMessage) {
         match message {
+            OwnedMessage::Text(s) if !s.is_empty() => {
+                let message: Result<NetMessage, _> = serde_json::from_str(&s);
+                match message {
+                    Ok(NetMessage::FrameTiming(timing)) => {
+                        self.stream_state.record_frame_timing(timing)
+                    }
+                    Ok(NetMessage::Keyframe) => {
+                        debug!("Keyframe requested by the client.");
+                        if let Some(video_encoder) = &mut self.video_encoder {
+                            video_encoder.request_keyframe();
+                        }
+                    }
+                    Ok(NetMessage::WebCodecs) => {
+                        // JPEGs are decoded by the browser anyway, clients resuming their session
+                        // send this again
+                        if self.encoder_options.codec != Codec::Mjpeg
+                            && self.encoder_options.container != Container::AnnexB
+                        {
+                            debug!("The client decodes via WebCodecs, sending NAL units.");
+                            self.encoder_options.container = Container::AnnexB;
+                            // the next frame starts a new stream
+                            self.video_encoder = None;
+                        }
+                    }
+                    Ok(_) => warn!("Unexpected message on video socket: {}", &s),
+                    Err(err) => warn!("Unable to parse message: {}", err),
+                }
+            }
+            // an empty message is a request for the next frame
             OwnedMessage::Text(_) => {
+                if self.audio.is_none() {
+                    self.audio = self.stream_state.subscribe_audio(&sender);
+                }
+                if self.stream_state.inhibit_screensaver && self.screensaver_inhibitor.is_none() {
+                    self.screensaver_inhibitor =
+                        Some(ScreenSaverInhibitor::new(self.stream_state.wake_display));
+                }
+                // nothing is captured while paused
+                if self.update_paused(&sender) {
+                    self.stats.frame_deferred();
+                    return;
+                }
                 let now = Instant::now();
                 let interval = now - self.last_update;
-                if interval < self.update_interval {
-                    let msg = Message::text(format!(
-                        "@{}",
-                        (self.update_interval - interval).as_millis().to_string()
-                    ));
+                let update_interval = self.stream_state.limit_frame_interval(
+                    self.frame_pacer.interval(
+                        self.stream_state
+                            .pointer_moved_within(POINTER_ACTIVITY_TIMEOUT),
+                    ),
+                );
+                if interval < update_interval {
+                    let wait = (update_interval - interval).min(MAX_FRAME_WAIT);
+                    let msg = Message::text(format!("@{}", wait.as_millis().to_string()));
                     if let Err(err) = sender.lock().unwrap().send_message(&msg) {
                         warn!("Error sending video: {}", err);
                     }
+                    self.stats.frame_deferred();
                     return;
                 }
-                self.screen_capture.capture();
+                let capture_timestamp = self.stream_state.timestamp();
+                {
+                    let span = info_span!("capture");
+                    let _enter = span.enter();
+                    self.screen_capture.capture();
+                }
+                self.stats.frame_captured();
+                let switched_target = self.screen_capture.switched_target();
                 let (width, height) = self.screen_capture.size();
+                // frames are scaled down to this size if the capture exceeds the maximum
+                // resolution, pointer events are relative to the video so they still cover the
+                // whole capture
+                let (video_width, video_height) = self.encoder_options.video_size(width, height);
                 // video encoder is not setup or setup for encoding the wrong size: restart it
-                if self.video_encoder.is_none()
-                    || !self
-                        .video_encoder
-                        .as_ref()
-                        .unwrap()
-                        .check_size(width, height)
-                {
-                    if let Err(err) = sender.lock().unwrap().send_message(&Message::text("new")) {
+                if self.needs_restart(video_width, video_height) {
+                    self.resize = None;
+                    if let Err(err) =
+                        send_new_stream(&mut sender.lock().unwrap(), &self.encoder_options)
+                    {
                         warn!("Error sending video: {}", err);
                     }
-                    let res = VideoEncoder::new(width, height, move |data| {
+                    *self.connection.lock().unwrap() = Some(sender.clone());
+                    let connection = self.connection.clone();
+                    let bytes_sent = self.stats.bytes_sent.clone();
+                    let sent_frames = self.stats.sent_frames.clone();
+                    let dropped_frames = self.stats.dropped_frames.clone();
+                    let options = self.encoder_options;
+                    let res = create_encoder(video_width, video_height, options, move |data| {
+                        let span = info_span!("send");
+                        let _enter = span.enter();
+                        let sender = match &*connection.lock().unwrap() {
+                            Some(sender) => sender.clone(),
+                            None => return,
+                        };
+                        bytes_sent.fetch_add(data.len() as u64, Ordering::Relaxed);
                         let msg = Message::binary(data);
-                        if let Err(err) = sender.lock().unwrap().send_message(&msg) {
-                            match err {
-                                WebSocketError::IoError(err) => {
-                                    // ignore broken pipe errors as those are caused by
-                                    // intentionally shutting down the websocket
-                                    if err.kind() == std::io::ErrorKind::BrokenPipe {
-                                        trace!("Error sending video: {}", err);
-                                    } else {
-                                        warn!("Error sending video: {}", err);
-                                    }
-                                }
-                                _ => warn!("Error sending video: {}", err),
+                        let res = sender.lock().unwrap().send_message(&msg);
+                        match res {
+                            Ok(()) => {
+                                sent_frames.fetch_add(1, Ordering::Relaxed);
+                            }
+                            Err(err) => {
+                                dropped_frames.fetch_add(1, Ordering::Relaxed);
+                                log_video_error(err);
                             }
                         }
                     });
@@ -113,12 +1092,457 @@ impl<T: ScreenCapture> StreamHandler for ScreenStreamHandler<T> {
                         return;
                     }
                     self.video_encoder = Some(res.unwrap());
+                    self.view.set_video_size(video_width, video_height);
+                }
+                // the client echoes this timestamp to measure latency
+                let msg = Message::text(format!("#{}", capture_timestamp));
+                if let Err(err) = sender.lock().unwrap().send_message(&msg) {
+                    warn!("Error sending video: {}", err);
                 }
                 let video_encoder = self.video_encoder.as_mut().unwrap();
-                video_encoder.encode(self.screen_capture.pixel_provider());
+                // the previous frames show something else entirely now
+                if switched_target {
+                    video_encoder.request_keyframe();
+                }
+                video_encoder.set_draft(self.stream_state.draft());
+                if self.encoder_options.pointer_roi {
+                    video_encoder.set_focus(self.stream_state.pointer_focus());
+                }
+                let encode_start = Instant::now();
+                let change =
+                    video_encoder.encode(self.screen_capture.pixel_provider(), width, height);
+                let encode_time = encode_start.elapsed();
+                self.frame_pacer.update(change);
+                self.stream_state.metrics.frame_encoded();
+                if let Some(stats) = self
+                    .stats
+                    .frame_sent(video_width, video_height, encode_time)
+                {
+                    send_stats(&sender, &stats);
+                }
                 self.last_update = Instant::now();
             }
             _ => (),
         }
     }
+
+    fn resume(&mut self, sender: &WsWriter) {
+        // frames sent while the connection was broken are lost, the client can only continue
+        // decoding from the next keyframe
+        *self.connection.lock().unwrap() = Some(sender.clone());
+        if let Some(video_encoder) = &mut self.video_encoder {
+            video_encoder.request_keyframe();
+        }
+        self.stream_state.resubscribe_audio(self.audio, sender);
+    }
+}
+
+impl<T: ScreenCapture> Drop for ScreenStreamHandler<T> {
+    fn drop(&mut self) {
+        self.stream_state.unsubscribe_audio(self.audio);
+    }
+}
+
+/// Whether every client gets a video encoded for it or all clients share a single video.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StreamMode {
+    PerClient,
+    Shared,
+}
+
+impl FromStr for StreamMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "per-client" => Ok(Self::PerClient),
+            "shared" => Ok(Self::Shared),
+            _ => Err(format!(
+                "Unknown stream mode '{}', use per-client or shared.",
+                s
+            )),
+        }
+    }
+}
+
+// a client watching the shared stream
+struct SharedClient {
+    id: u64,
+    queue: SendQueue,
+    stats: StatsReporter,
+    // whether the client has been told about the current stream and got its header, otherwise it
+    // can not decode the frames
+    joined: bool,
+    // set while a client that resumed its session has not asked for frames on its new connection
+    waiting: bool,
+}
+
+/// A video captured and encoded once and sent to all clients, this is much cheaper than encoding
+/// a video per client if many clients watch the same screen. Instead of being requested by every
+/// client frames are pushed to all clients by a thread that runs as long as there are clients.
+pub struct SharedStream {
+    capture_source: CaptureSource,
+    update_interval: Duration,
+    encoder_options: EncoderOptions,
+    stream_state: Arc<StreamState>,
+    clients: Mutex<Vec<SharedClient>>,
+    next_id: AtomicU64,
+    // whether the thread capturing and encoding is running
+    running: AtomicBool,
+    keyframe_requested: AtomicBool,
+}
+
+impl SharedStream {
+    pub fn new(
+        capture_source: CaptureSource,
+        update_interval: Duration,
+        encoder_options: EncoderOptions,
+        stream_state: Arc<StreamState>,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            capture_source,
+            update_interval,
+            encoder_options,
+            stream_state,
+            clients: Mutex::new(Vec::new()),
+            next_id: AtomicU64::new(0),
+            running: AtomicBool::new(false),
+            keyframe_requested: AtomicBool::new(false),
+        })
+    }
+
+    fn subscribe(self: &Arc<Self>, sender: WsWriter, stats: StatsReporter) -> u64 {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut clients = self.clients.lock().unwrap();
+        clients.push(SharedClient {
+            id,
+            queue: SendQueue::new(sender, log_video_error),
+            stats,
+            joined: false,
+            waiting: false,
+        });
+        // the thread checks for clients while holding the lock before it stops
+        if !self.running.swap(true, Ordering::SeqCst) {
+            let stream = self.clone();
+            std::thread::spawn(move || stream.run());
+        }
+        id
+    }
+
+    fn unsubscribe(&self, id: u64) {
+        self.clients
+            .lock()
+            .unwrap()
+            .retain(|client| client.id != id);
+    }
+
+    fn leave(&self, id: u64) {
+        if let Some(client) = self
+            .clients
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .find(|client| client.id == id)
+        {
+            client.joined = false;
+            client.waiting = true;
+        }
+    }
+
+    fn rejoin(&self, id: u64, sender: &WsWriter) {
+        if let Some(client) = self
+            .clients
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .find(|client| client.id == id)
+        {
+            client.queue = SendQueue::new(sender.clone(), log_video_error);
+            client.joined = false;
+            client.waiting = false;
+        }
+    }
+
+    // messages are only queued while holding the lock on the clients, they are sent by a thread
+    // per client
+    fn broadcast(&self, msg: OwnedMessage) {
+        let msg = Arc::new(msg);
+        for client in self.clients.lock().unwrap().iter_mut().filter(|c| c.joined) {
+            client.queue.send(vec![msg.clone()]);
+        }
+    }
+
+    // tell clients that did not join yet about the stream and send them its header, returns
+    // whether there have been any, clients that do not keep up join once their queue has room
+    fn join_clients(&self, header: Option<&[u8]>) -> bool {
+        let mut batch: Batch = new_stream_messages(&self.encoder_options)
+            .into_iter()
+            .map(Arc::new)
+            .collect();
+        if let Some(header) = header {
+            batch.push(Arc::new(OwnedMessage::Binary(header.to_vec())));
+        }
+        let mut joined = false;
+        for client in self
+            .clients
+            .lock()
+            .unwrap()
+            .iter_mut()
+            .filter(|c| !c.joined && !c.waiting)
+        {
+            if client.queue.send(batch.clone()) {
+                client.joined = true;
+                joined = true;
+            }
+        }
+        joined
+    }
+
+    fn run(self: Arc<Self>) {
+        if let Err(err) = self.stream() {
+            error!("Shared video stream failed: {}", err);
+            self.running.store(false, Ordering::SeqCst);
+        }
+    }
+
+    fn stream(self: &Arc<Self>) -> Result<(), Box<dyn std::error::Error>> {
+        info!("Starting the shared video stream.");
+        let mut capture = RotatedScreenCapture::new(
+            self.capture_source.create()?,
+            self.stream_state.shared_view.rotation.clone(),
+        );
+        let mut video_encoder: Option<Box<dyn VideoEncoder>> = None;
+        // the first chunk written by an encoder is the header of the stream, clients joining later
+        // need it to start decoding, MJPEG has none
+        let header: Rc<RefCell<Option<Vec<u8>>>> = Rc::new(RefCell::new(None));
+        let mut frame_pacer = FramePacer::new(self.update_interval);
+        let mut paused = None;
+        let _screensaver_inhibitor = if self.stream_state.inhibit_screensaver {
+            Some(ScreenSaverInhibitor::new(self.stream_state.wake_display))
+        } else {
+            None
+        };
+        loop {
+            let start = Instant::now();
+            {
+                let clients = self.clients.lock().unwrap();
+                if clients.is_empty() {
+                    self.running.store(false, Ordering::SeqCst);
+                    info!("Stopping the shared video stream, there are no clients left.");
+                    return Ok(());
+                }
+            }
+            let state = self.stream_state.paused();
+            if state != paused {
+                self.broadcast(OwnedMessage::Text(
+                    match state {
+                        Some(true) => "paused blank",
+                        Some(false) => "paused",
+                        None => "resumed",
+                    }
+                    .into(),
+                ));
+                paused = state;
+            }
+            if paused.is_some() {
+                std::thread::sleep(PAUSED_FRAME_WAIT);
+                continue;
+            }
+            let capture_timestamp = self.stream_state.timestamp();
+            {
+                let span = info_span!("capture");
+                let _enter = span.enter();
+                capture.capture();
+            }
+            for client in self.clients.lock().unwrap().iter_mut() {
+                client.stats.frame_captured();
+            }
+            let switched_target = capture.switched_target();
+            let (width, height) = capture.size();
+            let (video_width, video_height) = self.encoder_options.video_size(width, height);
+            let new_stream = video_encoder.as_ref().map_or(true, |encoder| {
+                !encoder.check_size(video_width, video_height)
+            });
+            if new_stream {
+                video_encoder = None;
+                *header.borrow_mut() = None;
+                for client in self.clients.lock().unwrap().iter_mut() {
+                    client.joined = false;
+                }
+            }
+            let joined = self.join_clients(header.borrow().as_deref());
+            if new_stream {
+                let stream = self.clone();
+                let header = header.clone();
+                let with_header = self.encoder_options.codec != Codec::Mjpeg;
+                video_encoder = Some(create_encoder(
+                    video_width,
+                    video_height,
+                    self.encoder_options,
+                    move |data| {
+                        let span = info_span!("send");
+                        let _enter = span.enter();
+                        if with_header && header.borrow().is_none() {
+                            *header.borrow_mut() = Some(data.to_vec());
+                        }
+                        let msg = Arc::new(OwnedMessage::Binary(data.to_vec()));
+                        let mut clients = stream.clients.lock().unwrap();
+                        for client in clients.iter_mut().filter(|c| c.joined) {
+                            if client.queue.send(vec![msg.clone()]) {
+                                client
+                                    .stats
+                                    .bytes_sent
+                                    .fetch_add(data.len() as u64, Ordering::Relaxed);
+                                client.stats.sent_frames.fetch_add(1, Ordering::Relaxed);
+                            } else {
+                                client.stats.dropped_frames.fetch_add(1, Ordering::Relaxed);
+                                // without the dropped frame the client can not decode the
+                                // following ones, it joins again and gets a keyframe
+                                if with_header {
+                                    client.joined = false;
+                                }
+                            }
+                        }
+                    },
+                )?);
+                self.stream_state
+                    .shared_view
+                    .set_video_size(video_width, video_height);
+            }
+            let video_encoder = video_encoder.as_mut().unwrap();
+            // clients that just joined can only start decoding at a keyframe, after switching the
+            // capture target the previous frames show something else entirely
+            if (joined && !new_stream)
+                || switched_target
+                || self.keyframe_requested.swap(false, Ordering::Relaxed)
+            {
+                video_encoder.request_keyframe();
+            }
+            video_encoder.set_draft(self.stream_state.draft());
+            // the clients echo this timestamp to measure latency
+            self.broadcast(OwnedMessage::Text(format!("#{}", capture_timestamp)));
+            if self.encoder_options.pointer_roi {
+                video_encoder.set_focus(self.stream_state.pointer_focus());
+            }
+            let encode_start = Instant::now();
+            let change = video_encoder.encode(capture.pixel_provider(), width, height);
+            let encode_time = encode_start.elapsed();
+            frame_pacer.update(change);
+            self.stream_state.metrics.frame_encoded();
+            for client in self.clients.lock().unwrap().iter_mut() {
+                if let Some(stats) = client
+                    .stats
+                    .frame_sent(video_width, video_height, encode_time)
+                {
+                    client.queue.send(vec![Arc::new(stats_message(&stats))]);
+                }
+            }
+            // wait for the next frame, unless the pointer starts moving or a client joins, clients
+            // that do not keep up can wait
+            loop {
+                let interval = self.stream_state.limit_frame_interval(
+                    frame_pacer.interval(
+                        self.stream_state
+                            .pointer_moved_within(POINTER_ACTIVITY_TIMEOUT),
+                    ),
+                );
+                if start.elapsed() >= interval
+                    || self
+                        .clients
+                        .lock()
+                        .unwrap()
+                        .iter()
+                        .any(|c| !c.joined && !c.waiting && !c.queue.is_full())
+                {
+                    break;
+                }
+                std::thread::sleep((interval - start.elapsed()).min(SHARED_STREAM_POLL_INTERVAL));
+            }
+        }
+    }
+}
+
+/// Serves a client of a SharedStream, the frames are pushed to the client so its requests for
+/// frames are ignored.
+pub struct SharedStreamHandler {
+    stream: Arc<SharedStream>,
+    // the client is subscribed to the stream on its first request for a frame
+    stats: Option<StatsReporter>,
+    id: Option<u64>,
+    audio: Option<u64>,
+    // the client resumed its session, like a new client it rejoins on its first request for a
+    // frame, so nothing is sent to the new connection before it asks for it
+    rejoin: bool,
+}
+
+impl SharedStreamHandler {
+    pub fn new(stream: Arc<SharedStream>, stats: StatsReporter) -> Self {
+        Self {
+            stream,
+            stats: Some(stats),
+            id: None,
+            audio: None,
+            rejoin: false,
+        }
+    }
+}
+
+impl StreamHandler for SharedStreamHandler {
+    fn process(&mut self, sender: WsWriter, message: &OwnedMessage) {
+        match message {
+            OwnedMessage::Text(s) if !s.is_empty() => {
+                let message: Result<NetMessage, _> = serde_json::from_str(&s);
+                match message {
+                    Ok(NetMessage::FrameTiming(timing)) => {
+                        self.stream.stream_state.record_frame_timing(timing)
+                    }
+                    Ok(NetMessage::Keyframe) => {
+                        debug!("Keyframe requested by the client.");
+                        self.stream
+                            .keyframe_requested
+                            .store(true, Ordering::Relaxed);
+                    }
+                    // all clients get the same stream, it stays fragmented mp4 which every client
+                    // can play
+                    Ok(NetMessage::WebCodecs) => {
+                        debug!("Client supports WebCodecs, the shared stream is sent as mp4.")
+                    }
+                    Ok(_) => warn!("Unexpected message on video socket: {}", &s),
+                    Err(err) => warn!("Unable to parse message: {}", err),
+                }
+            }
+            OwnedMessage::Text(_) => {
+                if let Some(stats) = self.stats.take() {
+                    self.audio = self.stream.stream_state.subscribe_audio(&sender);
+                    self.id = Some(self.stream.subscribe(sender, stats));
+                } else if self.rejoin {
+                    self.rejoin = false;
+                    if let Some(id) = self.id {
+                        self.stream.rejoin(id, &sender);
+                    }
+                    self.stream
+                        .stream_state
+                        .resubscribe_audio(self.audio, &sender);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn resume(&mut self, _sender: &WsWriter) {
+        if let Some(id) = self.id {
+            // frames are not sent to the broken connection until the client rejoins
+            self.stream.leave(id);
+            self.rejoin = true;
+        }
+    }
+}
+
+impl Drop for SharedStreamHandler {
+    fn drop(&mut self) {
+        self.stream.stream_state.unsubscribe_audio(self.audio);
+        if let Some(id) = self.id {
+            self.stream.unsubscribe(id);
+        }
+    }
 }
@@ -0,0 +1,100 @@
+use std::io;
+use std::net::TcpStream;
+
+use tracing::warn;
+
+/// Options applied to the TCP connections of websocket clients.
+#[derive(Clone, Copy, Debug)]
+pub struct TcpOptions {
+    // disables Nagle's algorithm, which holds back small video frames and pointer events until
+    // the previous packet has been acknowledged
+    pub nodelay: bool,
+    // in bytes, the operating system tunes the size if None
+    pub send_buffer_size: Option<usize>,
+}
+
+impl Default for TcpOptions {
+    fn default() -> Self {
+        Self {
+            nodelay: true,
+            send_buffer_size: None,
+        }
+    }
+}
+
+impl TcpOptions {
+    /// Problems are logged, the connection is usable anyway.
+    pub fn apply(&self, stream: &TcpStream) {
+        if let Err(err) = stream.set_nodelay(self.nodelay) {
+            warn!("Failed to set TCP_NODELAY: {}", err);
+        }
+        if let Some(size) = self.send_buffer_size {
+            if let Err(err) = set_send_buffer_size(stream, size) {
+                warn!(
+                    "Failed to set the send buffer size to {} bytes: {}",
+                    size, err
+                );
+            }
+        }
+    }
+}
+
+#[cfg(target_os = "linux")]
+const SOL_SOCKET: i32 = 1;
+#[cfg(target_os = "linux")]
+const SO_SNDBUF: i32 = 7;
+#[cfg(not(target_os = "linux"))]
+const SOL_SOCKET: i32 = 0xffff;
+#[cfg(not(target_os = "linux"))]
+const SO_SNDBUF: i32 = 0x1001;
+
+#[cfg(unix)]
+fn set_send_buffer_size(stream: &TcpStream, size: usize) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+    extern "C" {
+        fn setsockopt(
+            socket: i32,
+            level: i32,
+            name: i32,
+            value: *const std::ffi::c_void,
+            len: u32,
+        ) -> i32;
+    }
+    let size = size.min(i32::MAX as usize) as i32;
+    let res = unsafe {
+        setsockopt(
+            stream.as_raw_fd(),
+            SOL_SOCKET,
+            SO_SNDBUF,
+            &size as *const i32 as *const std::ffi::c_void,
+            std::mem::size_of::<i32>() as u32,
+        )
+    };
+    if res != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+#[cfg(target_os = "windows")]
+fn set_send_buffer_size(stream: &TcpStream, size: usize) -> io::Result<()> {
+    use std::os::windows::io::AsRawSocket;
+    #[link(name = "ws2_32")]
+    extern "system" {
+        fn setsockopt(socket: usize, level: i32, name: i32, value: *const u8, len: i32) -> i32;
+    }
+    let size = size.min(i32::MAX as usize) as i32;
+    let res = unsafe {
+        setsockopt(
+            stream.as_raw_socket() as usize,
+            SOL_SOCKET,
+            SO_SNDBUF,
+            &size as *const i32 as *const u8,
+            std::mem::size_of::<i32>() as i32,
+        )
+    };
+    if res != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
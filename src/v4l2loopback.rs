@@ -0,0 +1,128 @@
+//! Writes the captured frames to a v4l2loopback device, this way video calls can use them like the
+//! image of a webcam while they are streamed to the tablet.
+
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::os::raw::c_int;
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tracing::{info, warn};
+
+use crate::cerror::CError;
+use crate::screen_capture::CaptureSource;
+use crate::video::{ColorRange, ColorSpace, EncoderOptions, PixelProvider};
+use crate::yuv::{packed_rgb_to_yuv420p, YUV420PPlanes};
+
+extern "C" {
+    fn set_v4l2loopback_format(fd: c_int, width: c_int, height: c_int, err: *mut CError);
+}
+
+// webcams usually deliver 30 fps, so this is what video calls expect
+const FRAME_INTERVAL: Duration = Duration::from_millis(33);
+
+/// Capture and write frames to device on a new thread until shutdown is set.
+pub fn start(
+    source: CaptureSource,
+    device: PathBuf,
+    options: EncoderOptions,
+    shutdown: Arc<AtomicBool>,
+) {
+    std::thread::spawn(move || {
+        match run(source, &device, options, &shutdown) {
+            Ok(()) => info!("Stopped writing frames to {}.", device.display()),
+            Err(err) => warn!("Failed to write frames to {}: {}", device.display(), err),
+        }
+    });
+}
+
+fn run(
+    source: CaptureSource,
+    path: &Path,
+    options: EncoderOptions,
+    shutdown: &AtomicBool,
+) -> Result<(), Box<dyn Error>> {
+    // the format is announced as BT.601 in limited range, see set_v4l2loopback_format
+    let coefficients = EncoderOptions {
+        color_space: ColorSpace::BT601,
+        color_range: ColorRange::Limited,
+        ..options
+    }
+    .yuv_coefficients();
+
+    let mut capture = source.create()?;
+    let mut device: Option<(File, usize, usize)> = None;
+    let mut frame = Vec::new();
+    while !shutdown.load(Ordering::Relaxed) {
+        let start = Instant::now();
+        capture.capture();
+        let (capture_width, capture_height) = capture.size();
+        // yuv420p requires even dimensions, the last row or column is clipped if necessary
+        let (width, height) = (
+            capture_width - capture_width % 2,
+            capture_height - capture_height % 2,
+        );
+        if width > 0 && height > 0 {
+            if device
+                .as_ref()
+                .map_or(true, |(_, w, h)| (*w, *h) != (width, height))
+            {
+                // close the device first, otherwise the format can not be changed
+                drop(device.take());
+                device = Some((open_device(path, width, height)?, width, height));
+                frame.resize(width * height * 3 / 2, 0);
+            }
+            let (y, uv) = frame.split_at_mut(width * height);
+            let (u, v) = uv.split_at_mut(width * height / 4);
+            let pixel_provider = capture.pixel_provider();
+            let format = pixel_provider.format();
+            match pixel_provider {
+                PixelProvider::BGRA(data) | PixelProvider::RGBx(data) => {
+                    let mut planes = YUV420PPlanes {
+                        y,
+                        u,
+                        v,
+                        y_stride: width,
+                        u_stride: width / 2,
+                        v_stride: width / 2,
+                    };
+                    packed_rgb_to_yuv420p(
+                        data,
+                        format.unwrap(),
+                        capture_width,
+                        capture_height,
+                        &coefficients,
+                        &mut planes,
+                    );
+                }
+                PixelProvider::FillYUV420P(fill_yuv) => {
+                    fill_yuv(y, u, v, width, width / 2, width / 2)
+                }
+                _ => return Err(format!("Unsupported pixel format: {:?}", format).into()),
+            }
+            if let Some((file, _, _)) = &mut device {
+                file.write_all(&frame)?;
+            }
+        }
+        let elapsed = start.elapsed();
+        if elapsed < FRAME_INTERVAL {
+            std::thread::sleep(FRAME_INTERVAL - elapsed);
+        }
+    }
+    Ok(())
+}
+
+fn open_device(path: &Path, width: usize, height: usize) -> Result<File, Box<dyn Error>> {
+    let file = OpenOptions::new().write(true).open(path)?;
+    let mut err = CError::new();
+    unsafe { set_v4l2loopback_format(file.as_raw_fd(), width as c_int, height as c_int, &mut err) };
+    if err.is_err() {
+        return Err(err.into());
+    }
+    info!("Writing {}x{} frames to {}.", width, height, path.display());
+    Ok(file)
+}
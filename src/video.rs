@@ -1,18 +1,46 @@
+use std::fmt;
 use std::os::raw::{c_int, c_uchar, c_void};
-use std::time::Instant;
+use std::rc::Rc;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use tracing::{debug, info, info_span, warn};
 
 use crate::cerror::CError;
+#[cfg(feature = "gpu-convert")]
+use crate::gpu_convert::GpuConverter;
+use crate::mux::{create_muxer, Muxer, Packet};
+use crate::slice_pool::SlicePool;
+use crate::yuv::{packed_rgb_to_yuv420p_sliced, YUV420PPlanes};
 
 extern "C" {
-    fn init_video_encoder(rust_ctx: *mut c_void, width: c_int, height: c_int) -> *mut c_void;
+    fn init_video_encoder(
+        rust_ctx: *mut c_void,
+        width: c_int,
+        height: c_int,
+        options: *const CEncoderOptions,
+    ) -> *mut c_void;
     fn open_video(handle: *mut c_void, err: *mut CError);
+    fn video_encoder_available(codec: c_int) -> c_int;
     fn destroy_video_encoder(handle: *mut c_void);
     fn get_video_frame_data(handle: *const c_void, linesizes: *const *mut c_int) -> *const *mut u8;
     fn encode_video_frame(handle: *mut c_void, micros: c_int, err: *mut CError);
+    fn request_keyframe(handle: *mut c_void);
+    fn set_draft_quality(handle: *mut c_void, draft: c_int);
+    fn set_region_of_interest(
+        handle: *mut c_void,
+        enabled: c_int,
+        left: c_int,
+        top: c_int,
+        right: c_int,
+        bottom: c_int,
+    );
 
-    fn convert_bgra2yuv420p(
+    fn convert_to_yuv420p(
         ctx: *mut c_void,
-        src: *const u8,
+        pixel_format: c_int,
+        src: *const *const u8,
+        src_stride: *const c_int,
         width: c_int,
         height: c_int,
         dst: *const *mut u8,
@@ -20,53 +48,576 @@ extern "C" {
     );
 }
 
+/// Check whether an encoder for the codec has been built in, this does not check whether the
+/// required hardware is present.
+pub fn encoder_available(codec: Codec) -> bool {
+    unsafe { video_encoder_available(codec.as_c_int()) != 0 }
+}
+
+// size of the video encoders are tested with
+const PROBE_WIDTH: usize = 320;
+const PROBE_HEIGHT: usize = 240;
+
+/// Pick the first encoder of Codec::PRIORITY that can actually be initialized, this opens every
+/// candidate once and may take a moment. Falls back to x264 if none works.
+pub fn best_encoder() -> Codec {
+    for codec in Codec::PRIORITY.iter().copied() {
+        if !encoder_available(codec) {
+            continue;
+        }
+        let options = EncoderOptions {
+            scaling_filter: ScalingFilter::FastBilinear,
+            color_space: ColorSpace::BT709,
+            color_range: ColorRange::Limited,
+            pointer_roi: false,
+            codec,
+            threads: 1,
+            container: Container::Mp4,
+            gpu_convert: false,
+            max_resolution: None,
+            latency_budget: None,
+            intra_refresh: false,
+        };
+        match FfmpegEncoder::new(PROBE_WIDTH, PROBE_HEIGHT, options, |_| ()) {
+            Ok(_) => {
+                info!("Picked the {} encoder.", codec);
+                return codec;
+            }
+            Err(err) => debug!("The {} encoder does not work: {}", codec, err),
+        }
+    }
+    Codec::X264
+}
+
 #[no_mangle]
-fn write_video_packet(video_encoder: *mut c_void, buf: *const c_uchar, buf_size: c_int) -> c_int {
-    let video_encoder = unsafe { (video_encoder as *mut VideoEncoder).as_mut().unwrap() };
-    (video_encoder.write_data)(unsafe {
-        std::slice::from_raw_parts(buf as *const u8, buf_size as usize)
-    });
+fn write_video_packet(
+    video_encoder: *mut c_void,
+    buf: *const c_uchar,
+    buf_size: c_int,
+    pts: i64,
+    dts: i64,
+    keyframe: c_int,
+) -> c_int {
+    let video_encoder = unsafe { (video_encoder as *mut FfmpegEncoder).as_mut().unwrap() };
+    if let Some(muxer) = video_encoder.muxer.as_mut() {
+        muxer.write_packet(&Packet {
+            data: unsafe { std::slice::from_raw_parts(buf as *const u8, buf_size as usize) },
+            pts,
+            dts,
+            keyframe: keyframe != 0,
+        });
+    }
     0
 }
 
 pub enum PixelProvider<'a> {
-    // no restrictions on dimension
+    // packed, 4 bytes per pixel, no restrictions on dimension
     BGRA(&'a [u8]),
 
+    // like BGRA but red and blue are swapped, the fourth byte is ignored
+    RGBx(&'a [u8]),
+
+    // Y plane followed by a plane of interleaved U and V samples, the chroma plane has half the
+    // width and height of the image rounded up, all planes are tightly packed
+    NV12(&'a [u8], &'a [u8]),
+
+    // Y, U and V planes, U and V have half the width and height of the image rounded up, all
+    // planes are tightly packed
+    I420(&'a [u8], &'a [u8], &'a [u8]),
+
     // this writes to raw yuv420p ffmpeg buffers and those require that width and height are
     // even, this means a column or row of pixels of the source image might need to be clipped
     FillYUV420P(Box<dyn FnOnce(&mut [u8], &mut [u8], &mut [u8], usize, usize, usize) + 'a>),
 }
 
-pub struct VideoEncoder {
+/// Pixel formats a ScreenCapture can provide natively, the encoder converts all of them to
+/// yuv420p in a single step.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PixelFormat {
+    BGRA,
+    RGBx,
+    NV12,
+    I420,
+}
+
+impl PixelFormat {
+    // mirrors enum PixelFormat in encode_video.c
+    fn as_c_int(self) -> c_int {
+        match self {
+            PixelFormat::BGRA => 0,
+            PixelFormat::RGBx => 1,
+            PixelFormat::NV12 => 2,
+            PixelFormat::I420 => 3,
+        }
+    }
+}
+
+impl<'a> PixelProvider<'a> {
+    /// Format of the provided pixels, None if the capture fills the frame of the encoder itself.
+    pub fn format(&self) -> Option<PixelFormat> {
+        match self {
+            PixelProvider::BGRA(_) => Some(PixelFormat::BGRA),
+            PixelProvider::RGBx(_) => Some(PixelFormat::RGBx),
+            PixelProvider::NV12(..) => Some(PixelFormat::NV12),
+            PixelProvider::I420(..) => Some(PixelFormat::I420),
+            PixelProvider::FillYUV420P(_) => None,
+        }
+    }
+
+    // pointers to the planes and their strides as expected by swscale
+    fn planes(&self, width: usize) -> ([*const u8; 4], [c_int; 4]) {
+        let null = std::ptr::null();
+        let chroma_width = (width + 1) / 2;
+        match self {
+            PixelProvider::BGRA(data) | PixelProvider::RGBx(data) => (
+                [data.as_ptr(), null, null, null],
+                [(width * 4) as c_int, 0, 0, 0],
+            ),
+            PixelProvider::NV12(y, uv) => (
+                [y.as_ptr(), uv.as_ptr(), null, null],
+                [width as c_int, (2 * chroma_width) as c_int, 0, 0],
+            ),
+            PixelProvider::I420(y, u, v) => (
+                [y.as_ptr(), u.as_ptr(), v.as_ptr(), null],
+                [
+                    width as c_int,
+                    chroma_width as c_int,
+                    chroma_width as c_int,
+                    0,
+                ],
+            ),
+            PixelProvider::FillYUV420P(_) => ([null; 4], [0; 4]),
+        }
+    }
+}
+
+/// Size of the video for a captured image of the given size, yuv420p requires even dimensions so
+/// a column or row is added if necessary.
+pub fn padded_size(width: usize, height: usize) -> (usize, usize) {
+    (width + width % 2, height + height % 2)
+}
+
+/// Largest size of the video, larger captures are scaled down before encoding.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Resolution {
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Resolution {
+    /// Size of the video for a capture of the given size, the aspect ratio is kept. Portrait
+    /// captures are limited to the resolution rotated by 90 degrees.
+    pub fn fit(&self, width: usize, height: usize) -> (usize, usize) {
+        let (max_width, max_height) = if (width >= height) == (self.width >= self.height) {
+            (self.width, self.height)
+        } else {
+            (self.height, self.width)
+        };
+        let scale = (max_width as f64 / width as f64)
+            .min(max_height as f64 / height as f64)
+            .min(1.0);
+        (
+            ((width as f64 * scale).round() as usize).max(1),
+            ((height as f64 * scale).round() as usize).max(1),
+        )
+    }
+}
+
+impl FromStr for Resolution {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || format!("Invalid resolution '{}', use WIDTHxHEIGHT, e.g. 1920x1080.", s);
+        let (width, height) = s.split_once('x').ok_or_else(error)?;
+        let width: usize = width.trim().parse().map_err(|_| error())?;
+        let height: usize = height.trim().parse().map_err(|_| error())?;
+        if width == 0 || height == 0 {
+            return Err(error());
+        }
+        Ok(Self { width, height })
+    }
+}
+
+/// Codec and hardware used to encode the video.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Codec {
+    // H.264 encoded in software by libx264
+    X264,
+    // H.264 encoded by the GPU via the Video Acceleration API, Linux only
+    Vaapi,
+    // H.264 encoded by Nvidia GPUs
+    Nvenc,
+    // H.264 encoded by AMD GPUs via the Advanced Media Framework, Windows only
+    Amf,
+    // every frame is sent as JPEG, needs a lot of bandwidth but hardly any CPU time on the client
+    Mjpeg,
+}
+
+impl Codec {
+    pub const ALL: [Codec; 5] = [
+        Codec::X264,
+        Codec::Vaapi,
+        Codec::Nvenc,
+        Codec::Amf,
+        Codec::Mjpeg,
+    ];
+
+    /// H.264 encoders in the order they are tried if none has been chosen, hardware encoders save
+    /// CPU time and are preferred.
+    pub const PRIORITY: [Codec; 4] = [Codec::Nvenc, Codec::Vaapi, Codec::Amf, Codec::X264];
+
+    // encoder to try next if this one fails to initialize, MJPEG has no fallback as the client
+    // decodes it differently
+    fn fallback(self) -> Option<Codec> {
+        Codec::PRIORITY
+            .iter()
+            .skip_while(|codec| **codec != self)
+            .skip(1)
+            .find(|codec| encoder_available(**codec))
+            .copied()
+    }
+
+    // mirrors enum Codec in encode_video.c
+    fn as_c_int(self) -> c_int {
+        match self {
+            Codec::X264 => 0,
+            Codec::Vaapi => 1,
+            Codec::Nvenc => 2,
+            Codec::Mjpeg => 3,
+            Codec::Amf => 4,
+        }
+    }
+
+    /// Message telling the client that a new stream starts, the client has to know how to decode
+    /// it.
+    pub fn new_stream_message(self, container: Container) -> &'static str {
+        match (self, container) {
+            (Codec::Mjpeg, _) => "new mjpeg",
+            (_, Container::AnnexB) => "new annexb",
+            _ => "new",
+        }
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Codec::X264 => "x264",
+            Codec::Vaapi => "vaapi",
+            Codec::Nvenc => "nvenc",
+            Codec::Amf => "amf",
+            Codec::Mjpeg => "mjpeg",
+        })
+    }
+}
+
+impl FromStr for Codec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Codec::ALL
+            .iter()
+            .find(|codec| codec.to_string() == s.to_lowercase())
+            .copied()
+            .ok_or_else(|| {
+                format!(
+                    "Unknown encoder '{}', use x264, vaapi, nvenc, amf or mjpeg.",
+                    s
+                )
+            })
+    }
+}
+
+/// Format H.264 is muxed into, JPEGs are never muxed, see mux.rs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Container {
+    // fragmented mp4 as required by Media Source Extensions of the web client
+    Mp4,
+    // for media players, streams can be joined at any keyframe
+    MpegTs,
+    // NAL units as they come from the encoder, e.g. for WebCodecs or WebRTC
+    AnnexB,
+}
+
+/// Filter used by swscale when converting and scaling the captured image, the faster filters
+/// result in blurrier text if the image has to be scaled.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ScalingFilter {
+    FastBilinear,
+    Bicubic,
+    Lanczos,
+}
+
+impl ScalingFilter {
+    fn as_c_int(self) -> c_int {
+        match self {
+            ScalingFilter::FastBilinear => 0,
+            ScalingFilter::Bicubic => 1,
+            ScalingFilter::Lanczos => 2,
+        }
+    }
+}
+
+impl FromStr for ScalingFilter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "fast" => Ok(Self::FastBilinear),
+            "bicubic" => Ok(Self::Bicubic),
+            "lanczos" => Ok(Self::Lanczos),
+            _ => Err(format!(
+                "Unknown scaling filter '{}', use fast, bicubic or lanczos.",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorSpace {
+    BT601,
+    BT709,
+}
+
+impl FromStr for ColorSpace {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "bt601" => Ok(Self::BT601),
+            "bt709" => Ok(Self::BT709),
+            _ => Err(format!("Unknown color space '{}', use bt601 or bt709.", s)),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColorRange {
+    // Y in [16, 235], U and V in [16, 240]
+    Limited,
+    // Y, U and V in [0, 255]
+    Full,
+}
+
+impl FromStr for ColorRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_lowercase().as_str() {
+            "limited" => Ok(Self::Limited),
+            "full" => Ok(Self::Full),
+            _ => Err(format!("Unknown color range '{}', use limited or full.", s)),
+        }
+    }
+}
+
+/// Fixed point coefficients (scaled by 256) to convert RGB to YUV.
+pub struct YUVCoefficients {
+    pub y: [i32; 3],
+    pub u: [i32; 3],
+    pub v: [i32; 3],
+    pub y_offset: i32,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct EncoderOptions {
+    pub scaling_filter: ScalingFilter,
+    pub color_space: ColorSpace,
+    pub color_range: ColorRange,
+    // spend more bits on the region around the pointer, see VideoEncoder::set_focus
+    pub pointer_roi: bool,
+    pub codec: Codec,
+    // threads used for conversion and by software encoders, at least 1
+    pub threads: usize,
+    pub container: Container,
+    // scale and convert packed RGB on the GPU, requires the gpu-convert feature
+    #[cfg_attr(not(feature = "gpu-convert"), allow(dead_code))]
+    pub gpu_convert: bool,
+    // larger captures are scaled down to fit
+    pub max_resolution: Option<Resolution>,
+    // time a frame may take from encoding to being shown, see --latency-budget
+    pub latency_budget: Option<Duration>,
+    // refresh the picture gradually instead of with periodic keyframes, only supported by x264
+    pub intra_refresh: bool,
+}
+
+// mirrors VideoEncoderOptions in encode_video.c
+#[repr(C)]
+struct CEncoderOptions {
+    scaling_filter: c_int,
+    colorspace: c_int,
+    full_range: c_int,
+    pointer_roi: c_int,
+    codec: c_int,
+    threads: c_int,
+    global_header: c_int,
+    // 0 if there is no budget
+    latency_budget_ms: c_int,
+    intra_refresh: c_int,
+}
+
+impl EncoderOptions {
+    /// Size of the video for a capture of the given size, see max_resolution.
+    pub fn video_size(&self, width: usize, height: usize) -> (usize, usize) {
+        match self.max_resolution {
+            Some(max_resolution) => max_resolution.fit(width, height),
+            None => (width, height),
+        }
+    }
+
+    /// Coefficients for code that converts RGB to YUV itself instead of relying on swscale.
+    pub fn yuv_coefficients(&self) -> YUVCoefficients {
+        match (self.color_space, self.color_range) {
+            (ColorSpace::BT601, ColorRange::Limited) => YUVCoefficients {
+                y: [66, 129, 25],
+                u: [-38, -74, 112],
+                v: [112, -94, -18],
+                y_offset: 16,
+            },
+            (ColorSpace::BT601, ColorRange::Full) => YUVCoefficients {
+                y: [77, 150, 29],
+                u: [-43, -85, 128],
+                v: [128, -107, -21],
+                y_offset: 0,
+            },
+            (ColorSpace::BT709, ColorRange::Limited) => YUVCoefficients {
+                y: [47, 157, 16],
+                u: [-26, -86, 112],
+                v: [112, -102, -10],
+                y_offset: 16,
+            },
+            (ColorSpace::BT709, ColorRange::Full) => YUVCoefficients {
+                y: [54, 183, 19],
+                u: [-29, -99, 128],
+                v: [128, -116, -12],
+                y_offset: 0,
+            },
+        }
+    }
+}
+
+/// Encodes captured frames, the encoded data is passed to the callback given on creation.
+pub trait VideoEncoder {
+    /// Encode a frame of the given size and return the fraction of the frame that changed compared
+    /// to the previous one. Frames that do not have the size the encoder has been created for are
+    /// scaled to that size.
+    fn encode(&mut self, pixel_provider: PixelProvider, width: usize, height: usize) -> f64;
+
+    /// Set the point the user is currently looking at in coordinates relative to the frame size
+    /// (x and y in [0, 1]), encoders may spend more bits on the area around it. None encodes the
+    /// whole frame with the same quality.
+    fn set_focus(&mut self, _focus: Option<(f64, f64)>) {}
+
+    /// Encode the next frame as keyframe, clients request this if their decoder lost sync.
+    fn request_keyframe(&mut self) {}
+
+    /// Encode frames with lower quality while draft is set, once it is unset the next frame is a
+    /// keyframe in full quality.
+    fn set_draft(&mut self, _draft: bool) {}
+
+    /// Whether the encoder has been created for frames of this size.
+    fn check_size(&self, width: usize, height: usize) -> bool;
+}
+
+/// Create the encoder selected in options, write_data is called with the encoded data.
+/// Create an encoder for options.codec, if a hardware encoder fails to initialize the next one of
+/// Codec::PRIORITY is tried instead, down to x264.
+pub fn create_encoder(
+    width: usize,
+    height: usize,
+    mut options: EncoderOptions,
+    write_data: impl Fn(&[u8]) + 'static,
+) -> Result<Box<dyn VideoEncoder>, CError> {
+    let write_data = Rc::new(write_data);
+    loop {
+        // all codecs are provided by ffmpeg for now
+        let write = write_data.clone();
+        match FfmpegEncoder::new(width, height, options, move |data| write(data)) {
+            Ok(encoder) => return Ok(encoder),
+            Err(err) => match options.codec.fallback() {
+                Some(codec) => {
+                    warn!(
+                        "Failed to initialize the {} encoder, falling back to {}: {}",
+                        options.codec, codec, err
+                    );
+                    options.codec = codec;
+                }
+                None => return Err(err),
+            },
+        }
+    }
+}
+
+/// Encoder backed by ffmpeg, the packets are passed to the muxer for options.container.
+pub struct FfmpegEncoder {
     handle: *mut c_void,
+    // size of the captured image, the video itself has padded_size
     width: usize,
     height: usize,
-    write_data: Box<dyn Fn(&[u8])>,
+    // created once the encoder has been opened as it needs the codec parameters
+    muxer: Option<Box<dyn Muxer>>,
     start_time: Instant,
+    luma_samples: Vec<u8>,
+    coefficients: YUVCoefficients,
+    pool: SlicePool,
+    #[cfg(feature = "gpu-convert")]
+    gpu: Option<GpuConverter>,
 }
 
-impl VideoEncoder {
+// distance in pixels between luma samples used to detect changes between frames
+const CHANGE_SAMPLE_STEP: usize = 8;
+
+impl FfmpegEncoder {
     pub fn new(
         width: usize,
         height: usize,
+        options: EncoderOptions,
         write_data: impl Fn(&[u8]) + 'static,
     ) -> Result<Box<Self>, CError> {
-        // yuv420p only supports even width and height
-        let width = width;
-        let height = height;
         let mut video_encoder = Box::new(Self {
             handle: std::ptr::null_mut(),
             width,
             height,
-            write_data: Box::new(move |data| write_data(data)),
+            muxer: None,
             start_time: Instant::now(),
+            luma_samples: Vec::new(),
+            coefficients: options.yuv_coefficients(),
+            pool: SlicePool::new(options.threads),
+            #[cfg(feature = "gpu-convert")]
+            gpu: None,
         });
+        #[cfg(feature = "gpu-convert")]
+        {
+            if options.gpu_convert && GpuConverter::supports(width, height) {
+                video_encoder.gpu = GpuConverter::new()
+                    .map_err(|err| warn!("Failed to convert frames on the GPU: {}", err))
+                    .ok();
+            }
+        }
+        let c_options = CEncoderOptions {
+            scaling_filter: options.scaling_filter.as_c_int(),
+            colorspace: match options.color_space {
+                ColorSpace::BT601 => 0,
+                ColorSpace::BT709 => 1,
+            },
+            full_range: (options.color_range == ColorRange::Full).into(),
+            pointer_roi: options.pointer_roi.into(),
+            codec: options.codec.as_c_int(),
+            threads: options.threads as c_int,
+            global_header: (options.container.global_header() && options.codec != Codec::Mjpeg)
+                .into(),
+            latency_budget_ms: options
+                .latency_budget
+                .map_or(0, |budget| budget.as_millis() as c_int),
+            intra_refresh: options.intra_refresh.into(),
+        };
         let handle = unsafe {
             init_video_encoder(
                 video_encoder.as_mut() as *mut _ as *mut c_void,
                 width as c_int,
                 height as c_int,
+                &c_options,
             )
         };
         video_encoder.handle = handle;
@@ -76,27 +627,130 @@ impl VideoEncoder {
         if err.is_err() {
             return Err(err);
         }
+        video_encoder.muxer = Some(create_muxer(
+            options.container,
+            options.codec,
+            video_encoder.handle,
+            Box::new(write_data),
+        )?);
         Ok(video_encoder)
     }
 
-    pub fn encode(
-        &mut self,
-        pixel_provider: PixelProvider
+    // everything but FillYUV420P is converted and scaled to the even dimensions of the encoder by
+    // swscale
+    fn scale_and_convert(
+        &self,
+        pixel_provider: PixelProvider,
+        width: usize,
+        height: usize,
+        dst: *const *mut u8,
+        dst_stride: *const c_int,
     ) {
+        let format = pixel_provider
+            .format()
+            .expect("FillYUV420P can not be converted by swscale");
+        let (src, src_stride) = pixel_provider.planes(width);
+        unsafe {
+            convert_to_yuv420p(
+                self.handle,
+                format.as_c_int(),
+                src.as_ptr(),
+                src_stride.as_ptr(),
+                width as c_int,
+                height as c_int,
+                dst,
+                dst_stride,
+            )
+        };
+    }
+
+    // the GPU scales to the size of the encoder and converts to NV12, swscale then only has to
+    // deinterleave the chroma samples and pad the frame
+    #[cfg(feature = "gpu-convert")]
+    fn convert_on_gpu(
+        &mut self,
+        rgb: &[u8],
+        format: PixelFormat,
+        width: usize,
+        height: usize,
+        dst: *const *mut u8,
+        dst_stride: *const c_int,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        // the converter is dropped if it fails
+        let mut gpu = self.gpu.take().unwrap();
+        let (y, uv) = gpu.convert(
+            rgb,
+            format,
+            width,
+            height,
+            self.width,
+            self.height,
+            &self.coefficients,
+        )?;
+        self.scale_and_convert(
+            PixelProvider::NV12(y, uv),
+            self.width,
+            self.height,
+            dst,
+            dst_stride,
+        );
+        self.gpu = Some(gpu);
+        Ok(())
+    }
+
+    fn sample_change(&mut self, y: &[u8], y_linesize: usize) -> f64 {
+        let width = self.width - self.width % 2;
+        let height = self.height - self.height % 2;
+        let mut changed = 0;
+        let mut i = 0;
+        let first_frame = self.luma_samples.is_empty();
+        for row in (0..height).step_by(CHANGE_SAMPLE_STEP) {
+            for col in (0..width).step_by(CHANGE_SAMPLE_STEP) {
+                let sample = y[row * y_linesize + col];
+                if first_frame {
+                    self.luma_samples.push(sample);
+                } else {
+                    // ignore tiny differences caused by dithering and noise
+                    if (sample as i32 - self.luma_samples[i] as i32).abs() > 2 {
+                        changed += 1;
+                    }
+                    self.luma_samples[i] = sample;
+                }
+                i += 1;
+            }
+        }
+        if first_frame || i == 0 {
+            return 1.0;
+        }
+        changed as f64 / i as f64
+    }
+}
+
+impl VideoEncoder for FfmpegEncoder {
+    // the change is estimated by comparing a sparse grid of luma samples
+    fn encode(&mut self, pixel_provider: PixelProvider, width: usize, height: usize) -> f64 {
         let linsizes: *mut c_int = std::ptr::null_mut();
         let data = unsafe { get_video_frame_data(self.handle, &linsizes) };
+        let convert_span = info_span!("convert");
+        let convert_enter = convert_span.enter();
+        let format = pixel_provider.format();
+        let scale = !self.check_size(width, height);
         match pixel_provider {
-            PixelProvider::BGRA(bgra) => unsafe {
-                convert_bgra2yuv420p(
-                    self.handle,
-                    bgra.as_ptr(),
-                    self.width as c_int,
-                    self.height as c_int,
-                    data,
-                    linsizes,
-                );
-            },
-            PixelProvider::FillYUV420P(fill_yuv) => {
+            #[cfg(feature = "gpu-convert")]
+            PixelProvider::BGRA(rgb) | PixelProvider::RGBx(rgb) if self.gpu.is_some() => {
+                let format = format.unwrap();
+                if let Err(err) = self.convert_on_gpu(rgb, format, width, height, data, linsizes) {
+                    warn!("Converting on the GPU failed, falling back to the CPU: {}", err);
+                    let pixel_provider = match format {
+                        PixelFormat::BGRA => PixelProvider::BGRA(rgb),
+                        _ => PixelProvider::RGBx(rgb),
+                    };
+                    self.scale_and_convert(pixel_provider, width, height, data, linsizes);
+                }
+            }
+            PixelProvider::FillYUV420P(fill_yuv)
+                if !scale && self.width % 2 == 0 && self.height % 2 == 0 =>
+            {
                 let linesizes_slice = unsafe { std::slice::from_raw_parts(linsizes, 3) };
                 let y_linesize = linesizes_slice[0] as usize;
                 let u_linesize = linesizes_slice[1] as usize;
@@ -110,7 +764,71 @@ impl VideoEncoder {
                     unsafe { std::slice::from_raw_parts_mut(data[2], v_linesize * self.height) };
                 fill_yuv(y, u, v, y_linesize, u_linesize, v_linesize);
             }
+            // FillYUV420P clips odd dimensions, so the image is filled into a temporary image
+            // first which then is scaled and padded like any other I420 image
+            PixelProvider::FillYUV420P(fill_yuv) => {
+                let (width, height) = (width - width % 2, height - height % 2);
+                let mut y = vec![0; width * height];
+                let mut u = vec![0; width / 2 * height / 2];
+                let mut v = vec![0; width / 2 * height / 2];
+                fill_yuv(&mut y, &mut u, &mut v, width, width / 2, width / 2);
+                self.scale_and_convert(
+                    PixelProvider::I420(&y, &u, &v),
+                    width,
+                    height,
+                    data,
+                    linsizes,
+                );
+            }
+            // no scaling is required if the dimensions are even, so packed RGB can be converted
+            // without swscale
+            PixelProvider::BGRA(rgb) | PixelProvider::RGBx(rgb)
+                if !scale && self.width % 2 == 0 && self.height % 2 == 0 =>
+            {
+                let linesizes = unsafe { std::slice::from_raw_parts(linsizes, 3) };
+                let data_planes = unsafe { std::slice::from_raw_parts(data, 3) };
+                let (y_stride, u_stride, v_stride) = (
+                    linesizes[0] as usize,
+                    linesizes[1] as usize,
+                    linesizes[2] as usize,
+                );
+                let mut planes = unsafe {
+                    YUV420PPlanes {
+                        y: std::slice::from_raw_parts_mut(data_planes[0], y_stride * self.height),
+                        u: std::slice::from_raw_parts_mut(
+                            data_planes[1],
+                            u_stride * self.height / 2,
+                        ),
+                        v: std::slice::from_raw_parts_mut(
+                            data_planes[2],
+                            v_stride * self.height / 2,
+                        ),
+                        y_stride,
+                        u_stride,
+                        v_stride,
+                    }
+                };
+                packed_rgb_to_yuv420p_sliced(
+                    &self.pool,
+                    rgb,
+                    format.unwrap(),
+                    self.width,
+                    self.height,
+                    &self.coefficients,
+                    &mut planes,
+                );
+            }
+            pixel_provider => self.scale_and_convert(pixel_provider, width, height, data, linsizes),
         }
+        let change = unsafe {
+            let y_linesize = *linsizes as usize;
+            let height = self.height - self.height % 2;
+            let y = std::slice::from_raw_parts(*data, y_linesize * height);
+            self.sample_change(y, y_linesize)
+        };
+        drop(convert_enter);
+        let encode_span = info_span!("encode");
+        let _enter = encode_span.enter();
         let mut err = CError::new();
         unsafe {
             encode_video_frame(
@@ -119,15 +837,52 @@ impl VideoEncoder {
                 &mut err,
             )
         };
+        change
+    }
+
+    // the area around the focus is encoded with higher quality if pointer_roi is enabled
+    fn set_focus(&mut self, focus: Option<(f64, f64)>) {
+        match focus {
+            Some((x, y)) => {
+                // region of interest is a quarter of the frame in each dimension
+                let roi_width = self.width / 4;
+                let roi_height = self.height / 4;
+                let x = (x.max(0.0).min(1.0) * self.width as f64) as usize;
+                let y = (y.max(0.0).min(1.0) * self.height as f64) as usize;
+                let left = x.saturating_sub(roi_width / 2).min(self.width - roi_width);
+                let top = y.saturating_sub(roi_height / 2).min(self.height - roi_height);
+                unsafe {
+                    set_region_of_interest(
+                        self.handle,
+                        1,
+                        left as c_int,
+                        top as c_int,
+                        (left + roi_width) as c_int,
+                        (top + roi_height) as c_int,
+                    )
+                }
+            }
+            None => unsafe { set_region_of_interest(self.handle, 0, 0, 0, 0, 0) },
+        }
+    }
+
+    fn request_keyframe(&mut self) {
+        unsafe { request_keyframe(self.handle) };
+    }
+
+    fn set_draft(&mut self, draft: bool) {
+        unsafe { set_draft_quality(self.handle, draft.into()) };
     }
 
-    pub fn check_size(&self, width: usize, height: usize) -> bool {
+    fn check_size(&self, width: usize, height: usize) -> bool {
         (self.width == width) && (self.height == height)
     }
 }
 
-impl Drop for VideoEncoder {
+impl Drop for FfmpegEncoder {
     fn drop(&mut self) {
+        // the trailer is written before the encoder is gone
+        self.muxer = None;
         if !self.handle.is_null() {
             unsafe { destroy_video_encoder(self.handle) }
         }
@@ -0,0 +1,207 @@
+use std::error::Error;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::time::Duration;
+
+use tracing::{info, warn};
+
+use crate::config::WakeOptions;
+
+// a request line and a few headers are enough to answer, the rest is ignored
+const MAX_REQUEST_SIZE: usize = 4096;
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+// how long the page waits for the computer to boot before opening the redirect URL
+const REDIRECT_DELAY_SECS: u32 = 15;
+
+// a MAC address like 01:23:45:67:89:ab, dashes are accepted as separators too
+fn parse_mac(s: &str) -> Result<[u8; 6], String> {
+    let mut mac = [0u8; 6];
+    let parts: Vec<&str> = s.split(|c| c == ':' || c == '-').collect();
+    if parts.len() != mac.len() {
+        return Err(format!("{} is not a MAC address.", s));
+    }
+    for (byte, part) in mac.iter_mut().zip(parts) {
+        *byte = u8::from_str_radix(part, 16).map_err(|_| format!("{} is not a MAC address.", s))?;
+    }
+    Ok(mac)
+}
+
+/// Send a magic packet waking the computer with the given MAC address, if options.listen is set
+/// keep listening and send one for every HTTP request instead. This way a bookmark on the tablet
+/// wakes the computer via a machine that is always on.
+pub fn run(options: &WakeOptions) -> Result<(), Box<dyn Error>> {
+    let mac = parse_mac(&options.mac_address)?;
+    let port = match options.listen {
+        Some(port) => port,
+        None => return send_magic_packet(options, &mac),
+    };
+    let listener = TcpListener::bind(SocketAddr::new(options.bind_address, port))
+        .map_err(|err| format!("Failed to listen on port {}: {}", port, err))?;
+    info!(
+        "Waking {} on every request to port {}.",
+        options.mac_address, port
+    );
+    for stream in listener.incoming() {
+        match stream {
+            Ok(stream) => {
+                if let Err(err) = serve(stream, options, &mac) {
+                    warn!("Failed to answer wake request: {}", err);
+                }
+            }
+            Err(err) => warn!("Failed to accept connection: {}", err),
+        }
+    }
+    Ok(())
+}
+
+// six bytes 0xff followed by the MAC address repeated 16 times
+fn magic_packet(mac: &[u8; 6]) -> Vec<u8> {
+    let mut packet = vec![0xffu8; 6];
+    for _ in 0..16 {
+        packet.extend_from_slice(mac);
+    }
+    packet
+}
+
+fn send_magic_packet(options: &WakeOptions, mac: &[u8; 6]) -> Result<(), Box<dyn Error>> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.set_broadcast(true)?;
+    socket.send_to(
+        &magic_packet(mac),
+        (options.broadcast_address, options.port),
+    )?;
+    info!(
+        "Sent wake-on-LAN packet for {} to {}:{}.",
+        options.mac_address, options.broadcast_address, options.port
+    );
+    Ok(())
+}
+
+fn serve(
+    mut stream: TcpStream,
+    options: &WakeOptions,
+    mac: &[u8; 6],
+) -> Result<(), Box<dyn Error>> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut request = vec![0u8; MAX_REQUEST_SIZE];
+    let len = stream.read(&mut request)?;
+    let request = String::from_utf8_lossy(&request[..len]);
+    // browsers ask for an icon too, that should not wake the computer a second time
+    let path = request.split_whitespace().nth(1).unwrap_or("/");
+    if path != "/" {
+        stream.write_all(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n")?;
+        return Ok(());
+    }
+    let (status, message) = match send_magic_packet(options, mac) {
+        Ok(()) => (
+            "200 OK",
+            "Sent wake-up packet, the computer should start now.".to_string(),
+        ),
+        Err(err) => (
+            "500 Internal Server Error",
+            format!("Failed to send wake-up packet: {}", err),
+        ),
+    };
+    let refresh = match &options.redirect {
+        Some(url) => format!(
+            "<meta http-equiv=\"refresh\" content=\"{}; url={}\">",
+            REDIRECT_DELAY_SECS,
+            url.replace('"', "%22")
+        ),
+        None => String::new(),
+    };
+    let body = format!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\">\
+        <meta name=\"viewport\" content=\"width=device-width, initial-scale=1.0\">\
+        <title>Weylus</title>{}</head><body><p>{}</p></body></html>",
+        refresh, message
+    );
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\n\
+        Cache-Control: no-store\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    )?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    const LOCALHOST: IpAddr = IpAddr::V4(Ipv4Addr::LOCALHOST);
+
+    fn options(port: u16, redirect: Option<&str>) -> WakeOptions {
+        WakeOptions {
+            mac_address: "01:23:45:67:89:ab".into(),
+            broadcast_address: LOCALHOST,
+            port,
+            listen: None,
+            bind_address: LOCALHOST,
+            redirect: redirect.map(String::from),
+        }
+    }
+
+    // send the request to serve and return the response
+    fn request(options: &WakeOptions, request: &str) -> String {
+        let listener = TcpListener::bind((LOCALHOST, 0)).unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        client.write_all(request.as_bytes()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        serve(stream, options, &parse_mac(&options.mac_address).unwrap()).unwrap();
+        let mut response = String::new();
+        client.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn mac_addresses() {
+        let mac = [0x01, 0x23, 0x45, 0x67, 0x89, 0xab];
+        assert_eq!(parse_mac("01:23:45:67:89:ab").unwrap(), mac);
+        assert_eq!(parse_mac("01-23-45-67-89-AB").unwrap(), mac);
+        assert!(parse_mac("01:23:45:67:89").is_err());
+        assert!(parse_mac("01:23:45:67:89:ab:cd").is_err());
+        assert!(parse_mac("01:23:45:67:89:xy").is_err());
+        assert!(parse_mac("01:23:45:67:89:123").is_err());
+        assert!(parse_mac("").is_err());
+    }
+
+    #[test]
+    fn magic_packet_repeats_mac() {
+        let mac = [1, 2, 3, 4, 5, 6];
+        let packet = magic_packet(&mac);
+        assert_eq!(packet.len(), 102);
+        assert_eq!(&packet[..6], &[0xff; 6]);
+        assert!(packet[6..].chunks(6).all(|chunk| chunk == mac));
+    }
+
+    #[test]
+    fn requests_send_packets() {
+        let socket = UdpSocket::bind((LOCALHOST, 0)).unwrap();
+        socket.set_read_timeout(Some(REQUEST_TIMEOUT)).unwrap();
+        let options = options(
+            socket.local_addr().unwrap().port(),
+            Some("http://example.com/\"><script>"),
+        );
+        let response = request(&options, "GET / HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("url=http://example.com/%22><script>\">"));
+        let mut packet = [0; 128];
+        let len = socket.recv(&mut packet).unwrap();
+        assert_eq!(
+            &packet[..len],
+            &magic_packet(&parse_mac(&options.mac_address).unwrap())[..]
+        );
+
+        // icons the browser asks for do not wake the computer again
+        let response = request(&options, "GET /favicon.ico HTTP/1.1\r\n\r\n");
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+        socket
+            .set_read_timeout(Some(Duration::from_millis(100)))
+            .unwrap();
+        assert!(socket.recv(&mut packet).is_err());
+    }
+}
@@ -2,19 +2,32 @@ use handlebars::Handlebars;
 use hyper::service::{make_service_fn, service_fn};
 use hyper::{server::conn::AddrStream, Body, Method, Request, Response, Server, StatusCode};
 use serde::Serialize;
-use std::collections::HashMap;
 use std::net::SocketAddr;
 use std::sync::mpsc;
 use std::sync::mpsc::SendError;
 use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
 use tokio::sync::mpsc as mpsc_tokio;
 use tracing::{error, info, warn};
 
+use crate::access_log;
+use crate::logs::LogBuffer;
+use crate::metrics::Metrics;
+use crate::mpegts;
+use crate::screen_capture::CaptureSource;
+use crate::video::EncoderOptions;
+
 #[derive(Serialize)]
 struct WebConfig {
     password: Option<String>,
+    // hides the controls that only send input
+    view_only: bool,
     websocket_pointer_port: u16,
     websocket_video_port: u16,
+    shortcuts: Vec<String>,
+    // clients can only zoom into a video of their own and only if they have control
+    zoom: bool,
 }
 
 fn response_from_str(s: &str, content_type: &str) -> Response<Body> {
@@ -33,6 +46,86 @@ fn response_not_found() -> Response<Body> {
         .unwrap()
 }
 
+fn user_agent(req: &Request<Body>) -> Option<&str> {
+    req.headers()
+        .get(hyper::header::USER_AGENT)
+        .and_then(|user_agent| user_agent.to_str().ok())
+}
+
+// the password is passed in the query or via HTTP basic authentication, the latter keeps it out of
+// the browser history and the logs of proxies, the user name is ignored
+fn offered_password(req: &Request<Body>) -> Option<String> {
+    let from_query = req.uri().query().and_then(|query| {
+        url::form_urlencoded::parse(query.as_bytes())
+            .find(|(name, _)| name == "password")
+            .map(|(_, password)| password.into_owned())
+    });
+    from_query.or_else(|| {
+        let header = req.headers().get(hyper::header::AUTHORIZATION)?;
+        let credentials = decode_base64(header.to_str().ok()?.strip_prefix("Basic ")?)?;
+        let credentials = String::from_utf8(credentials).ok()?;
+        credentials
+            .split_once(':')
+            .map(|(_, password)| password.to_string())
+    })
+}
+
+fn decode_base64(s: &str) -> Option<Vec<u8>> {
+    let s = s.trim();
+    let data = s.trim_end_matches('=');
+    // padding only completes the last group of four characters and a single character left over
+    // does not make up a byte
+    let padding = s.len() - data.len();
+    if padding > 2 || (padding > 0 && s.len() % 4 != 0) || data.len() % 4 == 1 {
+        return None;
+    }
+    let mut bytes = Vec::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for c in data.bytes() {
+        let value = match c {
+            b'A'..=b'Z' => c - b'A',
+            b'a'..=b'z' => c - b'a' + 26,
+            b'0'..=b'9' => c - b'0' + 52,
+            b'+' => 62,
+            b'/' => 63,
+            _ => return None,
+        };
+        buffer = (buffer << 6) | value as u32;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    Some(bytes)
+}
+
+// serve the request and record it in the access log, the query is left out as it may contain the
+// password
+async fn serve_logged<'a>(
+    addr: SocketAddr,
+    req: Request<Body>,
+    context: Arc<Context<'a>>,
+    sender: mpsc::Sender<Web2GuiMessage>,
+) -> Result<Response<Body>, hyper::Error> {
+    let method = req.method().clone();
+    let path = req.uri().path().to_string();
+    let user_agent = user_agent(&req).map(String::from);
+    let response = serve(addr, req, context, sender).await;
+    if let Ok(response) = &response {
+        access_log::http(
+            addr,
+            method.as_str(),
+            &path,
+            user_agent.as_deref(),
+            response.status().as_u16(),
+        );
+    }
+    response
+}
+
 async fn serve<'a>(
     addr: SocketAddr,
     req: Request<Body>,
@@ -41,18 +134,30 @@ async fn serve<'a>(
 ) -> Result<Response<Body>, hyper::Error> {
     let context = &*context;
     let mut authed = false;
+    let mut view_only = false;
+    // a password has been offered but did not match
+    let mut rejected = false;
     if let Some(password) = &context.password {
-        if req.method() == Method::GET && req.uri().path() == "/" {
-            use url::form_urlencoded;
-            if let Some(query) = req.uri().query() {
-                let params = form_urlencoded::parse(query.as_bytes())
-                    .into_owned()
-                    .collect::<HashMap<String, String>>();
-                if let Some(pass) = params.get("password") {
-                    if pass == password {
-                        authed = true;
-                        info!("Client authenticated: {}.", &addr);
-                    }
+        if req.method() == Method::GET
+            && (req.uri().path() == "/"
+                || req.uri().path() == "/metrics"
+                || req.uri().path() == "/logs"
+                || req.uri().path() == "/screenshot.png"
+                || req.uri().path() == "/stream.ts")
+        {
+            if let Some(pass) = &offered_password(&req) {
+                if pass == password {
+                    authed = true;
+                    info!("Client authenticated: {}.", &addr);
+                } else if context.view_only_password.as_ref() == Some(pass)
+                    // the logs are only meant for those who may control the computer
+                    && req.uri().path() != "/logs"
+                {
+                    authed = true;
+                    view_only = true;
+                    info!("Client authenticated to only watch: {}.", &addr);
+                } else {
+                    rejected = true;
                 }
             }
         }
@@ -64,17 +169,40 @@ async fn serve<'a>(
     }
     match req.uri().path() {
         "/" => {
+            if !authed && context.basic_auth {
+                return Ok(Response::builder()
+                    .status(StatusCode::UNAUTHORIZED)
+                    .header(
+                        "www-authenticate",
+                        "Basic realm=\"Weylus\", charset=\"UTF-8\"",
+                    )
+                    .header("content-type", "text/plain; charset=utf-8")
+                    .body("Unauthorized".into())
+                    .unwrap());
+            }
             if !authed {
-                return Ok(response_from_str(
+                let mut response = response_from_str(
                     std::include_str!("../www/static/password.html"),
                     "text/html; charset=utf-8",
-                ));
+                );
+                // the password page is shown again, the access log records the failed attempt
+                if rejected {
+                    *response.status_mut() = StatusCode::UNAUTHORIZED;
+                }
+                return Ok(response);
             }
             info!("Client connected: {}", &addr);
             let config = WebConfig {
-                password: context.password.clone(),
+                password: if view_only {
+                    context.view_only_password.clone()
+                } else {
+                    context.password.clone()
+                },
+                view_only,
                 websocket_pointer_port: context.ws_pointer_port,
                 websocket_video_port: context.ws_video_port,
+                shortcuts: context.shortcuts.clone(),
+                zoom: context.zoom && !view_only,
             };
 
             Ok(response_from_str(
@@ -82,6 +210,72 @@ async fn serve<'a>(
                 "text/html; charset=utf-8",
             ))
         }
+        "/metrics" => {
+            if !authed {
+                return Ok(response_not_found());
+            }
+            Ok(response_from_str(
+                &context.metrics.render(),
+                "text/plain; version=0.0.4; charset=utf-8",
+            ))
+        }
+        "/logs" => {
+            if !authed {
+                return Ok(response_not_found());
+            }
+            Ok(Response::builder()
+                .status(StatusCode::OK)
+                .header("content-type", "application/zip")
+                .header(
+                    "content-disposition",
+                    "attachment; filename=\"weylus-logs.zip\"",
+                )
+                .body(context.logs.bundle().into())
+                .unwrap())
+        }
+        "/screenshot.png" => {
+            if !authed {
+                return Ok(response_not_found());
+            }
+            // capturing and encoding block, keep them off the threads serving requests
+            let capture_source = context.capture_source.clone();
+            match tokio::task::spawn_blocking(move || capture_source.screenshot()).await {
+                Ok(Ok(png)) => Ok(Response::builder()
+                    .status(StatusCode::OK)
+                    .header("content-type", "image/png")
+                    .header("cache-control", "no-store")
+                    .body(png.into())
+                    .unwrap()),
+                Ok(Err(err)) => {
+                    warn!("Failed to take screenshot: {}", err);
+                    Ok(Response::builder()
+                        .status(StatusCode::INTERNAL_SERVER_ERROR)
+                        .header("content-type", "text/plain; charset=utf-8")
+                        .body(format!("Failed to take screenshot: {}", err).into())
+                        .unwrap())
+                }
+                Err(err) => {
+                    error!("Screenshot task failed: {}", err);
+                    Ok(Response::builder()
+                        .status(StatusCode::INTERNAL_SERVER_ERROR)
+                        .body(Body::empty())
+                        .unwrap())
+                }
+            }
+        }
+        "/stream.ts" => {
+            if !authed {
+                return Ok(response_not_found());
+            }
+            info!("MPEG-TS stream requested by {}.", &addr);
+            let stream = mpegts::start(context.capture_source.clone(), context.encoder_options);
+            Ok(Response::builder()
+                .status(StatusCode::OK)
+                .header("content-type", "video/mp2t")
+                .header("cache-control", "no-store")
+                .body(Body::wrap_stream(stream))
+                .unwrap())
+        }
         "/style.css" => Ok(response_from_str(
             std::include_str!("../www/static/style.css"),
             "text/css; charset=utf-8",
@@ -90,6 +284,26 @@ async fn serve<'a>(
             std::include_str!("../www/static/lib.js"),
             "text/javascript; charset=utf-8",
         )),
+        // the manifest and service worker allow installing the client to the home screen
+        "/manifest.json" => Ok(response_from_str(
+            std::include_str!("../www/static/manifest.json"),
+            "application/manifest+json",
+        )),
+        "/icon.svg" => Ok(response_from_str(
+            std::include_str!("../www/static/icon.svg"),
+            "image/svg+xml",
+        )),
+        // the cache is named after the version, so updates of Weylus replace the cached assets
+        "/sw.js" => Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header("content-type", "text/javascript; charset=utf-8")
+            .header("cache-control", "no-cache")
+            .body(
+                std::include_str!("../www/static/sw.js")
+                    .replace("{{version}}", env!("CARGO_PKG_VERSION"))
+                    .into(),
+            )
+            .unwrap()),
         _ => Ok(response_not_found()),
     }
 }
@@ -100,6 +314,8 @@ pub enum Gui2WebMessage {
 }
 pub enum Web2GuiMessage {
     Shutdown,
+    // a client asked via the wake page for Weylus to be started
+    StartRequested(SocketAddr),
 }
 
 fn log_gui_send_error<T>(res: Result<(), SendError<T>>) {
@@ -113,7 +329,15 @@ struct Context<'a> {
     ws_pointer_port: u16,
     ws_video_port: u16,
     password: Option<String>,
+    view_only_password: Option<String>,
+    basic_auth: bool,
     templates: Handlebars<'a>,
+    metrics: Arc<Metrics>,
+    shortcuts: Vec<String>,
+    zoom: bool,
+    logs: Arc<LogBuffer>,
+    capture_source: CaptureSource,
+    encoder_options: EncoderOptions,
 }
 
 pub fn run(
@@ -123,6 +347,14 @@ pub fn run(
     ws_pointer_port: u16,
     ws_video_port: u16,
     password: Option<&str>,
+    view_only_password: Option<&str>,
+    basic_auth: bool,
+    metrics: Arc<Metrics>,
+    shortcuts: Vec<String>,
+    zoom: bool,
+    logs: Arc<LogBuffer>,
+    capture_source: CaptureSource,
+    encoder_options: EncoderOptions,
 ) {
     let mut templates = Handlebars::new();
     templates
@@ -139,7 +371,15 @@ pub fn run(
         ws_pointer_port,
         ws_video_port,
         password,
+        view_only_password: view_only_password.map(String::from),
+        basic_auth,
         templates,
+        metrics,
+        shortcuts,
+        zoom,
+        logs,
+        capture_source,
+        encoder_options,
     };
     std::thread::spawn(move || run_server(context, sender, receiver));
 }
@@ -162,7 +402,7 @@ async fn run_server(
         async move {
             Ok::<_, hyper::Error>(service_fn(move |req| {
                 let context = context.clone();
-                serve(addr, req, context, sender.clone())
+                serve_logged(addr, req, context, sender.clone())
             }))
         }
     });
@@ -180,3 +420,172 @@ async fn run_server(
     };
     log_gui_send_error(sender2.send(Web2GuiMessage::Shutdown));
 }
+
+// the web server may take a moment to release the port after it has been stopped
+const WAKE_PAGE_BIND_ATTEMPTS: u32 = 25;
+const WAKE_PAGE_BIND_RETRY: Duration = Duration::from_millis(200);
+
+/// Minimal web server running on the port of the web server while Weylus is stopped. It serves a
+/// page that lets a client ask for Weylus to be started, the host has to confirm this.
+pub struct WakePage {
+    sender: mpsc_tokio::Sender<Gui2WebMessage>,
+    thread: JoinHandle<()>,
+}
+
+impl WakePage {
+    pub fn start(bind_addr: SocketAddr, sender: mpsc::Sender<Web2GuiMessage>) -> Self {
+        let (sender_shutdown, receiver) = mpsc_tokio::channel(1);
+        let thread = std::thread::spawn(move || run_wake_page(bind_addr, sender, receiver));
+        Self {
+            sender: sender_shutdown,
+            thread,
+        }
+    }
+
+    /// Stop the server and wait until the port is free again.
+    pub fn stop(mut self) {
+        self.sender.try_send(Gui2WebMessage::Shutdown).ok();
+        if self.thread.join().is_err() {
+            error!("Wake page panicked.");
+        }
+    }
+}
+
+async fn serve_wake_page(
+    addr: SocketAddr,
+    req: Request<Body>,
+    sender: mpsc::Sender<Web2GuiMessage>,
+) -> Result<Response<Body>, hyper::Error> {
+    let response = match (req.method(), req.uri().path()) {
+        (&Method::POST, "/start") => {
+            info!("{} asks for Weylus to be started.", addr);
+            log_gui_send_error(sender.send(Web2GuiMessage::StartRequested(addr)));
+            Response::builder()
+                .status(StatusCode::ACCEPTED)
+                .body(Body::empty())
+                .unwrap()
+        }
+        (&Method::GET, "/style.css") => response_from_str(
+            std::include_str!("../www/static/style.css"),
+            "text/css; charset=utf-8",
+        ),
+        (&Method::GET, "/icon.svg") => {
+            response_from_str(std::include_str!("../www/static/icon.svg"), "image/svg+xml")
+        }
+        // any other page, e.g. the client with the password in the query, is replaced by the wake
+        // page, it reloads itself once Weylus has been started
+        (&Method::GET, _) => Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .header("content-type", "text/html; charset=utf-8")
+            .header("cache-control", "no-store")
+            .body(std::include_str!("../www/static/wake.html").into())
+            .unwrap(),
+        _ => response_not_found(),
+    };
+    access_log::http(
+        addr,
+        req.method().as_str(),
+        req.uri().path(),
+        user_agent(&req),
+        response.status().as_u16(),
+    );
+    Ok(response)
+}
+
+#[tokio::main]
+async fn run_wake_page(
+    bind_addr: SocketAddr,
+    sender: mpsc::Sender<Web2GuiMessage>,
+    mut receiver: mpsc_tokio::Receiver<Gui2WebMessage>,
+) {
+    let mut attempt = 1;
+    let builder = loop {
+        match Server::try_bind(&bind_addr) {
+            Ok(builder) => break builder,
+            Err(err) if attempt >= WAKE_PAGE_BIND_ATTEMPTS => {
+                warn!("Failed to serve wake page at {}: {}", bind_addr, err);
+                return;
+            }
+            Err(_) => {
+                attempt += 1;
+                tokio::time::delay_for(WAKE_PAGE_BIND_RETRY).await;
+            }
+        }
+    };
+    let service = make_service_fn(move |s: &AddrStream| {
+        let addr = s.remote_addr();
+        let sender = sender.clone();
+        async move {
+            Ok::<_, hyper::Error>(service_fn(move |req| {
+                serve_wake_page(addr, req, sender.clone())
+            }))
+        }
+    });
+    let server = builder.serve(service).with_graceful_shutdown(async move {
+        receiver.recv().await;
+    });
+    info!("Serving wake page at {}.", bind_addr);
+    if let Err(err) = server.await {
+        error!("Wake page exited with error: {}", err);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn basic_auth(credentials: &str) -> Request<Body> {
+        Request::builder()
+            .header(hyper::header::AUTHORIZATION, credentials)
+            .body(Body::empty())
+            .unwrap()
+    }
+
+    #[test]
+    fn base64_padding() {
+        assert_eq!(decode_base64("").unwrap(), b"");
+        assert_eq!(decode_base64("YQ==").unwrap(), b"a");
+        assert_eq!(decode_base64("YWI=").unwrap(), b"ab");
+        assert_eq!(decode_base64("YWJj").unwrap(), b"abc");
+        // padding is optional
+        assert_eq!(decode_base64("YQ").unwrap(), b"a");
+        assert_eq!(decode_base64("YWI").unwrap(), b"ab");
+        assert_eq!(decode_base64(" YWJj\r\n").unwrap(), b"abc");
+        assert_eq!(decode_base64("YQ="), None);
+        assert_eq!(decode_base64("YQ==="), None);
+        assert_eq!(decode_base64("YWJjZ"), None);
+        assert_eq!(decode_base64("YQ==YQ=="), None);
+    }
+
+    #[test]
+    fn base64_invalid_characters() {
+        assert_eq!(decode_base64("YW-j"), None);
+        assert_eq!(decode_base64("YW_j"), None);
+        assert_eq!(decode_base64("YW J"), None);
+        assert_eq!(decode_base64("YWJjä"), None);
+        assert_eq!(decode_base64("+/+/").unwrap(), vec![0xfb, 0xff, 0xbf]);
+    }
+
+    #[test]
+    fn password_from_basic_auth() {
+        // user:pass:word
+        let req = basic_auth("Basic dXNlcjpwYXNzOndvcmQ=");
+        assert_eq!(offered_password(&req).as_deref(), Some("pass:word"));
+        // the password in the query takes precedence
+        let mut req = basic_auth("Basic dXNlcjpwYXNzOndvcmQ=");
+        *req.uri_mut() = "/?password=query".parse().unwrap();
+        assert_eq!(offered_password(&req).as_deref(), Some("query"));
+        // no colon separating user and password
+        assert_eq!(offered_password(&basic_auth("Basic dXNlcg==")), None);
+        assert_eq!(offered_password(&basic_auth("Bearer dXNlcjpwYXNz")), None);
+        assert_eq!(offered_password(&basic_auth("Basic dXNlcjpwYXNz!")), None);
+    }
+
+    #[test]
+    fn non_utf8_credentials_are_rejected() {
+        // "user:p\xe4ss" as sent by browsers encoding credentials as ISO-8859-1
+        let req = basic_auth("Basic dXNlcjpw5HNz");
+        assert_eq!(decode_base64("dXNlcjpw5HNz").unwrap(), b"user:p\xe4ss");
+        assert_eq!(offered_password(&req), None);
+    }
+}
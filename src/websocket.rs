@@ -1,93 +1,143 @@
-use std::collections::HashMap;
-use std::net::{SocketAddr, TcpStream};
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use std::net::{IpAddr, SocketAddr, TcpStream};
+#[cfg(target_os = "linux")]
+use std::path::{Path, PathBuf};
 use std::sync::{
     atomic::{AtomicBool, Ordering},
-    mpsc, Arc, Mutex,
+    mpsc, Arc, Condvar, Mutex,
 };
 use std::thread::spawn;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 use tracing::{error, info, warn};
 
-use websocket::sender::Writer;
+use websocket::dataframe::{DataFrame, Opcode};
+use websocket::message::CloseData;
+use websocket::receiver::Reader;
 use websocket::sync::Server;
-use websocket::OwnedMessage;
+use websocket::ws::util::header::read_header;
+use websocket::ws::Message as _;
+use websocket::{OwnedMessage, WebSocketError, WebSocketResult};
 
+use crate::access_log;
+use crate::audio::{AudioMode, AudioStream};
+use crate::config::{ApprovedDevices, Config};
+#[cfg(target_os = "linux")]
+use crate::input::device::NoInput;
 use crate::input::mouse_device::Mouse;
 #[cfg(target_os = "linux")]
 use crate::input::uinput_device::GraphicTablet;
-use crate::stream_handler::{PointerStreamHandler, ScreenStreamHandler, StreamHandler};
+use crate::metrics::Metrics;
+use crate::notifications;
+use crate::stream_handler::{
+    ClientView, PointerOptions, PointerStreamHandler, ScreenStreamHandler, SharedStream,
+    SharedStreamHandler, StatsReporter, StreamHandler, StreamMode, StreamState, VideoStats,
+    WsWriter,
+};
+use crate::tcp_options::TcpOptions;
+use crate::video::EncoderOptions;
 
 use crate::screen_capture::generic::ScreenCaptureGeneric;
+use crate::screen_capture::rotation::RotatedScreenCapture;
+use crate::screen_capture::CaptureSource;
 
 #[cfg(target_os = "linux")]
-use crate::screen_capture::linux::ScreenCaptureX11;
+use crate::screen_capture::drm::ScreenCaptureDrm;
 #[cfg(target_os = "linux")]
-use crate::x11helper::Capturable;
+use crate::screen_capture::linux::{TargetedScreenCaptureX11, X11CaptureOptions};
+#[cfg(target_os = "linux")]
+use crate::x11helper::CaptureTarget;
 
-pub enum Ws2GuiMessage {}
+pub enum Ws2GuiMessage {
+    Connected(SocketAddr),
+    Disconnected(SocketAddr),
+    VideoStats(SocketAddr, VideoStats),
+    // no client has been connected for the idle timeout, the servers should be stopped
+    IdleTimeout,
+    // a device that has not been approved yet asks to connect, see --require-approval, the
+    // answer is sent back, the device is denied if there is none within APPROVAL_TIMEOUT
+    ApprovalRequested(SocketAddr, mpsc::Sender<bool>),
+}
+
+/// How long a device asking for approval waits for an answer.
+pub const APPROVAL_TIMEOUT: Duration = Duration::from_secs(60);
 
 pub enum Gui2WsMessage {
     Shutdown,
+    // close all connections of a client
+    Disconnect(IpAddr),
 }
 
 #[cfg(target_os = "linux")]
 pub fn run(
     sender: mpsc::Sender<Ws2GuiMessage>,
     receiver: mpsc::Receiver<Gui2WsMessage>,
-    ws_pointer_socket_addr: SocketAddr,
-    ws_video_socket_addr: SocketAddr,
-    password: Option<&str>,
-    screen_update_interval: Duration,
-    stylus_support: bool,
-    faster_capture: bool,
-    capture: Capturable,
-    capture_cursor: bool,
-    enable_mouse: bool,
-    enable_stylus: bool,
-    enable_touch: bool,
-) {
-    let clients = Arc::new(Mutex::new(HashMap::<
-        SocketAddr,
-        Arc<Mutex<Writer<TcpStream>>>,
-    >::new()));
+    config: &Config,
+    metrics: Arc<Metrics>,
+    capture: Arc<CaptureTarget>,
+) -> CaptureSource {
+    let capture_source = capture_source(config, &capture);
+    let clients: Clients = Arc::new(Mutex::new(HashMap::new()));
     let clients2 = clients.clone();
     let clients3 = clients.clone();
     let shutdown = Arc::new(AtomicBool::new(false));
     let shutdown2 = shutdown.clone();
     let shutdown3 = shutdown.clone();
     let sender2 = sender.clone();
-    let sender3 = sender;
-
-    spawn(move || match receiver.recv() {
-        Err(_) | Ok(Gui2WsMessage::Shutdown) => {
-            let clients = clients.lock().unwrap();
-            for client in clients.values() {
-                let client = client.lock().unwrap();
-                if let Err(err) = client.shutdown_all() {
-                    error!("Could not shutdown websocket: {}", err);
-                }
-            }
-            shutdown.store(true, Ordering::Relaxed);
-        }
-    });
-    let pass: Option<String> = password.map(|s| s.to_string());
+    let sender3 = sender.clone();
+    let stats_sender = sender;
+    let stream_state = StreamState::new(config, metrics);
+    let stream_state2 = stream_state.clone();
+    if let Some(audio) = &stream_state.audio {
+        let capture = capture.clone();
+        // the whole screen has no process id, its audio is the desktop mix
+        audio.set_target(move || capture.get().0.pid());
+    }
+
+    if let Some(device) = &config.v4l2_device {
+        crate::v4l2loopback::start(
+            capture_source.clone(),
+            device.clone(),
+            config.encoder_options(),
+            shutdown.clone(),
+        );
+    }
+    watch_idle(config, &clients, &shutdown, &stats_sender);
+    let tcp_options = config.tcp_options;
+    let access = ClientAccess::new(config, &clients, &stats_sender);
+    let access2 = access.clone();
+    spawn(move || handle_gui_messages(receiver, clients, shutdown));
+    let ws_pointer_socket_addr =
+        SocketAddr::new(config.bind_address, config.websocket_pointer_port);
+    let ws_video_socket_addr = SocketAddr::new(config.bind_address, config.websocket_video_port);
+    let enable_mouse = config.enable_mouse;
+    let enable_stylus = config.enable_stylus;
+    let enable_touch = config.enable_touch;
+    let pointer_options = config.pointer_options();
+    let screen_update_interval = config.screen_update_interval;
+    let encoder_options = config.encoder_options();
+    let capture_options = config.x11_capture_options();
     {
         let capture = capture.clone();
-        if stylus_support {
+        if config.stylus_support {
             spawn(move || {
                 listen_websocket(
                     ws_pointer_socket_addr,
-                    pass,
+                    tcp_options,
+                    access,
                     clients2,
                     shutdown2,
                     sender2,
-                    move |client_addr| {
+                    move |client_addr, session| {
                         create_graphic_tablet_stream_handler(
                             client_addr,
                             capture.clone(),
                             enable_mouse,
                             enable_stylus,
                             enable_touch,
+                            stream_state.clone(),
+                            stream_state.view(session),
+                            pointer_options,
                         )
                     },
                 )
@@ -96,16 +146,20 @@ pub fn run(
             spawn(move || {
                 listen_websocket(
                     ws_pointer_socket_addr,
-                    pass,
+                    tcp_options,
+                    access,
                     clients2,
                     shutdown2,
                     sender2,
-                    move |_| {
+                    move |_, session| {
                         create_mouse_stream_handler(
                             capture.clone(),
                             enable_mouse,
                             enable_stylus,
                             enable_touch,
+                            stream_state.clone(),
+                            stream_state.view(session),
+                            pointer_options,
                         )
                     },
                 )
@@ -113,21 +167,44 @@ pub fn run(
         }
     }
 
-    let pass: Option<String> = password.map(|s| s.to_string());
     {
-        if faster_capture {
+        if config.stream_mode == StreamMode::Shared {
+            let stream = SharedStream::new(
+                capture_source.clone(),
+                screen_update_interval,
+                encoder_options,
+                stream_state2,
+            );
+            spawn(move || {
+                listen_shared_video(
+                    ws_video_socket_addr,
+                    tcp_options,
+                    access2,
+                    clients3,
+                    shutdown3,
+                    sender3,
+                    stats_sender,
+                    stream,
+                )
+            });
+        } else if config.faster_capture {
             spawn(move || {
                 listen_websocket(
                     ws_video_socket_addr,
-                    pass,
+                    tcp_options,
+                    access2,
                     clients3,
                     shutdown3,
                     sender3,
-                    move |_| {
+                    move |client_addr, session| {
                         create_xscreen_stream_handler(
                             capture.clone(),
                             screen_update_interval,
-                            capture_cursor,
+                            encoder_options,
+                            capture_options.clone(),
+                            stream_state2.clone(),
+                            stream_state2.view(session),
+                            StatsReporter::new(*client_addr, stats_sender.clone()),
                         )
                     },
                 )
@@ -136,33 +213,45 @@ pub fn run(
             spawn(move || {
                 listen_websocket(
                     ws_video_socket_addr,
-                    pass,
+                    tcp_options,
+                    access2,
                     clients3,
                     shutdown3,
                     sender3,
-                    move |_| create_screen_stream_handler(screen_update_interval),
+                    move |client_addr, session| {
+                        create_screen_stream_handler(
+                            screen_update_interval,
+                            encoder_options,
+                            stream_state2.clone(),
+                            stream_state2.view(session),
+                            StatsReporter::new(*client_addr, stats_sender.clone()),
+                        )
+                    },
                 )
             });
         }
     }
+    capture_source
 }
 
-#[cfg(not(target_os = "linux"))]
-pub fn run(
+/// Mirror the framebuffer of a DRM device, used if there is no display server. Input can not be
+/// mapped to anything without one, so pointer events are dropped.
+#[cfg(target_os = "linux")]
+pub fn run_drm(
     sender: mpsc::Sender<Ws2GuiMessage>,
     receiver: mpsc::Receiver<Gui2WsMessage>,
-    ws_pointer_socket_addr: SocketAddr,
-    ws_video_socket_addr: SocketAddr,
-    password: Option<&str>,
-    screen_update_interval: Duration,
-    enable_mouse: bool,
-    enable_stylus: bool,
-    enable_touch: bool,
-) {
-    let clients = Arc::new(Mutex::new(HashMap::<
-        SocketAddr,
-        Arc<Mutex<Writer<TcpStream>>>,
-    >::new()));
+    config: &Config,
+    metrics: Arc<Metrics>,
+    device: PathBuf,
+) -> CaptureSource {
+    let capture_source = {
+        let device = device.clone();
+        CaptureSource::new(move || match ScreenCaptureDrm::new(&device) {
+            Ok(capture) => Ok(Box::new(capture)),
+            Err(err) => Err(err.to_string()),
+        })
+    };
+    let clients: Clients = Arc::new(Mutex::new(HashMap::new()));
     let clients2 = clients.clone();
     let clients3 = clients.clone();
     let shutdown = Arc::new(AtomicBool::new(false));
@@ -170,79 +259,287 @@ pub fn run(
     let shutdown3 = shutdown.clone();
     let sender2 = sender.clone();
     let sender3 = sender.clone();
+    let stats_sender = sender;
+    let stream_state = StreamState::new(config, metrics);
+    let stream_state2 = stream_state.clone();
+    if let Some(audio) = &stream_state.audio {
+        whole_screen_audio(config, audio);
+    }
 
-    spawn(move || loop {
-        match receiver.recv() {
-            Err(_) | Ok(Gui2WsMessage::Shutdown) => {
-                let clients = clients.lock().unwrap();
-                for client in clients.values() {
-                    let client = client.lock().unwrap();
-                    if let Err(err) = client.shutdown_all() {
-                        error!("Could not shutdown websocket: {}", err);
-                    }
-                }
-                shutdown.store(true, Ordering::Relaxed);
-                return;
-            }
-        }
-    });
-    let pass: Option<String> = password.map_or(None, |s| Some(s.to_string()));
+    if let Some(device) = &config.v4l2_device {
+        crate::v4l2loopback::start(
+            capture_source.clone(),
+            device.clone(),
+            config.encoder_options(),
+            shutdown.clone(),
+        );
+    }
+    watch_idle(config, &clients, &shutdown, &stats_sender);
+    let tcp_options = config.tcp_options;
+    let access = ClientAccess::new(config, &clients, &stats_sender);
+    let access2 = access.clone();
+    spawn(move || handle_gui_messages(receiver, clients, shutdown));
+    let ws_pointer_socket_addr =
+        SocketAddr::new(config.bind_address, config.websocket_pointer_port);
+    let ws_video_socket_addr = SocketAddr::new(config.bind_address, config.websocket_video_port);
+    let pointer_options = config.pointer_options();
+    let screen_update_interval = config.screen_update_interval;
+    let encoder_options = config.encoder_options();
 
+    info!(
+        "Capturing {}, input is not supported without display server.",
+        device.display()
+    );
     spawn(move || {
         listen_websocket(
             ws_pointer_socket_addr,
-            pass,
+            tcp_options,
+            access,
             clients2,
             shutdown2,
             sender2,
-            move |_| create_mouse_stream_handler(enable_mouse, enable_stylus, enable_touch),
+            move |_, session| {
+                Ok(PointerStreamHandler::new(
+                    NoInput,
+                    stream_state.clone(),
+                    stream_state.view(session),
+                    pointer_options,
+                ))
+            },
         )
     });
 
-    let pass: Option<String> = password.map_or(None, |s| Some(s.to_string()));
+    if config.stream_mode == StreamMode::Shared {
+        let stream = SharedStream::new(
+            capture_source.clone(),
+            screen_update_interval,
+            encoder_options,
+            stream_state2,
+        );
+        spawn(move || {
+            listen_shared_video(
+                ws_video_socket_addr,
+                tcp_options,
+                access2,
+                clients3,
+                shutdown3,
+                sender3,
+                stats_sender,
+                stream,
+            )
+        });
+    } else {
+        spawn(move || {
+            listen_websocket(
+                ws_video_socket_addr,
+                tcp_options,
+                access2,
+                clients3,
+                shutdown3,
+                sender3,
+                move |client_addr, session| {
+                    create_drm_stream_handler(
+                        &device,
+                        screen_update_interval,
+                        encoder_options,
+                        stream_state2.clone(),
+                        stream_state2.view(session),
+                        StatsReporter::new(*client_addr, stats_sender.clone()),
+                    )
+                },
+            )
+        });
+    }
+    capture_source
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn run(
+    sender: mpsc::Sender<Ws2GuiMessage>,
+    receiver: mpsc::Receiver<Gui2WsMessage>,
+    config: &Config,
+    metrics: Arc<Metrics>,
+) -> CaptureSource {
+    let clients: Clients = Arc::new(Mutex::new(HashMap::new()));
+    let clients2 = clients.clone();
+    let clients3 = clients.clone();
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let shutdown2 = shutdown.clone();
+    let shutdown3 = shutdown.clone();
+    let sender2 = sender.clone();
+    let sender3 = sender.clone();
+    let stats_sender = sender;
+    let stream_state = StreamState::new(config, metrics);
+    let stream_state2 = stream_state.clone();
+    if let Some(audio) = &stream_state.audio {
+        whole_screen_audio(config, audio);
+    }
+
+    if config.v4l2_device.is_some() {
+        warn!("Writing frames to a v4l2loopback device is only supported on Linux.");
+    }
+    watch_idle(config, &clients, &shutdown, &stats_sender);
+    let tcp_options = config.tcp_options;
+    let access = ClientAccess::new(config, &clients, &stats_sender);
+    let access2 = access.clone();
+    spawn(move || handle_gui_messages(receiver, clients, shutdown));
+    let ws_pointer_socket_addr =
+        SocketAddr::new(config.bind_address, config.websocket_pointer_port);
+    let ws_video_socket_addr = SocketAddr::new(config.bind_address, config.websocket_video_port);
+    let enable_mouse = config.enable_mouse;
+    let enable_stylus = config.enable_stylus;
+    let enable_touch = config.enable_touch;
+    let pointer_options = config.pointer_options();
+    let screen_update_interval = config.screen_update_interval;
+    let encoder_options = config.encoder_options();
 
     spawn(move || {
         listen_websocket(
-            ws_video_socket_addr,
-            pass,
-            clients3,
-            shutdown3,
-            sender3,
-            move |_| create_screen_stream_handler(screen_update_interval),
+            ws_pointer_socket_addr,
+            tcp_options,
+            access,
+            clients2,
+            shutdown2,
+            sender2,
+            move |_, session| {
+                create_mouse_stream_handler(
+                    enable_mouse,
+                    enable_stylus,
+                    enable_touch,
+                    stream_state.clone(),
+                    stream_state.view(session),
+                    pointer_options,
+                )
+            },
         )
     });
+
+    let capture_source = capture_source(config);
+    if config.stream_mode == StreamMode::Shared {
+        let stream = SharedStream::new(
+            capture_source.clone(),
+            screen_update_interval,
+            encoder_options,
+            stream_state2,
+        );
+        spawn(move || {
+            listen_shared_video(
+                ws_video_socket_addr,
+                tcp_options,
+                access2,
+                clients3,
+                shutdown3,
+                sender3,
+                stats_sender,
+                stream,
+            )
+        });
+    } else {
+        spawn(move || {
+            listen_websocket(
+                ws_video_socket_addr,
+                tcp_options,
+                access2,
+                clients3,
+                shutdown3,
+                sender3,
+                move |client_addr, session| {
+                    create_screen_stream_handler(
+                        screen_update_interval,
+                        encoder_options,
+                        stream_state2.clone(),
+                        stream_state2.view(session),
+                        StatsReporter::new(*client_addr, stats_sender.clone()),
+                    )
+                },
+            )
+        });
+    }
+    capture_source
+}
+
+// the web server captures the same way the video is captured
+#[cfg(target_os = "linux")]
+fn capture_source(config: &Config, capture: &Arc<CaptureTarget>) -> CaptureSource {
+    if !config.faster_capture {
+        return generic_capture_source(config);
+    }
+    let capture = capture.clone();
+    let options = config.x11_capture_options();
+    CaptureSource::new(move || {
+        match TargetedScreenCaptureX11::new(capture.clone(), options.clone()) {
+            Ok(capture) => Ok(Box::new(capture)),
+            Err(err) => Err(err.to_string()),
+        }
+    })
+}
+
+// without a captured window there is no application to narrow the audio down to
+fn whole_screen_audio(config: &Config, audio: &AudioStream) {
+    if config.audio == AudioMode::Application {
+        warn!(
+            "Only windows captured via X11 have their own audio, streaming the desktop mix \
+            instead."
+        );
+    }
+    audio.set_target(|| None);
+}
+
+#[cfg(not(target_os = "linux"))]
+fn capture_source(config: &Config) -> CaptureSource {
+    generic_capture_source(config)
+}
+
+fn generic_capture_source(config: &Config) -> CaptureSource {
+    let encoder_options = config.encoder_options();
+    CaptureSource::new(move || {
+        Ok(Box::new(ScreenCaptureGeneric::new(
+            encoder_options.yuv_coefficients(),
+        )))
+    })
 }
 
 #[cfg(target_os = "linux")]
 fn create_graphic_tablet_stream_handler(
     client_addr: &SocketAddr,
-    capture: Capturable,
+    capture: Arc<CaptureTarget>,
     enable_mouse: bool,
     enable_stylus: bool,
     enable_touch: bool,
+    stream_state: Arc<StreamState>,
+    view: Arc<ClientView>,
+    pointer_options: PointerOptions,
 ) -> Result<PointerStreamHandler<GraphicTablet>, Box<dyn std::error::Error>> {
-    Ok(PointerStreamHandler::new(GraphicTablet::new(
-        capture,
-        client_addr.to_string(),
-        enable_mouse,
-        enable_stylus,
-        enable_touch,
-    )?))
+    Ok(PointerStreamHandler::new(
+        GraphicTablet::new(
+            capture,
+            client_addr.to_string(),
+            enable_mouse,
+            enable_stylus,
+            enable_touch,
+        )?,
+        stream_state,
+        view,
+        pointer_options,
+    ))
 }
 
 #[cfg(target_os = "linux")]
 fn create_mouse_stream_handler(
-    capture: Capturable,
+    capture: Arc<CaptureTarget>,
     enable_mouse: bool,
     enable_stylus: bool,
     enable_touch: bool,
+    stream_state: Arc<StreamState>,
+    view: Arc<ClientView>,
+    pointer_options: PointerOptions,
 ) -> Result<PointerStreamHandler<Mouse>, Box<dyn std::error::Error>> {
-    Ok(PointerStreamHandler::new(Mouse::new(
-        capture,
-        enable_mouse,
-        enable_stylus,
-        enable_touch,
-    )))
+    Ok(PointerStreamHandler::new(
+        Mouse::new(capture, enable_mouse, enable_stylus, enable_touch),
+        stream_state,
+        view,
+        pointer_options,
+    ))
 }
 
 #[cfg(not(target_os = "linux"))]
@@ -250,45 +547,514 @@ fn create_mouse_stream_handler(
     enable_mouse: bool,
     enable_stylus: bool,
     enable_touch: bool,
+    stream_state: Arc<StreamState>,
+    view: Arc<ClientView>,
+    pointer_options: PointerOptions,
 ) -> Result<PointerStreamHandler<Mouse>, Box<dyn std::error::Error>> {
-    Ok(PointerStreamHandler::new(Mouse::new(
-        enable_mouse,
-        enable_stylus,
-        enable_touch,
-    )))
+    Ok(PointerStreamHandler::new(
+        Mouse::new(enable_mouse, enable_stylus, enable_touch),
+        stream_state,
+        view,
+        pointer_options,
+    ))
 }
 
 #[cfg(target_os = "linux")]
 fn create_xscreen_stream_handler(
-    capture: Capturable,
+    capture: Arc<CaptureTarget>,
+    update_interval: Duration,
+    encoder_options: EncoderOptions,
+    capture_options: X11CaptureOptions,
+    stream_state: Arc<StreamState>,
+    view: Arc<ClientView>,
+    stats: StatsReporter,
+) -> Result<
+    ScreenStreamHandler<RotatedScreenCapture<TargetedScreenCaptureX11>>,
+    Box<dyn std::error::Error>,
+> {
+    Ok(ScreenStreamHandler::new(
+        RotatedScreenCapture::new(
+            TargetedScreenCaptureX11::new(capture, capture_options)?,
+            view.rotation.clone(),
+        ),
+        update_interval,
+        encoder_options,
+        stream_state,
+        view,
+        stats,
+    ))
+}
+
+#[cfg(target_os = "linux")]
+fn create_drm_stream_handler(
+    device: &Path,
     update_interval: Duration,
-    capture_cursor: bool,
-) -> Result<ScreenStreamHandler<ScreenCaptureX11>, Box<dyn std::error::Error>> {
+    encoder_options: EncoderOptions,
+    stream_state: Arc<StreamState>,
+    view: Arc<ClientView>,
+    stats: StatsReporter,
+) -> Result<ScreenStreamHandler<RotatedScreenCapture<ScreenCaptureDrm>>, Box<dyn std::error::Error>>
+{
     Ok(ScreenStreamHandler::new(
-        ScreenCaptureX11::new(capture, capture_cursor)?,
+        RotatedScreenCapture::new(ScreenCaptureDrm::new(device)?, view.rotation.clone()),
         update_interval,
+        encoder_options,
+        stream_state,
+        view,
+        stats,
     ))
 }
 
+pub type Clients = Arc<Mutex<HashMap<SocketAddr, WsWriter>>>;
+
+/// Decides which clients may connect and keeps track of them across the pointer and the video
+/// socket, a client may have several connections to each.
+pub struct ClientAccess {
+    password: Option<String>,
+    // clients authenticated with this password receive video but their input is ignored
+    view_only_password: Option<String>,
+    notify: bool,
+    // input from new clients is ignored for this long, meanwhile they can be rejected
+    grace_period: Option<Duration>,
+    clients: Clients,
+    // number of authenticated connections of every client
+    connections: Mutex<HashMap<IpAddr, usize>>,
+    // end of the grace period of new clients
+    grace: Mutex<HashMap<IpAddr, Instant>>,
+    rejected: Mutex<HashSet<IpAddr>>,
+    require_approval: bool,
+    // device tokens or addresses of clients without token
+    approved: Mutex<HashSet<String>>,
+    // approvals asked for, further connections of the device wait for the answer
+    approvals: Mutex<HashMap<String, Arc<Approval>>>,
+    gui: Mutex<mpsc::Sender<Ws2GuiMessage>>,
+}
+
+#[derive(Default)]
+struct Approval {
+    approved: Mutex<Option<bool>>,
+    decided: Condvar,
+}
+
+impl ClientAccess {
+    pub fn new(
+        config: &Config,
+        clients: &Clients,
+        sender: &mpsc::Sender<Ws2GuiMessage>,
+    ) -> Arc<Self> {
+        let approved = if config.require_approval {
+            ApprovedDevices::load().tokens.into_iter().collect()
+        } else {
+            HashSet::new()
+        };
+        Arc::new(Self {
+            password: config.password.clone(),
+            view_only_password: config.view_only_password.clone(),
+            notify: config.notify_connections,
+            grace_period: config.reject_grace_period,
+            clients: clients.clone(),
+            connections: Mutex::new(HashMap::new()),
+            grace: Mutex::new(HashMap::new()),
+            rejected: Mutex::new(HashSet::new()),
+            require_approval: config.require_approval,
+            approved: Mutex::new(approved),
+            approvals: Mutex::new(HashMap::new()),
+            gui: Mutex::new(sender.clone()),
+        })
+    }
+
+    /// Called once a connection has been authenticated, returns false if the client has been
+    /// rejected.
+    fn connected(self: &Arc<Self>, addr: SocketAddr, device: Option<&str>) -> bool {
+        let ip = addr.ip();
+        if self.rejected.lock().unwrap().contains(&ip) {
+            info!("Refusing connection of rejected client {}.", ip);
+            return false;
+        }
+        if self.require_approval && !self.approve(addr, device) {
+            return false;
+        }
+        {
+            let mut connections = self.connections.lock().unwrap();
+            let count = connections.entry(ip).or_insert(0);
+            *count += 1;
+            if *count > 1 {
+                return true;
+            }
+        }
+        info!("Client {} connected.", ip);
+        if !self.notify {
+            return true;
+        }
+        let summary = "Weylus: Client connected";
+        match self.grace_period {
+            Some(grace_period) => {
+                self.grace
+                    .lock()
+                    .unwrap()
+                    .insert(ip, Instant::now() + grace_period);
+                let access = self.clone();
+                spawn(move || {
+                    let body = format!(
+                        "{} connected, input is accepted in {} s.",
+                        ip,
+                        grace_period.as_secs()
+                    );
+                    let action =
+                        notifications::ask(summary, &body, &[("reject", "Reject")], grace_period);
+                    if action.is_some() {
+                        access.reject(ip);
+                    }
+                });
+            }
+            None => notifications::notify(summary, &format!("{} connected.", ip)),
+        }
+        true
+    }
+
+    // ask for the device to be approved unless it has been approved already, connections to the
+    // pointer and the video socket are made at the same time, so only the first one asks
+    fn approve(&self, addr: SocketAddr, device: Option<&str>) -> bool {
+        let key = device.map_or_else(|| addr.ip().to_string(), String::from);
+        if self.approved.lock().unwrap().contains(&key) {
+            return true;
+        }
+        let (approval, ask) = {
+            let mut approvals = self.approvals.lock().unwrap();
+            match approvals.get(&key) {
+                Some(approval) => (approval.clone(), false),
+                None => {
+                    let approval = Arc::new(Approval::default());
+                    approvals.insert(key.clone(), approval.clone());
+                    (approval, true)
+                }
+            }
+        };
+        if !ask {
+            let mut approved = approval.approved.lock().unwrap();
+            while approved.is_none() {
+                approved = approval.decided.wait(approved).unwrap();
+            }
+            return approved.unwrap();
+        }
+        info!("Asking for {} to be approved.", addr.ip());
+        let (sender, receiver) = mpsc::channel();
+        let approved = self
+            .gui
+            .lock()
+            .unwrap()
+            .send(Ws2GuiMessage::ApprovalRequested(addr, sender))
+            .is_ok()
+            && receiver.recv_timeout(APPROVAL_TIMEOUT).unwrap_or(false);
+        if approved {
+            info!("{} has been approved.", addr.ip());
+            self.approved.lock().unwrap().insert(key.clone());
+            // addresses change, so only devices with a token are remembered
+            if device.is_some() {
+                let mut devices = ApprovedDevices::load();
+                devices.tokens.push(key.clone());
+                devices.save();
+            }
+        } else {
+            info!("{} has been denied.", addr.ip());
+        }
+        *approval.approved.lock().unwrap() = Some(approved);
+        approval.decided.notify_all();
+        self.approvals.lock().unwrap().remove(&key);
+        approved
+    }
+
+    // Some(view_only) if the password is accepted
+    fn check_password(&self, password: &str) -> Option<bool> {
+        if self.password.as_deref() == Some(password) {
+            Some(false)
+        } else if self.view_only_password.as_deref() == Some(password) {
+            Some(true)
+        } else {
+            None
+        }
+    }
+
+    fn disconnected(&self, addr: SocketAddr) {
+        let ip = addr.ip();
+        {
+            let mut connections = self.connections.lock().unwrap();
+            match connections.get_mut(&ip) {
+                Some(count) if *count > 1 => {
+                    *count -= 1;
+                    return;
+                }
+                Some(_) => {
+                    connections.remove(&ip);
+                }
+                None => return,
+            }
+        }
+        self.grace.lock().unwrap().remove(&ip);
+        info!("Client {} disconnected.", ip);
+        if self.notify {
+            notifications::notify(
+                "Weylus: Client disconnected",
+                &format!("{} disconnected.", ip),
+            );
+        }
+    }
+
+    // input of new clients is only accepted after the grace period
+    fn input_blocked(&self, ip: IpAddr) -> bool {
+        match self.grace.lock().unwrap().get(&ip) {
+            Some(until) => Instant::now() < *until,
+            None => false,
+        }
+    }
+
+    // close all connections of the client and refuse its connections until Weylus is restarted
+    fn reject(&self, ip: IpAddr) {
+        if !self.input_blocked(ip) {
+            info!("Not rejecting {}, its grace period is over.", ip);
+            return;
+        }
+        info!("Rejecting client {}.", ip);
+        self.rejected.lock().unwrap().insert(ip);
+        for (addr, client) in self.clients.lock().unwrap().iter() {
+            if addr.ip() == ip {
+                disconnect(client);
+            }
+        }
+    }
+}
+
+// how long the stream handler of a broken connection is kept for the client to resume its session,
+// this keeps the encoder running and the state of the input devices, e.g. a pressed pen
+const SESSION_TIMEOUT: Duration = Duration::from_secs(30);
+const MAX_SESSION_TOKEN_LENGTH: usize = 64;
+const IDLE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+// clients only send small JSON messages, larger ones are refused before allocating memory for them
+const MAX_FRAME_SIZE: u64 = 256 * 1024;
+const MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+const MAX_JSON_DEPTH: usize = 16;
+
+// a connection of a client after the websocket handshake
+struct Connection {
+    receiver: Reader<TcpStream>,
+    sender: WsWriter,
+    peer_addr: SocketAddr,
+    // only used for the access log
+    user_agent: Option<String>,
+    port: u16,
+    // identifies the device of the client across sessions, see --require-approval
+    device: Option<String>,
+    // password from the Authorization header of the handshake, see --basic-auth
+    basic_password: Option<String>,
+    // set once the client authenticated with the view only password
+    view_only: bool,
+    // frames of a fragmented message received so far
+    fragments: Vec<DataFrame>,
+}
+
+impl Connection {
+    // read the next message, unlike the reader of the websocket crate this enforces the limits
+    // above, so clients can not make the server allocate huge buffers or parse deeply nested JSON
+    fn recv_message(&mut self) -> WebSocketResult<OwnedMessage> {
+        let stream = &mut self.receiver.stream;
+        loop {
+            let header = read_header(stream)?;
+            if header.len > MAX_FRAME_SIZE {
+                return Err(WebSocketError::ProtocolError("Frame too large"));
+            }
+            let mut body = Vec::with_capacity(header.len as usize);
+            if (stream.take(header.len).read_to_end(&mut body)? as u64) < header.len {
+                return Err(WebSocketError::NoDataAvailable);
+            }
+            let frame = DataFrame::read_dataframe_body(header, body, true)?;
+            // control frames may arrive between the fragments of a message
+            if frame.opcode as u8 >= 8 {
+                return OwnedMessage::from_dataframes(vec![frame]);
+            }
+            let continuation = frame.opcode == Opcode::Continuation;
+            if continuation == self.fragments.is_empty() {
+                return Err(WebSocketError::ProtocolError(
+                    "Unexpected continuation frame",
+                ));
+            }
+            let size: usize = self.fragments.iter().map(|f| f.data.len()).sum();
+            if size + frame.data.len() > MAX_MESSAGE_SIZE {
+                return Err(WebSocketError::ProtocolError("Message too large"));
+            }
+            let finished = frame.finished;
+            self.fragments.push(frame);
+            if finished {
+                break;
+            }
+        }
+        match OwnedMessage::from_dataframes(std::mem::take(&mut self.fragments))? {
+            OwnedMessage::Binary(_) => {
+                Err(WebSocketError::ProtocolError("Unexpected binary message"))
+            }
+            OwnedMessage::Text(text) if json_depth(&text) > MAX_JSON_DEPTH => {
+                Err(WebSocketError::ProtocolError("JSON nested too deeply"))
+            }
+            message => Ok(message),
+        }
+    }
+
+    fn log_access(&self, outcome: &str) {
+        access_log::websocket(
+            self.peer_addr,
+            self.port,
+            self.user_agent.as_deref(),
+            outcome,
+        );
+    }
+}
+
+// a session is served by a single thread, connections of a client resuming the session are handed
+// over to that thread
+struct Session {
+    connections: mpsc::Sender<Connection>,
+    // the connection currently in use
+    sender: WsWriter,
+}
+
+type Sessions = Arc<Mutex<HashMap<String, Session>>>;
+
+// ask for the servers to be stopped once no client has been connected for the idle timeout
+fn watch_idle(
+    config: &Config,
+    clients: &Clients,
+    shutdown: &Arc<AtomicBool>,
+    sender: &mpsc::Sender<Ws2GuiMessage>,
+) {
+    let timeout = match config.idle_timeout {
+        Some(timeout) => timeout,
+        None => return,
+    };
+    let clients = clients.clone();
+    let shutdown = shutdown.clone();
+    let sender = sender.clone();
+    spawn(move || {
+        let mut idle_since = Instant::now();
+        while !shutdown.load(Ordering::Relaxed) {
+            std::thread::sleep(IDLE_CHECK_INTERVAL);
+            if !clients.lock().unwrap().is_empty() {
+                idle_since = Instant::now();
+            } else if idle_since.elapsed() >= timeout {
+                info!(
+                    "No client connected for {} minutes, stopping.",
+                    timeout.as_secs() / 60
+                );
+                sender.send(Ws2GuiMessage::IdleTimeout).ok();
+                return;
+            }
+        }
+    });
+}
+
+fn handle_gui_messages(
+    receiver: mpsc::Receiver<Gui2WsMessage>,
+    clients: Clients,
+    shutdown: Arc<AtomicBool>,
+) {
+    loop {
+        match receiver.recv() {
+            Err(_) | Ok(Gui2WsMessage::Shutdown) => {
+                let clients = clients.lock().unwrap();
+                for client in clients.values() {
+                    disconnect(client);
+                }
+                shutdown.store(true, Ordering::Relaxed);
+                return;
+            }
+            Ok(Gui2WsMessage::Disconnect(ip)) => {
+                info!("Disconnecting {}.", ip);
+                let clients = clients.lock().unwrap();
+                for (addr, client) in clients.iter() {
+                    if addr.ip() != ip {
+                        continue;
+                    }
+                    disconnect(client);
+                }
+            }
+        }
+    }
+}
+
+// close the connection of a client, the close message tells it not to resume its session
+fn disconnect(client: &WsWriter) {
+    let mut client = client.lock().unwrap();
+    let close = OwnedMessage::Close(Some(CloseData::new(1000, "Disconnected".into())));
+    client.send_message(&close).ok();
+    if let Err(err) = client.shutdown_all() {
+        error!("Could not shutdown websocket: {}", err);
+    }
+}
+
 fn create_screen_stream_handler(
     update_interval: Duration,
-) -> Result<ScreenStreamHandler<ScreenCaptureGeneric>, Box<dyn std::error::Error>> {
+    encoder_options: EncoderOptions,
+    stream_state: Arc<StreamState>,
+    view: Arc<ClientView>,
+    stats: StatsReporter,
+) -> Result<
+    ScreenStreamHandler<RotatedScreenCapture<ScreenCaptureGeneric>>,
+    Box<dyn std::error::Error>,
+> {
     Ok(ScreenStreamHandler::new(
-        ScreenCaptureGeneric::new(),
+        RotatedScreenCapture::new(
+            ScreenCaptureGeneric::new(encoder_options.yuv_coefficients()),
+            view.rotation.clone(),
+        ),
         update_interval,
+        encoder_options,
+        stream_state,
+        view,
+        stats,
     ))
 }
 
-fn listen_websocket<T, F>(
+// all clients of the video socket watch the same stream
+fn listen_shared_video(
     addr: SocketAddr,
-    password: Option<String>,
-    clients: Arc<Mutex<HashMap<SocketAddr, Arc<Mutex<Writer<TcpStream>>>>>>,
+    tcp_options: TcpOptions,
+    access: Arc<ClientAccess>,
+    clients: Clients,
     shutdown: Arc<AtomicBool>,
-    _sender: mpsc::Sender<Ws2GuiMessage>,
+    sender: mpsc::Sender<Ws2GuiMessage>,
+    stats_sender: mpsc::Sender<Ws2GuiMessage>,
+    stream: Arc<SharedStream>,
+) {
+    listen_websocket(
+        addr,
+        tcp_options,
+        access,
+        clients,
+        shutdown,
+        sender,
+        move |client_addr, _| {
+            Ok(SharedStreamHandler::new(
+                stream.clone(),
+                StatsReporter::new(*client_addr, stats_sender.clone()),
+            ))
+        },
+    )
+}
+
+pub fn listen_websocket<T, F>(
+    addr: SocketAddr,
+    tcp_options: TcpOptions,
+    access: Arc<ClientAccess>,
+    clients: Clients,
+    shutdown: Arc<AtomicBool>,
+    sender: mpsc::Sender<Ws2GuiMessage>,
     create_stream_handler: F,
 ) where
     T: StreamHandler,
-    F: Fn(&SocketAddr) -> Result<T, Box<dyn std::error::Error>> + Send + 'static + Clone,
+    // called with the address and the session token of the client
+    F: Fn(&SocketAddr, Option<&str>) -> Result<T, Box<dyn std::error::Error>>
+        + Send
+        + 'static
+        + Clone,
 {
     let server = Server::bind(addr);
     if let Err(err) = server {
@@ -302,6 +1068,7 @@ fn listen_websocket<T, F>(
             err
         );
     }
+    let sessions: Sessions = Default::default();
 
     loop {
         std::thread::sleep(std::time::Duration::from_millis(10));
@@ -310,20 +1077,45 @@ fn listen_websocket<T, F>(
             return;
         }
         let clients = clients.clone();
-        let password = password.clone();
+        let sender = sender.clone();
+        let access = access.clone();
+        let shutdown = shutdown.clone();
+        let sessions = sessions.clone();
         let create_stream_handler = create_stream_handler.clone();
         match server.accept() {
             Ok(request) => {
                 spawn(move || {
+                    let session = token_param(&request.uri(), "session");
+                    let device = token_param(&request.uri(), "device");
+                    let user_agent = request
+                        .request
+                        .headers
+                        .get::<websocket::header::UserAgent>()
+                        .map(|user_agent| user_agent.0.clone());
+                    let basic_password = request
+                        .request
+                        .headers
+                        .get::<websocket::header::Authorization<websocket::header::Basic>>()
+                        .and_then(|auth| auth.0.password.clone());
+                    let request_addr = request.stream.peer_addr();
                     let client = request.accept();
                     if let Err((_, err)) = client {
                         warn!("Failed to accept client: {}", err);
+                        if let Ok(request_addr) = request_addr {
+                            access_log::websocket(
+                                request_addr,
+                                addr.port(),
+                                user_agent.as_deref(),
+                                "handshake failed",
+                            );
+                        }
                         return;
                     }
                     let client = client.unwrap();
                     if let Err(err) = client.set_nonblocking(false) {
                         warn!("Failed to set client to blocking mode: {}", err);
                     }
+                    tcp_options.apply(client.stream_ref());
                     let peer_addr = client.peer_addr();
                     if let Err(err) = peer_addr {
                         warn!("Failed to retrieve client address: {}", err);
@@ -335,64 +1127,85 @@ fn listen_websocket<T, F>(
                         warn!("Failed to setup connection: {}", err);
                         return;
                     }
-                    let (mut ws_receiver, ws_sender) = client.unwrap();
+                    let (ws_receiver, ws_sender) = client.unwrap();
+                    let mut connection = Connection {
+                        receiver: ws_receiver,
+                        sender: Arc::new(Mutex::new(ws_sender)),
+                        peer_addr,
+                        user_agent,
+                        port: addr.port(),
+                        device,
+                        basic_password,
+                        view_only: false,
+                        fragments: Vec::new(),
+                    };
+                    connection.log_access("connected");
 
-                    let ws_sender = Arc::new(Mutex::new(ws_sender));
+                    // the session token alone must not allow to take over a session, anyone
+                    // seeing the URL knows it
+                    if !authenticate(&mut connection, &access, &clients, &sender) {
+                        return;
+                    }
+                    if let Some(token) = &session {
+                        match resume_session(&sessions, token, connection) {
+                            Ok(()) => return,
+                            Err(c) => connection = c,
+                        }
+                    }
 
-                    let stream_handler = create_stream_handler(&peer_addr);
+                    let stream_handler = create_stream_handler(&peer_addr, session.as_deref());
                     if let Err(err) = stream_handler {
                         error!("Failed to create stream handler: {}", err);
                         return;
                     }
+                    let mut stream_handler = stream_handler.unwrap();
 
-                    {
-                        let mut clients = clients.lock().unwrap();
-                        clients.insert(peer_addr, ws_sender.clone());
+                    let (connections_sender, connections) = mpsc::channel();
+                    if let Some(token) = &session {
+                        sessions.lock().unwrap().insert(
+                            token.clone(),
+                            Session {
+                                connections: connections_sender,
+                                sender: connection.sender.clone(),
+                            },
+                        );
                     }
 
-                    let mut authed = password.is_none();
-                    let password = password.unwrap_or_else(|| "".into());
-                    let mut stream_handler = stream_handler.unwrap();
-                    for msg in ws_receiver.incoming_messages() {
-                        match msg {
-                            Ok(msg) => {
-                                if !authed {
-                                    if let OwnedMessage::Text(pw) = &msg {
-                                        if pw == &password {
-                                            authed = true;
-                                        } else {
-                                            warn!(
-                                                "Authentication failed: {} sent wrong password: '{}'",
-                                                peer_addr, pw
-                                            );
-                                            let mut clients = clients.lock().unwrap();
-                                            clients.remove(&peer_addr);
-                                            return;
-                                        }
-                                    }
-                                } else {
-                                    stream_handler.process(ws_sender.clone(), &msg);
-                                }
-                                if msg.is_close() {
-                                    let mut clients = clients.lock().unwrap();
-                                    clients.remove(&peer_addr);
-                                    return;
-                                }
+                    loop {
+                        let end = serve_connection(
+                            &mut stream_handler,
+                            &mut connection,
+                            &access,
+                            &clients,
+                            &sender,
+                        );
+                        let token = match &session {
+                            Some(token) => token,
+                            None => return,
+                        };
+                        let timeout = match end {
+                            ConnectionEnd::Lost if !shutdown.load(Ordering::Relaxed) => {
+                                info!(
+                                    "Lost connection to {}, keeping its session for {} s.",
+                                    connection.peer_addr,
+                                    SESSION_TIMEOUT.as_secs()
+                                );
+                                SESSION_TIMEOUT
                             }
-                            Err(err) => {
-                                match err {
-                                    // this happens on calling shutdown, no need to log this
-                                    websocket::WebSocketError::NoDataAvailable => (),
-                                    _ => warn!(
-                                        "Error reading message from websocket, closing ({})",
-                                        err
-                                    ),
-                                }
-
-                                let mut clients = clients.lock().unwrap();
-                                clients.remove(&peer_addr);
-                                return;
+                            // the client may have resumed the session already
+                            _ => Duration::from_secs(0),
+                        };
+                        match wait_for_resume(&sessions, token, &connections, timeout) {
+                            Some(resumed) => {
+                                info!(
+                                    "{} resumed the session of {}.",
+                                    resumed.peer_addr, connection.peer_addr
+                                );
+                                connection = resumed;
+                                connection.log_access("resumed session");
+                                stream_handler.resume(&connection.sender);
                             }
+                            None => return,
                         }
                     }
                 });
@@ -405,3 +1218,287 @@ fn listen_websocket<T, F>(
         };
     }
 }
+
+// why a connection is not served anymore
+enum ConnectionEnd {
+    // closed by the client or the server or the client failed to authenticate
+    Closed,
+    // the connection broke, the client may resume its session on a new one
+    Lost,
+}
+
+// remove a client that is not served anymore, only authenticated clients count as connected
+fn remove_client(
+    connection: &Connection,
+    access: &Arc<ClientAccess>,
+    clients: &Clients,
+    sender: &mpsc::Sender<Ws2GuiMessage>,
+    authed: bool,
+    outcome: &str,
+) {
+    clients.lock().unwrap().remove(&connection.peer_addr);
+    if authed {
+        access.disconnected(connection.peer_addr);
+        sender
+            .send(Ws2GuiMessage::Disconnected(connection.peer_addr))
+            .ok();
+    }
+    connection.log_access(outcome);
+}
+
+// wait for the client to authenticate, messages before the password are ignored, returns false if
+// the connection has been closed
+fn authenticate(
+    connection: &mut Connection,
+    access: &Arc<ClientAccess>,
+    clients: &Clients,
+    sender: &mpsc::Sender<Ws2GuiMessage>,
+) -> bool {
+    let peer_addr = connection.peer_addr;
+    clients
+        .lock()
+        .unwrap()
+        .insert(peer_addr, connection.sender.clone());
+    // the credentials of HTTP basic authentication are as good as the password sent as message
+    let mut view_only = match &access.password {
+        None => Some(false),
+        Some(_) => connection
+            .basic_password
+            .as_deref()
+            .and_then(|pw| access.check_password(pw)),
+    };
+    while view_only.is_none() {
+        let msg = match connection.recv_message() {
+            Ok(msg) => msg,
+            Err(err) => {
+                match err {
+                    WebSocketError::NoDataAvailable => (),
+                    WebSocketError::ProtocolError(reason) => {
+                        warn!("Closing connection to {}: {}.", peer_addr, reason);
+                        disconnect(&connection.sender);
+                    }
+                    _ => warn!("Error reading message from websocket, closing ({})", err),
+                }
+                remove_client(connection, access, clients, sender, false, "closed");
+                return false;
+            }
+        };
+        match &msg {
+            OwnedMessage::Text(pw) => match access.check_password(pw) {
+                Some(v) => view_only = Some(v),
+                None => {
+                    // the offered password is left out, it might be a mistyped real one and the
+                    // log ends up in bundles handed out for bug reports
+                    warn!("Authentication failed: {} sent wrong password.", peer_addr);
+                    remove_client(
+                        connection,
+                        access,
+                        clients,
+                        sender,
+                        false,
+                        "authentication failed",
+                    );
+                    return false;
+                }
+            },
+            _ if msg.is_close() => {
+                remove_client(connection, access, clients, sender, false, "closed");
+                return false;
+            }
+            _ => (),
+        }
+    }
+    if !access.connected(peer_addr, connection.device.as_deref()) {
+        remove_client(connection, access, clients, sender, false, "rejected");
+        disconnect(&connection.sender);
+        return false;
+    }
+    connection.view_only = view_only.unwrap();
+    sender.send(Ws2GuiMessage::Connected(peer_addr)).ok();
+    if access.password.is_some() {
+        connection.log_access(if connection.view_only {
+            "authenticated (view only)"
+        } else {
+            "authenticated"
+        });
+    }
+    true
+}
+
+// pass the messages of an authenticated client to the stream handler until the connection ends
+fn serve_connection<T: StreamHandler>(
+    stream_handler: &mut T,
+    connection: &mut Connection,
+    access: &Arc<ClientAccess>,
+    clients: &Clients,
+    sender: &mpsc::Sender<Ws2GuiMessage>,
+) -> ConnectionEnd {
+    let peer_addr = connection.peer_addr;
+    let ws_sender = connection.sender.clone();
+    loop {
+        match connection.recv_message() {
+            Ok(msg) => {
+                stream_handler
+                    .block_input(connection.view_only || access.input_blocked(peer_addr.ip()));
+                stream_handler.process(ws_sender.clone(), &msg);
+                if msg.is_close() {
+                    remove_client(connection, access, clients, sender, true, "closed");
+                    return ConnectionEnd::Closed;
+                }
+            }
+            Err(err) => {
+                match err {
+                    // this happens on calling shutdown, no need to log this
+                    WebSocketError::NoDataAvailable => {
+                        remove_client(connection, access, clients, sender, true, "closed");
+                        return ConnectionEnd::Closed;
+                    }
+                    // the client is misbehaving, reconnecting will not help
+                    WebSocketError::ProtocolError(reason) => {
+                        warn!("Closing connection to {}: {}.", peer_addr, reason);
+                        remove_client(connection, access, clients, sender, true, "protocol error");
+                        disconnect(&ws_sender);
+                        return ConnectionEnd::Closed;
+                    }
+                    _ => warn!("Error reading message from websocket, closing ({})", err),
+                }
+                remove_client(connection, access, clients, sender, true, "connection lost");
+                return ConnectionEnd::Lost;
+            }
+        }
+    }
+}
+
+// nesting depth of the arrays and objects of a JSON document
+fn json_depth(json: &str) -> usize {
+    let (mut depth, mut max_depth) = (0usize, 0);
+    let (mut in_string, mut escaped) = (false, false);
+    for c in json.bytes() {
+        if in_string {
+            match c {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => (),
+            }
+            continue;
+        }
+        match c {
+            b'"' => in_string = true,
+            b'[' | b'{' => {
+                depth += 1;
+                max_depth = max_depth.max(depth);
+            }
+            b']' | b'}' => depth = depth.saturating_sub(1),
+            _ => (),
+        }
+    }
+    max_depth
+}
+
+// the client passes the token of its session and of its device in the query of the websocket URL:
+// ?session=TOKEN&device=TOKEN
+fn token_param(uri: &str, name: &str) -> Option<String> {
+    let (_, query) = uri.split_once('?')?;
+    query
+        .split('&')
+        .filter_map(|param| param.strip_prefix(name)?.strip_prefix('='))
+        .find(|token| {
+            !token.is_empty()
+                && token.len() <= MAX_SESSION_TOKEN_LENGTH
+                && token.chars().all(|c| c.is_ascii_alphanumeric())
+        })
+        .map(String::from)
+}
+
+// hand the authenticated connection to the thread serving the session, the connection is returned
+// if there is no such session
+fn resume_session(
+    sessions: &Sessions,
+    token: &str,
+    connection: Connection,
+) -> Result<(), Connection> {
+    let old_sender = {
+        let mut sessions = sessions.lock().unwrap();
+        let session = match sessions.get_mut(token) {
+            Some(session) => session,
+            None => return Err(connection),
+        };
+        let sender = connection.sender.clone();
+        if let Err(mpsc::SendError(connection)) = session.connections.send(connection) {
+            sessions.remove(token);
+            return Err(connection);
+        }
+        std::mem::replace(&mut session.sender, sender)
+    };
+    // the old connection may still seem to be alive if it broke without the server noticing
+    old_sender.lock().unwrap().shutdown_all().ok();
+    Ok(())
+}
+
+// wait for the client to resume its session on a new connection, if it does not the session ends
+fn wait_for_resume(
+    sessions: &Sessions,
+    token: &str,
+    connections: &mpsc::Receiver<Connection>,
+    timeout: Duration,
+) -> Option<Connection> {
+    if let Ok(connection) = connections.recv_timeout(timeout) {
+        return Some(connection);
+    }
+    // connections are handed over while holding the lock, so none can get lost here
+    let mut sessions = sessions.lock().unwrap();
+    let connection = connections.try_recv().ok();
+    if connection.is_none() {
+        sessions.remove(token);
+    }
+    connection
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn json_depth_counts_nesting() {
+        assert_eq!(json_depth(r#""Resume""#), 0);
+        assert_eq!(json_depth(r#"{"Pause":true}"#), 1);
+        assert_eq!(json_depth(r#"{"PointerEvents":[{"x":1},{"y":[2]}]}"#), 4);
+        assert_eq!(json_depth(&"[".repeat(100)), 100);
+        // brackets in strings do not count, neither do escaped quotes end them
+        assert_eq!(json_depth(r#"{"Shortcut":"[[[{{{"}"#), 1);
+        assert_eq!(json_depth(r#"{"Shortcut":"\"[[[\\"}"#), 1);
+        assert_eq!(json_depth(r#"{"Shortcut":"\\"}[["#), 2);
+        // superfluous closing brackets do not hide nesting that follows
+        assert_eq!(json_depth("]]]]{{"), 2);
+    }
+
+    #[test]
+    fn token_param_parses_query() {
+        let uri = "/?session=abc123&device=DEF456";
+        assert_eq!(token_param(uri, "session").as_deref(), Some("abc123"));
+        assert_eq!(token_param(uri, "device").as_deref(), Some("DEF456"));
+        assert_eq!(token_param(uri, "other"), None);
+        assert_eq!(token_param("/", "session"), None);
+        assert_eq!(token_param("/?sessions=abc", "session"), None);
+        assert_eq!(token_param("/?session", "session"), None);
+        // the first valid token counts
+        assert_eq!(
+            token_param("/?session=&session=a-b&session=xyz", "session").as_deref(),
+            Some("xyz")
+        );
+    }
+
+    #[test]
+    fn token_param_rejects_invalid_tokens() {
+        assert_eq!(token_param("/?session=", "session"), None);
+        assert_eq!(token_param("/?session=a%20b", "session"), None);
+        assert_eq!(token_param("/?session=../x", "session"), None);
+        assert_eq!(token_param("/?session=t\u{e4}st", "session"), None);
+        let longest = "a".repeat(MAX_SESSION_TOKEN_LENGTH);
+        let uri = format!("/?session={}", longest);
+        assert_eq!(token_param(&uri, "session"), Some(longest));
+        let uri = format!("/?session={}a", "a".repeat(MAX_SESSION_TOKEN_LENGTH));
+        assert_eq!(token_param(&uri, "session"), None);
+    }
+}
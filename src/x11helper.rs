@@ -1,7 +1,12 @@
 use std::ffi::{CStr, CString};
 use std::fmt;
-use std::os::raw::{c_char, c_float, c_int, c_void};
+use std::os::raw::{c_char, c_float, c_int, c_uint, c_ulong, c_ushort, c_void};
+#[cfg(not(feature = "gui"))]
+use std::sync::atomic::AtomicBool;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
 
+use serde::{Deserialize, Serialize};
 use tracing::{debug, trace};
 
 use crate::cerror::CError;
@@ -9,6 +14,19 @@ use crate::cerror::CError;
 extern "C" {
     fn XOpenDisplay(name: *const c_char) -> *mut c_void;
     fn XCloseDisplay(disp: *mut c_void) -> c_int;
+    fn XQueryExtension(
+        disp: *mut c_void,
+        name: *const c_char,
+        major_opcode: *mut c_int,
+        first_event: *mut c_int,
+        first_error: *mut c_int,
+    ) -> c_int;
+    fn XResetScreenSaver(disp: *mut c_void) -> c_int;
+    fn XForceScreenSaver(disp: *mut c_void, mode: c_int) -> c_int;
+    fn XFlush(disp: *mut c_void) -> c_int;
+
+    fn DPMSCapable(disp: *mut c_void) -> c_int;
+    fn DPMSForceLevel(disp: *mut c_void, level: c_ushort) -> c_int;
 
     fn create_capturables(
         disp: *mut c_void,
@@ -20,7 +38,18 @@ extern "C" {
     fn clone_capturable(handle: *const c_void) -> *mut c_void;
     fn destroy_capturable(handle: *mut c_void);
     fn get_capturable_name(handle: *const c_void) -> *const c_char;
+    fn get_capturable_class(handle: *const c_void) -> *const c_char;
+    fn get_capturable_pid(handle: *const c_void) -> c_ulong;
     fn capturable_before_input(handle: *mut c_void, err: *mut CError);
+    fn capturable_is_visible_window(handle: *const c_void) -> c_int;
+    fn get_geometry(
+        handle: *const c_void,
+        x: *mut c_int,
+        y: *mut c_int,
+        width: *mut c_uint,
+        height: *mut c_uint,
+        err: *mut CError,
+    );
     fn get_geometry_relative(
         handle: *const c_void,
         x: *mut c_float,
@@ -38,6 +67,44 @@ extern "C" {
     );
 }
 
+/// The GUI uses Xlib as well, so calls are serialized with it via the lock of FLTK. Has to be
+/// paired with unlock_x11.
+#[cfg(feature = "gui")]
+pub fn lock_x11() {
+    fltk::app::lock().unwrap();
+}
+
+#[cfg(feature = "gui")]
+pub fn unlock_x11() {
+    fltk::app::unlock();
+}
+
+// without GUI calls from different threads are still serialized like FLTK's lock would do
+#[cfg(not(feature = "gui"))]
+static X11_LOCKED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(not(feature = "gui"))]
+pub fn lock_x11() {
+    while X11_LOCKED
+        .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+        .is_err()
+    {
+        std::thread::yield_now();
+    }
+}
+
+#[cfg(not(feature = "gui"))]
+pub fn unlock_x11() {
+    X11_LOCKED.store(false, Ordering::Release);
+}
+
+/// Whether an X11 or Wayland display is set in the environment.
+pub fn display_server_available() -> bool {
+    ["DISPLAY", "WAYLAND_DISPLAY"]
+        .iter()
+        .any(|var| std::env::var_os(var).map_or(false, |value| !value.is_empty()))
+}
+
 pub struct Capturable {
     handle: *mut c_void,
 }
@@ -64,13 +131,63 @@ impl Capturable {
         }
     }
 
+    /// WM_CLASS of a window, empty for screens.
+    pub fn class(&self) -> String {
+        unsafe {
+            CStr::from_ptr(get_capturable_class(self.handle))
+                .to_string_lossy()
+                .into()
+        }
+    }
+
+    /// Id of the process that created a window, None if unknown and for screens.
+    pub fn pid(&self) -> Option<u32> {
+        match unsafe { get_capturable_pid(self.handle) } {
+            0 => None,
+            pid => Some(pid as u32),
+        }
+    }
+
+    /// Whether this is a window that is currently shown, false for screens.
+    pub fn is_visible_window(&self) -> bool {
+        lock_x11();
+        let visible = unsafe { capturable_is_visible_window(self.handle) };
+        unlock_x11();
+        visible != 0
+    }
+
+    /// Position and size in pixels: x, y, width, height
+    pub fn pixel_geometry(&self) -> Result<(i32, i32, u32, u32), CError> {
+        let mut x: c_int = 0;
+        let mut y: c_int = 0;
+        let mut width: c_uint = 0;
+        let mut height: c_uint = 0;
+        let mut err = CError::new();
+        lock_x11();
+        unsafe {
+            get_geometry(
+                self.handle,
+                &mut x,
+                &mut y,
+                &mut width,
+                &mut height,
+                &mut err,
+            );
+        }
+        unlock_x11();
+        if err.is_err() {
+            return Err(err);
+        }
+        Ok((x, y, width, height))
+    }
+
     pub fn geometry(&self) -> Result<CaptureGeometry, CError> {
         let mut x: c_float = 0.0;
         let mut y: c_float = 0.0;
         let mut width: c_float = 0.0;
         let mut height: c_float = 0.0;
         let mut err = CError::new();
-        fltk::app::lock().unwrap();
+        lock_x11();
         unsafe {
             get_geometry_relative(
                 self.handle,
@@ -81,7 +198,7 @@ impl Capturable {
                 &mut err,
             );
         }
-        fltk::app::unlock();
+        unlock_x11();
         if err.is_err() {
             return Err(err);
         }
@@ -95,9 +212,9 @@ impl Capturable {
 
     pub fn before_input(&mut self) -> Result<(), CError> {
         let mut err = CError::new();
-        fltk::app::lock().unwrap();
+        lock_x11();
         unsafe { capturable_before_input(self.handle, &mut err) };
-        fltk::app::unlock();
+        unlock_x11();
         if err.is_err() {
             Err(err)
         } else {
@@ -120,6 +237,110 @@ impl Drop for Capturable {
     }
 }
 
+/// Identifies a capturable across restarts of Weylus and of the captured application: the title
+/// of a window or the name of a screen, e.g. "Monitor: HDMI-1", and the WM_CLASS, process id and
+/// geometry of windows.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct CapturableId {
+    pub name: String,
+    #[serde(default)]
+    pub class: String,
+    #[serde(default)]
+    pub pid: Option<u32>,
+    // x, y, width, height in pixels
+    #[serde(default)]
+    pub geometry: Option<(i32, i32, u32, u32)>,
+}
+
+impl CapturableId {
+    pub fn new(capturable: &Capturable) -> Self {
+        Self {
+            name: capturable.name(),
+            class: capturable.class(),
+            pid: capturable.pid(),
+            geometry: capturable.pixel_geometry().ok(),
+        }
+    }
+
+    /// Index of the capturable this identifies. Screens are matched by name. Windows are matched
+    /// by their class, if there are several windows of the class the one with the same title is
+    /// preferred, then the one of the same process and then the one closest in geometry. This way
+    /// a window is found even if the application recreated it or has been restarted.
+    pub fn find(&self, capturables: &[Capturable]) -> Option<usize> {
+        if self.class.is_empty() {
+            return capturables
+                .iter()
+                .position(|c| c.class().is_empty() && c.name() == self.name);
+        }
+        capturables
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| c.class() == self.class)
+            .min_by_key(|(_, c)| {
+                (
+                    c.name() != self.name,
+                    self.pid.is_none() || c.pid() != self.pid,
+                    self.geometry_distance(c),
+                )
+            })
+            .map(|(i, _)| i)
+    }
+
+    fn geometry_distance(&self, capturable: &Capturable) -> u64 {
+        match (self.geometry, capturable.pixel_geometry()) {
+            (Some((x1, y1, w1, h1)), Ok((x2, y2, w2, h2))) => {
+                let d = |a: i64, b: i64| (a - b).abs() as u64;
+                d(x1.into(), x2.into())
+                    + d(y1.into(), y2.into())
+                    + d(w1.into(), w2.into())
+                    + d(h1.into(), h2.into())
+            }
+            _ => u64::MAX,
+        }
+    }
+}
+
+/// The capturable the servers capture, shared between the video and the input devices so that
+/// another capturable can be chosen while clients are connected. Every switch increases the
+/// generation, this way the users notice a switch without locking the capturable all the time.
+pub struct CaptureTarget {
+    capturable: Mutex<Capturable>,
+    generation: AtomicUsize,
+}
+
+impl CaptureTarget {
+    pub fn new(capturable: Capturable) -> Arc<Self> {
+        Arc::new(Self {
+            capturable: Mutex::new(capturable),
+            generation: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn set(&self, capturable: Capturable) {
+        let mut current = self.capturable.lock().unwrap();
+        *current = capturable;
+        self.generation.fetch_add(1, Ordering::SeqCst);
+    }
+
+    /// The current capturable and its generation.
+    pub fn get(&self) -> (Capturable, usize) {
+        let capturable = self.capturable.lock().unwrap();
+        (capturable.clone(), self.generation.load(Ordering::SeqCst))
+    }
+
+    /// Replace capture with the current capturable if it is not of the given generation anymore,
+    /// returns true if it has been replaced.
+    pub fn update(&self, capture: &mut Capturable, generation: &mut usize) -> bool {
+        if self.generation.load(Ordering::SeqCst) == *generation {
+            return false;
+        }
+        let (capturable, current) = self.get();
+        *capture = capturable;
+        *generation = current;
+        true
+    }
+}
+
 pub struct CaptureGeometry {
     pub x: f64,
     pub y: f64,
@@ -143,7 +364,7 @@ impl X11Context {
     pub fn capturables(&mut self) -> Result<Vec<Capturable>, CError> {
         let mut err = CError::new();
         let mut handles = [std::ptr::null_mut::<c_void>(); 128];
-        fltk::app::lock().unwrap();
+        lock_x11();
         let size = unsafe {
             create_capturables(
                 self.disp,
@@ -152,7 +373,7 @@ impl X11Context {
                 &mut err,
             )
         };
-        fltk::app::unlock();
+        unlock_x11();
         if err.is_err() {
             if err.code() == 2 {
                 debug!("{}", err);
@@ -166,12 +387,51 @@ impl X11Context {
             .collect::<Vec<Capturable>>())
     }
 
+    /// Reset the idle timer of the X server, this keeps the screensaver from starting and the
+    /// display from being blanked via DPMS for a while. If wake is set a screensaver that is
+    /// running already is stopped and a blanked display switched on.
+    pub fn reset_screensaver(&mut self, wake: bool) {
+        // ScreenSaverReset and DPMSModeOn
+        const SCREEN_SAVER_RESET: c_int = 0;
+        const DPMS_MODE_ON: c_ushort = 0;
+        let dpms = self.has_extension("DPMS");
+        lock_x11();
+        unsafe {
+            XResetScreenSaver(self.disp);
+            if wake {
+                XForceScreenSaver(self.disp, SCREEN_SAVER_RESET);
+                if dpms && DPMSCapable(self.disp) != 0 {
+                    DPMSForceLevel(self.disp, DPMS_MODE_ON);
+                }
+            }
+            XFlush(self.disp);
+        }
+        unlock_x11();
+    }
+
+    pub fn has_extension(&self, name: &str) -> bool {
+        let name_c_str = CString::new(name).unwrap();
+        let (mut major_opcode, mut first_event, mut first_error) = (0, 0, 0);
+        lock_x11();
+        let present = unsafe {
+            XQueryExtension(
+                self.disp,
+                name_c_str.as_ptr(),
+                &mut major_opcode,
+                &mut first_event,
+                &mut first_error,
+            )
+        };
+        unlock_x11();
+        present != 0
+    }
+
     pub fn map_input_device_to_entire_screen(
         &mut self,
         device_name: &str,
         pen: bool,
     ) -> CError {
-        fltk::app::lock().unwrap();
+        lock_x11();
         let mut err = CError::new();
         let device_name_c_str = CString::new(device_name).unwrap();
         unsafe {
@@ -182,7 +442,7 @@ impl X11Context {
                 &mut err,
             )
         };
-        fltk::app::unlock();
+        unlock_x11();
         if err.is_err() {
             trace!("Failed to map input device to screen: {}", &err);
         }
@@ -192,8 +452,8 @@ impl X11Context {
 
 impl Drop for X11Context {
     fn drop(&mut self) {
-        fltk::app::lock().unwrap();
+        lock_x11();
         unsafe { XCloseDisplay(self.disp) };
-        fltk::app::unlock();
+        unlock_x11();
     }
 }
@@ -0,0 +1,511 @@
+//! Conversion of packed RGB images to yuv420p without going through swscale. SIMD implementations
+//! are picked at runtime if the CPU supports them, the scalar implementation handles everything
+//! else and produces exactly the same output.
+
+use std::sync::Mutex;
+
+use crate::slice_pool::SlicePool;
+use crate::video::{PixelFormat, YUVCoefficients};
+
+/// Planes of a yuv420p image the converted pixels are written to.
+pub struct YUV420PPlanes<'a> {
+    pub y: &'a mut [u8],
+    pub u: &'a mut [u8],
+    pub v: &'a mut [u8],
+    pub y_stride: usize,
+    pub u_stride: usize,
+    pub v_stride: usize,
+}
+
+// two rows of the source image and the rows of the planes they are converted to
+struct RowPair<'a> {
+    src: [&'a [u8]; 2],
+    y: [&'a mut [u8]; 2],
+    u: &'a mut [u8],
+    v: &'a mut [u8],
+}
+
+// byte offsets of red, green and blue within a pixel
+fn rgb_offsets(format: PixelFormat) -> [usize; 3] {
+    match format {
+        PixelFormat::BGRA => [2, 1, 0],
+        PixelFormat::RGBx => [0, 1, 2],
+        _ => panic!("{:?} is not a packed RGB format.", format),
+    }
+}
+
+/// Convert a tightly packed BGRA or RGBx image to yuv420p, yuv420p requires even dimensions so
+/// the last row or column is clipped if necessary.
+pub fn packed_rgb_to_yuv420p(
+    src: &[u8],
+    format: PixelFormat,
+    width: usize,
+    height: usize,
+    coefficients: &YUVCoefficients,
+    dst: &mut YUV420PPlanes,
+) {
+    let offsets = rgb_offsets(format);
+    let stride = width * 4;
+    let width = width - width % 2;
+    let height = height - height % 2;
+    for row in (0..height).step_by(2) {
+        let (y0, y1) = dst.y[row * dst.y_stride..].split_at_mut(dst.y_stride);
+        let mut rows = RowPair {
+            src: [
+                &src[row * stride..(row + 1) * stride],
+                &src[(row + 1) * stride..(row + 2) * stride],
+            ],
+            y: [y0, y1],
+            u: &mut dst.u[row / 2 * dst.u_stride..],
+            v: &mut dst.v[row / 2 * dst.v_stride..],
+        };
+        let done = convert_simd(&mut rows, width, offsets, coefficients);
+        convert_scalar(&mut rows, done, width, offsets, coefficients);
+    }
+}
+
+/// Like packed_rgb_to_yuv420p but the image is split into horizontal slices which are converted
+/// in parallel by the threads of the pool.
+pub fn packed_rgb_to_yuv420p_sliced(
+    pool: &SlicePool,
+    src: &[u8],
+    format: PixelFormat,
+    width: usize,
+    height: usize,
+    coefficients: &YUVCoefficients,
+    dst: &mut YUV420PPlanes,
+) {
+    let height = height - height % 2;
+    // every slice consists of an even number of rows
+    let slice_rows = 2 * ((height / 2 + pool.slices() - 1) / pool.slices());
+    if pool.slices() == 1 || slice_rows == 0 {
+        packed_rgb_to_yuv420p(src, format, width, height, coefficients, dst);
+        return;
+    }
+    let (y_stride, u_stride, v_stride) = (dst.y_stride, dst.u_stride, dst.v_stride);
+    let slices: Vec<_> = src
+        .chunks(slice_rows * width * 4)
+        .zip(dst.y.chunks_mut(slice_rows * y_stride))
+        .zip(dst.u.chunks_mut(slice_rows / 2 * u_stride))
+        .zip(dst.v.chunks_mut(slice_rows / 2 * v_stride))
+        .enumerate()
+        .take(pool.slices())
+        .map(|(i, (((src, y), u), v))| {
+            let planes = YUV420PPlanes {
+                y,
+                u,
+                v,
+                y_stride,
+                u_stride,
+                v_stride,
+            };
+            let rows = slice_rows.min(height.saturating_sub(i * slice_rows));
+            Mutex::new(Some((src, planes, rows)))
+        })
+        .collect();
+    pool.run(&|slice| {
+        if let Some((src, mut planes, rows)) =
+            slices.get(slice).and_then(|s| s.lock().unwrap().take())
+        {
+            packed_rgb_to_yuv420p(src, format, width, rows, coefficients, &mut planes);
+        }
+    });
+}
+
+// converts as many columns as possible with SIMD instructions and returns how many that were
+#[cfg(target_arch = "x86_64")]
+fn convert_simd(
+    rows: &mut RowPair,
+    width: usize,
+    offsets: [usize; 3],
+    c: &YUVCoefficients,
+) -> usize {
+    if is_x86_feature_detected!("avx2") {
+        unsafe { avx2::convert(rows, width, offsets, c) }
+    } else {
+        0
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+fn convert_simd(
+    rows: &mut RowPair,
+    width: usize,
+    offsets: [usize; 3],
+    c: &YUVCoefficients,
+) -> usize {
+    if std::arch::is_aarch64_feature_detected!("neon") {
+        unsafe { neon::convert(rows, width, offsets, c) }
+    } else {
+        0
+    }
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+fn convert_simd(_: &mut RowPair, _: usize, _: [usize; 3], _: &YUVCoefficients) -> usize {
+    0
+}
+
+// converts the columns from start to width, start and width have to be even
+fn convert_scalar(
+    rows: &mut RowPair,
+    start: usize,
+    width: usize,
+    offsets: [usize; 3],
+    c: &YUVCoefficients,
+) {
+    for x in (start..width).step_by(2) {
+        let mut sum = [0; 3];
+        for (src, y) in rows.src.iter().zip(rows.y.iter_mut()) {
+            for x in x..x + 2 {
+                let r = src[4 * x + offsets[0]] as i32;
+                let g = src[4 * x + offsets[1]] as i32;
+                let b = src[4 * x + offsets[2]] as i32;
+                y[x] = (((c.y[0] * r + c.y[1] * g + c.y[2] * b + 128) >> 8) + c.y_offset) as u8;
+                sum[0] += r;
+                sum[1] += g;
+                sum[2] += b;
+            }
+        }
+        let (r, g, b) = (sum[0] >> 2, sum[1] >> 2, sum[2] >> 2);
+        rows.u[x / 2] = (((128 + c.u[0] * r + c.u[1] * g + c.u[2] * b) >> 8) + 128) as u8;
+        rows.v[x / 2] = (((128 + c.v[0] * r + c.v[1] * g + c.v[2] * b) >> 8) + 128) as u8;
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+mod avx2 {
+    use std::arch::x86_64::*;
+
+    use super::RowPair;
+    use crate::video::YUVCoefficients;
+
+    // pixels per iteration, a register holds 8 pixels widened to 32 bits per channel
+    const STEP: usize = 16;
+
+    #[target_feature(enable = "avx2")]
+    unsafe fn splat(k: [i32; 3]) -> [__m256i; 3] {
+        [
+            _mm256_set1_epi32(k[0]),
+            _mm256_set1_epi32(k[1]),
+            _mm256_set1_epi32(k[2]),
+        ]
+    }
+
+    // ((k · rgb + 128) >> 8) + offset for 8 pixels
+    #[target_feature(enable = "avx2")]
+    unsafe fn weigh(rgb: &[__m256i; 3], k: &[__m256i; 3], offset: __m256i) -> __m256i {
+        let sum = _mm256_add_epi32(
+            _mm256_add_epi32(
+                _mm256_mullo_epi32(rgb[0], k[0]),
+                _mm256_mullo_epi32(rgb[1], k[1]),
+            ),
+            _mm256_add_epi32(_mm256_mullo_epi32(rgb[2], k[2]), _mm256_set1_epi32(128)),
+        );
+        _mm256_add_epi32(_mm256_srai_epi32(sum, 8), offset)
+    }
+
+    // store the lowest byte of each 32 bit lane
+    #[target_feature(enable = "avx2")]
+    unsafe fn store8(dst: &mut [u8], v: __m256i) {
+        let dst = &mut dst[..8];
+        let bytes = _mm256_shuffle_epi8(
+            v,
+            _mm256_setr_epi8(
+                0, 4, 8, 12, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, -1, 0, 4, 8, 12, -1, -1,
+                -1, -1, -1, -1, -1, -1, -1, -1, -1, -1,
+            ),
+        );
+        let bytes = _mm256_permutevar8x32_epi32(bytes, _mm256_setr_epi32(0, 4, 0, 0, 0, 0, 0, 0));
+        _mm_storel_epi64(
+            dst.as_mut_ptr() as *mut __m128i,
+            _mm256_castsi256_si128(bytes),
+        );
+    }
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn convert(
+        rows: &mut RowPair,
+        width: usize,
+        offsets: [usize; 3],
+        c: &YUVCoefficients,
+    ) -> usize {
+        let shifts = [
+            _mm_cvtsi32_si128(8 * offsets[0] as i32),
+            _mm_cvtsi32_si128(8 * offsets[1] as i32),
+            _mm_cvtsi32_si128(8 * offsets[2] as i32),
+        ];
+        let mask = _mm256_set1_epi32(0xff);
+        let (cy, cu, cv) = (splat(c.y), splat(c.u), splat(c.v));
+        let y_offset = _mm256_set1_epi32(c.y_offset);
+        let uv_offset = _mm256_set1_epi32(128);
+        // hadd works within 128 bit lanes, this restores the order of the pixels
+        let order = _mm256_setr_epi32(0, 1, 4, 5, 2, 3, 6, 7);
+        let mut x = 0;
+        while x + STEP <= width {
+            // r, g and b of the left and right 8 pixels summed over both rows
+            let mut sums = [_mm256_setzero_si256(); 6];
+            for (src, y) in rows.src.iter().zip(rows.y.iter_mut()) {
+                for half in 0..2 {
+                    let i = x + 8 * half;
+                    let pixels = src[4 * i..4 * i + 32].as_ptr() as *const __m256i;
+                    let pixels = _mm256_loadu_si256(pixels);
+                    let rgb = [
+                        _mm256_and_si256(_mm256_srl_epi32(pixels, shifts[0]), mask),
+                        _mm256_and_si256(_mm256_srl_epi32(pixels, shifts[1]), mask),
+                        _mm256_and_si256(_mm256_srl_epi32(pixels, shifts[2]), mask),
+                    ];
+                    store8(&mut y[i..], weigh(&rgb, &cy, y_offset));
+                    for (sum, channel) in sums[3 * half..3 * half + 3].iter_mut().zip(&rgb) {
+                        *sum = _mm256_add_epi32(*sum, *channel);
+                    }
+                }
+            }
+            let mut average = [_mm256_setzero_si256(); 3];
+            for (ch, average) in average.iter_mut().enumerate() {
+                let sum = _mm256_hadd_epi32(sums[ch], sums[3 + ch]);
+                *average = _mm256_srai_epi32(_mm256_permutevar8x32_epi32(sum, order), 2);
+            }
+            store8(&mut rows.u[x / 2..], weigh(&average, &cu, uv_offset));
+            store8(&mut rows.v[x / 2..], weigh(&average, &cv, uv_offset));
+            x += STEP;
+        }
+        x
+    }
+}
+
+#[cfg(target_arch = "aarch64")]
+mod neon {
+    use std::arch::aarch64::*;
+
+    use super::RowPair;
+    use crate::video::YUVCoefficients;
+
+    // pixels per iteration, vld4q_u8 loads 16 pixels split into channels
+    const STEP: usize = 16;
+
+    // ((k · rgb + 128) >> 8) + offset for 8 pixels
+    #[target_feature(enable = "neon")]
+    unsafe fn weigh(rgb: [uint16x8_t; 3], k: [i32; 3], offset: i32) -> uint8x8_t {
+        let mut low = vdupq_n_s32(128);
+        let mut high = vdupq_n_s32(128);
+        for (channel, k) in rgb.iter().zip(&k) {
+            let channel = vreinterpretq_s16_u16(*channel);
+            low = vmlal_n_s16(low, vget_low_s16(channel), *k as i16);
+            high = vmlal_high_n_s16(high, channel, *k as i16);
+        }
+        let offset = vdupq_n_s32(offset);
+        let low = vmovn_s32(vaddq_s32(vshrq_n_s32::<8>(low), offset));
+        let high = vmovn_s32(vaddq_s32(vshrq_n_s32::<8>(high), offset));
+        vreinterpret_u8_s8(vmovn_s16(vcombine_s16(low, high)))
+    }
+
+    #[target_feature(enable = "neon")]
+    pub unsafe fn convert(
+        rows: &mut RowPair,
+        width: usize,
+        offsets: [usize; 3],
+        c: &YUVCoefficients,
+    ) -> usize {
+        let mut x = 0;
+        while x + STEP <= width {
+            // r, g and b of horizontally adjacent pixels summed over both rows
+            let mut sums = [vdupq_n_u16(0); 3];
+            for (src, y) in rows.src.iter().zip(rows.y.iter_mut()) {
+                let pixels = vld4q_u8(src[4 * x..4 * x + 64].as_ptr());
+                let channels = [pixels.0, pixels.1, pixels.2, pixels.3];
+                let rgb = [
+                    channels[offsets[0]],
+                    channels[offsets[1]],
+                    channels[offsets[2]],
+                ];
+                let low = weigh(
+                    [
+                        vmovl_u8(vget_low_u8(rgb[0])),
+                        vmovl_u8(vget_low_u8(rgb[1])),
+                        vmovl_u8(vget_low_u8(rgb[2])),
+                    ],
+                    c.y,
+                    c.y_offset,
+                );
+                let high = weigh(
+                    [
+                        vmovl_high_u8(rgb[0]),
+                        vmovl_high_u8(rgb[1]),
+                        vmovl_high_u8(rgb[2]),
+                    ],
+                    c.y,
+                    c.y_offset,
+                );
+                vst1q_u8(y[x..x + 16].as_mut_ptr(), vcombine_u8(low, high));
+                for (sum, channel) in sums.iter_mut().zip(&rgb) {
+                    *sum = vpadalq_u8(*sum, *channel);
+                }
+            }
+            let average = [
+                vshrq_n_u16::<2>(sums[0]),
+                vshrq_n_u16::<2>(sums[1]),
+                vshrq_n_u16::<2>(sums[2]),
+            ];
+            vst1_u8(
+                rows.u[x / 2..x / 2 + 8].as_mut_ptr(),
+                weigh(average, c.u, 128),
+            );
+            vst1_u8(
+                rows.v[x / 2..x / 2 + 8].as_mut_ptr(),
+                weigh(average, c.v, 128),
+            );
+            x += STEP;
+        }
+        x
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::video::{ColorRange, ColorSpace, EncoderOptions};
+
+    fn coefficients(color_space: ColorSpace, color_range: ColorRange) -> YUVCoefficients {
+        EncoderOptions {
+            scaling_filter: crate::video::ScalingFilter::FastBilinear,
+            color_space,
+            color_range,
+            pointer_roi: false,
+            codec: crate::video::Codec::X264,
+            threads: 1,
+            container: crate::video::Container::Mp4,
+            gpu_convert: false,
+            max_resolution: None,
+            latency_budget: None,
+            intra_refresh: false,
+        }
+        .yuv_coefficients()
+    }
+
+    // the SIMD implementations have to produce exactly what the scalar one produces
+    #[test]
+    fn simd_matches_scalar() {
+        let (width, height) = (70, 6);
+        let src: Vec<u8> = (0..width * height * 4)
+            .map(|i| (i * 7919 % 251) as u8)
+            .collect();
+        for &color_space in &[ColorSpace::BT601, ColorSpace::BT709] {
+            for &color_range in &[ColorRange::Limited, ColorRange::Full] {
+                let c = coefficients(color_space, color_range);
+                for &format in &[PixelFormat::BGRA, PixelFormat::RGBx] {
+                    let mut y = vec![0; width * height];
+                    let mut u = vec![0; width * height / 4];
+                    let mut v = vec![0; width * height / 4];
+                    let mut planes = YUV420PPlanes {
+                        y: &mut y,
+                        u: &mut u,
+                        v: &mut v,
+                        y_stride: width,
+                        u_stride: width / 2,
+                        v_stride: width / 2,
+                    };
+                    packed_rgb_to_yuv420p(&src, format, width, height, &c, &mut planes);
+
+                    let mut y_scalar = vec![0; y.len()];
+                    let mut u_scalar = vec![0; u.len()];
+                    let mut v_scalar = vec![0; v.len()];
+                    let offsets = rgb_offsets(format);
+                    for row in (0..height).step_by(2) {
+                        let (y0, y1) = y_scalar[row * width..].split_at_mut(width);
+                        let mut rows = RowPair {
+                            src: [
+                                &src[row * width * 4..(row + 1) * width * 4],
+                                &src[(row + 1) * width * 4..(row + 2) * width * 4],
+                            ],
+                            y: [y0, y1],
+                            u: &mut u_scalar[row / 2 * width / 2..],
+                            v: &mut v_scalar[row / 2 * width / 2..],
+                        };
+                        convert_scalar(&mut rows, 0, width, offsets, &c);
+                    }
+                    assert_eq!(y, y_scalar);
+                    assert_eq!(u, u_scalar);
+                    assert_eq!(v, v_scalar);
+                }
+            }
+        }
+    }
+
+    // the rows of U and V sum up to zero, so grey stays free of any tint
+    #[test]
+    fn grey_has_no_chroma() {
+        let (width, height) = (70, 2);
+        for &grey in &[0u8, 37, 128, 200, 255] {
+            let src = vec![grey; width * height * 4];
+            for &color_space in &[ColorSpace::BT601, ColorSpace::BT709] {
+                for &color_range in &[ColorRange::Limited, ColorRange::Full] {
+                    let c = coefficients(color_space, color_range);
+                    // with SIMD if the CPU supports it
+                    let mut y = vec![0; width * height];
+                    let mut u = vec![0; width / 2];
+                    let mut v = vec![0; width / 2];
+                    let mut planes = YUV420PPlanes {
+                        y: &mut y,
+                        u: &mut u,
+                        v: &mut v,
+                        y_stride: width,
+                        u_stride: width / 2,
+                        v_stride: width / 2,
+                    };
+                    packed_rgb_to_yuv420p(&src, PixelFormat::BGRA, width, height, &c, &mut planes);
+                    assert!(u.iter().chain(&v).all(|&x| x == 128), "{:?}", (grey, u, v));
+
+                    // and with the scalar implementation only
+                    let (mut u, mut v) = (vec![0; width / 2], vec![0; width / 2]);
+                    let (y0, y1) = y.split_at_mut(width);
+                    let mut rows = RowPair {
+                        src: [&src[..width * 4], &src[width * 4..]],
+                        y: [y0, y1],
+                        u: &mut u,
+                        v: &mut v,
+                    };
+                    convert_scalar(&mut rows, 0, width, rgb_offsets(PixelFormat::BGRA), &c);
+                    assert!(u.iter().chain(&v).all(|&x| x == 128), "{:?}", (grey, u, v));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn slices_match_whole_image() {
+        let (width, height) = (33, 14);
+        let src: Vec<u8> = (0..width * height * 4).map(|i| (i % 253) as u8).collect();
+        let c = coefficients(ColorSpace::BT709, ColorRange::Limited);
+        let convert = |pool: Option<&SlicePool>| {
+            let (mut y, mut u, mut v) = (vec![0; 32 * 14], vec![0; 16 * 7], vec![0; 16 * 7]);
+            let mut planes = YUV420PPlanes {
+                y: &mut y,
+                u: &mut u,
+                v: &mut v,
+                y_stride: 32,
+                u_stride: 16,
+                v_stride: 16,
+            };
+            match pool {
+                Some(pool) => packed_rgb_to_yuv420p_sliced(
+                    pool,
+                    &src,
+                    PixelFormat::BGRA,
+                    width,
+                    height,
+                    &c,
+                    &mut planes,
+                ),
+                None => {
+                    packed_rgb_to_yuv420p(&src, PixelFormat::BGRA, width, height, &c, &mut planes)
+                }
+            }
+            (y, u, v)
+        };
+        let whole = convert(None);
+        for threads in 1..6 {
+            assert_eq!(convert(Some(&SlicePool::new(threads))), whole);
+        }
+    }
+}
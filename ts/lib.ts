@@ -1,5 +1,102 @@
 function run(password: string, websocket_pointer_port: number, websocket_video_port: number) {
     window.onload = () => { init(password, websocket_pointer_port, websocket_video_port) };
+    // caches the client for installs to the home screen, browsers only allow this via HTTPS
+    if ("serviceWorker" in navigator) {
+        navigator.serviceWorker.register("sw.js").catch(
+            (err) => console.log("Failed to register service worker: " + err)
+        );
+    }
+}
+
+// how often and how fast a lost connection is reestablished, the server keeps the session of a
+// client for 30 s, see SESSION_TIMEOUT
+const RECONNECT_ATTEMPTS = 10;
+const RECONNECT_DELAY_MILLIS = 500;
+
+// identifies the session of this page on the server, after a connection broke the new connection
+// resumes the session: input devices keep their state and the video continues with a keyframe
+function random_token(): string {
+    return Array.from(
+        window.crypto.getRandomValues(new Uint8Array(16)),
+        (b) => b.toString(16).padStart(2, "0")
+    ).join("");
+}
+
+const session_token = random_token();
+
+// identifies this device across sessions, a device approved on the server once is not asked
+// about again, see --require-approval
+const device_token = (() => {
+    let token = localStorage.getItem("device_token");
+    if (token == null) {
+        token = random_token();
+        localStorage.setItem("device_token", token);
+    }
+    return token;
+})();
+
+// a websocket that reconnects if the connection is lost
+class Connection {
+    url: string;
+    password: string;
+    binaryType: BinaryType;
+    socket: WebSocket;
+    attempts = 0;
+    closed = false;
+    // called whenever a connection has been (re)established and authenticated
+    onopen: () => void = () => { };
+    onmessage: (event: MessageEvent) => void = () => { };
+
+    constructor(port: number, password: string, binaryType: BinaryType = "blob") {
+        this.url = "ws://" + window.location.hostname + ":" + port + "/?session=" + session_token
+            + "&device=" + device_token;
+        this.password = password;
+        this.binaryType = binaryType;
+        this.connect();
+    }
+
+    connect() {
+        let socket = new WebSocket(this.url);
+        socket.binaryType = this.binaryType;
+        socket.onopen = () => {
+            this.attempts = 0;
+            if (this.password)
+                socket.send(this.password);
+            this.onopen();
+        };
+        socket.onmessage = (event: MessageEvent) => this.onmessage(event);
+        socket.onclose = (event: CloseEvent) => this.reconnect(event);
+        this.socket = socket;
+    }
+
+    reconnect(event: CloseEvent) {
+        if (this.closed)
+            return;
+        // the server closes connections normally if the client has been disconnected on purpose
+        if (event.code == 1000 || this.attempts >= RECONNECT_ATTEMPTS) {
+            this.closed = true;
+            handle_disconnect(event.code == 1000 ? "Connection closed." : "Lost connection.");
+            return;
+        }
+        this.attempts++;
+        console.log("Lost connection, reconnecting (attempt " + this.attempts + ").");
+        setTimeout(() => this.connect(), RECONNECT_DELAY_MILLIS * this.attempts);
+    }
+
+    isOpen() {
+        return this.socket.readyState == WebSocket.OPEN;
+    }
+
+    // messages are dropped while reconnecting
+    send(data: string) {
+        if (this.isOpen())
+            this.socket.send(data);
+    }
+
+    close() {
+        this.closed = true;
+        this.socket.close();
+    }
 }
 
 class ClientConfig {
@@ -18,6 +115,7 @@ class PEvent {
     timestamp: number;
     is_primary: boolean;
     pointer_type: string;
+    tool: string;
     button: number;
     buttons: number;
     x: number;
@@ -31,7 +129,7 @@ class PEvent {
     width: number;
     height: number;
 
-    constructor(eventType: string, event: PointerEvent, video: HTMLVideoElement) {
+    constructor(eventType: string, event: PointerEvent, video: HTMLVideoElement, tool: string) {
         let videoRect = video.getBoundingClientRect();
         let diag_len = Math.sqrt(videoRect.width * videoRect.width + videoRect.height * videoRect.height)
         this.event_type = eventType.toString();
@@ -39,6 +137,8 @@ class PEvent {
         this.timestamp = Math.round(event.timeStamp * 1000);
         this.is_primary = event.isPrimary;
         this.pointer_type = event.pointerType;
+        // pens with an eraser tip report it as the sixth button
+        this.tool = event.buttons & 32 ? "eraser" : tool;
         this.button = event.button < 0 ? 0 : 1 << event.button;
         this.buttons = event.buttons;
         this.x = (event.clientX - videoRect.left) / videoRect.width;
@@ -56,106 +156,598 @@ class PEvent {
 
 class PointerHandler {
     video: HTMLVideoElement;
-    webSocket: WebSocket;
+    webSocket: Connection;
+    // tool of pens selected with the tool button
+    tool: string = "pen";
 
-    constructor(video: HTMLVideoElement, webSocket: WebSocket) {
+    constructor(video: HTMLVideoElement, webSocket: Connection) {
         this.video = video;
         this.webSocket = webSocket;
         this.video.addEventListener("pointerdown", (e) => { this.onDown(e) }, false);
         this.video.addEventListener("pointerup", (e) => { this.onUp(e) }, false);
         this.video.addEventListener("pointercancel", (e) => { this.onCancel(e) }, false);
         this.video.addEventListener("pointermove", (e) => { this.onMove(e) }, false);
+        this.video.addEventListener("pointerleave", (e) => { this.onLeave(e) }, false);
     }
 
     onDown(event: PointerEvent) {
-        this.webSocket.send(JSON.stringify({ "PointerEvent": new PEvent("pointerdown", event, this.video) }));
+        this.webSocket.send(JSON.stringify({ "PointerEvent": new PEvent("pointerdown", event, this.video, this.tool) }));
     }
 
     onUp(event: PointerEvent) {
-        this.webSocket.send(JSON.stringify({ "PointerEvent": new PEvent("pointerup", event, this.video) }));
+        this.webSocket.send(JSON.stringify({ "PointerEvent": new PEvent("pointerup", event, this.video, this.tool) }));
     }
 
     onCancel(event: PointerEvent) {
-        this.webSocket.send(JSON.stringify({ "PointerEvent": new PEvent("pointercancel", event, this.video) }));
+        this.webSocket.send(JSON.stringify({ "PointerEvent": new PEvent("pointercancel", event, this.video, this.tool) }));
+    }
+
+    onLeave(event: PointerEvent) {
+        // the server needs to know when a hovering pen goes out of range
+        if (event.pointerType == "pen")
+            this.webSocket.send(JSON.stringify({ "PointerEvent": new PEvent("pointerleave", event, this.video, this.tool) }));
     }
 
     onMove(event: PointerEvent) {
-        this.webSocket.send(JSON.stringify({ "PointerEvent": new PEvent("pointermove", event, this.video) }));
+        // browsers may combine several movements into a single event, send all of them to allow
+        // for smoother strokes
+        let events: PointerEvent[] = "getCoalescedEvents" in event ? (event as any).getCoalescedEvents() : [];
+        if (events.length > 1)
+            this.webSocket.send(JSON.stringify({ "PointerEvents": events.map((e) => new PEvent("pointermove", e, this.video, this.tool)) }));
+        else
+            this.webSocket.send(JSON.stringify({ "PointerEvent": new PEvent("pointermove", event, this.video, this.tool) }));
     }
 }
 
-function process_stream(videoWebSocket: WebSocket, video: HTMLVideoElement) {
+// audio comes in chunks of raw PCM, signed 16 bit stereo at 48 kHz, see --audio
+const AUDIO_SAMPLE_RATE = 48000;
+const AUDIO_CHANNELS = 2;
+// seconds audio is played ahead to cover jitter of the network, if playback lags further behind
+// than the maximum delay chunks are skipped to stay in sync with the video
+const AUDIO_BUFFER = 0.06;
+const AUDIO_MAX_DELAY = 0.3;
+
+class AudioPlayer {
+    context: AudioContext = null;
+    // when the next chunk starts playing on the clock of the audio context
+    next_time = 0;
+
+    play(data: ArrayBuffer) {
+        if (this.context == null) {
+            this.context = new AudioContext({
+                sampleRate: AUDIO_SAMPLE_RATE,
+                latencyHint: "interactive"
+            });
+            // browsers only play audio once the user interacted with the page
+            document.addEventListener("pointerdown", () => this.context.resume(), { passive: true });
+        }
+        if (this.context.state != "running")
+            return;
+        let samples = new Int16Array(data);
+        let frames = Math.floor(samples.length / AUDIO_CHANNELS);
+        let buffer = this.context.createBuffer(AUDIO_CHANNELS, frames, AUDIO_SAMPLE_RATE);
+        for (let c = 0; c < AUDIO_CHANNELS; c++) {
+            let channel = buffer.getChannelData(c);
+            for (let i = 0; i < frames; i++)
+                channel[i] = samples[i * AUDIO_CHANNELS + c] / 32768;
+        }
+        let now = this.context.currentTime;
+        if (this.next_time < now || this.next_time > now + AUDIO_MAX_DELAY)
+            this.next_time = now + AUDIO_BUFFER;
+        let source = this.context.createBufferSource();
+        source.buffer = buffer;
+        source.connect(this.context.destination);
+        source.start(this.next_time);
+        this.next_time += buffer.duration;
+    }
+}
+
+// H.264 as sent to clients decoding via WebCodecs is in Annex B format: NAL units that each follow
+// a start code 00 00 01, keyframes are preceded by the parameter sets
+const NAL_IDR = 5;
+const NAL_SPS = 7;
+
+// offsets of the NAL units of a frame up to its first slice, they tell whether the frame is a
+// keyframe and contain the parameter sets
+function nal_units(data: Uint8Array): number[] {
+    let offsets: number[] = [];
+    for (let i = 2; i + 1 < data.length; i++) {
+        if (data[i] != 1 || data[i - 1] != 0 || data[i - 2] != 0)
+            continue;
+        offsets.push(i + 1);
+        let type = data[i + 1] & 0x1f;
+        if (type >= 1 && type <= NAL_IDR)
+            break;
+    }
+    return offsets;
+}
+
+// codec string as required by WebCodecs, profile, constraints and level follow the header of the
+// sequence parameter set
+function h264_codec(data: Uint8Array, sps: number): string {
+    return "avc1." + Array.from(
+        data.slice(sps + 1, sps + 4),
+        (b) => b.toString(16).padStart(2, "0")
+    ).join("");
+}
+
+// browsers offer WebCodecs only in secure contexts, i.e. via HTTPS or on localhost, otherwise the
+// video is played via Media Source Extensions
+function webcodecs_supported(): boolean {
+    return "VideoDecoder" in window && "captureStream" in HTMLCanvasElement.prototype;
+}
+
+function process_stream(videoWebSocket: Connection, video: HTMLVideoElement) {
     let mediaSource: MediaSource = null;
     let sourceBuffer: SourceBuffer = null;
     let queue = [];
+    // capture timestamp of the frame that is currently being received
+    let frame_timestamp: number = null;
+    // with MJPEG every frame is a JPEG, it is shown as poster of the video element
+    let mjpeg = false;
+    let poster_url: string = null;
+    let last_timing_report = 0;
+    // the first chunk of a stream is its header, it is kept to restart decoding after errors
+    let header: ArrayBuffer = null;
+    let last_recovery = 0;
+    // the server announces audio with a text message, the next binary message is not video
+    let audio = new AudioPlayer();
+    let audio_next = false;
+    // seconds the video may lag behind before skipping ahead, the server may send a latency budget
+    let max_lag = 0.01;
+    // with WebCodecs frames are decoded as they arrive and drawn on a canvas, the canvas is streamed
+    // to the video element so everything else keeps working as with Media Source Extensions
+    let annexb = false;
+    let decoder: any = null;
     function upd_buf() {
         if (sourceBuffer == null)
             return;
         if (!sourceBuffer.updating && queue.length > 0 && mediaSource.readyState == "open") {
-            sourceBuffer.appendBuffer(queue.shift());
+            try {
+                sourceBuffer.appendBuffer(queue.shift());
+            } catch (err) {
+                recover();
+            }
+        }
+    }
+    function open_media_source() {
+        mediaSource = new MediaSource();
+        sourceBuffer = null;
+        video.src = URL.createObjectURL(mediaSource);
+        mediaSource.addEventListener("sourceopen", (_) => {
+            let mimeType = 'video/mp4; codecs="avc1.4D403D"';
+            if (!MediaSource.isTypeSupported(mimeType))
+                mimeType = "video/mp4";
+            sourceBuffer = mediaSource.addSourceBuffer(mimeType);
+            sourceBuffer.addEventListener("updateend", upd_buf);
+            sourceBuffer.addEventListener("error", recover);
+            upd_buf();
+        })
+    }
+    function open_decoder() {
+        close_decoder();
+        let canvas = document.createElement("canvas");
+        let context = canvas.getContext("2d");
+        decoder = new (window as any).VideoDecoder({
+            output: (frame: any) => {
+                if (canvas.width != frame.displayWidth || canvas.height != frame.displayHeight) {
+                    canvas.width = frame.displayWidth;
+                    canvas.height = frame.displayHeight;
+                }
+                context.drawImage(frame, 0, 0);
+                frame.close();
+            },
+            error: (err: any) => {
+                console.log("Failed to decode video: " + err);
+                recover();
+            }
+        });
+        video.srcObject = (canvas as any).captureStream();
+    }
+    function close_decoder() {
+        if (decoder != null && decoder.state != "closed")
+            decoder.close();
+        decoder = null;
+        video.srcObject = null;
+    }
+    // the decoder is configured with the parameter sets of the first keyframe, frames before it
+    // can not be decoded
+    function decode(data: ArrayBuffer, received: number) {
+        if (decoder == null || decoder.state == "closed") {
+            recover();
+            return;
+        }
+        let bytes = new Uint8Array(data);
+        let units = nal_units(bytes);
+        let key = units.some((offset) => (bytes[offset] & 0x1f) == NAL_IDR);
+        if (decoder.state == "unconfigured") {
+            let sps = units.find((offset) => (bytes[offset] & 0x1f) == NAL_SPS);
+            if (!key || sps == undefined)
+                return;
+            // without a description the decoder expects Annex B
+            decoder.configure({ codec: h264_codec(bytes, sps), optimizeForLatency: true });
         }
+        decoder.decode(new (window as any).EncodedVideoChunk({
+            type: key ? "key" : "delta",
+            timestamp: Math.round(received * 1000),
+            data: data
+        }));
     }
+    // the decoder lost sync, instead of reloading the page decoding starts over with the header
+    // and the server is asked for a keyframe, frames until then are dropped by the browser
+    function recover() {
+        let now = performance.now();
+        if (mjpeg || (!annexb && header == null) || now - last_recovery < 1000)
+            return;
+        last_recovery = now;
+        if (annexb) {
+            // a new decoder waits for the keyframe
+            open_decoder();
+        } else {
+            queue = [header];
+            open_media_source();
+        }
+        videoWebSocket.send(JSON.stringify("Keyframe"));
+    }
+    video.addEventListener("error", recover);
+    // frames are requested one at a time, requests scheduled before the connection broke must not
+    // start a second request loop on the new connection
+    let connection_id = 0;
+    function request_frame(id: number) {
+        if (id == connection_id)
+            videoWebSocket.send("");
+    }
+    videoWebSocket.onopen = () => {
+        connection_id++;
+        // the server sends NAL units instead of fragmented mp4 from the next stream on
+        if (webcodecs_supported())
+            videoWebSocket.send(JSON.stringify("WebCodecs"));
+        request_frame(connection_id);
+    };
     videoWebSocket.onmessage = (event: MessageEvent) => {
+        let id = connection_id;
         if (typeof event.data == "string") {
             if (event.data[0] == "@") {
-                let interval_millis: number = parseInt(event.data.slice(1, -1));
-                setTimeout(() => videoWebSocket.send(""), interval_millis);
+                let interval_millis: number = parseInt(event.data.slice(1));
+                setTimeout(() => request_frame(id), interval_millis);
+            } else if (event.data == "audio") {
+                audio_next = true;
+            } else if (event.data[0] == "#") {
+                frame_timestamp = parseFloat(event.data.slice(1));
+            } else if (event.data.startsWith("paused")) {
+                show_paused(video, event.data == "paused blank");
+            } else if (event.data == "resumed") {
+                hide_paused(video);
+            } else if (event.data.startsWith("budget ")) {
+                max_lag = Math.max(parseInt(event.data.slice(7)) / 1000, 0.01);
+            } else if (event.data.startsWith("stats ")) {
+                show_stats(JSON.parse(event.data.slice(6)));
+            } else if (event.data == "new mjpeg") {
+                mjpeg = true;
+                annexb = false;
+                close_decoder();
+                mediaSource = null;
+                sourceBuffer = null;
+                video.removeAttribute("src");
+                video.load();
+                requestAnimationFrame(() => request_frame(id));
             } else if (event.data == "new") {
-                mediaSource = new MediaSource();
+                mjpeg = false;
+                annexb = false;
+                close_decoder();
+                header = null;
+                video.removeAttribute("poster");
+                open_media_source();
+                requestAnimationFrame(() => request_frame(id));
+            } else if (event.data == "new annexb") {
+                mjpeg = false;
+                annexb = true;
+                mediaSource = null;
                 sourceBuffer = null;
-                video.src = URL.createObjectURL(mediaSource);
-                mediaSource.addEventListener("sourceopen", (_) => {
-                    let mimeType = 'video/mp4; codecs="avc1.4D403D"';
-                    if (!MediaSource.isTypeSupported(mimeType))
-                        mimeType = "video/mp4";
-                    sourceBuffer = mediaSource.addSourceBuffer(mimeType);
-                    sourceBuffer.addEventListener("updateend", upd_buf);
-                })
-                requestAnimationFrame(() => videoWebSocket.send(""));
+                video.removeAttribute("poster");
+                video.removeAttribute("src");
+                open_decoder();
+                requestAnimationFrame(() => request_frame(id));
             }
             return;
         }
-        queue.push(event.data);
-        upd_buf();
-        if (video.seekable.length > 0 && video.seekable.end(0) - video.currentTime > 0.01)
-            video.currentTime = video.seekable.end(0)
-        requestAnimationFrame(() => videoWebSocket.send(""));
+        if (audio_next) {
+            audio_next = false;
+            audio.play(event.data);
+            return;
+        }
+        let received = performance.now();
+        let capture_timestamp = frame_timestamp;
+        if (mjpeg) {
+            if (poster_url != null)
+                URL.revokeObjectURL(poster_url);
+            poster_url = URL.createObjectURL(new Blob([event.data], { type: "image/jpeg" }));
+            video.poster = poster_url;
+            stretch_video(video);
+        } else if (annexb) {
+            decode(event.data, received);
+        } else {
+            queue.push(event.data);
+            upd_buf();
+            // the header is followed by the frame that has been requested
+            if (header == null) {
+                header = event.data;
+                return;
+            }
+            if (video.seekable.length > 0 && video.seekable.end(0) - video.currentTime > max_lag)
+                video.currentTime = video.seekable.end(0)
+        }
+        requestAnimationFrame(() => {
+            let displayed = performance.now();
+            // report timings about once a second so the server can estimate the latency
+            if (capture_timestamp != null && displayed - last_timing_report > 1000) {
+                last_timing_report = displayed;
+                videoWebSocket.send(JSON.stringify({
+                    "FrameTiming": {
+                        "capture_timestamp": capture_timestamp,
+                        "received": received,
+                        "displayed": displayed,
+                        "clock_offset": clock_offset
+                    }
+                }));
+            }
+            request_frame(id);
+        });
     }
 }
 
 function init(password: string, websocket_pointer_port: number, websocket_video_port: number) {
 
-    // pointer
-    let webSocket = new WebSocket("ws://" + window.location.hostname + ":" + websocket_pointer_port);
-    webSocket.onopen = function(event) {
-        if (password)
-            webSocket.send(password);
-        let pointerHandler = new PointerHandler(video, webSocket);
-    }
-
-    webSocket.onerror = () => handle_disconnect("Lost connection.");
-    webSocket.onclose = () => handle_disconnect("Connection closed.");
-
     // videostreaming
     let video = document.getElementById("video") as HTMLVideoElement;
 
-    window.onresize = () => stretch_video(video);
+    // pointer
+    let webSocket = new Connection(websocket_pointer_port, password);
+    let pointerHandler = new PointerHandler(video, webSocket);
+    let send_viewport = setup_zoom(webSocket, video);
+    setup_tool(pointerHandler);
+    webSocket.onopen = () => {
+        send_orientation(webSocket);
+        send_viewport();
+        sync_clock(webSocket);
+        setup_shortcuts(webSocket);
+        setup_gestures(webSocket);
+        setup_pause(webSocket);
+        setup_pressure_calibration(webSocket);
+    }
+    setup_stats();
+
+    window.onresize = () => {
+        stretch_video(video);
+        send_orientation(webSocket);
+    };
     video.controls = false;
     video.onloadeddata = () => stretch_video(video);
-    let videoWebSocket = new WebSocket("ws://" + window.location.hostname + ":" + websocket_video_port);
-    videoWebSocket.binaryType = "arraybuffer";
-    videoWebSocket.onopen = () => {
-        if (password)
-            videoWebSocket.send(password);
-        videoWebSocket.send("");
-    }
-    videoWebSocket.onerror = () => handle_disconnect("Lost connection.");
-    videoWebSocket.onclose = () => handle_disconnect("Connection closed.");
+    let videoWebSocket = new Connection(websocket_video_port, password, "arraybuffer");
     process_stream(videoWebSocket, video);
     window.onunload = () => { webSocket.close(); videoWebSocket.close(); }
 }
 
 
+// offset between the clock of the server and performance.now(), null until sync_clock finished
+let clock_offset: number = null;
+
+// estimate the offset between the clock of the server and performance.now() the same way NTP
+// does, the server uses this to relate timestamps of pointer events and frames to its own clock
+function sync_clock(webSocket: Connection) {
+    const num_probes = 8;
+    let best_offset: number = null;
+    let best_round_trip: number = null;
+    let received = 0;
+    webSocket.onmessage = (event: MessageEvent) => {
+        let msg = JSON.parse(event.data);
+        if (!("ClockProbe" in msg))
+            return;
+        let now = performance.now();
+        let probe = msg["ClockProbe"];
+        let round_trip = now - probe["client_time"];
+        // the probe with the shortest round trip gives the most accurate estimate
+        if (best_round_trip == null || round_trip < best_round_trip) {
+            best_round_trip = round_trip;
+            best_offset = probe["server_time"] - (probe["client_time"] + now) / 2;
+        }
+        received++;
+        if (received == num_probes) {
+            clock_offset = best_offset;
+            webSocket.send(JSON.stringify({
+                "ClockOffset": { "offset": best_offset, "round_trip": best_round_trip }
+            }));
+        }
+    };
+    for (let i = 0; i < num_probes; i++)
+        setTimeout(() => {
+            webSocket.send(JSON.stringify({
+                "ClockProbe": { "client_time": performance.now(), "server_time": null }
+            }));
+        }, 100 * i);
+}
+
+// buttons for the shortcuts configured on the server, see the index template
+function setup_shortcuts(webSocket: Connection) {
+    document.querySelectorAll(".shortcut").forEach((button: HTMLElement) => {
+        button.onclick = () => webSocket.send(JSON.stringify({ "Shortcut": button.dataset.name }));
+    });
+}
+
+// let the server translate two finger gestures into scrolling and zooming, the setting is
+// remembered by the browser
+function setup_gestures(webSocket: Connection) {
+    let button = document.getElementById("gestures");
+    // left out for clients that may only watch
+    if (!button)
+        return;
+    let enabled = localStorage.getItem("gestures") == "true";
+    let update = () => {
+        if (enabled)
+            button.classList.add("enabled");
+        else
+            button.classList.remove("enabled");
+        webSocket.send(JSON.stringify({ "Gestures": enabled }));
+    };
+    button.onclick = () => {
+        enabled = !enabled;
+        localStorage.setItem("gestures", enabled.toString());
+        update();
+    };
+    update();
+}
+
+// stop the video for a moment, e.g. to enter a password on the computer, the video can also be
+// hidden on the tablet while paused
+function setup_pause(webSocket: Connection) {
+    // only clients that may control the computer can pause the video, it is paused for everyone
+    if (!document.getElementById("pause"))
+        return;
+    document.getElementById("pause").onclick = () => webSocket.send(JSON.stringify({ "Pause": false }));
+    document.getElementById("blank").onclick = () => webSocket.send(JSON.stringify({ "Pause": true }));
+    document.getElementById("paused").onclick = () => webSocket.send(JSON.stringify("Resume"));
+}
+
+// the server reports whether the video is paused on the video socket, this way all clients show
+// the card even if only one of them paused the video
+function show_paused(video: HTMLVideoElement, blank: boolean) {
+    let card = document.getElementById("paused");
+    card.style.display = "flex";
+    if (blank)
+        card.classList.add("blank");
+    else
+        card.classList.remove("blank");
+    video.style.visibility = blank ? "hidden" : "visible";
+}
+
+function hide_paused(video: HTMLVideoElement) {
+    document.getElementById("paused").style.display = "none";
+    video.style.visibility = "visible";
+}
+
+// overlay showing statistics about the video the server sends about once a second, they help
+// with bug reports, the setting is remembered by the browser
+function setup_stats() {
+    let button = document.getElementById("stats_toggle");
+    let overlay = document.getElementById("stats");
+    let enabled = localStorage.getItem("stats") == "true";
+    let update = () => {
+        if (enabled)
+            button.classList.add("enabled");
+        else
+            button.classList.remove("enabled");
+        overlay.style.display = enabled ? "block" : "none";
+    };
+    button.onclick = () => {
+        enabled = !enabled;
+        localStorage.setItem("stats", enabled.toString());
+        update();
+    };
+    update();
+}
+
+function show_stats(stats: any) {
+    document.getElementById("stats").textContent =
+        stats["width"] + "x" + stats["height"] + " @ " + stats["fps"].toFixed(1) + " fps\n" +
+        "encode: " + stats["encode_time"].toFixed(1) + " ms\n" +
+        "bitrate: " + (stats["bitrate"] / 1e6).toFixed(2) + " Mbit/s\n" +
+        "dropped: " + stats["dropped_frames"];
+}
+
+// the server measures the pressure range of the pen during the next stroke and rescales the
+// pressure of later strokes to the full range, the result is remembered by the browser and sent
+// to the server again on reconnects
+function setup_pressure_calibration(webSocket: Connection) {
+    let button = document.getElementById("calibrate");
+    // left out for clients that may only watch
+    if (!button)
+        return;
+    let range = localStorage.getItem("pressure_range");
+    webSocket.send(JSON.stringify({ "PressureRange": range ? JSON.parse(range) : null }));
+    let onmessage = webSocket.onmessage;
+    webSocket.onmessage = (event: MessageEvent) => {
+        onmessage(event);
+        let msg = JSON.parse(event.data);
+        if (!("PressureRange" in msg))
+            return;
+        localStorage.setItem("pressure_range", JSON.stringify(msg["PressureRange"]));
+        button.classList.remove("enabled");
+    };
+    button.onclick = () => {
+        button.classList.add("enabled");
+        webSocket.send(JSON.stringify("CalibratePressure"));
+    };
+}
+
+// switch the tool of pens, the host sees it like a tablet pen with several tools, the choice is
+// remembered by the browser
+function setup_tool(pointerHandler: PointerHandler) {
+    const tools = ["pen", "eraser", "highlighter"];
+    let button = document.getElementById("tool");
+    // left out for clients that may only watch
+    if (!button)
+        return;
+    let update = () => {
+        button.textContent = pointerHandler.tool.charAt(0).toUpperCase() + pointerHandler.tool.slice(1);
+        if (pointerHandler.tool == "pen")
+            button.classList.remove("enabled");
+        else
+            button.classList.add("enabled");
+    };
+    let tool = localStorage.getItem("tool");
+    if (tools.indexOf(tool) >= 0)
+        pointerHandler.tool = tool;
+    button.onclick = () => {
+        pointerHandler.tool = tools[(tools.indexOf(pointerHandler.tool) + 1) % tools.length];
+        localStorage.setItem("tool", pointerHandler.tool);
+        update();
+    };
+    update();
+}
+
+// zoom into the video around the position last touched, the server crops the captured image so
+// the zoomed video is as sharp as the screen, returns a function that sends the current viewport
+function setup_zoom(webSocket: Connection, video: HTMLVideoElement) {
+    const factors = [1, 2, 4];
+    let button = document.getElementById("zoom");
+    // left out if all clients share the same video or the client is view-only
+    if (!button)
+        return () => {};
+    let level = 0;
+    // viewport and last touched position relative to the video as shown without zoom
+    let viewport = { "x": 0, "y": 0, "width": 1, "height": 1 };
+    let center = [0.5, 0.5];
+    video.onpointerdown = (event: PointerEvent) => {
+        let rect = video.getBoundingClientRect();
+        center = [
+            viewport.x + (event.clientX - rect.left) / rect.width * viewport.width,
+            viewport.y + (event.clientY - rect.top) / rect.height * viewport.height
+        ];
+    };
+    let send = () => webSocket.send(JSON.stringify({ "Viewport": level == 0 ? null : viewport }));
+    button.onclick = () => {
+        level = (level + 1) % factors.length;
+        let size = 1 / factors[level];
+        // keep the viewport within the video
+        let start = (c: number) => Math.min(Math.max(c - size / 2, 0), 1 - size);
+        viewport = { "x": start(center[0]), "y": start(center[1]), "width": size, "height": size };
+        button.textContent = "Zoom " + factors[level] + "x";
+        if (level == 0)
+            button.classList.remove("enabled");
+        else
+            button.classList.add("enabled");
+        send();
+    };
+    return send;
+}
+
+// the server uses this to decide whether the video needs to be rotated
+function send_orientation(webSocket: Connection) {
+    let orientation = window.innerHeight > window.innerWidth ? "portrait" : "landscape";
+    webSocket.send(JSON.stringify({ "Orientation": orientation }));
+}
+
+
 // object-fit: fill; <-- this is unfortunately not supported on iOS, so we use the following
 // workaround
 function stretch_video(video: HTMLVideoElement) {
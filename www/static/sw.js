@@ -0,0 +1,45 @@
+// Caches the static parts of the client so it starts instantly once installed to the home screen.
+// The page itself is always loaded from Weylus as it contains the settings of the running server.
+const CACHE = "weylus-{{version}}";
+const ASSETS = ["style.css", "lib.js", "manifest.json", "icon.svg"];
+
+self.addEventListener("install", (event) => {
+    event.waitUntil(caches.open(CACHE).then((cache) => cache.addAll(ASSETS)));
+    self.skipWaiting();
+});
+
+// drop the caches of other versions of Weylus
+self.addEventListener("activate", (event) => {
+    event.waitUntil(
+        caches
+            .keys()
+            .then((keys) => Promise.all(keys.filter((key) => key !== CACHE).map((key) => caches.delete(key))))
+            .then(() => self.clients.claim())
+    );
+});
+
+// assets are served from the cache and updated in the background
+self.addEventListener("fetch", (event) => {
+    const url = new URL(event.request.url);
+    if (
+        event.request.method !== "GET" ||
+        url.origin !== self.location.origin ||
+        !ASSETS.includes(url.pathname.slice(1))
+    )
+        return;
+    event.respondWith(
+        caches.open(CACHE).then((cache) =>
+            cache.match(event.request).then((cached) => {
+                const fetched = fetch(event.request).then((response) => {
+                    if (response.ok) cache.put(event.request, response.clone());
+                    return response;
+                });
+                if (cached) {
+                    fetched.catch(() => {});
+                    return cached;
+                }
+                return fetched;
+            })
+        )
+    );
+});